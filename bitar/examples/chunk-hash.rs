@@ -0,0 +1,42 @@
+use std::time::Instant;
+
+use bitar::HashAlgorithm;
+
+// Hash the same data in chunks using each chunk hash algorithm and print the throughput.
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let size_mib: usize = match std::env::args().nth(1) {
+        Some(size) => size.parse()?,
+        None => 512,
+    };
+    const CHUNK_SIZE: usize = 64 * 1024;
+    // Pseudo random data, hashing time doesn't depend on the data but keep it realistic.
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let data: Vec<u8> = (0..size_mib * 1024 * 1024)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+
+    for algorithm in [
+        HashAlgorithm::Blake2b,
+        HashAlgorithm::Blake2s,
+        HashAlgorithm::Xxh3,
+    ] {
+        let start = Instant::now();
+        for chunk in data.chunks(CHUNK_SIZE) {
+            std::hint::black_box(algorithm.digest(chunk));
+        }
+        let elapsed = start.elapsed();
+        println!(
+            "{:>8}: {} KiB chunks in {:.2?} ({:.0} MiB/s)",
+            algorithm.to_string(),
+            CHUNK_SIZE / 1024,
+            elapsed,
+            size_mib as f64 / elapsed.as_secs_f64()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use std::io::Write;
+use std::time::Instant;
+
+use bitar::chunker;
+use futures_util::StreamExt;
+use tokio::fs::File;
+
+// Chunk the same file using each chunker, with the same average chunk size, and print the
+// throughput of each. Only boundaries are searched for, chunks are not hashed.
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let size_mib: usize = match std::env::args().nth(1) {
+        Some(size) => size.parse()?,
+        None => 2048,
+    };
+    // Pseudo random data, to get chunks of varying size.
+    let mut file = tempfile::NamedTempFile::new()?;
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut block = vec![0u8; 1024 * 1024];
+    for _ in 0..size_mib {
+        for byte in block.iter_mut() {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            *byte = state as u8;
+        }
+        file.write_all(&block)?;
+    }
+    file.flush()?;
+
+    let filter = chunker::FilterConfig::default();
+    let avg_chunk_size = filter.filter_bits.chunk_target_average() as usize;
+    for (name, config) in [
+        ("FixedSize", chunker::Config::FixedSize(avg_chunk_size)),
+        ("RollSum", chunker::Config::RollSum(filter)),
+        ("BuzHash", chunker::Config::BuzHash(filter)),
+    ] {
+        let start = Instant::now();
+        let mut chunks = 0usize;
+        let mut chunk_stream = config.new_chunker(File::open(file.path()).await?);
+        while let Some(result) = chunk_stream.next().await {
+            result?;
+            chunks += 1;
+        }
+        let elapsed = start.elapsed();
+        println!(
+            "{:>9}: {} chunks in {:.2?} ({:.0} MiB/s)",
+            name,
+            chunks,
+            elapsed,
+            size_mib as f64 / elapsed.as_secs_f64()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,163 @@
+use std::future::Future;
+use std::io::{self, Cursor, SeekFrom};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use bitar::{
+    api::compress::{create_archive, CreateArchiveOptions},
+    archive_reader::IoReader,
+    chunker, clone, Archive, CloneOutput, Compression,
+};
+use tokio::{
+    io::{AsyncSeek, AsyncWrite},
+    task::{spawn_blocking, JoinHandle},
+};
+
+const CHUNK_SIZE: usize = 256 * 1024;
+
+// Output where every write takes a fixed time, on a blocking thread like a tokio file.
+struct SlowOutput {
+    delay: Duration,
+    pending: Option<(JoinHandle<()>, usize)>,
+    position: u64,
+    busy: Arc<Mutex<Duration>>,
+}
+
+impl AsyncWrite for SlowOutput {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let output = self.get_mut();
+        let (handle, size) = output.pending.get_or_insert_with(|| {
+            let delay = output.delay;
+            *output.busy.lock().unwrap() += delay;
+            (spawn_blocking(move || std::thread::sleep(delay)), buf.len())
+        });
+        match Pin::new(handle).poll(cx) {
+            Poll::Ready(result) => {
+                let size = *size;
+                output.pending = None;
+                output.position += size as u64;
+                Poll::Ready(
+                    result
+                        .map(|()| size)
+                        .map_err(|err| io::Error::new(io::ErrorKind::Other, err)),
+                )
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for SlowOutput {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        match position {
+            SeekFrom::Start(position) => self.get_mut().position = position,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "only seeking from start is supported",
+                ))
+            }
+        }
+        Ok(())
+    }
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.position))
+    }
+}
+
+// Text like data, compressible but taking some time to decompress.
+fn text_like_data(size: usize) -> Vec<u8> {
+    const WORDS: [&str; 8] = [
+        "chunk", "archive", "offset", "clone", "seed", "header", "output", "stream",
+    ];
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut data = Vec::with_capacity(size);
+    while data.len() < size {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        data.extend_from_slice(WORDS[(state % 8) as usize].as_bytes());
+        data.extend_from_slice(format!(" {} ", state % 10_000).as_bytes());
+    }
+    data.truncate(size);
+    data
+}
+
+// Clone an in memory archive to an output where every write is slow, and compare the clone
+// time to the time spent writing and decompressing. The clone takes about as long as the
+// slower of the two when decompression and writes overlap, and their sum when they don't.
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let size_mib: usize = match std::env::args().nth(1) {
+        Some(size) => size.parse()?,
+        None => 64,
+    };
+    let write_delay = Duration::from_millis(match std::env::args().nth(2) {
+        Some(delay) => delay.parse()?,
+        None => 4,
+    });
+    let source = text_like_data(size_mib * 1024 * 1024);
+    let mut archive_buf = Vec::new();
+    create_archive(
+        &mut &source[..],
+        &mut archive_buf,
+        &CreateArchiveOptions {
+            chunker_config: chunker::Config::FixedSize(CHUNK_SIZE),
+            compression: Some(Compression::brotli(9)?),
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    // Time to decompress all chunks on a single thread.
+    let mut archive = Archive::try_init(IoReader::new(Cursor::new(archive_buf.clone()))).await?;
+    let index = archive.build_source_index();
+    let compressed: Vec<_> = {
+        use futures_util::TryStreamExt;
+        archive.chunk_stream(&index).try_collect().await?
+    };
+    let start = Instant::now();
+    for chunk in compressed {
+        chunk.into_verified()?;
+    }
+    let decompress_time = start.elapsed();
+
+    let busy = Arc::new(Mutex::new(Duration::ZERO));
+    let mut output = CloneOutput::new(
+        SlowOutput {
+            delay: write_delay,
+            pending: None,
+            position: 0,
+            busy: busy.clone(),
+        },
+        index,
+    );
+    let opts = clone::Options::default();
+    let start = Instant::now();
+    clone::from_archive(&opts, &mut archive, &mut output).await?;
+    let clone_time = start.elapsed();
+
+    let write_time = *busy.lock().unwrap();
+    println!(
+        "{} MiB in {} chunks, {} buffered chunks",
+        size_mib,
+        size_mib * 1024 * 1024 / CHUNK_SIZE,
+        opts.max_buffered_chunks
+    );
+    println!("  write time:                    {:?}", write_time);
+    println!("  decompress time (one thread):  {:?}", decompress_time);
+    println!("  clone time:                    {:?}", clone_time);
+    Ok(())
+}
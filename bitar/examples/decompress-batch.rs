@@ -0,0 +1,71 @@
+use std::io::Cursor;
+use std::time::Instant;
+
+use bitar::{
+    api::compress::{create_archive, CreateArchiveOptions},
+    archive_reader::IoReader,
+    chunker, clone, Archive, CloneOutput,
+};
+
+const CHUNK_SIZE: usize = 64;
+
+// Text like data where every chunk is unique.
+fn text_like_data(size: usize) -> Vec<u8> {
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut data = Vec::with_capacity(size);
+    while data.len() < size {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        data.extend_from_slice(format!("offset {} seed {} ", data.len(), state % 997).as_bytes());
+    }
+    data.truncate(size);
+    data
+}
+
+// Clone an in memory archive of very small chunks with different decompress batch sizes, and
+// print the clone time of each. Chunks are stored uncompressed so that the time spent per
+// blocking task isn't hidden by the time to decompress.
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let size_mib: usize = match std::env::args().nth(1) {
+        Some(size) => size.parse()?,
+        None => 32,
+    };
+    let source = text_like_data(size_mib * 1024 * 1024);
+    let mut archive_buf = Vec::new();
+    create_archive(
+        &mut &source[..],
+        &mut archive_buf,
+        &CreateArchiveOptions {
+            chunker_config: chunker::Config::FixedSize(CHUNK_SIZE),
+            compression: None,
+            ..Default::default()
+        },
+    )
+    .await?;
+    println!(
+        "{} MiB in {} chunks of {} bytes",
+        size_mib,
+        source.len() / CHUNK_SIZE,
+        CHUNK_SIZE
+    );
+    for decompress_batch_size in [1, 4, 16, 64, 256] {
+        let mut archive =
+            Archive::try_init(IoReader::new(Cursor::new(archive_buf.clone()))).await?;
+        let mut output = CloneOutput::new(Cursor::new(Vec::new()), archive.build_source_index());
+        let opts = clone::Options {
+            decompress_batch_size,
+            ..Default::default()
+        };
+        let start = Instant::now();
+        clone::from_archive(&opts, &mut archive, &mut output).await?;
+        let clone_time = start.elapsed();
+        assert!(output.into_inner().into_inner() == source);
+        println!(
+            "  batch size {:>3}:  {:?}",
+            decompress_batch_size, clone_time
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use std::io::{self, Cursor, SeekFrom};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use bitar::{archive_reader::IoReader, chunker, clone, Archive, CloneOutput, Compression};
+use tokio::io::{AsyncSeek, AsyncWrite};
+
+const CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+// Output discarding everything written.
+struct Discard(u64);
+
+impl AsyncWrite for Discard {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(Ok(buf.len()))
+    }
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for Discard {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        if let SeekFrom::Start(position) = position {
+            self.get_mut().0 = position;
+        }
+        Ok(())
+    }
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.0))
+    }
+}
+
+// Memory status of the process from /proc, in MiB.
+fn memory_status(key: &str) -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with(key))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib / 1024)
+}
+
+// Clone an archive of large compressed chunks, buffering many chunks while decompressing,
+// and print the peak memory use (Linux only) of the clone.
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let size_mib: usize = match std::env::args().nth(1) {
+        Some(size) => size.parse()?,
+        None => 512,
+    };
+    // Pseudo random bytes of a small alphabet, compressible but with chunks all different.
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let source: Vec<u8> = (0..size_mib * 1024 * 1024)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            b'a' + (state % 16) as u8
+        })
+        .collect();
+    let mut archive_data = Vec::new();
+    bitar::api::compress::create_archive(
+        &source[..],
+        &mut archive_data,
+        &bitar::api::compress::CreateArchiveOptions {
+            chunker_config: chunker::Config::FixedSize(CHUNK_SIZE),
+            compression: Some(Compression::brotli(1)?),
+            ..Default::default()
+        },
+    )
+    .await?;
+    drop(source);
+
+    let mut archive = Archive::try_init(IoReader::new(Cursor::new(archive_data))).await?;
+    let opts = clone::Options {
+        max_buffered_chunks: 16,
+        ..Default::default()
+    };
+    let rss_before = memory_status("VmRSS:");
+    // Reset the peak resident set size, which was reached while compressing.
+    let _ = std::fs::write("/proc/self/clear_refs", "5");
+    let start = Instant::now();
+    let mut output = CloneOutput::new(Discard(0), archive.build_source_index());
+    let fetched = clone::from_archive(&opts, &mut archive, &mut output).await?;
+    let elapsed = start.elapsed();
+    println!(
+        "Cloned {} MiB ({} MiB compressed) of {} MiB chunks in {:.2?}",
+        size_mib,
+        fetched / 1024 / 1024,
+        CHUNK_SIZE / 1024 / 1024,
+        elapsed
+    );
+    match (rss_before, memory_status("VmHWM:")) {
+        (Some(before), Some(peak)) => println!(
+            "Resident memory: {} MiB before clone, peak {} MiB (+{} MiB)",
+            before,
+            peak,
+            peak.saturating_sub(before)
+        ),
+        _ => println!("Memory use not available"),
+    }
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use std::collections::HashSet;
+
+use bitar::chunker;
+use futures_util::StreamExt;
+use tokio::fs::File;
+
+// Get the hashes of the unique chunks of a file, and the total size of the file.
+async fn unique_chunks(
+    path: &str,
+    config: &chunker::Config,
+) -> Result<(HashSet<(bitar::HashSum, usize)>, u64), Box<dyn std::error::Error>> {
+    let mut chunks = HashSet::new();
+    let mut total_size = 0u64;
+    let mut chunk_stream = chunker::chunk_stream(
+        config,
+        File::open(path).await?,
+        bitar::HashAlgorithm::Blake2b,
+        32,
+    );
+    while let Some(result) = chunk_stream.next().await {
+        let (_offset, hash, size) = result?;
+        total_size += size as u64;
+        chunks.insert((hash, size));
+    }
+    Ok((chunks, total_size))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1);
+    let (first, second) = match (args.next(), args.next()) {
+        (Some(first), Some(second)) => (first, second),
+        _ => {
+            eprintln!("usage: dedup-ratio FILE FILE");
+            std::process::exit(1);
+        }
+    };
+    let config = chunker::Config::RollSum(chunker::FilterConfig::default());
+    let (first_chunks, first_size) = unique_chunks(&first, &config).await?;
+    let (second_chunks, second_size) = unique_chunks(&second, &config).await?;
+
+    // Bytes needed to store both files when each unique chunk is only stored once.
+    let unique_size: u64 = first_chunks
+        .union(&second_chunks)
+        .map(|(_hash, size)| *size as u64)
+        .sum();
+    let shared_size: u64 = first_chunks
+        .intersection(&second_chunks)
+        .map(|(_hash, size)| *size as u64)
+        .sum();
+    println!(
+        "{}: {} bytes, {} unique chunks",
+        first,
+        first_size,
+        first_chunks.len()
+    );
+    println!(
+        "{}: {} bytes, {} unique chunks",
+        second,
+        second_size,
+        second_chunks.len()
+    );
+    println!("Shared chunks: {} bytes", shared_size);
+    println!(
+        "Dedup ratio: {:.2}",
+        (first_size + second_size) as f64 / unique_size.max(1) as f64
+    );
+    Ok(())
+}
@@ -15,6 +15,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut output = CloneOutput::new(
         OpenOptions::new()
             .create(true)
+            .truncate(true)
             .write(true)
             .open(output_name)
             .await
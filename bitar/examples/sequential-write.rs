@@ -0,0 +1,130 @@
+use std::io::{self, SeekFrom};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bitar::{Chunk, ChunkIndex, CloneOutput, HashSum};
+use tokio::io::{AsyncSeek, AsyncWrite};
+
+const CHUNK_SIZE: usize = 16 * 1024;
+// Rotational disk characteristics.
+const SEEK_TIME: Duration = Duration::from_millis(8);
+const TRANSFER_RATE: f64 = 150.0 * 1024.0 * 1024.0;
+
+// Disk only keeping track of the time spent seeking and transferring data.
+#[derive(Default)]
+struct SimulatedDisk {
+    head: u64,
+    position: u64,
+    seeks: u64,
+    time: Duration,
+}
+
+impl AsyncWrite for SimulatedDisk {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let disk = self.get_mut();
+        if disk.position != disk.head {
+            disk.seeks += 1;
+            disk.time += SEEK_TIME;
+        }
+        disk.time += Duration::from_secs_f64(buf.len() as f64 / TRANSFER_RATE);
+        disk.position += buf.len() as u64;
+        disk.head = disk.position;
+        Poll::Ready(Ok(buf.len()))
+    }
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for SimulatedDisk {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        match position {
+            SeekFrom::Start(position) => self.get_mut().position = position,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "only seeking from start is supported",
+                ))
+            }
+        }
+        Ok(())
+    }
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.position))
+    }
+}
+
+// Clone a source of repeating chunks to a simulated rotational disk, writing chunks as
+// fetched from the archive and using sequential write with different buffer sizes.
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let size_mib: usize = match std::env::args().nth(1) {
+        Some(size) => size.parse()?,
+        None => 256,
+    };
+    let num_chunks = size_mib * 1024 * 1024 / CHUNK_SIZE;
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next_random = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    // A quarter of the source repeats chunks from elsewhere in the source.
+    let mut chunks: Vec<Chunk> = Vec::new();
+    let source: Vec<usize> = (0..num_chunks)
+        .map(|_| {
+            if chunks.is_empty() || next_random() % 4 != 0 {
+                let data: Vec<u8> = (0..CHUNK_SIZE).map(|_| next_random() as u8).collect();
+                chunks.push(Chunk::from(data));
+                chunks.len() - 1
+            } else {
+                next_random() as usize % chunks.len()
+            }
+        })
+        .collect();
+    let chunks: Vec<_> = chunks.into_iter().map(Chunk::verify).collect();
+    let mut clone_index = ChunkIndex::new_empty(HashSum::MAX_LEN);
+    for (chunk_index, chunk) in chunks.iter().enumerate() {
+        let offsets: Vec<u64> = source
+            .iter()
+            .enumerate()
+            .filter(|(_position, &index)| index == chunk_index)
+            .map(|(position, _index)| (position * CHUNK_SIZE) as u64)
+            .collect();
+        clone_index.add_chunk(chunk.hash().clone(), CHUNK_SIZE, &offsets);
+    }
+
+    for max_buffered in [None, Some(16), Some(64), Some(256)] {
+        let builder = CloneOutput::builder(SimulatedDisk::default(), clone_index.clone());
+        let mut output = match max_buffered {
+            Some(mib) => builder.sequential_write(mib * 1024 * 1024).build(),
+            None => builder.build(),
+        };
+        // Chunks are fetched from the archive in order of first appearance in source.
+        for chunk in &chunks {
+            output.feed(chunk).await?;
+        }
+        let disk = output.into_inner();
+        let label = match max_buffered {
+            Some(mib) => format!("sequential write, {} MiB buffer", mib),
+            None => "write as fetched".to_string(),
+        };
+        println!(
+            "{:>32}: {} seeks, {:.1?} simulated ({:.0} MiB/s)",
+            label,
+            disk.seeks,
+            disk.time,
+            size_mib as f64 / disk.time.as_secs_f64()
+        );
+    }
+    Ok(())
+}
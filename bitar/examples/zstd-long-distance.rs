@@ -0,0 +1,89 @@
+use std::time::Instant;
+
+use bitar::{chunker, Compression};
+
+const CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+// Log like text: lines of a timestamp, a level and one of a set of messages with varying
+// numbers, where every few MiB a long run of earlier lines is logged again (like a repeated
+// boot sequence).
+fn log_like_data(size: usize) -> Vec<u8> {
+    const LEVELS: [&str; 4] = ["DEBUG", "INFO", "WARN", "ERROR"];
+    const MESSAGES: [&str; 6] = [
+        "connection accepted from 10.0.{}.{}",
+        "request {} completed in {} ms",
+        "cache miss for key session:{}:{}",
+        "retrying upload of block {} (attempt {})",
+        "worker {} idle for {} s",
+        "checkpoint {} written, {} entries",
+    ];
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next = || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    let mut data = Vec::with_capacity(size);
+    let mut timestamp = 1_700_000_000_000u64;
+    while data.len() < size {
+        if data.len() > 4 * 1024 * 1024 && next() % 20_000 == 0 {
+            // Repeat a run of earlier lines from far back.
+            let start = (next() as usize) % (data.len() / 2);
+            let end = (start + 1024 * 1024).min(data.len());
+            data.extend_from_within(start..end);
+            continue;
+        }
+        timestamp += next() % 50;
+        let message = MESSAGES[(next() % MESSAGES.len() as u64) as usize]
+            .replacen("{}", &(next() % 1000).to_string(), 1)
+            .replacen("{}", &(next() % 100).to_string(), 1);
+        let line = format!(
+            "{} {} {}\n",
+            timestamp,
+            LEVELS[(next() % LEVELS.len() as u64) as usize],
+            message
+        );
+        data.extend_from_slice(line.as_bytes());
+    }
+    data.truncate(size);
+    data
+}
+
+// Compress log like data in 16 MiB chunks with and without zstd long distance matching, and
+// print the archive size of each.
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let size_mib: usize = match std::env::args().nth(1) {
+        Some(size) => size.parse()?,
+        None => 64,
+    };
+    let level: u32 = match std::env::args().nth(2) {
+        Some(level) => level.parse()?,
+        None => 3,
+    };
+    let source = log_like_data(size_mib * 1024 * 1024);
+    for long_distance in [false, true] {
+        let mut archive_data = Vec::new();
+        let start = Instant::now();
+        bitar::api::compress::create_archive(
+            &source[..],
+            &mut archive_data,
+            &bitar::api::compress::CreateArchiveOptions {
+                chunker_config: chunker::Config::FixedSize(CHUNK_SIZE),
+                compression: Some(Compression::zstd(level)?.with_zstd_long_distance(long_distance)),
+                ..Default::default()
+            },
+        )
+        .await?;
+        println!(
+            "Long distance matching {}: {} MiB compressed to {:.2} MiB (ratio {:.2}) in {:.2?}",
+            if long_distance { "on " } else { "off" },
+            size_mib,
+            archive_data.len() as f64 / 1024.0 / 1024.0,
+            source.len() as f64 / archive_data.len() as f64,
+            start.elapsed()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,84 @@
+//! Analyze how much of an archive other data, or another archive, could provide.
+use futures_util::StreamExt;
+use std::io;
+use tokio::io::AsyncRead;
+
+use crate::{chunker, Archive};
+
+/// How much of an archive source a seed could provide, see [`seed_coverage`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Coverage {
+    /// Number of unique archive chunks found in the seed.
+    pub matched_chunks: usize,
+    /// Bytes of the archive source covered by chunks found in the seed.
+    pub matched_bytes: u64,
+    /// Total size of the archive source.
+    pub source_size: u64,
+}
+
+impl Coverage {
+    /// Fraction of the archive source covered by the seed, in the range 0 to 1.
+    ///
+    /// An empty source is fully covered by any seed.
+    pub fn fraction(&self) -> f64 {
+        if self.source_size == 0 {
+            1.0
+        } else {
+            self.matched_bytes as f64 / self.source_size as f64
+        }
+    }
+}
+
+/// Chunks two archives have in common, see [`Archive::shared_chunks`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SharedStats {
+    /// Number of unique chunks found in both archives.
+    pub shared_chunks: usize,
+    /// Size of the chunks found in both archives.
+    pub shared_bytes: u64,
+    /// Size of the chunks only found in the archive compared.
+    pub unique_bytes: u64,
+    /// Size of the chunks only found in the other archive.
+    pub other_unique_bytes: u64,
+}
+
+/// Find how much of the archive source could be cloned from seed.
+///
+/// The seed is chunked using the chunker configuration of the archive, like when cloning,
+/// and the chunks matched against the archive source. Chunks are hashed on the blocking
+/// thread pool of the tokio runtime.
+/// Fails with `io::ErrorKind::Unsupported` if the archive can't tell where seed chunks start
+/// and end (see [`Archive::can_scan_seeds`]).
+pub async fn seed_coverage<R, S>(archive: &Archive<R>, seed: S) -> io::Result<Coverage>
+where
+    S: AsyncRead + Unpin + Send,
+{
+    if !archive.can_scan_seeds() {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "archive chunk boundaries are not known, can't scan seed",
+        ));
+    }
+    let mut source_index = archive.build_source_index();
+    let mut coverage = Coverage {
+        source_size: archive.total_source_size(),
+        ..Default::default()
+    };
+    let mut chunk_stream = chunker::chunk_stream(
+        archive.chunker_config(),
+        seed,
+        archive.chunk_hash_algorithm(),
+        archive.chunk_hash_length(),
+    );
+    while let Some(result) = chunk_stream.next().await {
+        let (_offset, hash, _size) = result?;
+        if let Some(location) = source_index.remove(&hash) {
+            coverage.matched_chunks += 1;
+            coverage.matched_bytes += (location.size() * location.offsets().len()) as u64;
+        }
+        if source_index.is_empty() {
+            break;
+        }
+    }
+    Ok(coverage)
+}
@@ -0,0 +1,333 @@
+//! Extend an existing archive with data appended to its source.
+//!
+//! Only archives with the header at the end (see `CreateArchiveOptions::header_at_end`) can
+//! be extended. The last source chunk of the archive is chunked again together with the
+//! appended data, and chunks not already in the archive are written where the old header
+//! started, followed by a new header and footer. Chunk data already in the archive is never
+//! moved or rewritten.
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::io::{Cursor, SeekFrom};
+use std::path::Path;
+
+use blake2::{Blake2b512, Digest};
+use futures_util::{future, StreamExt};
+use tokio::fs::OpenOptions;
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::task::JoinError;
+
+use crate::api::compress::{CreateArchiveResult, PKG_VERSION};
+use crate::archive_reader::IoReader;
+use crate::{chunk_dictionary, header, Archive, ArchiveError, HashSum};
+
+/// Options for the `append_archive` function
+#[derive(Clone, Debug)]
+pub struct AppendArchiveOptions {
+    /// Number of parallel buffers to use when manipulating chunks
+    pub num_chunk_buffers: usize,
+
+    /// Key used to sign the new archive header, or None to leave the extended archive
+    /// unsigned
+    pub signing_key: Option<crate::header::SigningKey>,
+}
+
+impl Default for AppendArchiveOptions {
+    fn default() -> AppendArchiveOptions {
+        let num_buffers = match num_cpus::get() {
+            0 | 1 => 1,
+            n => n * 2,
+        };
+        AppendArchiveOptions {
+            num_chunk_buffers: num_buffers,
+            signing_key: None,
+        }
+    }
+}
+
+/// Error from the `append_archive` function
+#[derive(Debug)]
+pub enum AppendArchiveError {
+    /// The existing archive could not be read
+    ArchiveError(ArchiveError<io::Error>),
+    /// The archive header is at the start of the archive
+    HeaderNotAtEnd,
+    /// The archive is a delta against a base archive
+    DeltaArchive,
+    /// Failed to chunk the appended data
+    ChunkerError(JoinError),
+    /// Failed to read the appended data while chunking
+    ChunkerRead(io::Error),
+    /// Failed to open, read or write the archive file
+    ArchiveFileError(io::Error),
+}
+
+impl fmt::Display for AppendArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppendArchiveError::ArchiveError(_) => write!(f, "Error reading the archive"),
+            AppendArchiveError::HeaderNotAtEnd => {
+                write!(f, "Error appending to archive with the header at the start")
+            }
+            AppendArchiveError::DeltaArchive => {
+                write!(f, "Error appending to a delta archive")
+            }
+            AppendArchiveError::ChunkerError(_) => write!(f, "Error chunking the input"),
+            AppendArchiveError::ChunkerRead(_) => write!(f, "Error reading the input"),
+            AppendArchiveError::ArchiveFileError(_) => {
+                write!(f, "Error operating on the archive file")
+            }
+        }
+    }
+}
+
+impl error::Error for AppendArchiveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AppendArchiveError::ArchiveError(e) => Some(e),
+            AppendArchiveError::HeaderNotAtEnd | AppendArchiveError::DeltaArchive => None,
+            AppendArchiveError::ChunkerError(e) => Some(e),
+            AppendArchiveError::ChunkerRead(e) => Some(e),
+            AppendArchiveError::ArchiveFileError(e) => Some(e),
+        }
+    }
+}
+
+impl From<ArchiveError<io::Error>> for AppendArchiveError {
+    fn from(e: ArchiveError<io::Error>) -> Self {
+        Self::ArchiveError(e)
+    }
+}
+
+// Read the offset of the header, the chunk dictionary and whether the dictionary is
+// compressed, of an archive with the header at the end.
+async fn read_trailer(
+    file: &mut tokio::fs::File,
+) -> Result<(u64, chunk_dictionary::ChunkDictionary, bool), AppendArchiveError> {
+    let mut footer = [0u8; header::FOOTER_SIZE];
+    file.seek(SeekFrom::End(-(header::FOOTER_SIZE as i64)))
+        .await
+        .map_err(AppendArchiveError::ArchiveFileError)?;
+    file.read_exact(&mut footer)
+        .await
+        .map_err(AppendArchiveError::ArchiveFileError)?;
+    let header_offset = u64::from_le_bytes(footer[0..8].try_into().unwrap());
+
+    let mut pre_header = [0u8; header::PRE_HEADER_SIZE];
+    file.seek(SeekFrom::Start(header_offset))
+        .await
+        .map_err(AppendArchiveError::ArchiveFileError)?;
+    file.read_exact(&mut pre_header)
+        .await
+        .map_err(AppendArchiveError::ArchiveFileError)?;
+    let dictionary_size = u64::from_le_bytes(
+        pre_header[header::ARCHIVE_MAGIC.len()..]
+            .try_into()
+            .unwrap(),
+    ) as usize;
+    let mut dictionary = vec![0u8; dictionary_size];
+    file.read_exact(&mut dictionary)
+        .await
+        .map_err(AppendArchiveError::ArchiveFileError)?;
+    // The archive has already been initialized, so the dictionary size is within limits.
+    let compressed = pre_header.starts_with(header::COMPRESSED_ARCHIVE_MAGIC);
+    if compressed {
+        dictionary = header::decompress_dictionary(&dictionary, usize::MAX).map_err(|err| {
+            AppendArchiveError::ArchiveError(ArchiveError::InvalidArchive(err.into()))
+        })?;
+    }
+    let dictionary = prost::Message::decode(&dictionary[..])
+        .map_err(|err| AppendArchiveError::ArchiveError(ArchiveError::from(err)))?;
+    Ok((header_offset, dictionary, compressed))
+}
+
+/// Append data to the source of an existing archive.
+///
+/// `tail` is the data appended to the archive source, the source itself is not needed.
+/// The archive at `path` must have its header at the end and can't be a delta archive.
+/// Chunks of the appended data are compressed using the archive default compression.
+///
+/// The archive file is modified in place. If the operation fails or is interrupted the
+/// archive is left unusable, so keep a copy if it can't be recreated.
+pub async fn append_archive<T: AsyncRead + Unpin + Send>(
+    path: &Path,
+    tail: T,
+    options: &AppendArchiveOptions,
+) -> Result<CreateArchiveResult, AppendArchiveError> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .await
+        .map_err(AppendArchiveError::ArchiveFileError)?;
+
+    let mut magic = [0u8; 6];
+    file.read_exact(&mut magic)
+        .await
+        .map_err(AppendArchiveError::ArchiveFileError)?;
+    if &magic != header::TRAILER_MAGIC {
+        // Let the archive init tell whether it's an archive at all.
+        Archive::try_init(IoReader::new(&mut file)).await?;
+        return Err(AppendArchiveError::HeaderNotAtEnd);
+    }
+    let mut archive = Archive::try_init(IoReader::new(&mut file)).await?;
+    if archive.base_header_checksum().is_some() {
+        return Err(AppendArchiveError::DeltaArchive);
+    }
+    let chunker_config = archive.chunker_config().clone();
+    let chunk_hash_algorithm = archive.chunk_hash_algorithm();
+    let chunk_hash_length = archive.chunk_hash_length();
+    let compression = archive.chunk_compression();
+
+    // The last chunk of source ended where the source ended, so its boundary is chunked
+    // again together with the appended data.
+    let (last_chunk_offset, last_chunk_size) = archive
+        .iter_source_chunks()
+        .last()
+        .map_or((0, 0), |(offset, descriptor)| {
+            (offset, u64::from(descriptor.source_size))
+        });
+    let mut source_hasher = Blake2b512::new();
+    let mut source_length = last_chunk_offset as usize;
+    {
+        let mut stream = archive.read_source_range(0, last_chunk_offset);
+        while let Some(bytes) = stream.next().await {
+            source_hasher.update(&bytes?);
+        }
+    }
+    let mut last_chunk = Vec::with_capacity(last_chunk_size as usize);
+    {
+        let mut stream = archive.read_source_range(last_chunk_offset, last_chunk_size);
+        while let Some(bytes) = stream.next().await {
+            last_chunk.extend_from_slice(&bytes?);
+        }
+    }
+    drop(archive);
+
+    let (header_offset, mut dictionary, compressed_dictionary) = read_trailer(&mut file).await?;
+    dictionary.rebuild_order.pop();
+
+    let mut chunk_indexes: HashMap<HashSum, usize> = dictionary
+        .chunk_descriptors
+        .iter()
+        .enumerate()
+        .map(|(index, descriptor)| (HashSum::from(&descriptor.checksum[..]), index))
+        .collect();
+    let mut next_chunk_index = dictionary.chunk_descriptors.len();
+    let mut rebuild_order = Vec::new();
+
+    let chunker = chunker_config.new_chunker(Cursor::new(last_chunk).chain(tail));
+    let mut chunk_stream = chunker
+        .map(|result| {
+            if let Ok((_offset, chunk)) = &result {
+                source_hasher.update(chunk.data());
+                source_length += chunk.len();
+            }
+            async move {
+                let (_offset, chunk) = result.map_err(AppendArchiveError::ChunkerRead)?;
+                tokio::task::spawn_blocking(move || chunk.verify_with(chunk_hash_algorithm))
+                    .await
+                    .map_err(AppendArchiveError::ChunkerError)
+            }
+        })
+        .buffered(options.num_chunk_buffers)
+        .filter_map(|result| {
+            let verified = match result {
+                Ok(verified) => verified,
+                Err(err) => return future::ready(Some(Err(err))),
+            };
+            let mut hash = verified.hash().clone();
+            hash.truncate(chunk_hash_length);
+            let (unique, chunk_index) = match chunk_indexes.get(&hash) {
+                Some(&chunk_index) => (false, chunk_index),
+                None => {
+                    let chunk_index = next_chunk_index;
+                    chunk_indexes.insert(hash.clone(), chunk_index);
+                    next_chunk_index += 1;
+                    (true, chunk_index)
+                }
+            };
+            rebuild_order.push(chunk_index as u32);
+            future::ready(if unique {
+                Some(Ok((hash, verified)))
+            } else {
+                None
+            })
+        })
+        .map(|result| async move {
+            let (hash, verified) = result?;
+            tokio::task::spawn_blocking(move || {
+                let compressed = verified
+                    .chunk()
+                    .clone()
+                    .compress(compression)
+                    .expect("compress chunk");
+                let (_algorithm, bytes) = compressed.into_inner();
+                (hash, verified, bytes)
+            })
+            .await
+            .map_err(AppendArchiveError::ChunkerError)
+        })
+        .buffered(options.num_chunk_buffers);
+
+    // New chunk data replaces the old header, offsets are relative the chunk data start.
+    let chunk_data_offset = header::TRAILER_MAGIC.len() as u64;
+    let mut archive_offset = header_offset - chunk_data_offset;
+    let mut new_chunks = Vec::new();
+    file.seek(SeekFrom::Start(header_offset))
+        .await
+        .map_err(AppendArchiveError::ArchiveFileError)?;
+    while let Some(result) = chunk_stream.next().await {
+        let (hash, verified, compressed_bytes) = result?;
+        let use_data = if compressed_bytes.len() < verified.len() {
+            &compressed_bytes
+        } else {
+            verified.chunk().data()
+        };
+        file.write_all(use_data)
+            .await
+            .map_err(AppendArchiveError::ArchiveFileError)?;
+        new_chunks.push(chunk_dictionary::ChunkDescriptor {
+            checksum: hash.to_vec(),
+            source_size: verified.len() as u32,
+            archive_offset,
+            archive_size: use_data.len() as u32,
+            compression: None,
+            in_base: false,
+        });
+        archive_offset += use_data.len() as u64;
+    }
+    drop(chunk_stream);
+
+    let source_hash = source_hasher.finalize().to_vec();
+    dictionary.chunk_descriptors.extend(new_chunks);
+    dictionary.rebuild_order.extend(rebuild_order);
+    dictionary.source_checksum = source_hash.clone();
+    dictionary.source_total_size = source_length as u64;
+    dictionary.application_version = PKG_VERSION.to_string();
+
+    // Keep the dictionary compressed if it was.
+    let build_trailer = if compressed_dictionary {
+        header::build_compressed_trailer
+    } else {
+        header::build_trailer
+    };
+    let trailer = build_trailer(&dictionary, archive_offset, options.signing_key.as_ref())
+        .expect("Failed to create header");
+    file.write_all(&trailer)
+        .await
+        .map_err(AppendArchiveError::ArchiveFileError)?;
+    file.flush()
+        .await
+        .map_err(AppendArchiveError::ArchiveFileError)?;
+    // The new trailer may be shorter than the old one, eg. when no longer signed.
+    file.set_len(chunk_data_offset + archive_offset + trailer.len() as u64)
+        .await
+        .map_err(AppendArchiveError::ArchiveFileError)?;
+
+    Ok(CreateArchiveResult {
+        source_length,
+        source_hash,
+        header: dictionary,
+    })
+}
@@ -1,24 +1,36 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::default::Default;
 use std::error;
 use std::fmt;
+use std::future::Future;
 use std::path::PathBuf;
+use std::sync::Arc;
 
 use blake2::{Blake2b512, Digest};
-use futures_util::{future, StreamExt};
+use bytes::Bytes;
+use futures_util::{stream, Stream, StreamExt};
 use tokio::fs;
 use tokio::io;
 use tokio::io::AsyncSeekExt;
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::sync::oneshot;
 use tokio::task::JoinError;
 
 use crate::chunk_dictionary;
 use crate::chunker;
+use crate::rolling_hash::BuzHash;
 use crate::Compression;
 use crate::CompressionAlgorithm;
+use crate::HashAlgorithm;
+use crate::HashSum;
+use crate::VerifiedChunk;
 
 pub const PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// Version recorded in the header of archives created with `reproducible` set.
+pub const REPRODUCIBLE_VERSION: &str = "reproducible";
+
 /// Options for the `create_archive` function
 #[derive(Clone, Debug)]
 pub struct CreateArchiveOptions {
@@ -28,9 +40,18 @@ pub struct CreateArchiveOptions {
     /// Number of parallel buffers to use when manipulating chunks
     pub num_chunk_buffers: usize,
 
-    /// The length that the chunk hash should be truncated to for the output
+    /// The length that the chunk hash should be truncated to for the output. Never longer
+    /// than the hash of `chunk_hash_algorithm`
+    ///
+    /// Chunks are told apart by their full hash while compressing, only the stored chunk
+    /// descriptors use the truncated hash. Compressing fails with
+    /// `CreateArchiveError::ChunkHashCollision` if two different chunks would be stored with
+    /// the same truncated hash.
     pub chunk_hash_length: usize,
 
+    /// The algorithm used to hash chunks. The source checksum is always a blake2 hash
+    pub chunk_hash_algorithm: HashAlgorithm,
+
     /// A temporary file is used to write intermediate chunk data. Setting this
     /// option forces this file to be used instead of a randomly generated one
     pub temporary_file_override: Option<PathBuf>,
@@ -38,8 +59,37 @@ pub struct CreateArchiveOptions {
     /// The type of compression to use when compressing a chunk
     pub compression: Option<Compression>,
 
+    /// Compressions to try for each unique chunk. When not empty each chunk is stored using
+    /// whichever of these gives the smallest result, and `compression` is only recorded as
+    /// the archive default
+    pub auto_compression: Vec<Compression>,
+
+    /// Store chunks with a byte entropy (in bits per byte, see `Chunk::entropy`) at or above
+    /// this threshold uncompressed, without trying to compress them. Saves the CPU time
+    /// otherwise spent compressing already compressed or random data. None to try
+    /// compressing every chunk
+    pub compress_skip_entropy: Option<f64>,
+
     /// Custom string/bytes key-value pair metadata to be stored in the archive header
     pub metadata: BTreeMap<String, Vec<u8>>,
+
+    /// Key used to sign the archive header, or None to create an unsigned archive
+    pub signing_key: Option<crate::header::SigningKey>,
+
+    /// Write the header at the end of the archive. Chunk data is then written directly to
+    /// the output, without going through a temporary file
+    pub header_at_end: bool,
+
+    /// Brotli compress the chunk dictionary of the header, see
+    /// [`crate::header::build_compressed`]
+    pub compress_header: bool,
+
+    /// Record [`REPRODUCIBLE_VERSION`] rather than the bitar version in the header
+    ///
+    /// The archive is otherwise given by the input and options alone. So archives of the
+    /// same input are byte identical also when created by different versions of bitar, as
+    /// long as the archive format and the compressed chunks are the same
+    pub reproducible: bool,
 }
 
 impl Default for CreateArchiveOptions {
@@ -53,12 +103,20 @@ impl Default for CreateArchiveOptions {
             chunker_config: chunker::Config::RollSum(chunker::FilterConfig::default()),
             num_chunk_buffers: num_buffers,
             chunk_hash_length: 64,
+            chunk_hash_algorithm: HashAlgorithm::Blake2b,
             temporary_file_override: None,
             compression: Some(Compression {
                 algorithm: CompressionAlgorithm::Brotli,
                 level: 6,
+                zstd_long_distance: false,
             }),
+            auto_compression: Vec::new(),
+            compress_skip_entropy: None,
             metadata: BTreeMap::new(),
+            signing_key: None,
+            header_at_end: false,
+            compress_header: false,
+            reproducible: false,
         }
     }
 }
@@ -78,8 +136,18 @@ pub enum CreateArchiveError {
     TempFileError(io::Error),
     /// Failed to chunk the input file
     ChunkerError(JoinError),
+    /// Failed to read the input file while chunking
+    ChunkerRead(io::Error),
     /// Failed to write to the output file
     OutputWriteError(io::Error),
+    /// The chunk data stream of `create_archive_streaming` failed or was dropped before it
+    /// ended
+    Incomplete,
+    /// Two different chunks of the input have the same hash when truncated to the chunk hash
+    /// length
+    ChunkHashCollision(HashSum),
+    /// The explicit chunk boundaries of the chunker config are not ascending
+    InvalidChunkerConfig(chunker::InvalidConfigError),
 }
 
 impl fmt::Display for CreateArchiveError {
@@ -89,7 +157,18 @@ impl fmt::Display for CreateArchiveError {
                 write!(f, "Error occurred while operating on the temp file")
             }
             CreateArchiveError::ChunkerError(_) => write!(f, "Error chunking the input file"),
+            CreateArchiveError::ChunkerRead(_) => write!(f, "Error reading the input file"),
             CreateArchiveError::OutputWriteError(_) => write!(f, "Error writing to output file"),
+            CreateArchiveError::Incomplete => {
+                write!(f, "Chunk data stream ended before the archive was complete")
+            }
+            CreateArchiveError::ChunkHashCollision(hash) => write!(
+                f,
+                "Different chunks share the truncated chunk hash {} ({} bytes), use a longer chunk hash length",
+                hash,
+                hash.len()
+            ),
+            CreateArchiveError::InvalidChunkerConfig(_) => write!(f, "Invalid chunker config"),
         }
     }
 }
@@ -99,180 +178,412 @@ impl error::Error for CreateArchiveError {
         match self {
             CreateArchiveError::TempFileError(e) => Some(e),
             CreateArchiveError::ChunkerError(e) => Some(e),
+            CreateArchiveError::ChunkerRead(e) => Some(e),
             CreateArchiveError::OutputWriteError(e) => Some(e),
+            CreateArchiveError::InvalidChunkerConfig(e) => Some(e),
+            CreateArchiveError::Incomplete | CreateArchiveError::ChunkHashCollision(_) => None,
         }
     }
 }
 
-/// Compress the input into the output as a bita archive
-pub async fn create_archive<R: AsyncRead + Unpin + Send, W: AsyncWrite + Unpin>(
-    mut input: R,
-    mut output: W,
-    options: &CreateArchiveOptions,
-) -> Result<CreateArchiveResult, CreateArchiveError> {
-    let mut source_hasher = Blake2b512::new();
-    let mut source_length: usize = 0;
+// A chunk of the source, in source order. Only the first occurrence of each unique chunk is
+// stored in the archive, and has the compression and data to store set.
+struct SourceChunk {
+    chunk_index: usize,
+    verified: VerifiedChunk,
+    stored: Option<(Option<Compression>, Bytes)>,
+}
 
-    let mut chunk_order = Vec::new();
+// Chunk, hash and compress the input. Chunks are hashed and compressed in parallel but
+// returned in source order.
+fn source_chunks<'a, R: AsyncRead + Unpin + Send + 'a>(
+    input: R,
+    options: &'a CreateArchiveOptions,
+) -> impl Stream<Item = Result<SourceChunk, CreateArchiveError>> + Send + 'a {
     let mut unique_chunks = HashMap::new();
-    let mut unique_chunk_index: usize = 0;
-
-    let chunker = options.chunker_config.new_chunker(&mut input);
-    let mut chunk_stream = chunker
-        .map(|result| {
-            let (offset, chunk) = result.expect("Error chunking");
-
-            // Create some metadata of the input source
-            source_hasher.update(chunk.data());
-            source_length += chunk.len();
-
+    // Only what's needed to compress, to not copy the options for every chunk.
+    let compression = options.compression;
+    let compress_skip_entropy = options.compress_skip_entropy;
+    let auto_compression = Arc::new(options.auto_compression.clone());
+    // Chunking at invalid explicit boundaries would silently skip some of them.
+    let invalid_config = match options.chunker_config {
+        chunker::Config::Explicit(_) => options.chunker_config.validate().err(),
+        _ => None,
+    };
+    let chunk_stream = options
+        .chunker_config
+        .new_chunker(input)
+        .map(move |result| async move {
+            let (offset, chunk) = result.map_err(CreateArchiveError::ChunkerRead)?;
             // Convert each chunk into a `VerifiedChunk`
-            tokio::task::spawn_blocking(move || (offset, chunk.verify()))
+            let algorithm = options.chunk_hash_algorithm;
+            tokio::task::spawn_blocking(move || (offset, chunk.verify_with(algorithm)))
+                .await
+                .map_err(CreateArchiveError::ChunkerError)
         })
         .buffered(options.num_chunk_buffers)
-        .filter_map(|result| {
+        .map(move |result| {
             // Create a lookup table of unique chunks by hash
-            let (offset, verified) = result.expect("error while hashing chunk");
-            let (unique, chunk_index) = if unique_chunks.contains_key(verified.hash()) {
-                (false, *unique_chunks.get(verified.hash()).unwrap())
-            } else {
-                let chunk_index = unique_chunk_index;
-                unique_chunks.insert(verified.hash().clone(), chunk_index);
-                unique_chunk_index += 1;
-                (true, chunk_index)
+            let (_offset, verified) = result?;
+            let next_index = unique_chunks.len();
+            let (chunk_index, unique) = match unique_chunks.entry(verified.hash().clone()) {
+                Entry::Occupied(entry) => (*entry.get(), false),
+                Entry::Vacant(entry) => (*entry.insert(next_index), true),
             };
-
-            // Store a pointer (as index) to unique chunk index for each chunk
-            chunk_order.push(chunk_index);
-            future::ready(if unique {
-                Some((chunk_index, offset, verified))
-            } else {
-                None
-            })
+            Ok((chunk_index, unique, verified))
         })
-        .map(|(chunk_index, offset, verified)| {
-            let _opt = options.clone();
-
-            tokio::task::spawn_blocking(move || {
-                let compressed = verified
-                    .chunk()
-                    .clone()
-                    .compress(_opt.compression)
-                    .expect("compress chunk");
-                let (_compression, bytes) = compressed.into_inner();
-                (chunk_index, offset, verified, bytes)
-            })
+        .map(move |result: Result<_, CreateArchiveError>| {
+            let auto_compression = auto_compression.clone();
+            async move {
+                let (chunk_index, unique, verified) = result?;
+                if !unique {
+                    return Ok(SourceChunk {
+                        chunk_index,
+                        verified,
+                        stored: None,
+                    });
+                }
+                tokio::task::spawn_blocking(move || {
+                    let skip = compress_skip_entropy
+                        .is_some_and(|threshold| verified.chunk().entropy() >= threshold);
+                    let (compression, compressed) = if skip {
+                        // Stored uncompressed, since the result is never smaller than the chunk
+                        (
+                            None,
+                            verified
+                                .chunk()
+                                .clone()
+                                .compress(None)
+                                .expect("compress chunk"),
+                        )
+                    } else if auto_compression.is_empty() {
+                        (
+                            compression,
+                            verified
+                                .chunk()
+                                .clone()
+                                .compress(compression)
+                                .expect("compress chunk"),
+                        )
+                    } else {
+                        verified
+                            .chunk()
+                            .clone()
+                            .compress_smallest(&auto_compression)
+                            .expect("compress chunk")
+                    };
+                    let (_algorithm, bytes) = compressed.into_inner();
+                    SourceChunk {
+                        chunk_index,
+                        verified,
+                        stored: Some((compression, bytes)),
+                    }
+                })
+                .await
+                .map_err(CreateArchiveError::ChunkerError)
+            }
         })
         .buffered(options.num_chunk_buffers);
+    stream::iter(invalid_config.map(|err| Err(CreateArchiveError::InvalidChunkerConfig(err))))
+        .chain(chunk_stream)
+}
 
-    let mut archive_offset: u64 = 0;
-    let mut archive_chunks = Vec::new();
+// Builds the chunk dictionary of an archive from the source chunks, in source order.
+struct DictionaryBuilder<'a> {
+    options: &'a CreateArchiveOptions,
+    source_hasher: Blake2b512,
+    source_length: usize,
+    chunk_order: Vec<usize>,
+    archive_chunks: Vec<chunk_dictionary::ChunkDescriptor>,
+    // Truncated hashes of the chunks stored, to catch different chunks stored with the same
+    // truncated hash.
+    stored_hashes: HashSet<HashSum>,
+    archive_offset: u64,
+}
 
-    let mut temp_file = if let Some(p) = &options.temporary_file_override {
-        fs::File::create(p).await
-    } else {
-        tempfile::tempfile().map(tokio::fs::File::from_std)
+impl<'a> DictionaryBuilder<'a> {
+    fn new(options: &'a CreateArchiveOptions) -> Self {
+        Self {
+            options,
+            source_hasher: Blake2b512::new(),
+            source_length: 0,
+            chunk_order: Vec::new(),
+            archive_chunks: Vec::new(),
+            stored_hashes: HashSet::new(),
+            archive_offset: 0,
+        }
     }
-    .map_err(CreateArchiveError::TempFileError)?;
-
-    while let Some(result) = chunk_stream.next().await {
-        let (_chunk_index, _offset, verified, compressed_bytes) =
-            result.map_err(CreateArchiveError::ChunkerError)?;
 
-        let use_data = {
-            if compressed_bytes.len() < verified.chunk().len() {
-                &compressed_bytes
-            } else {
-                verified.chunk().data()
-            }
+    // Add the next chunk of the source. Returns the data to store in the archive, if the
+    // chunk is to be stored.
+    fn add(&mut self, chunk: SourceChunk) -> Result<Option<Bytes>, CreateArchiveError> {
+        let SourceChunk {
+            chunk_index,
+            verified,
+            stored,
+        } = chunk;
+        // Create some metadata of the input source
+        self.source_hasher.update(verified.data());
+        self.source_length += verified.len();
+        // Store a pointer (as index) to unique chunk index for each chunk
+        self.chunk_order.push(chunk_index);
+
+        let Some((compression, compressed_bytes)) = stored else {
+            return Ok(None);
         };
-
-        let mut hash = verified.hash().clone();
-        hash.truncate(options.chunk_hash_length);
-
-        // Write the compressed chunks to the file. This is not the final output
-        // as we need to calculate the header and prepend it
-        temp_file
-            .write_all(use_data)
-            .await
-            .map_err(CreateArchiveError::TempFileError)?;
+        let compressed = compressed_bytes.len() < verified.len();
+        let (mut hash, chunk) = verified.into_parts();
+        let source_size = chunk.len();
+        let use_data = if compressed {
+            compressed_bytes
+        } else {
+            chunk.into_inner()
+        };
+        hash.truncate(self.options.chunk_hash_length);
+        if !self.stored_hashes.insert(hash.clone()) {
+            return Err(CreateArchiveError::ChunkHashCollision(hash));
+        }
 
         // Store a descriptor which refers to the compressed data
-        archive_chunks.push(chunk_dictionary::ChunkDescriptor {
+        self.archive_chunks.push(chunk_dictionary::ChunkDescriptor {
             checksum: hash.to_vec(),
-            source_size: verified.len() as u32,
-            archive_offset,
+            source_size: source_size as u32,
+            archive_offset: self.archive_offset,
             archive_size: use_data.len() as u32,
+            compression: (compressed && compression != self.options.compression)
+                .then(|| compression.into()),
+            in_base: false,
         });
-        archive_offset += use_data.len() as u64;
+        self.archive_offset += use_data.len() as u64;
+        Ok(Some(use_data))
     }
 
-    let chunker_params = match &options.chunker_config {
-        chunker::Config::BuzHash(hash_config) => chunk_dictionary::ChunkerParameters {
-            chunk_filter_bits: hash_config.filter_bits.bits(),
-            min_chunk_size: hash_config.min_chunk_size as u32,
-            max_chunk_size: hash_config.max_chunk_size as u32,
-            rolling_hash_window_size: hash_config.window_size as u32,
-            chunk_hash_length: options.chunk_hash_length as u32,
-            chunking_algorithm: chunk_dictionary::chunker_parameters::ChunkingAlgorithm::Buzhash
-                as i32,
-        },
-        chunker::Config::RollSum(hash_config) => chunk_dictionary::ChunkerParameters {
-            chunk_filter_bits: hash_config.filter_bits.bits(),
-            min_chunk_size: hash_config.min_chunk_size as u32,
-            max_chunk_size: hash_config.max_chunk_size as u32,
-            rolling_hash_window_size: hash_config.window_size as u32,
-            chunk_hash_length: options.chunk_hash_length as u32,
-            chunking_algorithm: chunk_dictionary::chunker_parameters::ChunkingAlgorithm::Rollsum
-                as i32,
-        },
-        chunker::Config::FixedSize(chunk_size) => chunk_dictionary::ChunkerParameters {
-            min_chunk_size: 0,
-            chunk_filter_bits: 0,
-            rolling_hash_window_size: 0,
-            max_chunk_size: *chunk_size as u32,
-            chunk_hash_length: options.chunk_hash_length as u32,
-            chunking_algorithm: chunk_dictionary::chunker_parameters::ChunkingAlgorithm::FixedSize
-                as i32,
-        },
-    };
+    fn build(self) -> chunk_dictionary::ChunkDictionary {
+        let options = self.options;
+        let chunk_hash_length = options
+            .chunk_hash_length
+            .min(options.chunk_hash_algorithm.hash_length()) as u32;
+        let chunk_hash_algorithm = chunk_dictionary::chunker_parameters::ChunkHashAlgorithm::from(
+            options.chunk_hash_algorithm,
+        ) as i32;
+        let chunker_params = match &options.chunker_config {
+            chunker::Config::BuzHash(hash_config) => chunk_dictionary::ChunkerParameters {
+                chunk_filter_bits: hash_config.filter_bits.bits(),
+                min_chunk_size: hash_config.min_chunk_size as u32,
+                max_chunk_size: hash_config.max_chunk_size as u32,
+                rolling_hash_window_size: hash_config.window_size as u32,
+                chunk_hash_length,
+                chunking_algorithm: chunk_dictionary::chunker_parameters::ChunkingAlgorithm::Buzhash
+                    as i32,
+                buzhash_seed: (hash_config.buzhash_seed != BuzHash::DEFAULT_SEED)
+                    .then_some(hash_config.buzhash_seed),
+                chunk_hash_algorithm,
+            },
+            chunker::Config::RollSum(hash_config) => chunk_dictionary::ChunkerParameters {
+                chunk_filter_bits: hash_config.filter_bits.bits(),
+                min_chunk_size: hash_config.min_chunk_size as u32,
+                max_chunk_size: hash_config.max_chunk_size as u32,
+                rolling_hash_window_size: hash_config.window_size as u32,
+                chunk_hash_length,
+                chunking_algorithm: chunk_dictionary::chunker_parameters::ChunkingAlgorithm::Rollsum
+                    as i32,
+                buzhash_seed: None,
+                chunk_hash_algorithm,
+            },
+            chunker::Config::FixedSize(chunk_size) => chunk_dictionary::ChunkerParameters {
+                min_chunk_size: 0,
+                chunk_filter_bits: 0,
+                rolling_hash_window_size: 0,
+                max_chunk_size: *chunk_size as u32,
+                chunk_hash_length,
+                chunking_algorithm:
+                    chunk_dictionary::chunker_parameters::ChunkingAlgorithm::FixedSize as i32,
+                buzhash_seed: None,
+                chunk_hash_algorithm,
+            },
+            chunker::Config::Explicit(_) => chunk_dictionary::ChunkerParameters {
+                min_chunk_size: 0,
+                chunk_filter_bits: 0,
+                rolling_hash_window_size: 0,
+                max_chunk_size: 0,
+                chunk_hash_length,
+                chunking_algorithm:
+                    chunk_dictionary::chunker_parameters::ChunkingAlgorithm::Explicit as i32,
+                buzhash_seed: None,
+                chunk_hash_algorithm,
+            },
+        };
+        chunk_dictionary::ChunkDictionary {
+            rebuild_order: self.chunk_order.iter().map(|&index| index as u32).collect(),
+            application_version: if options.reproducible {
+                REPRODUCIBLE_VERSION
+            } else {
+                PKG_VERSION
+            }
+            .to_string(),
+            chunk_descriptors: self.archive_chunks,
+            source_checksum: self.source_hasher.finalize().to_vec(),
+            source_total_size: self.source_length as u64,
+            chunker_params: Some(chunker_params),
+            chunk_compression: Some(options.compression.into()),
+            metadata: options.metadata.clone(),
+            base_header_checksum: Vec::new(),
+        }
+    }
+}
 
-    // Explicity drop the stream so we can reduce the lifetime of references to
-    // variables we need to create the result object.
-    drop(chunk_stream);
-
-    let source_hash = source_hasher.finalize().to_vec();
-
-    let file_header = chunk_dictionary::ChunkDictionary {
-        rebuild_order: chunk_order.iter().map(|&index| index as u32).collect(),
-        application_version: PKG_VERSION.to_string(),
-        chunk_descriptors: archive_chunks,
-        source_checksum: source_hash.clone(),
-        source_total_size: source_length as u64,
-        chunker_params: Some(chunker_params),
-        chunk_compression: Some(options.compression.into()),
-        metadata: options.metadata.clone(),
-    };
+/// Compress the input into the output as a bita archive
+pub async fn create_archive<R: AsyncRead + Unpin + Send, W: AsyncWrite + Unpin>(
+    input: R,
+    mut output: W,
+    options: &CreateArchiveOptions,
+) -> Result<CreateArchiveResult, CreateArchiveError> {
+    let mut chunk_stream = source_chunks(input, options);
+    let mut dictionary = DictionaryBuilder::new(options);
 
-    let header_buf = crate::header::build(&file_header, None).expect("Failed to create header");
+    let mut temp_file = if options.header_at_end {
+        output
+            .write_all(crate::header::TRAILER_MAGIC)
+            .await
+            .map_err(CreateArchiveError::OutputWriteError)?;
+        None
+    } else if let Some(p) = &options.temporary_file_override {
+        Some(
+            fs::File::create(p)
+                .await
+                .map_err(CreateArchiveError::TempFileError)?,
+        )
+    } else {
+        Some(
+            tempfile::tempfile()
+                .map(tokio::fs::File::from_std)
+                .map_err(CreateArchiveError::TempFileError)?,
+        )
+    };
 
-    output
-        .write_all(&header_buf)
-        .await
-        .map_err(CreateArchiveError::OutputWriteError)?;
+    while let Some(result) = chunk_stream.next().await {
+        let Some(use_data) = dictionary.add(result?)? else {
+            continue;
+        };
+        // Write the compressed chunks to the temp file, unless the header goes at the end.
+        // The temp file is not the final output as we need to calculate the header and
+        // prepend it
+        match &mut temp_file {
+            Some(temp_file) => temp_file
+                .write_all(&use_data)
+                .await
+                .map_err(CreateArchiveError::TempFileError)?,
+            None => output
+                .write_all(&use_data)
+                .await
+                .map_err(CreateArchiveError::OutputWriteError)?,
+        }
+    }
 
-    temp_file
-        .rewind()
-        .await
-        .map_err(CreateArchiveError::TempFileError)?;
+    let chunk_data_size = dictionary.archive_offset;
+    let file_header = dictionary.build();
 
-    io::copy(&mut temp_file, &mut output)
-        .await
-        .map_err(CreateArchiveError::OutputWriteError)?;
+    match temp_file {
+        Some(mut temp_file) => {
+            let build_header = if options.compress_header {
+                crate::header::build_compressed
+            } else {
+                crate::header::build
+            };
+            let header_buf = build_header(&file_header, None, options.signing_key.as_ref())
+                .expect("Failed to create header");
+
+            output
+                .write_all(&header_buf)
+                .await
+                .map_err(CreateArchiveError::OutputWriteError)?;
+
+            temp_file
+                .rewind()
+                .await
+                .map_err(CreateArchiveError::TempFileError)?;
+
+            io::copy(&mut temp_file, &mut output)
+                .await
+                .map_err(CreateArchiveError::OutputWriteError)?;
+        }
+        None => {
+            let build_trailer = if options.compress_header {
+                crate::header::build_compressed_trailer
+            } else {
+                crate::header::build_trailer
+            };
+            let trailer =
+                build_trailer(&file_header, chunk_data_size, options.signing_key.as_ref())
+                    .expect("Failed to create header");
+
+            output
+                .write_all(&trailer)
+                .await
+                .map_err(CreateArchiveError::OutputWriteError)?;
+        }
+    }
 
     Ok(CreateArchiveResult {
-        source_length,
-        source_hash,
-        header: file_header.clone(),
+        source_length: file_header.source_total_size as usize,
+        source_hash: file_header.source_checksum.clone(),
+        header: file_header,
     })
 }
+
+/// Compress the input as a bita archive, streaming the chunk data instead of writing it.
+///
+/// Returns a stream of the archive chunk data and a future resolving to the chunk
+/// dictionary of the archive. Nothing is written to a temporary file or to any output, eg to
+/// upload the chunk data and the header as separate objects.
+///
+/// Each item of the stream is the data of one chunk stored in the archive. Items are yielded
+/// in the order the chunks are first found in the input, and the `archive_offset` of each
+/// chunk descriptor is relative to the start of the first item. Hence concatenating the
+/// items gives the archive chunk data addressed by the dictionary. The header options of
+/// `options` (`header_at_end`, `signing_key`, `compress_header` and `temporary_file_override`)
+/// are not used.
+///
+/// The dictionary future resolves once the stream has yielded its last item, hence the stream
+/// must be driven to its end first. If the stream fails, or is dropped before it ends, the
+/// future resolves to `CreateArchiveError::Incomplete`.
+///
+/// To reconstruct the archive write the header from [`crate::header::build`], using
+/// `None` as chunk data offset, followed by the chunk data. Or keep the header and the chunk
+/// data apart and open the archive using [`crate::Archive::try_init_with_header`].
+pub fn create_archive_streaming<'a, R: AsyncRead + Unpin + Send + 'a>(
+    input: R,
+    options: &'a CreateArchiveOptions,
+) -> (
+    impl Stream<Item = Result<Bytes, CreateArchiveError>> + Unpin + Send + 'a,
+    impl Future<Output = Result<chunk_dictionary::ChunkDictionary, CreateArchiveError>> + Send,
+) {
+    let (sender, receiver) = oneshot::channel();
+    let state = (
+        source_chunks(input, options),
+        DictionaryBuilder::new(options),
+        sender,
+    );
+    let chunk_data = stream::unfold(Some(state), |state| async move {
+        let (mut chunk_stream, mut dictionary, sender) = state?;
+        loop {
+            match chunk_stream.next().await {
+                Some(Ok(chunk)) => match dictionary.add(chunk) {
+                    Ok(Some(data)) => {
+                        return Some((Ok(data), Some((chunk_stream, dictionary, sender))));
+                    }
+                    Ok(None) => {}
+                    Err(err) => return Some((Err(err), None)),
+                },
+                // The sender is dropped, failing the dictionary future.
+                Some(Err(err)) => return Some((Err(err), None)),
+                None => {
+                    let _ = sender.send(dictionary.build());
+                    return None;
+                }
+            }
+        }
+    });
+    let dictionary = async move { receiver.await.map_err(|_| CreateArchiveError::Incomplete) };
+    (Box::pin(chunk_data), dictionary)
+}
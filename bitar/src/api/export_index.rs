@@ -0,0 +1,129 @@
+//! Export the chunk index of an archive to formats of other chunking tools.
+//!
+//! # casync (caibx)
+//!
+//! A casync blob index lists the end offset and a 32 byte chunk id of each chunk in source
+//! order. The exported chunk ids are the first 32 bytes of bita's BLAKE2b chunk hashes, not
+//! the SHA-256 (or SHA512/256) hashes casync calculates. So the export is only meaningful to
+//! tools treating chunk ids as opaque, like when matching chunks against indexes exported
+//! from other bita archives, and the archive chunk hash length must be at least 32 bytes.
+//!
+//! The chunk size limits are taken from the archive chunker configuration. Neither of bita's
+//! rolling hash chunkers find the same boundaries as casync's chunker, so chunks of the
+//! exported index never match chunks of an index created by casync itself.
+use std::{error, fmt};
+
+use crate::{chunker, Archive};
+
+/// Size of the chunk ids in a casync index.
+const CAIBX_CHUNK_ID_SIZE: usize = 32;
+const CA_FORMAT_INDEX: u64 = 0x9682_4d9c_7b12_9ff9;
+const CA_FORMAT_TABLE: u64 = 0xe75b_9e11_2f17_417d;
+const CA_FORMAT_TABLE_TAIL_MARKER: u64 = 0x4b4f_050e_5549_ecd1;
+const CA_FORMAT_INDEX_SIZE: u64 = 48;
+const CA_FORMAT_TABLE_HEADER_SIZE: u64 = 16;
+const CA_FORMAT_TABLE_ITEM_SIZE: u64 = 8 + CAIBX_CHUNK_ID_SIZE as u64;
+const CA_FORMAT_TABLE_TAIL_SIZE: u64 = 40;
+
+/// Index format to export to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexFormat {
+    /// casync blob index.
+    Caibx,
+}
+
+/// Error from the export functions
+#[derive(Debug)]
+pub enum ExportIndexError {
+    /// Chunk hashes of the archive are shorter than the chunk ids of the format
+    HashTooShort { hash_length: usize, required: usize },
+}
+
+impl fmt::Display for ExportIndexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportIndexError::HashTooShort {
+                hash_length,
+                required,
+            } => write!(
+                f,
+                "chunk hash length {} is shorter than the required {} bytes",
+                hash_length, required
+            ),
+        }
+    }
+}
+
+impl error::Error for ExportIndexError {}
+
+/// Build an index of the archive source chunks in the given format.
+pub fn build<R>(archive: &Archive<R>, format: IndexFormat) -> Result<Vec<u8>, ExportIndexError> {
+    match format {
+        IndexFormat::Caibx => build_caibx(archive),
+    }
+}
+
+// A max chunk size of 0 means unbounded in bita, but casync expects an actual limit.
+fn max_chunk_size(size: usize) -> u64 {
+    match size {
+        0 => chunker::UNBOUNDED_CHUNK_SIZE as u64,
+        size => size as u64,
+    }
+}
+
+/// Build a casync blob index (caibx) of the archive source chunks.
+pub fn build_caibx<R>(archive: &Archive<R>) -> Result<Vec<u8>, ExportIndexError> {
+    if archive.chunk_hash_length() < CAIBX_CHUNK_ID_SIZE {
+        return Err(ExportIndexError::HashTooShort {
+            hash_length: archive.chunk_hash_length(),
+            required: CAIBX_CHUNK_ID_SIZE,
+        });
+    }
+    let (min, avg, max) = match archive.chunker_config() {
+        chunker::Config::BuzHash(config) | chunker::Config::RollSum(config) => (
+            config.min_chunk_size as u64,
+            u64::from(config.filter_bits.chunk_target_average()),
+            max_chunk_size(config.max_chunk_size),
+        ),
+        chunker::Config::FixedSize(size) => {
+            let size = max_chunk_size(*size);
+            (size, size, size)
+        }
+        // No limits are stored with explicit boundaries, use the sizes of the actual chunks.
+        chunker::Config::Explicit(_) => {
+            let sizes = archive
+                .chunk_descriptors()
+                .iter()
+                .map(|cd| u64::from(cd.source_size));
+            (
+                sizes.clone().min().unwrap_or(0),
+                archive.total_source_size() / (archive.total_chunks().max(1) as u64),
+                sizes.max().unwrap_or(0),
+            )
+        }
+    };
+    let table_size = CA_FORMAT_TABLE_HEADER_SIZE
+        + archive.total_chunks() as u64 * CA_FORMAT_TABLE_ITEM_SIZE
+        + CA_FORMAT_TABLE_TAIL_SIZE;
+    let mut index = Vec::with_capacity((CA_FORMAT_INDEX_SIZE + table_size) as usize);
+    for value in [CA_FORMAT_INDEX_SIZE, CA_FORMAT_INDEX, 0, min, avg, max] {
+        index.extend_from_slice(&value.to_le_bytes());
+    }
+    index.extend_from_slice(&u64::MAX.to_le_bytes());
+    index.extend_from_slice(&CA_FORMAT_TABLE.to_le_bytes());
+    for (offset, descriptor) in archive.iter_source_chunks() {
+        let end_offset = offset + u64::from(descriptor.source_size);
+        index.extend_from_slice(&end_offset.to_le_bytes());
+        index.extend_from_slice(&descriptor.checksum.slice()[..CAIBX_CHUNK_ID_SIZE]);
+    }
+    for value in [
+        0,
+        0,
+        CA_FORMAT_INDEX_SIZE,
+        table_size,
+        CA_FORMAT_TABLE_TAIL_MARKER,
+    ] {
+        index.extend_from_slice(&value.to_le_bytes());
+    }
+    Ok(index)
+}
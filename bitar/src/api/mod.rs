@@ -1,4 +1,7 @@
 //! High level API for using bitar
 
+#[cfg(feature = "compress")]
+pub mod append;
 #[cfg(feature = "compress")]
 pub mod compress;
+pub mod export_index;
@@ -1,14 +1,20 @@
 use crate::{
-    archive_reader::ArchiveReader, chunk_dictionary as dict, chunker,
-    compression::CompressionAlgorithm, header, ChunkIndex, ChunkOffset, CompressedArchiveChunk,
-    CompressedChunk, Compression, HashSum,
+    analyze::SharedStats,
+    archive_reader::{ArchiveReader, IoReader},
+    chunk_dictionary as dict, chunker,
+    compression::CompressionAlgorithm,
+    header::{self, VerifyingKey},
+    rolling_hash::BuzHash,
+    ChunkIndex, ChunkOffset, CompressedArchiveChunk, CompressedChunk, Compression, HashAlgorithm,
+    HashSum,
 };
 use blake2::{Blake2b512, Digest};
+use bytes::Bytes;
 use futures_util::{stream::Stream, StreamExt};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
 use std::{
     convert::TryInto,
-    fmt,
+    fmt, io,
     task::{ready, Poll},
 };
 
@@ -61,6 +67,10 @@ pub struct ChunkDescriptor {
     pub archive_offset: u64,
     /// Size of the chunk data in source (uncompressed).
     pub source_size: u32,
+    /// Compression of the chunk data in the archive, None if stored uncompressed.
+    pub compression: Option<Compression>,
+    /// Chunk data is stored in the base archive and not in this (delta) archive.
+    pub in_base: bool,
 }
 
 impl ChunkDescriptor {
@@ -69,6 +79,85 @@ impl ChunkDescriptor {
     }
 }
 
+/// Builder of an [`Archive`], see [`Archive::builder`].
+pub struct ArchiveBuilder<R> {
+    reader: R,
+    public_key: Option<VerifyingKey>,
+    max_header_size: usize,
+    verify_header_checksum: bool,
+}
+
+impl<R> ArchiveBuilder<R> {
+    /// Default max size of the archive header.
+    pub const DEFAULT_MAX_HEADER_SIZE: usize = 1024 * 1024 * 1024;
+
+    /// Verify the header signature using the given public key.
+    ///
+    /// Fails with `ArchiveError::InvalidArchive` if the archive is not signed or if the
+    /// signature does not match the public key.
+    #[must_use]
+    pub fn verify_signature(mut self, public_key: &VerifyingKey) -> Self {
+        self.public_key = Some(*public_key);
+        self
+    }
+
+    /// Set the max size of the archive header.
+    ///
+    /// An archive claiming a bigger header is rejected as invalid before the header is read.
+    /// Defaults to [`Self::DEFAULT_MAX_HEADER_SIZE`].
+    #[must_use]
+    pub fn max_header_size(mut self, size: usize) -> Self {
+        self.max_header_size = size;
+        self
+    }
+
+    /// Verify the header against the checksum stored in the header.
+    ///
+    /// Enabled by default, and initialization fails with `ArchiveError::InvalidArchive` if the
+    /// header doesn't match its checksum. Disabling it is a best-effort way of recovering data
+    /// from a slightly corrupt archive: a mismatch is only logged, and a corrupt header may
+    /// still fail to decode or describe the wrong source. Chunks read from the archive are
+    /// still verified against the chunk hashes of the header, but nothing verifies those
+    /// hashes, so a clone of an archive with a corrupt header may not be the archived source.
+    #[must_use]
+    pub fn verify_header_checksum(mut self, verify: bool) -> Self {
+        self.verify_header_checksum = verify;
+        self
+    }
+
+    /// Try to initialize the archive.
+    pub async fn try_init(self) -> Result<Archive<R>, ArchiveError<R::Error>>
+    where
+        R: ArchiveReader,
+    {
+        Archive::init(
+            self.reader,
+            self.public_key.as_ref(),
+            self.max_header_size,
+            self.verify_header_checksum,
+        )
+        .await
+    }
+
+    /// Try to initialize the archive, reading the header using the builder reader and the
+    /// chunk data using `data_reader`. See [`Archive::try_init_with_header`].
+    pub async fn try_init_with_data<D>(
+        self,
+        data_reader: D,
+    ) -> Result<Archive<D>, ArchiveError<D::Error>>
+    where
+        R: ArchiveReader,
+        R::Error: Into<D::Error>,
+        D: ArchiveReader,
+    {
+        let archive = self.try_init().await.map_err(|err| match err {
+            ArchiveError::InvalidArchive(err) => ArchiveError::InvalidArchive(err),
+            ArchiveError::ReaderError(err) => ArchiveError::ReaderError(err.into()),
+        })?;
+        archive.with_data_reader(data_reader).await
+    }
+}
+
 /// A readable archive.
 pub struct Archive<R> {
     reader: R,
@@ -80,32 +169,226 @@ pub struct Archive<R> {
     total_chunks: usize,
     header_size: usize,
     header_checksum: HashSum,
+    base_header_checksum: Option<HashSum>,
     chunk_compression: Option<Compression>,
     created_by_app_version: String,
     chunk_data_offset: u64,
     source_total_size: u64,
     source_checksum: HashSum,
     chunker_config: chunker::Config,
+    chunk_hash_algorithm: HashAlgorithm,
     chunk_hash_length: usize,
     metadata: BTreeMap<String, Vec<u8>>,
 }
 
+// Max size of each read of the header, to only allocate memory for header data actually read.
+const HEADER_READ_SIZE: usize = 4 * 1024 * 1024;
+
+// Read size bytes from offset in multiple reads of at most HEADER_READ_SIZE.
+//
+// Fails as an invalid archive if the archive ends before size bytes were read, so a header
+// size larger than the archive never allocates more than the archive holds.
+async fn read_header_part<R>(
+    reader: &mut R,
+    offset: u64,
+    size: usize,
+    header: &mut Vec<u8>,
+) -> Result<(), ArchiveError<R::Error>>
+where
+    R: ArchiveReader,
+{
+    let mut read = 0;
+    while read < size {
+        let read_size = (size - read).min(HEADER_READ_SIZE);
+        let data = reader
+            .read_at(offset + read as u64, read_size)
+            .await
+            .map_err(ArchiveError::ReaderError)?;
+        if data.len() != read_size {
+            return Err(ArchiveError::invalid_archive(
+                "header extends beyond end of archive",
+            ));
+        }
+        header.extend_from_slice(&data);
+        read += read_size;
+    }
+    Ok(())
+}
+
 impl<R> Archive<R> {
     fn verify_pre_header<E>(pre_header: &[u8]) -> Result<(), ArchiveError<E>> {
-        if pre_header.len() < header::ARCHIVE_MAGIC.len() {
+        if pre_header.len() < header::PRE_HEADER_SIZE {
             return Err(ArchiveError::invalid_archive("not an archive"));
         }
         // Allow both legacy type file magic (prefixed with \0 but no null
-        // termination) and 'BITA\0'.
-        if &pre_header[0..header::ARCHIVE_MAGIC.len()] != header::ARCHIVE_MAGIC
-            && &pre_header[0..header::ARCHIVE_MAGIC.len()] != b"\0BITA1"
+        // termination), 'BITA\0' and 'BITA1C' of a compressed dictionary.
+        let magic = &pre_header[0..header::ARCHIVE_MAGIC.len()];
+        if magic != header::ARCHIVE_MAGIC
+            && magic != b"\0BITA1"
+            && magic != header::COMPRESSED_ARCHIVE_MAGIC
         {
             return Err(ArchiveError::invalid_archive("not an archive"));
         }
         Ok(())
     }
+    // Get header offset and size from the footer of an archive with the header at the end.
+    fn parse_footer<E>(footer: &[u8]) -> Result<(u64, u64), ArchiveError<E>> {
+        if footer.len() != header::FOOTER_SIZE || !footer.ends_with(header::TRAILER_MAGIC) {
+            return Err(ArchiveError::invalid_archive("invalid archive footer"));
+        }
+        let header_offset = u64::from_le_bytes(footer[0..8].try_into().unwrap());
+        let header_size = u64::from_le_bytes(footer[8..16].try_into().unwrap());
+        Ok((header_offset, header_size))
+    }
+    /// Create a builder to initialize an archive from a reader with non default options.
+    pub fn builder(reader: R) -> ArchiveBuilder<R> {
+        ArchiveBuilder {
+            reader,
+            public_key: None,
+            max_header_size: ArchiveBuilder::<R>::DEFAULT_MAX_HEADER_SIZE,
+            verify_header_checksum: true,
+        }
+    }
     /// Try to initialize an archive from a reader.
-    pub async fn try_init(mut reader: R) -> Result<Self, ArchiveError<R::Error>>
+    ///
+    /// Archives written by bita 0.1 and later are accepted, including those with the legacy
+    /// file magic of early versions (see [`crate::header`]).
+    /// Any header signature is ignored, use `try_init_with_signature` to verify it.
+    pub async fn try_init(reader: R) -> Result<Self, ArchiveError<R::Error>>
+    where
+        R: ArchiveReader,
+    {
+        Self::builder(reader).try_init().await
+    }
+    /// Try to initialize an archive from a reader and verify its header signature.
+    ///
+    /// Fails with `ArchiveError::InvalidArchive` if the archive is not signed or if the signature
+    /// does not match the given public key.
+    pub async fn try_init_with_signature(
+        reader: R,
+        public_key: &VerifyingKey,
+    ) -> Result<Self, ArchiveError<R::Error>>
+    where
+        R: ArchiveReader,
+    {
+        Self::builder(reader)
+            .verify_signature(public_key)
+            .try_init()
+            .await
+    }
+    /// Try to initialize an archive reading the header and the chunk data from separate
+    /// readers.
+    ///
+    /// The header is read from `header_reader`, eg a small sidecar holding the start of the
+    /// archive up to its chunk data. Chunks are read from `data_reader`, which may hold either
+    /// the whole archive or only its chunk data. The layout is detected by reading the first
+    /// and last chunk of the archive, and initialization fails with
+    /// `ArchiveError::InvalidArchive` if they don't match the header.
+    pub async fn try_init_with_header<H>(
+        header_reader: H,
+        data_reader: R,
+    ) -> Result<Self, ArchiveError<R::Error>>
+    where
+        H: ArchiveReader,
+        H::Error: Into<R::Error>,
+        R: ArchiveReader,
+    {
+        Archive::builder(header_reader)
+            .try_init_with_data(data_reader)
+            .await
+    }
+    // Read chunk data using data_reader from now on, with the chunk offsets adjusted to where
+    // the chunk data is found.
+    async fn with_data_reader<D>(self, data_reader: D) -> Result<Archive<D>, ArchiveError<D::Error>>
+    where
+        D: ArchiveReader,
+    {
+        let mut archive = Archive {
+            reader: data_reader,
+            archive_chunks: self.archive_chunks,
+            source_order: self.source_order,
+            total_chunks: self.total_chunks,
+            header_size: self.header_size,
+            header_checksum: self.header_checksum,
+            base_header_checksum: self.base_header_checksum,
+            chunk_compression: self.chunk_compression,
+            created_by_app_version: self.created_by_app_version,
+            chunk_data_offset: self.chunk_data_offset,
+            source_total_size: self.source_total_size,
+            source_checksum: self.source_checksum,
+            chunker_config: self.chunker_config,
+            chunk_hash_algorithm: self.chunk_hash_algorithm,
+            chunk_hash_length: self.chunk_hash_length,
+            metadata: self.metadata,
+        };
+        let stored = || archive.archive_chunks.iter().filter(|cd| !cd.in_base);
+        let (Some(first), Some(last)) = (
+            stored().min_by_key(|cd| cd.archive_offset).cloned(),
+            stored().max_by_key(|cd| cd.archive_end_offset()).cloned(),
+        ) else {
+            return Ok(archive);
+        };
+        // The data reader holds either the whole archive, or only the chunk data.
+        let shift = match archive.chunk_matches(&first, 0).await {
+            Ok(true) => 0,
+            _ => match archive
+                .chunk_matches(&first, archive.chunk_data_offset)
+                .await
+            {
+                Ok(true) => archive.chunk_data_offset,
+                Ok(false) => {
+                    return Err(ArchiveError::invalid_archive(
+                        "chunk data doesn't match header",
+                    ))
+                }
+                Err(err) => return Err(ArchiveError::ReaderError(err)),
+            },
+        };
+        if !archive
+            .chunk_matches(&last, shift)
+            .await
+            .map_err(ArchiveError::ReaderError)?
+        {
+            return Err(ArchiveError::invalid_archive(
+                "chunk data doesn't match header",
+            ));
+        }
+        if shift > 0 {
+            archive
+                .archive_chunks
+                .iter_mut()
+                .for_each(|cd| cd.archive_offset -= shift);
+            archive.chunk_data_offset -= shift;
+        }
+        Ok(archive)
+    }
+    // Test if the chunk found shift bytes before its archive offset is the expected one.
+    async fn chunk_matches(&mut self, cd: &ChunkDescriptor, shift: u64) -> Result<bool, R::Error>
+    where
+        R: ArchiveReader,
+    {
+        let data = self
+            .reader
+            .read_at(cd.archive_offset - shift, cd.archive_size)
+            .await?;
+        let chunk = CompressedArchiveChunk {
+            chunk: CompressedChunk {
+                compression: cd.compression.map(|c| c.algorithm),
+                data,
+                source_size: cd.source_size.try_into().unwrap(),
+            },
+            expected_hash: cd.checksum.clone(),
+            hash_algorithm: self.chunk_hash_algorithm,
+            archive_offset: cd.archive_offset - shift,
+        };
+        Ok(chunk.into_verified().is_ok())
+    }
+    async fn init(
+        mut reader: R,
+        public_key: Option<&VerifyingKey>,
+        max_header_size: usize,
+        verify_header_checksum: bool,
+    ) -> Result<Self, ArchiveError<R::Error>>
     where
         R: ArchiveReader,
     {
@@ -115,21 +398,60 @@ impl<R> Archive<R> {
             .await
             .map_err(ArchiveError::ReaderError)?
             .to_vec();
+
+        // Locate the header if it's at the end of the archive.
+        let trailer = if header.starts_with(header::TRAILER_MAGIC) {
+            let footer = reader
+                .read_tail(header::FOOTER_SIZE)
+                .await
+                .map_err(ArchiveError::ReaderError)?
+                .ok_or_else(|| {
+                    ArchiveError::invalid_archive("reader can't read header at end of archive")
+                })?;
+            let (header_offset, header_size) = Self::parse_footer(&footer)?;
+            header = reader
+                .read_at(header_offset, header::PRE_HEADER_SIZE)
+                .await
+                .map_err(ArchiveError::ReaderError)?
+                .to_vec();
+            Some((header_offset, header_size))
+        } else {
+            None
+        };
+        let header_offset = trailer.map_or(0, |(offset, _size)| offset);
         Self::verify_pre_header(&header)?;
 
         let dictionary_size = u64::from_le_bytes(
             header[header::ARCHIVE_MAGIC.len()..header::PRE_HEADER_SIZE]
                 .try_into()
                 .unwrap(),
-        ) as usize;
+        );
+
+        // Don't trust the dictionary size before reading it
+        let header_size = dictionary_size.saturating_add((header::PRE_HEADER_SIZE + 8 + 64) as u64);
+        if header_size > max_header_size as u64 {
+            return Err(ArchiveError::invalid_archive(format!(
+                "header size {} exceeds max header size {}",
+                header_size, max_header_size
+            )));
+        }
+        if let Some((_offset, size)) = trailer {
+            if header_size > size {
+                return Err(ArchiveError::invalid_archive(
+                    "dictionary size exceeds header size",
+                ));
+            }
+        }
+        let dictionary_size = dictionary_size as usize;
 
         // Read the dictionary, chunk data offset and header hash
-        header.extend_from_slice(
-            &reader
-                .read_at(header::PRE_HEADER_SIZE as u64, dictionary_size + 8 + 64)
-                .await
-                .map_err(ArchiveError::ReaderError)?,
-        );
+        read_header_part(
+            &mut reader,
+            header_offset + header::PRE_HEADER_SIZE as u64,
+            dictionary_size + 8 + 64,
+            &mut header,
+        )
+        .await?;
 
         // Verify the header against the header checksum
         let header_checksum = {
@@ -138,7 +460,10 @@ impl<R> Archive<R> {
             hasher.update(&header[..offs]);
             let header_checksum = HashSum::from(&header[offs..(offs + 64)]);
             if header_checksum != &hasher.finalize()[..] {
-                return Err(ArchiveError::invalid_archive("invalid header checksum"));
+                if verify_header_checksum {
+                    return Err(ArchiveError::invalid_archive("invalid header checksum"));
+                }
+                log::warn!("invalid header checksum, header not verified");
             }
             header_checksum
         };
@@ -146,7 +471,14 @@ impl<R> Archive<R> {
         // Deserialize the chunk dictionary
         let dictionary: dict::ChunkDictionary = {
             let offs = header::PRE_HEADER_SIZE;
-            prost::Message::decode(&header[offs..(offs + dictionary_size)])?
+            let dictionary_buf = &header[offs..(offs + dictionary_size)];
+            if header.starts_with(header::COMPRESSED_ARCHIVE_MAGIC) {
+                let dictionary_buf = header::decompress_dictionary(dictionary_buf, max_header_size)
+                    .map_err(ArchiveError::invalid_archive)?;
+                prost::Message::decode(&dictionary_buf[..])?
+            } else {
+                prost::Message::decode(dictionary_buf)?
+            }
         };
 
         // Get chunk data offset
@@ -154,41 +486,131 @@ impl<R> Archive<R> {
             let offs = header::PRE_HEADER_SIZE + dictionary_size;
             u64::from_le_bytes(header[offs..(offs + 8)].try_into().unwrap())
         };
+
+        // Verify the header signature if requested
+        if let Some(public_key) = public_key {
+            // Signature is stored between header and chunk data, or header and footer.
+            let header_end = match trailer {
+                Some((offset, size)) => offset + size,
+                None => chunk_data_offset,
+            };
+            if header_end < header_offset + (header.len() + header::SIGNATURE_SIZE) as u64 {
+                return Err(ArchiveError::invalid_archive("archive is not signed"));
+            }
+            let signature = reader
+                .read_at(header_offset + header.len() as u64, header::SIGNATURE_SIZE)
+                .await
+                .map_err(ArchiveError::ReaderError)?;
+            let signature = ed25519_dalek::Signature::from_slice(&signature)
+                .map_err(|_| ArchiveError::invalid_archive("invalid header signature"))?;
+            public_key
+                .verify_strict(&header, &signature)
+                .map_err(|_| ArchiveError::invalid_archive("invalid header signature"))?;
+        }
+        let header_end = header_offset + header.len() as u64;
+        let chunk_compression = compression_from_dictionary(
+            dictionary
+                .chunk_compression
+                .ok_or_else(|| ArchiveError::invalid_archive("invalid compression"))?,
+        )?;
         let archive_chunks = dictionary
             .chunk_descriptors
             .into_iter()
-            .map(|dict| ChunkDescriptor {
-                checksum: dict.checksum.into(),
-                archive_size: dict.archive_size as usize,
-                archive_offset: chunk_data_offset + dict.archive_offset,
-                source_size: dict.source_size,
+            .map(|dict| {
+                let compression = if dict.in_base || dict.archive_size == dict.source_size {
+                    // When chunk size matches the source chunk size chunk has not been compressed
+                    // since compressing it probably made it bigger.
+                    None
+                } else if let Some(compression) = dict.compression {
+                    compression_from_dictionary(compression)?
+                } else {
+                    chunk_compression
+                };
+                let archive_offset = chunk_data_offset
+                    .checked_add(dict.archive_offset)
+                    .filter(|offset| offset.checked_add(dict.archive_size.into()).is_some())
+                    .ok_or_else(|| ArchiveError::invalid_archive("invalid chunk offset"))?;
+                // Chunk data ends where the header starts in an archive with the header at end,
+                // and starts after the header otherwise.
+                if trailer.is_none() && !dict.in_base && archive_offset < header_end {
+                    return Err(ArchiveError::invalid_archive(
+                        "chunk data within archive header",
+                    ));
+                }
+                if let Some((header_offset, _size)) = trailer {
+                    if !dict.in_base
+                        && archive_offset + u64::from(dict.archive_size) > header_offset
+                    {
+                        return Err(ArchiveError::invalid_archive(
+                            "chunk data beyond end of data region",
+                        ));
+                    }
+                }
+                Ok(ChunkDescriptor {
+                    checksum: dict.checksum.into(),
+                    archive_size: dict.archive_size as usize,
+                    archive_offset,
+                    source_size: dict.source_size,
+                    compression,
+                    in_base: dict.in_base,
+                })
             })
-            .collect();
+            .collect::<Result<Vec<ChunkDescriptor>, ArchiveError<R::Error>>>()?;
+        let base_header_checksum = if dictionary.base_header_checksum.is_empty() {
+            None
+        } else {
+            Some(HashSum::from(dictionary.base_header_checksum))
+        };
+        if base_header_checksum.is_none() && archive_chunks.iter().any(|cd| cd.in_base) {
+            return Err(ArchiveError::invalid_archive(
+                "chunk stored in base of an archive without base",
+            ));
+        }
         let chunker_params = dictionary
             .chunker_params
             .ok_or_else(|| ArchiveError::invalid_archive("invalid chunker parameters"))?;
         let chunk_hash_length = chunker_params.chunk_hash_length as usize;
+        let chunk_hash_algorithm = hash_algorithm_from_params(&chunker_params)?;
+        if chunk_hash_length > chunk_hash_algorithm.hash_length() {
+            return Err(ArchiveError::invalid_archive(
+                "chunk hash length exceeds the length of the hash algorithm",
+            ));
+        }
         let source_order: Vec<usize> = dictionary
             .rebuild_order
             .into_iter()
             .map(|v| v as usize)
             .collect();
+        if source_order
+            .iter()
+            .any(|&index| index >= archive_chunks.len())
+        {
+            return Err(ArchiveError::invalid_archive(
+                "source refers to missing chunk descriptor",
+            ));
+        }
+        let source_size = source_order.iter().try_fold(0u64, |size, &index| {
+            size.checked_add(archive_chunks[index].source_size.into())
+        });
+        if source_size != Some(dictionary.source_total_size) {
+            return Err(ArchiveError::invalid_archive(
+                "source size doesn't match size of source chunks",
+            ));
+        }
         Ok(Self {
             reader,
             archive_chunks,
             header_checksum,
+            base_header_checksum,
             header_size: header.len(),
             source_total_size: dictionary.source_total_size,
             source_checksum: dictionary.source_checksum.into(),
             created_by_app_version: dictionary.application_version.clone(),
-            chunk_compression: compression_from_dictionary(
-                dictionary
-                    .chunk_compression
-                    .ok_or_else(|| ArchiveError::invalid_archive("invalid compression"))?,
-            )?,
+            chunk_compression,
             total_chunks: source_order.len(),
             source_order,
             chunk_data_offset,
+            chunk_hash_algorithm,
             chunk_hash_length,
             chunker_config: chunker_config_from_params(chunker_params)?,
             metadata: dictionary.metadata,
@@ -217,6 +639,10 @@ impl<R> Archive<R> {
     pub fn chunk_descriptors(&self) -> &[ChunkDescriptor] {
         &self.archive_chunks
     }
+    /// Get the order of chunks in source, as indexes into the archive chunk descriptors.
+    pub fn source_order(&self) -> &[usize] {
+        &self.source_order
+    }
     /// Total size of the original source file.
     pub fn total_source_size(&self) -> u64 {
         self.source_total_size
@@ -233,6 +659,13 @@ impl<R> Archive<R> {
     pub fn header_checksum(&self) -> &HashSum {
         &self.header_checksum
     }
+    /// Get the header checksum of the base archive if this is a delta archive.
+    ///
+    /// Chunks of a delta archive marked as stored in base are not stored in the archive and
+    /// must be provided by the base, eg by using the base source as seed.
+    pub fn base_header_checksum(&self) -> Option<&HashSum> {
+        self.base_header_checksum.as_ref()
+    }
     /// Get the size of the archive header.
     pub fn header_size(&self) -> usize {
         self.header_size
@@ -241,7 +674,16 @@ impl<R> Archive<R> {
     pub fn chunk_hash_length(&self) -> usize {
         self.chunk_hash_length
     }
+    /// Get the algorithm used to hash chunks when building the archive.
+    ///
+    /// Seeds must be hashed using the same algorithm for their chunks to be found in the
+    /// archive.
+    pub fn chunk_hash_algorithm(&self) -> HashAlgorithm {
+        self.chunk_hash_algorithm
+    }
     /// Get the compression used for chunks in the archive.
+    ///
+    /// Individual chunks may override it with their own compression.
     pub fn chunk_compression(&self) -> Option<Compression> {
         self.chunk_compression
     }
@@ -249,6 +691,10 @@ impl<R> Archive<R> {
     pub fn built_with_version(&self) -> &str {
         &self.created_by_app_version
     }
+    /// Get all custom key-value pair metadata stored in the archive header.
+    pub fn metadata(&self) -> &BTreeMap<String, Vec<u8>> {
+        &self.metadata
+    }
     /// Get the custom key-value pair metadata stored in the archive header.
     pub fn metadata_iter(&self) -> impl Iterator<Item = (&str, &[u8])> {
         self.metadata
@@ -269,6 +715,67 @@ impl<R> Archive<R> {
             (offset, cd)
         })
     }
+    /// Iterate chunks as ordered in archive, by archive offset.
+    pub fn iter_archive_order(&self) -> impl Iterator<Item = &ChunkDescriptor> {
+        let mut descriptors: Vec<&ChunkDescriptor> = self.archive_chunks.iter().collect();
+        descriptors.sort_by_key(|cd| cd.archive_offset);
+        descriptors.into_iter()
+    }
+    /// Check if the chunk data in archive is contiguous, each chunk starting where the
+    /// previous one ends without any gaps or overlaps.
+    pub fn is_contiguous(&self) -> bool {
+        let mut next_offset = self.chunk_data_offset;
+        self.iter_archive_order().all(|cd| {
+            let contiguous = cd.archive_offset == next_offset;
+            next_offset = cd.archive_end_offset();
+            contiguous
+        })
+    }
+    /// Check if seeds can be chunked like the source to find chunks of the archive.
+    ///
+    /// Not the case for an archive chunked at explicit boundaries, as the boundaries are not
+    /// stored in the archive.
+    pub fn can_scan_seeds(&self) -> bool {
+        !matches!(self.chunker_config, chunker::Config::Explicit(_))
+    }
+    /// Compare the chunks of this archive with the chunks of another archive.
+    ///
+    /// Chunks are matched by hash, truncated to the shorter chunk hash length of the two
+    /// archives, and bytes are counted once per unique chunk. Only the archive headers are
+    /// used. Archives hashing chunks using different algorithms have no chunks in common.
+    pub fn shared_chunks<O>(&self, other: &Archive<O>) -> SharedStats {
+        let hash_length = self.chunk_hash_length.min(other.chunk_hash_length);
+        let truncated = |cd: &ChunkDescriptor| {
+            let mut hash = cd.checksum.clone();
+            hash.truncate(hash_length);
+            hash
+        };
+        let comparable = self.chunk_hash_algorithm == other.chunk_hash_algorithm;
+        let hashes = |archive_chunks: &[ChunkDescriptor]| -> HashSet<HashSum> {
+            if comparable {
+                archive_chunks.iter().map(truncated).collect()
+            } else {
+                HashSet::new()
+            }
+        };
+        let (chunks, other_chunks) = (hashes(&self.archive_chunks), hashes(&other.archive_chunks));
+        let mut stats = SharedStats::default();
+        for cd in &self.archive_chunks {
+            if other_chunks.contains(&truncated(cd)) {
+                stats.shared_chunks += 1;
+                stats.shared_bytes += cd.source_size as u64;
+            } else {
+                stats.unique_bytes += cd.source_size as u64;
+            }
+        }
+        stats.other_unique_bytes = other
+            .archive_chunks
+            .iter()
+            .filter(|cd| !chunks.contains(&truncated(cd)))
+            .map(|cd| cd.source_size as u64)
+            .sum();
+        stats
+    }
     /// Build a ChunkIndex representing the source file.
     pub fn build_source_index(&self) -> ChunkIndex {
         let mut ci = ChunkIndex::new_empty(self.chunk_hash_length);
@@ -277,7 +784,75 @@ impl<R> Archive<R> {
         });
         ci
     }
+    /// Build a ChunkIndex of the source chunks overlapping the given range of source.
+    ///
+    /// Chunks are at their offsets in source, so chunks starting before the range start at
+    /// an offset before it. A range extending past the end of source is cut at the end of
+    /// source. See [`crate::CloneOutputBuilder::source_range`] to clone only the range.
+    pub fn build_source_range_index(&self, offset: u64, len: u64) -> ChunkIndex {
+        let mut ci = ChunkIndex::new_empty(self.chunk_hash_length);
+        self.source_chunks_in_range(offset, len)
+            .for_each(|(offset, cd)| {
+                ci.add_chunk(cd.checksum.clone(), cd.source_size as usize, &[offset]);
+            });
+        ci
+    }
+    // Source chunks overlapping the given range, with their offset in source.
+    fn source_chunks_in_range(
+        &self,
+        offset: u64,
+        len: u64,
+    ) -> impl Iterator<Item = (u64, &ChunkDescriptor)> {
+        let end = offset.saturating_add(len).min(self.source_total_size);
+        self.iter_source_chunks()
+            .skip_while(move |(chunk_offset, cd)| {
+                chunk_offset + u64::from(cd.source_size) <= offset
+            })
+            .take_while(move |(chunk_offset, _cd)| *chunk_offset < end && offset < end)
+    }
+    /// Hint the archive reader that the given chunks are about to be streamed.
+    ///
+    /// Readers supporting it (eg `HttpReader`) start fetching the first group of adjacent
+    /// chunks in the background, to be used by the following call to `chunk_stream`.
+    pub async fn prefetch(&mut self, chunks: &ChunkIndex)
+    where
+        R: ArchiveReader,
+    {
+        let read_at: Vec<ChunkOffset> = self
+            .stored_chunks(chunks)
+            .iter()
+            .map(|cd| ChunkOffset::new(cd.archive_offset, cd.archive_size))
+            .collect();
+        self.reader.prefetch(&read_at).await
+    }
+    /// Number of chunks in the index which are stored in the base of a delta archive.
+    ///
+    /// These can't be fetched from the archive and must be provided by the base before
+    /// fetching the rest, eg by using the base source as seed. Cloning fails with
+    /// `CloneError::MissingBaseChunks` unless none are left.
+    pub fn base_chunk_count(&self, chunks: &ChunkIndex) -> usize {
+        self.archive_chunks
+            .iter()
+            .filter(|cd| cd.in_base && chunks.contains(&cd.checksum))
+            .count()
+    }
+    /// Total number of bytes to fetch from the archive to get the chunks in the index.
+    ///
+    /// Chunks stored in the base of a delta archive are not counted.
+    pub fn fetch_size(&self, chunks: &ChunkIndex) -> u64 {
+        stored_chunks(&self.archive_chunks, chunks)
+            .iter()
+            .map(|cd| cd.archive_size as u64)
+            .sum()
+    }
+    // Descriptors of the chunks in the index which are stored in this archive, in the order
+    // `chunk_stream` yields them.
+    pub(crate) fn stored_chunks(&self, chunks: &ChunkIndex) -> Vec<&ChunkDescriptor> {
+        stored_chunks(&self.archive_chunks, chunks)
+    }
     /// Get a stream of chunks from the archive.
+    ///
+    /// Chunks stored in the base of a delta archive are not part of the stream.
     pub fn chunk_stream<'a>(
         &'a mut self,
         chunks: &ChunkIndex,
@@ -285,47 +860,100 @@ impl<R> Archive<R> {
     where
         R: ArchiveReader + 'a,
     {
-        let descriptors: Vec<&ChunkDescriptor> = self
-            .archive_chunks
-            .iter()
-            .filter(|cd| chunks.contains(&cd.checksum))
-            .collect();
+        let descriptors = stored_chunks(&self.archive_chunks, chunks);
+        let hash_algorithm = self.chunk_hash_algorithm;
         let read_at: Vec<ChunkOffset> = descriptors
             .iter()
             .map(|cd| ChunkOffset::new(cd.archive_offset, cd.archive_size))
             .collect();
-        let archive_chunk_compression = self.chunk_compression().map(|c| c.algorithm);
+        let stream = self
+            .reader
+            .read_chunks(read_at)
+            .enumerate()
+            .map(move |(index, result)| match result {
+                Ok(chunk) => {
+                    let descriptor = descriptors[index];
+                    Ok(CompressedArchiveChunk {
+                        chunk: CompressedChunk {
+                            compression: descriptor.compression.map(|c| c.algorithm),
+                            data: chunk,
+                            source_size: descriptor.source_size.try_into().unwrap(),
+                        },
+                        expected_hash: descriptor.checksum.clone(),
+                        hash_algorithm,
+                        archive_offset: descriptor.archive_offset,
+                    })
+                }
+                Err(err) => Err(err),
+            });
+        StreamUntilFirstError::new(stream)
+    }
+    /// Get a stream of the source bytes in the given range, without cloning the whole source.
+    ///
+    /// Only the source chunks overlapping the range are read from the archive, in source
+    /// order. Each chunk is decompressed and verified before the bytes within range are
+    /// yielded. A range extending past the end of source is cut at the end of source.
+    /// Reading a chunk stored in the base of a delta archive fails with
+    /// `ArchiveError::InvalidArchive`.
+    pub fn read_source_range<'a>(
+        &'a mut self,
+        offset: u64,
+        len: u64,
+    ) -> impl Stream<Item = Result<Bytes, ArchiveError<R::Error>>> + Unpin + Sized + 'a
+    where
+        R: ArchiveReader + 'a,
+    {
+        let end = offset.saturating_add(len).min(self.source_total_size);
+        let hash_algorithm = self.chunk_hash_algorithm;
+        let chunks: Vec<(u64, ChunkDescriptor)> = self
+            .source_chunks_in_range(offset, len)
+            .map(|(chunk_offset, cd)| (chunk_offset, cd.clone()))
+            .collect();
+        let read_at: Vec<ChunkOffset> = chunks
+            .iter()
+            .map(|(_, cd)| ChunkOffset::new(cd.archive_offset, cd.archive_size))
+            .collect();
         let stream = self
             .reader
             .read_chunks(read_at)
             .enumerate()
             .map(move |(index, result)| {
-                match result {
-                    Ok(chunk) => {
-                        let descriptor = descriptors[index];
-                        let source_size: usize = descriptor.source_size.try_into().unwrap();
-                        Ok(CompressedArchiveChunk {
-                            chunk: CompressedChunk {
-                                compression: if source_size == chunk.len() {
-                                    // When chunk size matches the source chunk size chunk has not been compressed
-                                    // since compressing it probably made it bigger.
-                                    None
-                                } else {
-                                    archive_chunk_compression
-                                },
-                                data: chunk,
-                                source_size,
-                            },
-                            expected_hash: descriptor.checksum.clone(),
-                        })
-                    }
-                    Err(err) => Err(err),
+                let (chunk_offset, descriptor) = &chunks[index];
+                if descriptor.in_base {
+                    return Err(ArchiveError::invalid_archive(
+                        "chunk is stored in base archive",
+                    ));
+                }
+                let verified = CompressedArchiveChunk {
+                    chunk: CompressedChunk {
+                        compression: descriptor.compression.map(|c| c.algorithm),
+                        data: result.map_err(ArchiveError::ReaderError)?,
+                        source_size: descriptor.source_size.try_into().unwrap(),
+                    },
+                    expected_hash: descriptor.checksum.clone(),
+                    hash_algorithm,
+                    archive_offset: descriptor.archive_offset,
                 }
+                .into_verified()
+                .map_err(ArchiveError::invalid_archive)?;
+                let start = offset.saturating_sub(*chunk_offset) as usize;
+                let end = (end - chunk_offset).min(u64::from(descriptor.source_size)) as usize;
+                Ok(verified.into_parts().1.into_inner().slice(start..end))
             });
         StreamUntilFirstError::new(stream)
     }
 }
 
+fn stored_chunks<'a>(
+    archive_chunks: &'a [ChunkDescriptor],
+    chunks: &ChunkIndex,
+) -> Vec<&'a ChunkDescriptor> {
+    archive_chunks
+        .iter()
+        .filter(|cd| !cd.in_base && chunks.contains(&cd.checksum))
+        .collect()
+}
+
 fn chunker_config_from_params<R>(
     p: dict::ChunkerParameters,
 ) -> Result<chunker::Config, ArchiveError<R>> {
@@ -336,20 +964,38 @@ fn chunker_config_from_params<R>(
             min_chunk_size: p.min_chunk_size as usize,
             max_chunk_size: p.max_chunk_size as usize,
             window_size: p.rolling_hash_window_size as usize,
+            buzhash_seed: p.buzhash_seed.unwrap_or(BuzHash::DEFAULT_SEED),
         })),
         Ok(ChunkingAlgorithm::Rollsum) => Ok(chunker::Config::RollSum(chunker::FilterConfig {
             filter_bits: chunker::FilterBits::from_bits(p.chunk_filter_bits),
             min_chunk_size: p.min_chunk_size as usize,
             max_chunk_size: p.max_chunk_size as usize,
             window_size: p.rolling_hash_window_size as usize,
+            buzhash_seed: BuzHash::DEFAULT_SEED,
         })),
         Ok(ChunkingAlgorithm::FixedSize) => {
             Ok(chunker::Config::FixedSize(p.max_chunk_size as usize))
         }
+        // The boundaries given when compressing are not stored.
+        Ok(ChunkingAlgorithm::Explicit) => Ok(chunker::Config::Explicit(Vec::new())),
         Err(_err) => Err(ArchiveError::invalid_archive("unknown chunking algorithm")),
     }
 }
 
+fn hash_algorithm_from_params<R>(
+    p: &dict::ChunkerParameters,
+) -> Result<HashAlgorithm, ArchiveError<R>> {
+    use dict::chunker_parameters::ChunkHashAlgorithm;
+    match ChunkHashAlgorithm::try_from(p.chunk_hash_algorithm) {
+        Ok(ChunkHashAlgorithm::Blake2b) => Ok(HashAlgorithm::Blake2b),
+        Ok(ChunkHashAlgorithm::Xxh3) => Ok(HashAlgorithm::Xxh3),
+        Ok(ChunkHashAlgorithm::Blake2s) => Ok(HashAlgorithm::Blake2s),
+        Err(_err) => Err(ArchiveError::invalid_archive(
+            "unknown chunk hash algorithm",
+        )),
+    }
+}
+
 fn compression_from_dictionary<R>(
     c: dict::ChunkCompression,
 ) -> Result<Option<Compression>, ArchiveError<R>> {
@@ -359,6 +1005,7 @@ fn compression_from_dictionary<R>(
         Ok(dict::chunk_compression::CompressionType::Lzma) => Ok(Some(Compression {
             algorithm: CompressionAlgorithm::Lzma,
             level: c.compression_level,
+            zstd_long_distance: false,
         })),
         #[cfg(not(feature = "lzma-compression"))]
         Ok(CompressionType::Lzma) => Err(ArchiveError::invalid_archive(
@@ -368,20 +1015,66 @@ fn compression_from_dictionary<R>(
         Ok(CompressionType::Zstd) => Ok(Some(Compression {
             algorithm: CompressionAlgorithm::Zstd,
             level: c.compression_level,
+            zstd_long_distance: false,
         })),
         #[cfg(not(feature = "zstd-compression"))]
         Ok(CompressionType::Zstd) => Err(ArchiveError::invalid_archive(
             "ZSTD compression not enabled",
         )),
+        #[cfg(feature = "lz4-compression")]
+        Ok(CompressionType::Lz4) => Ok(Some(Compression {
+            algorithm: CompressionAlgorithm::Lz4,
+            level: c.compression_level,
+            zstd_long_distance: false,
+        })),
+        #[cfg(not(feature = "lz4-compression"))]
+        Ok(CompressionType::Lz4) => {
+            Err(ArchiveError::invalid_archive("LZ4 compression not enabled"))
+        }
         Ok(CompressionType::Brotli) => Ok(Some(Compression {
             algorithm: CompressionAlgorithm::Brotli,
             level: c.compression_level,
+            zstd_long_distance: false,
         })),
         Ok(CompressionType::None) => Ok(None),
         Err(_err) => Err(ArchiveError::invalid_archive("unknown compression")),
     }
 }
 
+impl Archive<IoReader<io::Cursor<Vec<u8>>>> {
+    /// Try to initialize an archive held in memory.
+    ///
+    /// Same as `try_init` with an [`IoReader`] over a cursor of the bytes, useful for tests
+    /// and small archives:
+    /// ```
+    /// # #[cfg(feature = "compress")]
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use bitar::{api::compress, Archive};
+    /// use futures_util::StreamExt;
+    ///
+    /// let source = b"hello hello hello".to_vec();
+    /// let mut bytes = Vec::new();
+    /// compress::create_archive(&source[..], &mut bytes, &Default::default()).await?;
+    ///
+    /// let mut archive = Archive::from_bytes(bytes).await?;
+    /// assert_eq!(archive.total_source_size(), source.len() as u64);
+    /// let mut data = Vec::new();
+    /// let mut stream = archive.read_source_range(0, source.len() as u64);
+    /// while let Some(bytes) = stream.next().await {
+    ///     data.extend_from_slice(&bytes?);
+    /// }
+    /// assert_eq!(data, source);
+    /// # Ok(())
+    /// # }
+    /// # #[cfg(not(feature = "compress"))]
+    /// # fn main() {}
+    /// ```
+    pub async fn from_bytes(bytes: Vec<u8>) -> Result<Self, ArchiveError<io::Error>> {
+        Self::try_init(IoReader::new(io::Cursor::new(bytes))).await
+    }
+}
+
 /// The first error returned by the underlying stream will be emitted.
 /// Any following read from the stream will result in end of stream (None).
 struct StreamUntilFirstError<S> {
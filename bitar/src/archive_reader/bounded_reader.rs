@@ -0,0 +1,226 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use core::pin::Pin;
+use futures_util::stream::{self, Stream, StreamExt};
+use std::fmt;
+
+use crate::archive_reader::ArchiveReader;
+use crate::ChunkOffset;
+
+/// Read an archive stored within a larger file.
+///
+/// Serves reads relative to `offset` and bounded by `length` in the inner reader, eg for an
+/// archive stored uncompressed as an entry of a tar or zip file. Every read is checked to be
+/// within the bounds before being passed to the inner reader.
+pub struct BoundedReader<R> {
+    inner: R,
+    offset: u64,
+    length: u64,
+}
+
+impl<R> BoundedReader<R> {
+    /// Create a reader for the `length` bytes at `offset` of the inner reader.
+    pub fn new(inner: R, offset: u64, length: u64) -> Self {
+        Self {
+            inner,
+            offset,
+            length,
+        }
+    }
+
+    /// Offset of the archive in the inner reader.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Size of the archive.
+    pub fn len(&self) -> u64 {
+        self.length
+    }
+
+    /// Returns true if the archive is of zero size.
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// Get back the inner reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn inner_offset(&self, offset: u64, size: usize) -> Option<u64> {
+        let end = offset.checked_add(size as u64)?;
+        if end > self.length {
+            return None;
+        }
+        self.offset.checked_add(offset)
+    }
+}
+
+#[async_trait]
+impl<R> ArchiveReader for BoundedReader<R>
+where
+    R: ArchiveReader + Send,
+    R::Error: Send,
+{
+    type Error = BoundedReaderError<R::Error>;
+
+    async fn read_at(&mut self, offset: u64, size: usize) -> Result<Bytes, Self::Error> {
+        let inner_offset = self
+            .inner_offset(offset, size)
+            .ok_or(BoundedReaderError::OutOfBounds)?;
+        self.inner
+            .read_at(inner_offset, size)
+            .await
+            .map_err(BoundedReaderError::InnerError)
+    }
+
+    fn read_chunks<'a>(
+        &'a mut self,
+        chunks: Vec<ChunkOffset>,
+    ) -> Pin<Box<dyn Stream<Item = Result<Bytes, Self::Error>> + Send + 'a>> {
+        let inner_chunks: Option<Vec<ChunkOffset>> = chunks
+            .iter()
+            .map(|chunk| {
+                self.inner_offset(chunk.offset, chunk.size)
+                    .map(|offset| ChunkOffset::new(offset, chunk.size))
+            })
+            .collect();
+        match inner_chunks {
+            Some(inner_chunks) => Box::pin(
+                self.inner
+                    .read_chunks(inner_chunks)
+                    .map(|result| result.map_err(BoundedReaderError::InnerError)),
+            ),
+            None => Box::pin(stream::once(async { Err(BoundedReaderError::OutOfBounds) })),
+        }
+    }
+
+    async fn read_tail(&mut self, size: usize) -> Result<Option<Bytes>, Self::Error> {
+        let offset = self
+            .length
+            .checked_sub(size as u64)
+            .ok_or(BoundedReaderError::OutOfBounds)?;
+        self.read_at(offset, size).await.map(Some)
+    }
+
+    async fn prefetch(&mut self, chunks: &[ChunkOffset]) {
+        let inner_chunks: Vec<ChunkOffset> = chunks
+            .iter()
+            .filter_map(|chunk| {
+                self.inner_offset(chunk.offset, chunk.size)
+                    .map(|offset| ChunkOffset::new(offset, chunk.size))
+            })
+            .collect();
+        self.inner.prefetch(&inner_chunks).await
+    }
+}
+
+#[derive(Debug)]
+pub enum BoundedReaderError<E> {
+    OutOfBounds,
+    InnerError(E),
+}
+
+impl<E> std::error::Error for BoundedReaderError<E>
+where
+    E: std::error::Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BoundedReaderError::InnerError(err) => Some(err),
+            BoundedReaderError::OutOfBounds => None,
+        }
+    }
+}
+
+impl<E> fmt::Display for BoundedReaderError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OutOfBounds => write!(f, "read out of bounds"),
+            Self::InnerError(_) => write!(f, "failed to read from inner reader"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive_reader::IoReader;
+    use std::io::Cursor;
+
+    fn new_reader(
+        data: &[u8],
+        offset: u64,
+        length: u64,
+    ) -> BoundedReader<IoReader<Cursor<Vec<u8>>>> {
+        BoundedReader::new(IoReader::new(Cursor::new(data.to_vec())), offset, length)
+    }
+
+    #[tokio::test]
+    async fn read_within_bounds() {
+        let mut reader = new_reader(&[1, 2, 3, 4, 5, 6, 7, 8], 2, 4);
+        assert_eq!(&reader.read_at(0, 2).await.unwrap()[..], &[3, 4]);
+        assert_eq!(&reader.read_at(1, 3).await.unwrap()[..], &[4, 5, 6]);
+        assert_eq!(&reader.read_at(4, 0).await.unwrap()[..], &[0u8; 0]);
+    }
+
+    #[tokio::test]
+    async fn read_out_of_bounds() {
+        let mut reader = new_reader(&[1, 2, 3, 4, 5, 6, 7, 8], 2, 4);
+        assert!(matches!(
+            reader.read_at(2, 3).await,
+            Err(BoundedReaderError::OutOfBounds)
+        ));
+        assert!(matches!(
+            reader.read_at(u64::MAX, 1).await,
+            Err(BoundedReaderError::OutOfBounds)
+        ));
+    }
+
+    #[tokio::test]
+    async fn inner_shorter_than_bounds() {
+        let mut reader = new_reader(&[1, 2, 3, 4], 2, 4);
+        assert!(matches!(
+            reader.read_at(0, 4).await,
+            Err(BoundedReaderError::InnerError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn read_tail() {
+        let mut reader = new_reader(&[1, 2, 3, 4, 5, 6, 7, 8], 2, 4);
+        assert_eq!(
+            &reader.read_tail(2).await.unwrap().unwrap()[..],
+            &[5, 6][..]
+        );
+        assert!(matches!(
+            reader.read_tail(5).await,
+            Err(BoundedReaderError::OutOfBounds)
+        ));
+    }
+
+    #[tokio::test]
+    async fn read_chunks() {
+        let mut reader = new_reader(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10], 1, 8);
+        let chunks = vec![ChunkOffset::new(0, 3), ChunkOffset::new(5, 3)];
+        let read: Vec<Bytes> = reader
+            .read_chunks(chunks)
+            .map(|v| v.expect("item"))
+            .collect()
+            .await;
+        assert_eq!(
+            read,
+            vec![Bytes::from(vec![2, 3, 4]), Bytes::from(vec![7, 8, 9])]
+        );
+    }
+
+    #[tokio::test]
+    async fn read_chunks_out_of_bounds() {
+        let mut reader = new_reader(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10], 1, 8);
+        let chunks = vec![ChunkOffset::new(0, 3), ChunkOffset::new(6, 3)];
+        let read: Vec<_> = reader.read_chunks(chunks).collect().await;
+        assert_eq!(read.len(), 1);
+        assert!(matches!(read[0], Err(BoundedReaderError::OutOfBounds)));
+    }
+}
@@ -0,0 +1,341 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use core::pin::Pin;
+use futures_util::future::BoxFuture;
+use futures_util::stream::{self, Stream, StreamExt};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use crate::archive_reader::ArchiveReader;
+use crate::ChunkOffset;
+
+// Least recently used reads, evicted when the total size exceeds max_size.
+#[derive(Debug)]
+struct Lru {
+    max_size: usize,
+    size: usize,
+    tick: u64,
+    entries: HashMap<ChunkOffset, (Bytes, u64)>,
+    // Tick of last use to read.
+    order: BTreeMap<u64, ChunkOffset>,
+}
+
+impl Lru {
+    fn get(&mut self, chunk: &ChunkOffset) -> Option<Bytes> {
+        let (data, last_used) = self.entries.get_mut(chunk)?;
+        self.order.remove(last_used);
+        self.tick += 1;
+        *last_used = self.tick;
+        self.order.insert(self.tick, *chunk);
+        Some(data.clone())
+    }
+
+    fn insert(&mut self, chunk: ChunkOffset, data: Bytes) {
+        if data.len() > self.max_size {
+            return;
+        }
+        self.tick += 1;
+        self.size += data.len();
+        if let Some((old, last_used)) = self.entries.insert(chunk, (data, self.tick)) {
+            self.size -= old.len();
+            self.order.remove(&last_used);
+        }
+        self.order.insert(self.tick, chunk);
+        while self.size > self.max_size {
+            let (_tick, chunk) = self.order.pop_first().unwrap();
+            let (data, _tick) = self.entries.remove(&chunk).unwrap();
+            self.size -= data.len();
+        }
+    }
+}
+
+/// Cache of reads, shared between the `CachingReader`s it's used by.
+///
+/// Reads are cached by archive offset and size. So a cache must only be shared between
+/// readers of the same archive.
+#[derive(Clone, Debug)]
+pub struct ReadCache {
+    lru: Arc<Mutex<Lru>>,
+}
+
+impl ReadCache {
+    /// Create a cache keeping at most `max_size` bytes of reads.
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            lru: Arc::new(Mutex::new(Lru {
+                max_size,
+                size: 0,
+                tick: 0,
+                entries: HashMap::new(),
+                order: BTreeMap::new(),
+            })),
+        }
+    }
+
+    /// Number of bytes currently cached.
+    pub fn size(&self) -> usize {
+        self.lru.lock().unwrap().size
+    }
+
+    fn get(&self, chunk: &ChunkOffset) -> Option<Bytes> {
+        self.lru.lock().unwrap().get(chunk)
+    }
+
+    fn insert(&self, chunk: ChunkOffset, data: Bytes) {
+        self.lru.lock().unwrap().insert(chunk, data)
+    }
+}
+
+/// Archive reader keeping the most recently read chunks in memory.
+///
+/// Reads already in the cache are served without touching the inner reader, all others
+/// are passed on to the inner reader and then cached. Useful when cloning the same archive
+/// several times, eg to multiple outputs. Clones of the reader share the same cache, and a
+/// cache may also be given to readers of the same archive using `with_cache`.
+///
+/// Reads of the archive tail are never cached.
+#[derive(Clone, Debug)]
+pub struct CachingReader<R> {
+    inner: R,
+    cache: ReadCache,
+}
+
+impl<R> CachingReader<R> {
+    /// Create a reader caching at most `max_size` bytes of reads from `inner`.
+    pub fn new(inner: R, max_size: usize) -> Self {
+        Self::with_cache(inner, ReadCache::new(max_size))
+    }
+
+    /// Create a reader caching reads from `inner` in the given cache.
+    pub fn with_cache(inner: R, cache: ReadCache) -> Self {
+        Self { inner, cache }
+    }
+
+    /// Get the cache used by the reader.
+    pub fn cache(&self) -> &ReadCache {
+        &self.cache
+    }
+
+    /// Get the inner reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+#[async_trait]
+impl<R> ArchiveReader for CachingReader<R>
+where
+    R: ArchiveReader + Send,
+    R::Error: Send,
+{
+    type Error = R::Error;
+
+    async fn read_at(&mut self, offset: u64, size: usize) -> Result<Bytes, Self::Error> {
+        let chunk = ChunkOffset::new(offset, size);
+        if let Some(data) = self.cache.get(&chunk) {
+            return Ok(data);
+        }
+        let data = self.inner.read_at(offset, size).await?;
+        self.cache.insert(chunk, data.clone());
+        Ok(data)
+    }
+
+    fn read_chunks<'a>(
+        &'a mut self,
+        chunks: Vec<ChunkOffset>,
+    ) -> Pin<Box<dyn Stream<Item = Result<Bytes, Self::Error>> + Send + 'a>> {
+        // Look up all chunks first, to only read the ones missing from the inner reader.
+        let cached: Vec<(ChunkOffset, Option<Bytes>)> = chunks
+            .into_iter()
+            .map(|chunk| (chunk, self.cache.get(&chunk)))
+            .collect();
+        let missing = cached
+            .iter()
+            .filter(|(_chunk, data)| data.is_none())
+            .map(|(chunk, _data)| *chunk)
+            .collect();
+        let cache = self.cache.clone();
+        let inner = self.inner.read_chunks(missing);
+        Box::pin(stream::unfold(
+            (inner, cached.into_iter(), cache),
+            |(mut inner, mut cached, cache)| async move {
+                let result = match cached.next()? {
+                    (_chunk, Some(data)) => Ok(data),
+                    (chunk, None) => {
+                        let result = inner.next().await?;
+                        if let Ok(data) = &result {
+                            cache.insert(chunk, data.clone());
+                        }
+                        result
+                    }
+                };
+                Some((result, (inner, cached, cache)))
+            },
+        ))
+    }
+
+    fn read_tail<'life0, 'async_trait>(
+        &'life0 mut self,
+        size: usize,
+    ) -> BoxFuture<'async_trait, Result<Option<Bytes>, Self::Error>>
+    where
+        'life0: 'async_trait,
+        Self: 'async_trait,
+    {
+        self.inner.read_tail(size)
+    }
+
+    fn prefetch<'life0, 'life1, 'async_trait>(
+        &'life0 mut self,
+        chunks: &'life1 [ChunkOffset],
+    ) -> BoxFuture<'async_trait, ()>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        Self: 'async_trait,
+    {
+        let missing: Vec<ChunkOffset> = chunks
+            .iter()
+            .filter(|chunk| self.cache.get(chunk).is_none())
+            .copied()
+            .collect();
+        Box::pin(async move { self.inner.prefetch(&missing).await })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive_reader::IoReader;
+    use std::io::Cursor;
+
+    // Reader keeping track of the chunks read.
+    struct CountingReader {
+        inner: IoReader<Cursor<Vec<u8>>>,
+        reads: Arc<Mutex<Vec<ChunkOffset>>>,
+    }
+
+    #[async_trait]
+    impl ArchiveReader for CountingReader {
+        type Error = std::io::Error;
+
+        async fn read_at(&mut self, offset: u64, size: usize) -> Result<Bytes, Self::Error> {
+            self.reads
+                .lock()
+                .unwrap()
+                .push(ChunkOffset::new(offset, size));
+            self.inner.read_at(offset, size).await
+        }
+
+        fn read_chunks<'a>(
+            &'a mut self,
+            chunks: Vec<ChunkOffset>,
+        ) -> Pin<Box<dyn Stream<Item = Result<Bytes, Self::Error>> + Send + 'a>> {
+            self.reads.lock().unwrap().extend_from_slice(&chunks);
+            self.inner.read_chunks(chunks)
+        }
+    }
+
+    fn new_reader(
+        max_size: usize,
+    ) -> (CachingReader<CountingReader>, Arc<Mutex<Vec<ChunkOffset>>>) {
+        let reads = Arc::new(Mutex::new(Vec::new()));
+        let inner = CountingReader {
+            inner: IoReader::new(Cursor::new((0..100).collect())),
+            reads: reads.clone(),
+        };
+        (CachingReader::new(inner, max_size), reads)
+    }
+
+    #[tokio::test]
+    async fn repeated_read_at() {
+        let (mut reader, reads) = new_reader(1000);
+        for _ in 0..3 {
+            assert_eq!(
+                &reader.read_at(10, 5).await.unwrap()[..],
+                &[10, 11, 12, 13, 14]
+            );
+        }
+        assert_eq!(*reads.lock().unwrap(), vec![ChunkOffset::new(10, 5)]);
+        assert_eq!(reader.cache().size(), 5);
+    }
+
+    #[tokio::test]
+    async fn repeated_read_chunks() {
+        let (mut reader, reads) = new_reader(1000);
+        let chunks = vec![ChunkOffset::new(0, 2), ChunkOffset::new(50, 3)];
+        let expected = vec![Bytes::from(vec![0, 1]), Bytes::from(vec![50, 51, 52])];
+        let read: Vec<Bytes> = reader
+            .read_chunks(chunks.clone())
+            .map(|v| v.unwrap())
+            .collect()
+            .await;
+        assert_eq!(read, expected);
+
+        // Only the chunk not already read is passed on to the inner reader.
+        let mut chunks = chunks;
+        chunks.insert(1, ChunkOffset::new(20, 1));
+        let read: Vec<Bytes> = reader
+            .read_chunks(chunks.clone())
+            .map(|v| v.unwrap())
+            .collect()
+            .await;
+        assert_eq!(
+            read,
+            vec![
+                expected[0].clone(),
+                Bytes::from(vec![20]),
+                expected[1].clone()
+            ]
+        );
+        assert_eq!(
+            *reads.lock().unwrap(),
+            vec![
+                ChunkOffset::new(0, 2),
+                ChunkOffset::new(50, 3),
+                ChunkOffset::new(20, 1)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn evicts_least_recently_used() {
+        let (mut reader, reads) = new_reader(10);
+        reader.read_at(0, 4).await.unwrap();
+        reader.read_at(10, 4).await.unwrap();
+        // Use the first read again, making the second the least recently used.
+        reader.read_at(0, 4).await.unwrap();
+        reader.read_at(20, 4).await.unwrap();
+        assert_eq!(reader.cache().size(), 8);
+        reader.read_at(0, 4).await.unwrap();
+        reader.read_at(10, 4).await.unwrap();
+        // Too big to be cached.
+        reader.read_at(30, 11).await.unwrap();
+        reader.read_at(30, 11).await.unwrap();
+        assert_eq!(
+            *reads.lock().unwrap(),
+            vec![
+                ChunkOffset::new(0, 4),
+                ChunkOffset::new(10, 4),
+                ChunkOffset::new(20, 4),
+                ChunkOffset::new(10, 4),
+                ChunkOffset::new(30, 11),
+                ChunkOffset::new(30, 11),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn shared_cache() {
+        let (mut reader, reads) = new_reader(1000);
+        reader.read_at(10, 5).await.unwrap();
+        let (other, other_reads) = new_reader(0);
+        let mut other = CachingReader::with_cache(other.into_inner(), reader.cache().clone());
+        assert_eq!(
+            &other.read_at(10, 5).await.unwrap()[..],
+            &[10, 11, 12, 13, 14]
+        );
+        assert_eq!(reads.lock().unwrap().len(), 1);
+        assert!(other_reads.lock().unwrap().is_empty());
+    }
+}
@@ -1,53 +1,207 @@
 use bytes::Bytes;
 use core::pin::Pin;
 use core::task::{Context, Poll};
-use futures_util::{ready, stream::Stream, StreamExt};
-use reqwest::RequestBuilder;
+use futures_util::{future::BoxFuture, ready, stream::Stream, FutureExt, StreamExt};
+use reqwest::{header::HeaderName, StatusCode};
 use std::future::Future;
-use std::time::Duration;
 use tokio::time::sleep;
 
-use crate::archive_reader::HttpReaderError;
+use crate::archive_reader::{HttpReaderError, RetryPolicy, TokenProvider};
+
+/// Builder of the request to read from, using either a plain client or a client with
+/// middleware.
+pub(crate) enum Request {
+    Reqwest(reqwest::RequestBuilder),
+    #[cfg(feature = "reqwest-middleware")]
+    Middleware(reqwest_middleware::RequestBuilder),
+}
+
+impl Request {
+    pub fn try_clone(&self) -> Option<Self> {
+        match self {
+            Self::Reqwest(request) => request.try_clone().map(Self::Reqwest),
+            #[cfg(feature = "reqwest-middleware")]
+            Self::Middleware(request) => request.try_clone().map(Self::Middleware),
+        }
+    }
+
+    fn header(self, name: HeaderName, value: String) -> Self {
+        match self {
+            Self::Reqwest(request) => Self::Reqwest(request.header(name, value)),
+            #[cfg(feature = "reqwest-middleware")]
+            Self::Middleware(request) => Self::Middleware(request.header(name, value)),
+        }
+    }
+
+    // Turn the request into a HEAD request of the same resource.
+    fn head(self) -> Result<Self, HttpReaderError> {
+        match self {
+            Self::Reqwest(request) => {
+                let (client, request) = request.build_split();
+                let mut request = request?;
+                *request.method_mut() = reqwest::Method::HEAD;
+                Ok(Self::Reqwest(reqwest::RequestBuilder::from_parts(
+                    client, request,
+                )))
+            }
+            #[cfg(feature = "reqwest-middleware")]
+            Self::Middleware(request) => {
+                let (client, request) = request.build_split();
+                let mut request = request?;
+                *request.method_mut() = reqwest::Method::HEAD;
+                Ok(Self::Middleware(
+                    reqwest_middleware::RequestBuilder::from_parts(client, request),
+                ))
+            }
+        }
+    }
+
+    fn send(self) -> BoxFuture<'static, Result<reqwest::Response, HttpReaderError>> {
+        match self {
+            Self::Reqwest(request) => async move { Ok(request.send().await?) }.boxed(),
+            #[cfg(feature = "reqwest-middleware")]
+            Self::Middleware(request) => async move { Ok(request.send().await?) }.boxed(),
+        }
+    }
+}
 
 pub(crate) struct HttpRangeRequest {
-    request: RequestBuilder,
+    request: Request,
     state: RequestState,
     size: u64,
     offset: u64,
-    retry_delay: Duration,
+    // Request the last size bytes rather than size bytes at offset.
+    suffix: bool,
+    // Request the whole resource, without any range.
+    full: bool,
+    retry_policy: RetryPolicy,
     retry_count: u32,
+    retry_attempt: u32,
+    token_provider: Option<TokenProvider>,
+    // Let the server compress the response on the wire.
+    transport_compression: bool,
 }
 
 impl HttpRangeRequest {
-    pub fn new(request: RequestBuilder, offset: u64, size: u64) -> Self {
+    pub fn new(request: Request, offset: u64, size: u64) -> Self {
         Self {
             request,
             offset,
             size,
-            retry_delay: Duration::from_secs(0),
+            suffix: false,
+            full: false,
+            retry_policy: RetryPolicy::default(),
             retry_count: 0,
+            retry_attempt: 0,
+            token_provider: None,
+            transport_compression: false,
             state: RequestState::Init,
         }
     }
 
-    pub fn retry(mut self, retry_count: u32, retry_delay: Duration) -> Self {
-        self.retry_delay = retry_delay;
+    /// Request the last `size` bytes of the resource.
+    pub fn suffix(request: Request, size: u64) -> Self {
+        Self {
+            suffix: true,
+            ..Self::new(request, 0, size)
+        }
+    }
+
+    /// Request the whole resource, for servers not supporting range requests.
+    pub fn full(request: Request) -> Self {
+        Self {
+            full: true,
+            ..Self::new(request, 0, 0)
+        }
+    }
+
+    pub fn retry(mut self, retry_count: u32, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
         self.retry_count = retry_count;
         self
     }
 
+    // Get the time to wait before retrying after err, or None if we should give up.
+    fn next_retry(&mut self, err: &HttpReaderError) -> Option<std::time::Duration> {
+        if self.retry_count == 0 || !err.is_transient() {
+            return None;
+        }
+        let delay = self.retry_policy.delay(self.retry_attempt);
+        log::warn!("request failed (retrying in {:?}): {}", delay, err);
+        self.retry_count -= 1;
+        self.retry_attempt += 1;
+        Some(delay)
+    }
+
+    pub fn token_provider(mut self, token_provider: Option<TokenProvider>) -> Self {
+        self.token_provider = token_provider;
+        self
+    }
+
+    pub fn transport_compression(mut self, allow: bool) -> Self {
+        self.transport_compression = allow;
+        self
+    }
+
+    fn range_request(
+        request: Request,
+        offset: u64,
+        size: u64,
+        suffix: bool,
+        full: bool,
+        transport_compression: bool,
+        authorization: Option<String>,
+    ) -> Request {
+        // Ranges refer to the unencoded resource, but an encoded response body is not
+        // byte-exact to it.
+        let request = if transport_compression {
+            request
+        } else {
+            request.header(reqwest::header::ACCEPT_ENCODING, "identity".to_string())
+        };
+        let request = if full {
+            request
+        } else if suffix {
+            request.header(reqwest::header::RANGE, format!("bytes=-{}", size))
+        } else {
+            request.header(
+                reqwest::header::RANGE,
+                format!("bytes={}-{}", offset, offset + size - 1),
+            )
+        };
+        match authorization {
+            Some(authorization) => request.header(reqwest::header::AUTHORIZATION, authorization),
+            None => request,
+        }
+    }
+
     async fn single_fail(
-        request: RequestBuilder,
+        request: Request,
         offset: u64,
         size: u64,
+        suffix: bool,
+        full: bool,
+        transport_compression: bool,
+        token_provider: Option<TokenProvider>,
     ) -> Result<Bytes, HttpReaderError> {
-        let end_offset = offset + size - 1;
-        let request = request.header(
-            reqwest::header::RANGE,
-            format!("bytes={}-{}", offset, end_offset),
-        );
-        let response = request.send().await?;
-        Ok(response.bytes().await?)
+        let authorization = match token_provider {
+            Some(token_provider) => Some(token_provider().await),
+            None => None,
+        };
+        let response = Self::range_request(
+            request,
+            offset,
+            size,
+            suffix,
+            full,
+            transport_compression,
+            authorization,
+        )
+        .send()
+        .await?;
+        // The tail of the whole resource is as good as the requested suffix.
+        let expected_size = (!full && !suffix).then_some(size);
+        Ok(Self::check_status(response, expected_size)?.bytes().await?)
     }
 
     pub async fn single(mut self) -> Result<Bytes, HttpReaderError> {
@@ -58,46 +212,122 @@ impl HttpRangeRequest {
                     .ok_or(HttpReaderError::RequestNotClonable)?,
                 self.offset,
                 self.size,
+                self.suffix,
+                self.full,
+                self.transport_compression,
+                self.token_provider.clone(),
             )
             .await
             {
                 Ok(item) => return Ok(item),
-                Err(err) => {
-                    if self.retry_count == 0 {
-                        return Err(err);
-                    } else {
-                        log::warn!("request failed (retrying soon): {}", err);
-                        self.retry_count -= 1;
-                    }
-                }
+                Err(err) => match self.next_retry(&err) {
+                    Some(delay) => sleep(delay).await,
+                    None => return Err(err),
+                },
+            }
+        }
+    }
+
+    /// Get the size of the resource from the Content-Length of a HEAD request.
+    pub async fn content_length(mut self) -> Result<u64, HttpReaderError> {
+        loop {
+            match Self::content_length_fail(
+                self.request
+                    .try_clone()
+                    .ok_or(HttpReaderError::RequestNotClonable)?,
+                self.transport_compression,
+                self.token_provider.clone(),
+            )
+            .await
+            {
+                Ok(length) => return Ok(length),
+                Err(err) => match self.next_retry(&err) {
+                    Some(delay) => sleep(delay).await,
+                    None => return Err(err),
+                },
             }
-            sleep(self.retry_delay).await;
+        }
+    }
+
+    async fn content_length_fail(
+        request: Request,
+        transport_compression: bool,
+        token_provider: Option<TokenProvider>,
+    ) -> Result<u64, HttpReaderError> {
+        let request = request.head()?;
+        // An encoded response would give the size of the encoded resource, but is rejected
+        // by check_status anyway.
+        let request = if transport_compression {
+            request
+        } else {
+            request.header(reqwest::header::ACCEPT_ENCODING, "identity".to_string())
+        };
+        let request = match token_provider {
+            Some(token_provider) => {
+                request.header(reqwest::header::AUTHORIZATION, token_provider().await)
+            }
+            None => request,
+        };
+        let response = Self::check_status(request.send().await?, None)?;
+        // Read the header rather than the size hint of the (empty) body.
+        response
+            .headers()
+            .get(reqwest::header::CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok()?.parse().ok())
+            .ok_or(HttpReaderError::UnknownSize)
+    }
+
+    // Check the response status, that a range request of expected_size bytes didn't get
+    // the whole resource and that the body is not left encoded.
+    fn check_status(
+        response: reqwest::Response,
+        expected_size: Option<u64>,
+    ) -> Result<reqwest::Response, HttpReaderError> {
+        // A client decoding the body also removes the Content-Encoding header, so any
+        // encoding left means the body is not the requested bytes.
+        if let Some(encoding) = response
+            .headers()
+            .get(reqwest::header::CONTENT_ENCODING)
+            .filter(|encoding| !encoding.as_bytes().eq_ignore_ascii_case(b"identity"))
+        {
+            return Err(HttpReaderError::ContentEncoding(
+                String::from_utf8_lossy(encoding.as_bytes()).into_owned(),
+            ));
+        }
+        match response.status() {
+            StatusCode::UNAUTHORIZED => Err(HttpReaderError::Unauthorized),
+            status if !status.is_success() => Err(HttpReaderError::UnexpectedStatus(status)),
+            // A server ignoring the range responds with the whole resource, which is more
+            // than requested unless the range starts at 0 (and then the data is right anyway).
+            StatusCode::OK
+                if response
+                    .content_length()
+                    .zip(expected_size)
+                    .is_some_and(|(length, expected)| length > expected) =>
+            {
+                Err(HttpReaderError::RangeNotSupported)
+            }
+            _ => Ok(response),
         }
     }
 
     fn poll_read_fail(&mut self, cx: &mut Context) -> Poll<Option<Result<Bytes, HttpReaderError>>> {
         loop {
             match &mut self.state {
-                RequestState::Init => {
-                    let end_offset = self.offset + self.size - 1;
-                    let request = match self.request.try_clone() {
-                        Some(request) => request,
-                        None => return Poll::Ready(Some(Err(HttpReaderError::RequestNotClonable))),
-                    };
-                    let request = request
-                        .header(
-                            reqwest::header::RANGE,
-                            format!("bytes={}-{}", self.offset, end_offset),
-                        )
-                        .send();
-                    self.state = RequestState::Request(Box::new(request));
-                }
-                RequestState::Request(request) => match ready!(Pin::new(&mut *request).poll(cx)) {
-                    Ok(response) => {
-                        self.state = RequestState::Stream(Box::new(response.bytes_stream()))
-                    }
-                    Err(err) => return Poll::Ready(Some(Err(HttpReaderError::from(err)))),
+                RequestState::Init => match &self.token_provider {
+                    Some(token_provider) => self.state = RequestState::Token(token_provider()),
+                    None => self.send_request(None)?,
                 },
+                RequestState::Token(token) => {
+                    let authorization = ready!(token.poll_unpin(cx));
+                    self.send_request(Some(authorization))?;
+                }
+                RequestState::Request(request) => {
+                    let response = ready!(request.poll_unpin(cx))?;
+                    let expected_size = (!self.full && !self.suffix).then_some(self.size);
+                    let response = Self::check_status(response, expected_size)?;
+                    self.state = RequestState::Stream(Box::new(response.bytes_stream()));
+                }
                 RequestState::Stream(stream) => match ready!(stream.poll_next_unpin(cx)) {
                     Some(Ok(item)) => {
                         self.offset += item.len() as u64;
@@ -115,18 +345,31 @@ impl HttpRangeRequest {
         }
     }
 
+    fn send_request(&mut self, authorization: Option<String>) -> Result<(), HttpReaderError> {
+        let request = self
+            .request
+            .try_clone()
+            .ok_or(HttpReaderError::RequestNotClonable)?;
+        let request = Self::range_request(
+            request,
+            self.offset,
+            self.size,
+            self.suffix,
+            self.full,
+            self.transport_compression,
+            authorization,
+        );
+        self.state = RequestState::Request(request.send());
+        Ok(())
+    }
+
     fn poll_read(&mut self, cx: &mut Context) -> Poll<Option<Result<Bytes, HttpReaderError>>> {
         loop {
             match self.poll_read_fail(cx) {
-                Poll::Ready(Some(Err(err))) => {
-                    if self.retry_count == 0 {
-                        return Poll::Ready(Some(Err(err)));
-                    } else {
-                        log::warn!("request failed (retrying soon): {}", err);
-                        self.retry_count -= 1;
-                        self.state = RequestState::Delay(Box::pin(sleep(self.retry_delay)));
-                    }
-                }
+                Poll::Ready(Some(Err(err))) => match self.next_retry(&err) {
+                    Some(delay) => self.state = RequestState::Delay(Box::pin(sleep(delay))),
+                    None => return Poll::Ready(Some(Err(err))),
+                },
                 result => return result,
             }
         }
@@ -135,7 +378,8 @@ impl HttpRangeRequest {
 
 enum RequestState {
     Init,
-    Request(Box<dyn Future<Output = Result<reqwest::Response, reqwest::Error>> + Send + Unpin>),
+    Token(BoxFuture<'static, String>),
+    Request(BoxFuture<'static, Result<reqwest::Response, HttpReaderError>>),
     Stream(Box<dyn Stream<Item = Result<Bytes, reqwest::Error>> + Send + Unpin>),
     Delay(Pin<Box<tokio::time::Sleep>>),
 }
@@ -2,27 +2,215 @@ use async_trait::async_trait;
 use bytes::{Bytes, BytesMut};
 use core::pin::Pin;
 use core::task::{Context, Poll};
-use futures_util::{ready, stream::Stream, StreamExt};
-use reqwest::{RequestBuilder, Url};
-use std::{fmt, time::Duration};
+use futures_util::{future::BoxFuture, ready, stream::Stream, FutureExt, StreamExt};
+use reqwest::{RequestBuilder, StatusCode, Url};
+use std::{
+    collections::hash_map::RandomState,
+    fmt,
+    hash::{BuildHasher, Hasher},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::task::JoinHandle;
 
-use super::http_range_request::HttpRangeRequest;
-use crate::archive_reader::{ArchiveReader, ChunkOffset};
+use super::http_range_request::{HttpRangeRequest, Request};
+use crate::archive_reader::{adjacent_reads, ArchiveReader, ChunkOffset};
+
+/// Callback providing the value of the Authorization header to use for a request.
+pub type TokenProvider = Arc<dyn Fn() -> BoxFuture<'static, String> + Send + Sync>;
+
+/// Policy for how long to wait before retrying a failed request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RetryPolicy {
+    /// Wait the same time before each retry.
+    Fixed(Duration),
+    /// Double the wait for each retry, starting at `base` and never waiting longer than `max`.
+    ///
+    /// With `jitter` set the wait is randomized to somewhere between half and the full
+    /// delay, to keep many clients from retrying against a recovering server in lockstep.
+    Exponential {
+        base: Duration,
+        max: Duration,
+        jitter: bool,
+    },
+}
+
+impl RetryPolicy {
+    /// Get the time to wait before the given retry attempt (0 for the first retry).
+    pub fn delay(&self, attempt: u32) -> Duration {
+        match *self {
+            RetryPolicy::Fixed(delay) => delay,
+            RetryPolicy::Exponential { base, max, jitter } => {
+                let delay = base
+                    .checked_mul(2u32.saturating_pow(attempt))
+                    .map_or(max, |delay| delay.min(max));
+                if jitter {
+                    // Randomness from std's hasher keys is plenty for spreading out retries.
+                    let random = RandomState::new().build_hasher().finish();
+                    delay / 2 + delay.mul_f64((random % 1000) as f64 / 2000.0)
+                } else {
+                    delay
+                }
+            }
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::Fixed(Duration::from_secs(0))
+    }
+}
+
+/// Configuration of a http client to share between readers.
+///
+/// Build the client once and create readers using `HttpReader::from_client_url` to reuse
+/// connections between them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HttpClientConfig {
+    http2: bool,
+    connect_timeout: Option<Duration>,
+    pool_idle_timeout: Option<Duration>,
+    pool_max_idle_per_host: Option<usize>,
+    tcp_keepalive: Option<Duration>,
+}
+
+impl HttpClientConfig {
+    /// Use HTTP/2 for all requests, without first negotiating it with the server.
+    ///
+    /// With HTTP/2 the range requests of many readers are multiplexed on a single connection,
+    /// which helps when reading scattered small chunks. The server must support HTTP/2.
+    #[must_use]
+    pub fn http2(mut self, http2: bool) -> Self {
+        self.http2 = http2;
+        self
+    }
+
+    /// Set how long to wait for a connection to be established.
+    ///
+    /// Unlike a request timeout this only limits connecting, not reading the response.
+    #[must_use]
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Set how long an idle connection is kept in the pool before closing it.
+    #[must_use]
+    pub fn pool_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.pool_idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Set max number of idle connections kept in the pool per host.
+    #[must_use]
+    pub fn pool_max_idle_per_host(mut self, max: usize) -> Self {
+        self.pool_max_idle_per_host = Some(max);
+        self
+    }
+
+    /// Enable TCP keep-alive probes on connections, sent at the given interval.
+    #[must_use]
+    pub fn tcp_keepalive(mut self, interval: Duration) -> Self {
+        self.tcp_keepalive = Some(interval);
+        self
+    }
+
+    /// Build a client using the configuration.
+    pub fn build(&self) -> Result<reqwest::Client, HttpReaderError> {
+        let mut builder = reqwest::Client::builder();
+        if self.http2 {
+            builder = builder.http2_prior_knowledge();
+        }
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.pool_idle_timeout {
+            builder = builder.pool_idle_timeout(timeout);
+        }
+        if let Some(max) = self.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max);
+        }
+        if let Some(interval) = self.tcp_keepalive {
+            builder = builder.tcp_keepalive(interval);
+        }
+        Ok(builder.build()?)
+    }
+}
 
 /// Read a http/https hosted archive.
 pub struct HttpReader {
-    request_builder: RequestBuilder,
+    request_builder: Request,
     retry_count: u32,
-    retry_delay: Duration,
+    retry_policy: RetryPolicy,
+    token_provider: Option<TokenProvider>,
+    prefetch: Option<Prefetch>,
+    full_download_fallback: bool,
+    transport_compression: bool,
+    // The whole archive, when downloaded since the server doesn't support range requests.
+    full_download: Option<Bytes>,
+}
+
+// Range request running in the background, started by a call to prefetch.
+struct Prefetch {
+    offset: u64,
+    size: u64,
+    task: JoinHandle<Result<Bytes, HttpReaderError>>,
+}
+
+impl Prefetch {
+    fn covers(&self, offset: u64, size: u64) -> bool {
+        offset >= self.offset && offset + size <= self.offset + self.size
+    }
+}
+
+impl Drop for Prefetch {
+    fn drop(&mut self) {
+        // Nobody is interested in the data anymore.
+        self.task.abort();
+    }
 }
 
 impl HttpReader {
     /// Create a remote archive reader using RequestBuilder for the http request.
+    ///
+    /// The request is used as is, with the configuration (proxy, root certificates, timeouts
+    /// etc) of the client it was built from, see `from_client_url`. A copy of it, with the
+    /// Range header added, is made using `RequestBuilder::try_clone` for every read and every
+    /// retry. The request must therefore be clonable, ie not have a streaming body, or reads
+    /// fail with `HttpReaderError::RequestNotClonable`.
     pub fn from_request(request_builder: RequestBuilder) -> Self {
+        Self::from_reader_request(Request::Reqwest(request_builder))
+    }
+
+    /// Create a remote archive reader using RequestBuilder of a client with middleware.
+    ///
+    /// The middleware (eg tracing or request signing) is run for every request sent,
+    /// including retries. Like for `from_request` the request must be clonable.
+    #[cfg(feature = "reqwest-middleware")]
+    pub fn from_middleware_request(request_builder: reqwest_middleware::RequestBuilder) -> Self {
+        Self::from_reader_request(Request::Middleware(request_builder))
+    }
+
+    /// Create a remote archive reader using an URL and a shared client with middleware.
+    #[cfg(feature = "reqwest-middleware")]
+    pub fn from_middleware_client_url(
+        client: &reqwest_middleware::ClientWithMiddleware,
+        url: Url,
+    ) -> Self {
+        Self::from_middleware_request(client.get(url))
+    }
+
+    fn from_reader_request(request_builder: Request) -> Self {
         Self {
             request_builder,
             retry_count: 0,
-            retry_delay: Duration::from_secs(0),
+            retry_policy: RetryPolicy::default(),
+            token_provider: None,
+            prefetch: None,
+            full_download_fallback: false,
+            transport_compression: false,
+            full_download: None,
         }
     }
 
@@ -31,10 +219,20 @@ impl HttpReader {
         Self::from_request(reqwest::Client::new().get(url))
     }
 
+    /// Create a remote archive reader using an URL and a shared client for the request.
+    ///
+    /// Readers created from the same client share its connection pool, TLS and proxy
+    /// configuration. Prefer this over `from_url` when reading many archives from the same
+    /// server(s).
+    pub fn from_client_url(client: &reqwest::Client, url: Url) -> Self {
+        Self::from_request(client.get(url))
+    }
+
     /// Set number of times to retry on failure
     ///
     /// The reader will try to reconnect and continue download from where the failure occurred.
-    /// Any progress made so far should not be lost.
+    /// Any progress made so far should not be lost. Only transient errors (timeouts, lost
+    /// connections, 5xx responses etc) are retried.
     #[must_use]
     pub fn retries(mut self, retry_count: u32) -> Self {
         self.retry_count = retry_count;
@@ -46,36 +244,162 @@ impl HttpReader {
     /// On failure the reader will wait for the given time before trying to reconnect.
     #[must_use]
     pub fn retry_delay(mut self, retry_delay: Duration) -> Self {
-        self.retry_delay = retry_delay;
+        self.retry_policy = RetryPolicy::Fixed(retry_delay);
+        self
+    }
+
+    /// Set the policy for how long to wait between attempts to reconnect.
+    ///
+    /// Replaces any delay set by `retry_delay`.
+    #[must_use]
+    pub fn retry_backoff(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Set a provider of the Authorization header.
+    ///
+    /// The provider is called before each range request (and each retry of it) and the
+    /// returned string is used as the Authorization header value, eg `Bearer <token>`.
+    /// Useful when the server uses short lived tokens. If the server responds with
+    /// 401 Unauthorized the request is retried, with a fresh header, as long as there are
+    /// retries left.
+    #[must_use]
+    pub fn token_provider(mut self, token_provider: TokenProvider) -> Self {
+        self.token_provider = Some(token_provider);
+        self
+    }
+
+    /// Download the whole archive if the server doesn't support range requests.
+    ///
+    /// A server ignoring the Range header responds with the whole archive. With the fallback
+    /// allowed the archive is then downloaded once, kept in memory, and all reads are served
+    /// from it. Without it such reads fail with `HttpReaderError::RangeNotSupported`.
+    ///
+    /// Downloading the whole archive defeats the purpose of fetching only the chunks missing
+    /// from seeds, so a warning is logged when falling back.
+    #[must_use]
+    pub fn allow_full_download_fallback(mut self, allow: bool) -> Self {
+        self.full_download_fallback = allow;
+        self
+    }
+
+    /// Allow the server to compress responses on the wire.
+    ///
+    /// By default requests carry `Accept-Encoding: identity`, since a compressed response
+    /// body doesn't match the requested range of archive bytes. Proxies may still compress
+    /// responses, eg with `Content-Encoding: gzip`, and such responses fail with
+    /// `HttpReaderError::ContentEncoding` rather than being read as archive data.
+    ///
+    /// Allowing transport compression leaves the Accept-Encoding header to the client, for
+    /// environments requiring compressed responses. The client must then decode the
+    /// responses (eg reqwest with its `gzip` feature), responses left encoded still fail.
+    #[must_use]
+    pub fn allow_transport_compression(mut self, allow: bool) -> Self {
+        self.transport_compression = allow;
         self
     }
 
+    // Get the whole archive, downloading it unless already done.
+    async fn full_download(&mut self) -> Result<Bytes, HttpReaderError> {
+        if let Some(data) = &self.full_download {
+            return Ok(data.clone());
+        }
+        let data = full_download_request(
+            &self.request_builder,
+            self.retry_count,
+            self.retry_policy,
+            self.transport_compression,
+            self.token_provider.clone(),
+        )?
+        .await?;
+        self.full_download = Some(data.clone());
+        Ok(data)
+    }
+
     fn read_chunk_stream(
         &mut self,
         chunks: Vec<ChunkOffset>,
     ) -> impl Stream<Item = Result<Bytes, HttpReaderError>> + '_ {
         ChunkReader {
             request_builder: &self.request_builder,
+            token_provider: self.token_provider.as_ref(),
             chunk_buf: BytesMut::new(),
             chunk_index: 0,
             num_adjacent_reads: 0,
             chunks,
             retry_count: self.retry_count,
-            retry_delay: self.retry_delay,
+            retry_policy: self.retry_policy,
             request: None,
+            // A prefetch is only valid for the read following it.
+            prefetch: self.prefetch.take(),
+            full_download_fallback: self.full_download_fallback,
+            transport_compression: self.transport_compression,
+            full_download: &mut self.full_download,
         }
     }
 }
 
+// Status of a server rejecting a suffix range, rather than the request.
+fn suffix_range_rejected(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::BAD_REQUEST | StatusCode::RANGE_NOT_SATISFIABLE | StatusCode::NOT_IMPLEMENTED
+    )
+}
+
+// Request the whole archive, for servers not supporting range requests.
+fn full_download_request(
+    request_builder: &Request,
+    retry_count: u32,
+    retry_policy: RetryPolicy,
+    transport_compression: bool,
+    token_provider: Option<TokenProvider>,
+) -> Result<BoxFuture<'static, Result<Bytes, HttpReaderError>>, HttpReaderError> {
+    log::warn!("server does not support range requests, downloading the whole archive");
+    let request_builder = request_builder
+        .try_clone()
+        .ok_or(HttpReaderError::RequestNotClonable)?;
+    Ok(HttpRangeRequest::full(request_builder)
+        .retry(retry_count, retry_policy)
+        .transport_compression(transport_compression)
+        .token_provider(token_provider)
+        .single()
+        .boxed())
+}
+
+// Get size bytes at offset of the whole archive.
+fn slice_full_download(data: &Bytes, offset: u64, size: usize) -> Result<Bytes, HttpReaderError> {
+    let start = usize::try_from(offset).map_err(|_| HttpReaderError::UnexpectedEnd)?;
+    match start.checked_add(size) {
+        Some(end) if end <= data.len() => Ok(data.slice(start..end)),
+        _ => Err(HttpReaderError::UnexpectedEnd),
+    }
+}
+
+// Source of the data for a group of adjacent chunks.
+enum GroupRequest {
+    Range(Box<HttpRangeRequest>),
+    Prefetched(Prefetch),
+    FullDownload(BoxFuture<'static, Result<Bytes, HttpReaderError>>),
+    // Served from the whole archive, already downloaded.
+    Downloaded,
+}
+
 struct ChunkReader<'a> {
-    request_builder: &'a RequestBuilder,
+    request_builder: &'a Request,
+    token_provider: Option<&'a TokenProvider>,
     chunk_buf: BytesMut,
     chunks: Vec<ChunkOffset>,
     chunk_index: usize,
     num_adjacent_reads: usize,
     retry_count: u32,
-    retry_delay: Duration,
-    request: Option<HttpRangeRequest>,
+    retry_policy: RetryPolicy,
+    request: Option<GroupRequest>,
+    prefetch: Option<Prefetch>,
+    full_download_fallback: bool,
+    transport_compression: bool,
+    full_download: &'a mut Option<Bytes>,
 }
 
 impl ChunkReader<'_>
@@ -101,40 +425,87 @@ where
                 return Poll::Ready(Some(Ok(chunk)));
             }
             if self.request.is_none() {
-                // Create a new range request.
-                let request_builder = self
-                    .request_builder
-                    .try_clone()
-                    .ok_or(HttpReaderError::RequestNotClonable)?;
-
-                self.num_adjacent_reads = Self::adjacent_reads(chunks);
+                self.num_adjacent_reads = adjacent_reads(chunks);
                 let last_adjacent = &chunks[self.num_adjacent_reads - 1];
                 let total_size = last_adjacent.end() - next.offset;
                 self.chunk_buf.clear();
-                self.request = Some(
-                    HttpRangeRequest::new(request_builder, next.offset, total_size)
-                        .retry(self.retry_count, self.retry_delay),
-                );
+                if let Some(data) = self.full_download.as_ref() {
+                    let group = slice_full_download(data, next.offset, total_size as usize)?;
+                    self.chunk_buf.extend_from_slice(&group);
+                    self.request = Some(GroupRequest::Downloaded);
+                    continue;
+                }
+                self.request = Some(match self.prefetch.take() {
+                    Some(prefetch) if prefetch.covers(next.offset, total_size) => {
+                        GroupRequest::Prefetched(prefetch)
+                    }
+                    prefetch => {
+                        self.prefetch = prefetch;
+                        GroupRequest::Range(Box::new(self.range_request(next.offset, total_size)?))
+                    }
+                });
             };
 
             // Poll for chunks.
-            let request = self.request.as_mut().unwrap();
-            match ready!(request.poll_next_unpin(cx)) {
-                Some(Ok(chunk)) => {
-                    self.chunk_buf.extend(chunk);
+            match self.request.as_mut().unwrap() {
+                GroupRequest::Range(request) => match ready!(request.poll_next_unpin(cx)) {
+                    Some(Ok(chunk)) => {
+                        self.chunk_buf.extend(chunk);
+                    }
+                    Some(Err(HttpReaderError::RangeNotSupported))
+                        if self.full_download_fallback =>
+                    {
+                        self.request = Some(GroupRequest::FullDownload(full_download_request(
+                            self.request_builder,
+                            self.retry_count,
+                            self.retry_policy,
+                            self.transport_compression,
+                            self.token_provider.cloned(),
+                        )?));
+                    }
+                    Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                    None => return Poll::Ready(Some(Err(HttpReaderError::UnexpectedEnd))),
+                },
+                GroupRequest::Prefetched(prefetch) => {
+                    let last_adjacent = &chunks[self.num_adjacent_reads - 1];
+                    let start = (next.offset - prefetch.offset) as usize;
+                    let end = (last_adjacent.end() - prefetch.offset) as usize;
+                    match ready!(prefetch.task.poll_unpin(cx)) {
+                        Ok(Ok(data)) if data.len() >= end => {
+                            self.chunk_buf.extend_from_slice(&data[start..end]);
+                        }
+                        result => {
+                            // Let a regular request do the job (and report any error).
+                            log::debug!("prefetch failed ({:?}), requesting again", result.err());
+                            let size = (end - start) as u64;
+                            self.request = Some(GroupRequest::Range(Box::new(
+                                self.range_request(next.offset, size)?,
+                            )));
+                        }
+                    }
+                }
+                GroupRequest::FullDownload(download) => {
+                    let data = ready!(download.poll_unpin(cx))?;
+                    *self.full_download = Some(data);
+                    // Start over serving the group from the downloaded archive.
+                    self.request = None;
+                }
+                GroupRequest::Downloaded => {
+                    return Poll::Ready(Some(Err(HttpReaderError::UnexpectedEnd)))
                 }
-                Some(Err(err)) => return Poll::Ready(Some(Err(err))),
-                None => return Poll::Ready(Some(Err(HttpReaderError::UnexpectedEnd))),
             }
         }
     }
 
-    fn adjacent_reads(chunks: &[ChunkOffset]) -> usize {
-        chunks
-            .windows(2)
-            .take_while(|p| (p[0].offset + p[0].size as u64) == p[1].offset)
-            .count()
-            + 1
+    fn range_request(&self, offset: u64, size: u64) -> Result<HttpRangeRequest, HttpReaderError> {
+        let request_builder = self
+            .request_builder
+            .try_clone()
+            .ok_or(HttpReaderError::RequestNotClonable)?;
+        Ok(HttpRangeRequest::new(request_builder, offset, size)
+            .retry(self.retry_count, self.retry_policy)
+            .transport_compression(self.transport_compression)
+            .token_provider(self.token_provider.cloned()))
     }
 }
 
@@ -156,6 +527,9 @@ impl ArchiveReader for HttpReader {
     type Error = HttpReaderError;
 
     async fn read_at(&mut self, offset: u64, size: usize) -> Result<Bytes, HttpReaderError> {
+        if self.full_download.is_some() {
+            return slice_full_download(&self.full_download().await?, offset, size);
+        }
         let request = HttpRangeRequest::new(
             self.request_builder
                 .try_clone()
@@ -163,9 +537,16 @@ impl ArchiveReader for HttpReader {
             offset,
             size as u64,
         )
-        .retry(self.retry_count, self.retry_delay);
+        .retry(self.retry_count, self.retry_policy)
+        .transport_compression(self.transport_compression)
+        .token_provider(self.token_provider.clone());
 
-        let mut res = request.single().await?;
+        let mut res = match request.single().await {
+            Err(HttpReaderError::RangeNotSupported) if self.full_download_fallback => {
+                return slice_full_download(&self.full_download().await?, offset, size);
+            }
+            result => result?,
+        };
         if res.len() >= size {
             // Truncate the response if bigger than requested size
             Ok(res.split_to(size))
@@ -182,20 +563,139 @@ impl ArchiveReader for HttpReader {
     ) -> Pin<Box<dyn Stream<Item = Result<Bytes, HttpReaderError>> + Send + 'a>> {
         Box::pin(self.read_chunk_stream(chunks))
     }
+
+    async fn read_tail(&mut self, size: usize) -> Result<Option<Bytes>, HttpReaderError> {
+        if let Some(data) = &self.full_download {
+            if data.len() < size {
+                return Err(HttpReaderError::UnexpectedEnd);
+            }
+            return Ok(Some(data.slice(data.len() - size..)));
+        }
+        let request = HttpRangeRequest::suffix(
+            self.request_builder
+                .try_clone()
+                .ok_or(HttpReaderError::RequestNotClonable)?,
+            size as u64,
+        )
+        .retry(self.retry_count, self.retry_policy)
+        .transport_compression(self.transport_compression)
+        .token_provider(self.token_provider.clone());
+        let res = match request.single().await {
+            // Server not supporting suffix ranges, read the tail by its absolute offset.
+            Err(HttpReaderError::UnexpectedStatus(status)) if suffix_range_rejected(status) => {
+                log::warn!(
+                    "server rejected suffix range ({}), requesting the archive size",
+                    status
+                );
+                let archive_size = HttpRangeRequest::new(
+                    self.request_builder
+                        .try_clone()
+                        .ok_or(HttpReaderError::RequestNotClonable)?,
+                    0,
+                    0,
+                )
+                .retry(self.retry_count, self.retry_policy)
+                .transport_compression(self.transport_compression)
+                .token_provider(self.token_provider.clone())
+                .content_length()
+                .await?;
+                let offset = archive_size
+                    .checked_sub(size as u64)
+                    .ok_or(HttpReaderError::UnexpectedEnd)?;
+                return self.read_at(offset, size).await.map(Some);
+            }
+            result => result?,
+        };
+        if res.len() < size {
+            return Err(HttpReaderError::UnexpectedEnd);
+        }
+        // Keep only the tail if the server ignored the range.
+        Ok(Some(res.slice(res.len() - size..)))
+    }
+
+    /// Start fetching the first group of adjacent chunks in the background.
+    ///
+    /// The data is kept until the next call to `read_chunks`, which uses it if the
+    /// prefetched range covers one of its groups of adjacent chunks. Any earlier prefetch
+    /// is cancelled.
+    async fn prefetch(&mut self, chunks: &[ChunkOffset]) {
+        self.prefetch = None;
+        if chunks.is_empty() || self.full_download.is_some() {
+            return;
+        }
+        let Some(request_builder) = self.request_builder.try_clone() else {
+            return;
+        };
+        let offset = chunks[0].offset;
+        let size = chunks[adjacent_reads(chunks) - 1].end() - offset;
+        if size == 0 {
+            return;
+        }
+        let request = HttpRangeRequest::new(request_builder, offset, size)
+            .retry(self.retry_count, self.retry_policy)
+            .transport_compression(self.transport_compression)
+            .token_provider(self.token_provider.clone());
+        self.prefetch = Some(Prefetch {
+            offset,
+            size,
+            task: tokio::spawn(request.single()),
+        });
+    }
 }
 
 #[derive(Debug)]
 pub enum HttpReaderError {
     UnexpectedEnd,
     RequestNotClonable,
+    Unauthorized,
+    UnexpectedStatus(StatusCode),
+    RangeNotSupported,
+    /// The server did not tell the size of the archive.
+    UnknownSize,
+    ContentEncoding(String),
     Http(reqwest::Error),
+    /// Error returned by a middleware of the client.
+    #[cfg(feature = "reqwest-middleware")]
+    Middleware(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl HttpReaderError {
+    /// Returns true if the error is likely temporary and the request worth retrying.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            // A fresh token might be all that is needed.
+            HttpReaderError::Unauthorized => true,
+            HttpReaderError::UnexpectedStatus(status) => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            HttpReaderError::Http(err) => {
+                err.is_timeout() || err.is_connect() || err.is_request() || err.is_body()
+            }
+            HttpReaderError::UnexpectedEnd
+            | HttpReaderError::RequestNotClonable
+            | HttpReaderError::RangeNotSupported
+            | HttpReaderError::UnknownSize
+            | HttpReaderError::ContentEncoding(_) => false,
+            // Up to the middleware to retry if it makes sense.
+            #[cfg(feature = "reqwest-middleware")]
+            HttpReaderError::Middleware(_) => false,
+        }
+    }
 }
 
 impl std::error::Error for HttpReaderError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             HttpReaderError::Http(err) => Some(err),
-            HttpReaderError::UnexpectedEnd | HttpReaderError::RequestNotClonable => None,
+            #[cfg(feature = "reqwest-middleware")]
+            HttpReaderError::Middleware(err) => Some(err.as_ref()),
+            HttpReaderError::UnexpectedEnd
+            | HttpReaderError::RequestNotClonable
+            | HttpReaderError::Unauthorized
+            | HttpReaderError::UnexpectedStatus(_)
+            | HttpReaderError::RangeNotSupported
+            | HttpReaderError::UnknownSize
+            | HttpReaderError::ContentEncoding(_) => None,
         }
     }
 }
@@ -205,7 +705,16 @@ impl fmt::Display for HttpReaderError {
         match self {
             Self::UnexpectedEnd => write!(f, "unexpected end"),
             Self::RequestNotClonable => write!(f, "request is not clonable"),
+            Self::Unauthorized => write!(f, "unauthorized"),
+            Self::UnexpectedStatus(status) => write!(f, "unexpected status {}", status),
+            Self::RangeNotSupported => write!(f, "server does not support range requests"),
+            Self::UnknownSize => write!(f, "unknown size of archive"),
+            Self::ContentEncoding(encoding) => {
+                write!(f, "unexpected content encoding {}", encoding)
+            }
             Self::Http(_) => write!(f, "http error"),
+            #[cfg(feature = "reqwest-middleware")]
+            Self::Middleware(_) => write!(f, "middleware error"),
         }
     }
 }
@@ -216,11 +725,24 @@ impl From<reqwest::Error> for HttpReaderError {
     }
 }
 
+#[cfg(feature = "reqwest-middleware")]
+impl From<reqwest_middleware::Error> for HttpReaderError {
+    fn from(e: reqwest_middleware::Error) -> Self {
+        match e {
+            reqwest_middleware::Error::Reqwest(err) => Self::Http(err),
+            reqwest_middleware::Error::Middleware(err) => Self::Middleware(err.into()),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use http_body_util::Full;
-    use hyper::{server::conn::http1, service::service_fn};
+    use hyper::{
+        server::conn::{http1, http2},
+        service::service_fn,
+    };
     use tokio::net::TcpListener;
 
     async fn new_server(listener: TcpListener, data: Vec<u8>) {
@@ -237,11 +759,18 @@ mod tests {
                         .expect("range header")
                         .to_str()
                         .unwrap()[6..]
-                        .split('-')
-                        .map(|s| s.parse::<u64>().unwrap())
-                        .collect::<Vec<u64>>();
-                    let start = range[0] as usize;
-                    let end = std::cmp::min(range[1] as usize + 1, data.len());
+                        .to_string();
+                    let (start, end) = match range.strip_prefix('-') {
+                        // Suffix range, the last n bytes.
+                        Some(n) => (data.len().saturating_sub(n.parse().unwrap()), data.len()),
+                        None => {
+                            let range = range
+                                .split('-')
+                                .map(|s| s.parse::<usize>().unwrap())
+                                .collect::<Vec<usize>>();
+                            (range[0], std::cmp::min(range[1] + 1, data.len()))
+                        }
+                    };
                     let data = data[start..end].to_vec();
                     async move {
                         Ok::<_, hyper::Error>(hyper::Response::new(Full::new(
@@ -254,42 +783,288 @@ mod tests {
             .unwrap();
     }
 
-    fn new_reader(port: u16) -> HttpReader {
-        HttpReader::from_url(Url::parse(&format!("http://127.0.0.1:{}", port)).unwrap())
+    // Serve data but only to requests carrying the expected Authorization header.
+    async fn new_auth_server(listener: TcpListener, data: Vec<u8>, authorization: &'static str) {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let io = hyper_util::rt::TokioIo::new(stream);
+            let data = data.clone();
+            tokio::spawn(http1::Builder::new().serve_connection(
+                io,
+                service_fn(move |req| {
+                    let authorized = req
+                        .headers()
+                        .get("authorization")
+                        .is_some_and(|value| value == authorization);
+                    let range = req
+                        .headers()
+                        .get("range")
+                        .expect("range header")
+                        .to_str()
+                        .unwrap()[6..]
+                        .split('-')
+                        .map(|s| s.parse::<usize>().unwrap())
+                        .collect::<Vec<usize>>();
+                    let data = data[range[0]..std::cmp::min(range[1] + 1, data.len())].to_vec();
+                    async move {
+                        let mut response = hyper::Response::new(Full::new(
+                            hyper::body::Bytes::from(if authorized { data } else { vec![] }),
+                        ));
+                        if !authorized {
+                            *response.status_mut() = hyper::StatusCode::UNAUTHORIZED;
+                        }
+                        Ok::<_, hyper::Error>(response)
+                    }
+                }),
+            ));
+        }
     }
 
-    async fn new_listener() -> (TcpListener, u16) {
-        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
-        let port = listener.local_addr().unwrap().port();
-        (listener, port)
+    // Respond with the given status to the first `failures` requests, then serve data.
+    // Returns the total number of requests served.
+    async fn new_failing_server(
+        listener: TcpListener,
+        data: Vec<u8>,
+        status: hyper::StatusCode,
+        failures: u32,
+        requests: Arc<std::sync::atomic::AtomicU32>,
+    ) {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let io = hyper_util::rt::TokioIo::new(stream);
+            let data = data.clone();
+            let requests = requests.clone();
+            tokio::spawn(http1::Builder::new().serve_connection(
+                io,
+                service_fn(move |_req| {
+                    let n = requests.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    let data = data.clone();
+                    async move {
+                        if n < failures {
+                            let mut response =
+                                hyper::Response::new(Full::new(hyper::body::Bytes::from(vec![])));
+                            *response.status_mut() = status;
+                            Ok::<_, hyper::Error>(response)
+                        } else {
+                            Ok(hyper::Response::new(Full::new(hyper::body::Bytes::from(
+                                data,
+                            ))))
+                        }
+                    }
+                }),
+            ));
+        }
     }
 
-    #[test]
-    fn one_adjacent_reads() {
-        let chunks = [ChunkOffset::new(0, 1), ChunkOffset::new(10, 1)];
-        assert_eq!(ChunkReader::adjacent_reads(&chunks[..]), 1);
+    // Serve the requested range of data after an artificial delay, counting the requests.
+    async fn new_delayed_server(
+        listener: TcpListener,
+        data: Vec<u8>,
+        delay: Duration,
+        requests: Arc<std::sync::atomic::AtomicU32>,
+    ) {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let io = hyper_util::rt::TokioIo::new(stream);
+            let data = data.clone();
+            let requests = requests.clone();
+            tokio::spawn(http1::Builder::new().serve_connection(
+                io,
+                service_fn(move |req| {
+                    requests.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    let range = req
+                        .headers()
+                        .get("range")
+                        .expect("range header")
+                        .to_str()
+                        .unwrap()[6..]
+                        .split('-')
+                        .map(|s| s.parse::<usize>().unwrap())
+                        .collect::<Vec<usize>>();
+                    let data = data[range[0]..std::cmp::min(range[1] + 1, data.len())].to_vec();
+                    async move {
+                        tokio::time::sleep(delay).await;
+                        Ok::<_, hyper::Error>(hyper::Response::new(Full::new(
+                            hyper::body::Bytes::from(data),
+                        )))
+                    }
+                }),
+            ));
+        }
     }
 
-    #[test]
-    fn two_adjacent_reads() {
-        let chunks = [
-            ChunkOffset::new(0, 1),
-            ChunkOffset::new(1, 3),
-            ChunkOffset::new(10, 3),
-        ];
-        assert_eq!(ChunkReader::adjacent_reads(&chunks[..]), 2);
+    // Serve the requested range of data over HTTP/2 after an artificial delay, counting the
+    // accepted connections.
+    async fn new_delayed_http2_server(
+        listener: TcpListener,
+        data: Vec<u8>,
+        delay: Duration,
+        accepts: Arc<std::sync::atomic::AtomicU32>,
+    ) {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            accepts.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let io = hyper_util::rt::TokioIo::new(stream);
+            let data = data.clone();
+            tokio::spawn(
+                http2::Builder::new(hyper_util::rt::TokioExecutor::new()).serve_connection(
+                    io,
+                    service_fn(move |req| {
+                        let range = req
+                            .headers()
+                            .get("range")
+                            .expect("range header")
+                            .to_str()
+                            .unwrap()[6..]
+                            .split('-')
+                            .map(|s| s.parse::<usize>().unwrap())
+                            .collect::<Vec<usize>>();
+                        let data = data[range[0]..std::cmp::min(range[1] + 1, data.len())].to_vec();
+                        async move {
+                            tokio::time::sleep(delay).await;
+                            Ok::<_, hyper::Error>(hyper::Response::new(Full::new(
+                                hyper::body::Bytes::from(data),
+                            )))
+                        }
+                    }),
+                ),
+            );
+        }
     }
 
-    #[test]
-    fn multiple_adjacent_reads() {
-        let chunks = [
-            ChunkOffset::new(0, 1),
-            ChunkOffset::new(1, 3),
-            ChunkOffset::new(4, 3),
-            ChunkOffset::new(7, 3),
-            ChunkOffset::new(50, 3),
-        ];
-        assert_eq!(ChunkReader::adjacent_reads(&chunks[..]), 4);
+    // Serve the whole data to every request, ignoring any Range header, counting the requests.
+    async fn new_range_ignoring_server(
+        listener: TcpListener,
+        data: Vec<u8>,
+        requests: Arc<std::sync::atomic::AtomicU32>,
+    ) {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let io = hyper_util::rt::TokioIo::new(stream);
+            let data = data.clone();
+            let requests = requests.clone();
+            tokio::spawn(http1::Builder::new().serve_connection(
+                io,
+                service_fn(move |_req| {
+                    requests.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    let data = data.clone();
+                    async move {
+                        Ok::<_, hyper::Error>(hyper::Response::new(Full::new(
+                            hyper::body::Bytes::from(data),
+                        )))
+                    }
+                }),
+            ));
+        }
+    }
+
+    // Serve absolute ranges and HEAD requests, but reject suffix ranges with status. Requests
+    // are recorded with their method, range and accept encoding.
+    async fn new_suffix_rejecting_server(
+        listener: TcpListener,
+        data: Vec<u8>,
+        status: hyper::StatusCode,
+        requests: Arc<std::sync::Mutex<Vec<String>>>,
+    ) {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let io = hyper_util::rt::TokioIo::new(stream);
+            let data = data.clone();
+            let requests = requests.clone();
+            tokio::spawn(http1::Builder::new().serve_connection(
+                io,
+                service_fn(move |req: hyper::Request<hyper::body::Incoming>| {
+                    let range = req
+                        .headers()
+                        .get("range")
+                        .map(|range| range.to_str().unwrap()[6..].to_string());
+                    let encoding = req
+                        .headers()
+                        .get("accept-encoding")
+                        .map(|encoding| encoding.to_str().unwrap().to_string());
+                    requests.lock().unwrap().push(format!(
+                        "{} {:?} {:?}",
+                        req.method(),
+                        range,
+                        encoding
+                    ));
+                    let mut response = hyper::Response::new(Full::new(hyper::body::Bytes::new()));
+                    match range {
+                        Some(range) if range.starts_with('-') => *response.status_mut() = status,
+                        Some(range) => {
+                            let range = range
+                                .split('-')
+                                .map(|s| s.parse::<usize>().unwrap())
+                                .collect::<Vec<usize>>();
+                            *response.body_mut() = Full::new(hyper::body::Bytes::from(
+                                data[range[0]..range[1] + 1].to_vec(),
+                            ));
+                            *response.status_mut() = hyper::StatusCode::PARTIAL_CONTENT;
+                        }
+                        None => {
+                            response.headers_mut().insert(
+                                hyper::header::CONTENT_LENGTH,
+                                data.len().to_string().parse().unwrap(),
+                            );
+                        }
+                    }
+                    async move { Ok::<_, hyper::Error>(response) }
+                }),
+            ));
+        }
+    }
+
+    // Serve the requested range like a proxy compressing responses on the wire, unless the
+    // request asks for the identity encoding.
+    async fn new_compressing_server(listener: TcpListener, data: Vec<u8>) {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let io = hyper_util::rt::TokioIo::new(stream);
+            let data = data.clone();
+            tokio::spawn(http1::Builder::new().serve_connection(
+                io,
+                service_fn(move |req: hyper::Request<hyper::body::Incoming>| {
+                    let range = req.headers()["range"].to_str().unwrap()[6..]
+                        .split('-')
+                        .map(|s| s.parse::<usize>().unwrap())
+                        .collect::<Vec<usize>>();
+                    let mut body = data[range[0]..range[1] + 1].to_vec();
+                    let identity = req
+                        .headers()
+                        .get("accept-encoding")
+                        .is_some_and(|encoding| encoding == "identity");
+                    let mut response = hyper::Response::builder();
+                    if !identity {
+                        // Stand-in for the gzipped body, what matters is that it differs.
+                        body.reverse();
+                        response = response.header("content-encoding", "gzip");
+                    }
+                    let response = response
+                        .body(Full::new(hyper::body::Bytes::from(body)))
+                        .unwrap();
+                    async move { Ok::<_, hyper::Error>(response) }
+                }),
+            ));
+        }
+    }
+
+    // Token provider returning a new token on each call, starting at "Bearer 1".
+    fn counting_token_provider() -> TokenProvider {
+        let counter = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        Arc::new(move || {
+            let n = counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+            Box::pin(async move { format!("Bearer {}", n) })
+        })
+    }
+
+    fn new_reader(port: u16) -> HttpReader {
+        HttpReader::from_url(Url::parse(&format!("http://127.0.0.1:{}", port)).unwrap())
+    }
+
+    async fn new_listener() -> (TcpListener, u16) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        (listener, port)
     }
 
     #[test]
@@ -297,13 +1072,133 @@ mod tests {
         let reader = HttpReader::from_url(Url::parse("http://localhost/file").unwrap())
             .retries(3)
             .retry_delay(Duration::from_secs(10));
-        assert_eq!(reader.retry_delay, Duration::from_secs(10));
+        assert_eq!(
+            reader.retry_policy,
+            RetryPolicy::Fixed(Duration::from_secs(10))
+        );
         assert_eq!(reader.retry_count, 3);
-        let request = reader.request_builder.build().unwrap();
+        let request = match reader.request_builder {
+            Request::Reqwest(request) => request.build().unwrap(),
+            #[cfg(feature = "reqwest-middleware")]
+            Request::Middleware(_) => unreachable!(),
+        };
         assert_eq!(request.url(), &Url::parse("http://localhost/file").unwrap());
         assert_eq!(request.method(), reqwest::Method::GET);
     }
 
+    #[test]
+    fn retry_policy_delay() {
+        let fixed = RetryPolicy::Fixed(Duration::from_millis(100));
+        assert_eq!(fixed.delay(0), Duration::from_millis(100));
+        assert_eq!(fixed.delay(5), Duration::from_millis(100));
+
+        let exponential = RetryPolicy::Exponential {
+            base: Duration::from_millis(100),
+            max: Duration::from_secs(1),
+            jitter: false,
+        };
+        assert_eq!(exponential.delay(0), Duration::from_millis(100));
+        assert_eq!(exponential.delay(1), Duration::from_millis(200));
+        assert_eq!(exponential.delay(3), Duration::from_millis(800));
+        assert_eq!(exponential.delay(4), Duration::from_secs(1));
+        assert_eq!(exponential.delay(100), Duration::from_secs(1));
+
+        let jitter = RetryPolicy::Exponential {
+            base: Duration::from_millis(100),
+            max: Duration::from_secs(1),
+            jitter: true,
+        };
+        for attempt in 0..10 {
+            let delay = jitter.delay(attempt);
+            let full = exponential.delay(attempt);
+            assert!(delay >= full / 2 && delay <= full, "{:?}", delay);
+        }
+    }
+
+    #[tokio::test]
+    async fn read_tail() {
+        let data: Vec<u8> = (0..100).collect();
+        let (listener, port) = new_listener().await;
+        let server = new_server(listener, data.clone());
+        let mut reader = new_reader(port);
+        let read = reader.read_tail(10);
+        tokio::select! {
+            _ = server => panic!("server ended"),
+            tail = read => assert_eq!(tail.unwrap().unwrap(), &data[90..]),
+        };
+    }
+
+    #[tokio::test]
+    async fn read_tail_suffix_range_rejected() {
+        let data: Vec<u8> = (0..100).collect();
+        let (listener, port) = new_listener().await;
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+        tokio::spawn(new_suffix_rejecting_server(
+            listener,
+            data.clone(),
+            hyper::StatusCode::RANGE_NOT_SATISFIABLE,
+            requests.clone(),
+        ));
+        let mut reader = new_reader(port);
+        assert_eq!(reader.read_tail(10).await.unwrap().unwrap(), &data[90..]);
+        // Falls back to the size from a HEAD request and an absolute range.
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![
+                "GET Some(\"-10\") Some(\"identity\")".to_string(),
+                "HEAD None Some(\"identity\")".to_string(),
+                "GET Some(\"90-99\") Some(\"identity\")".to_string(),
+            ]
+        );
+        assert!(matches!(
+            reader.read_tail(101).await,
+            Err(HttpReaderError::UnexpectedEnd)
+        ));
+    }
+
+    #[tokio::test]
+    async fn read_tail_suffix_range_rejected_transport_compression() {
+        let data: Vec<u8> = (0..100).collect();
+        let (listener, port) = new_listener().await;
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+        tokio::spawn(new_suffix_rejecting_server(
+            listener,
+            data.clone(),
+            hyper::StatusCode::RANGE_NOT_SATISFIABLE,
+            requests.clone(),
+        ));
+        let mut reader = new_reader(port).allow_transport_compression(true);
+        assert_eq!(reader.read_tail(10).await.unwrap().unwrap(), &data[90..]);
+        // No request of the fallback asks for the identity encoding.
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert!(
+            requests.iter().all(|request| !request.contains("identity")),
+            "{:?}",
+            requests
+        );
+    }
+
+    #[tokio::test]
+    async fn read_tail_suffix_range_other_error() {
+        let (listener, port) = new_listener().await;
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+        tokio::spawn(new_suffix_rejecting_server(
+            listener,
+            (0..100).collect(),
+            hyper::StatusCode::NOT_FOUND,
+            requests.clone(),
+        ));
+        let mut reader = new_reader(port);
+        assert!(matches!(
+            reader.read_tail(10).await,
+            Err(HttpReaderError::UnexpectedStatus(
+                hyper::StatusCode::NOT_FOUND
+            ))
+        ));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
     #[tokio::test]
     async fn read_single() {
         let expect = vec![1, 2, 3, 4, 5, 6];
@@ -338,7 +1233,7 @@ mod tests {
         let read = reader.read_at(1, 0);
         tokio::select! {
             _ = server => panic!("server ended"),
-            data = read => assert_eq!(&data.unwrap()[..], &[]),
+            data = read => assert_eq!(&data.unwrap()[..], &[0u8; 0]),
         };
     }
 
@@ -410,6 +1305,398 @@ mod tests {
         };
     }
 
+    #[tokio::test]
+    async fn read_single_refresh_token() {
+        let (listener, port) = new_listener().await;
+        let server = new_auth_server(listener, vec![1, 2, 3, 4, 5, 6], "Bearer 2");
+        let mut reader = new_reader(port)
+            .token_provider(counting_token_provider())
+            .retries(1);
+        let read = reader.read_at(1, 3);
+        tokio::select! {
+            _ = server => panic!("server ended"),
+            data = read => assert_eq!(&data.unwrap()[..], &[2, 3, 4]),
+        };
+    }
+
+    #[tokio::test]
+    async fn read_chunks_refresh_token() {
+        let (listener, port) = new_listener().await;
+        let server = new_auth_server(listener, (1..=20).collect(), "Bearer 2");
+        let mut reader = new_reader(port)
+            .token_provider(counting_token_provider())
+            .retries(1);
+        let chunks = vec![ChunkOffset { offset: 2, size: 4 }];
+        let stream = reader.read_chunks(chunks).map(|v| v.expect("item"));
+        tokio::select! {
+            _ = server => panic!("server ended"),
+            chunks = stream.collect::<Vec<Bytes>>() => assert_eq!(chunks, vec![Bytes::from(vec![3, 4, 5, 6])]),
+        };
+    }
+
+    #[tokio::test]
+    async fn unauthorized_without_retries() {
+        let (listener, port) = new_listener().await;
+        let server = new_auth_server(listener, vec![1, 2, 3, 4, 5, 6], "Bearer 2");
+        let mut reader = new_reader(port).token_provider(counting_token_provider());
+        let read = reader.read_at(0, 6);
+        tokio::select! {
+            _ = server => panic!("server ended"),
+            data = read => assert!(matches!(data, Err(HttpReaderError::Unauthorized))),
+        };
+    }
+
+    #[tokio::test]
+    async fn retry_server_error() {
+        let (listener, port) = new_listener().await;
+        let requests = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let server = new_failing_server(
+            listener,
+            vec![1, 2, 3, 4, 5, 6],
+            hyper::StatusCode::SERVICE_UNAVAILABLE,
+            2,
+            requests.clone(),
+        );
+        let mut reader = new_reader(port)
+            .retries(3)
+            .retry_backoff(RetryPolicy::Exponential {
+                base: Duration::from_millis(1),
+                max: Duration::from_millis(10),
+                jitter: true,
+            });
+        let chunks = vec![ChunkOffset { offset: 0, size: 6 }];
+        let stream = reader.read_chunks(chunks).map(|v| v.expect("item"));
+        tokio::select! {
+            _ = server => panic!("server ended"),
+            chunks = stream.collect::<Vec<Bytes>>() => assert_eq!(chunks, vec![Bytes::from(vec![1, 2, 3, 4, 5, 6])]),
+        };
+        assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn no_retry_on_not_found() {
+        let (listener, port) = new_listener().await;
+        let requests = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let server = new_failing_server(
+            listener,
+            vec![1, 2, 3, 4, 5, 6],
+            hyper::StatusCode::NOT_FOUND,
+            1,
+            requests.clone(),
+        );
+        let mut reader = new_reader(port).retries(3);
+        let read = reader.read_at(0, 6);
+        tokio::select! {
+            _ = server => panic!("server ended"),
+            data = read => assert!(matches!(
+                data,
+                Err(HttpReaderError::UnexpectedStatus(hyper::StatusCode::NOT_FOUND))
+            )),
+        };
+        assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    // Middleware counting the requests passing through it.
+    #[cfg(feature = "reqwest-middleware")]
+    struct CountingMiddleware(Arc<std::sync::atomic::AtomicU32>);
+
+    #[cfg(feature = "reqwest-middleware")]
+    #[async_trait]
+    impl reqwest_middleware::Middleware for CountingMiddleware {
+        async fn handle(
+            &self,
+            req: reqwest::Request,
+            extensions: &mut hyper::http::Extensions,
+            next: reqwest_middleware::Next<'_>,
+        ) -> reqwest_middleware::Result<reqwest::Response> {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            next.run(req, extensions).await
+        }
+    }
+
+    #[cfg(feature = "reqwest-middleware")]
+    #[tokio::test]
+    async fn middleware_client_retry() {
+        let (listener, port) = new_listener().await;
+        let requests = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let server = new_failing_server(
+            listener,
+            vec![1, 2, 3, 4, 5, 6],
+            hyper::StatusCode::SERVICE_UNAVAILABLE,
+            2,
+            requests.clone(),
+        );
+        let handled = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+            .with(CountingMiddleware(handled.clone()))
+            .build();
+        let mut reader = HttpReader::from_middleware_client_url(
+            &client,
+            Url::parse(&format!("http://127.0.0.1:{}", port)).unwrap(),
+        )
+        .retries(3)
+        .retry_delay(Duration::from_millis(1));
+        let chunks = vec![ChunkOffset { offset: 0, size: 6 }];
+        let stream = reader.read_chunks(chunks).map(|v| v.expect("item"));
+        tokio::select! {
+            _ = server => panic!("server ended"),
+            chunks = stream.collect::<Vec<Bytes>>() => assert_eq!(chunks, vec![Bytes::from(vec![1, 2, 3, 4, 5, 6])]),
+        };
+        // Every retry is a new request passing through the middleware.
+        assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 3);
+        assert_eq!(handled.load(std::sync::atomic::Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn range_not_supported() {
+        let (listener, port) = new_listener().await;
+        let requests = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        tokio::spawn(new_range_ignoring_server(
+            listener,
+            (0..100).collect(),
+            requests.clone(),
+        ));
+        let mut reader = new_reader(port);
+        assert!(matches!(
+            reader.read_at(10, 5).await,
+            Err(HttpReaderError::RangeNotSupported)
+        ));
+        let chunks = vec![ChunkOffset::new(10, 5)];
+        let mut stream = reader.read_chunks(chunks);
+        assert!(matches!(
+            stream.next().await,
+            Some(Err(HttpReaderError::RangeNotSupported))
+        ));
+    }
+
+    #[tokio::test]
+    async fn identity_encoding() {
+        let data: Vec<u8> = (0..100).collect();
+        let (listener, port) = new_listener().await;
+        tokio::spawn(new_compressing_server(listener, data.clone()));
+        let mut reader = new_reader(port);
+        assert_eq!(reader.read_at(10, 5).await.unwrap(), &data[10..15]);
+        let read: Vec<Bytes> = reader
+            .read_chunks(vec![ChunkOffset::new(20, 5), ChunkOffset::new(40, 10)])
+            .map(|result| result.unwrap())
+            .collect()
+            .await;
+        assert_eq!(read, vec![&data[20..25], &data[40..50]]);
+    }
+
+    #[tokio::test]
+    async fn transport_compression_not_decoded() {
+        let (listener, port) = new_listener().await;
+        tokio::spawn(new_compressing_server(listener, (0..100).collect()));
+        let mut reader = new_reader(port).allow_transport_compression(true);
+        assert!(matches!(
+            reader.read_at(10, 5).await,
+            Err(HttpReaderError::ContentEncoding(encoding)) if encoding == "gzip"
+        ));
+        let mut stream = reader.read_chunks(vec![ChunkOffset::new(10, 5)]);
+        assert!(matches!(
+            stream.next().await,
+            Some(Err(HttpReaderError::ContentEncoding(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn full_download_fallback_read_at() {
+        let data: Vec<u8> = (0..100).collect();
+        let (listener, port) = new_listener().await;
+        let requests = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        tokio::spawn(new_range_ignoring_server(
+            listener,
+            data.clone(),
+            requests.clone(),
+        ));
+        let mut reader = new_reader(port).allow_full_download_fallback(true);
+        assert_eq!(reader.read_at(10, 5).await.unwrap(), &data[10..15]);
+        // Everything else is served from the downloaded archive.
+        let chunks = vec![ChunkOffset::new(20, 5), ChunkOffset::new(40, 10)];
+        let read: Vec<Bytes> = reader
+            .read_chunks(chunks)
+            .map(|result| result.unwrap())
+            .collect()
+            .await;
+        assert_eq!(read, vec![&data[20..25], &data[40..50]]);
+        assert_eq!(reader.read_tail(10).await.unwrap().unwrap(), &data[90..100]);
+        assert!(matches!(
+            reader.read_at(95, 10).await,
+            Err(HttpReaderError::UnexpectedEnd)
+        ));
+        assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn full_download_fallback_read_chunks() {
+        let data: Vec<u8> = (0..100).collect();
+        let (listener, port) = new_listener().await;
+        let requests = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        tokio::spawn(new_range_ignoring_server(
+            listener,
+            data.clone(),
+            requests.clone(),
+        ));
+        let mut reader = new_reader(port).allow_full_download_fallback(true);
+        let chunks = vec![
+            ChunkOffset::new(10, 5),
+            ChunkOffset::new(15, 5),
+            ChunkOffset::new(60, 20),
+        ];
+        let read: Vec<Bytes> = reader
+            .read_chunks(chunks)
+            .map(|result| result.unwrap())
+            .collect()
+            .await;
+        assert_eq!(read, vec![&data[10..15], &data[15..20], &data[60..80]]);
+        assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn prefetch_hides_request_latency() {
+        const DELAY: Duration = Duration::from_millis(300);
+        let data: Vec<u8> = (0..100).collect();
+        let (listener, port) = new_listener().await;
+        let requests = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        tokio::spawn(new_delayed_server(
+            listener,
+            data.clone(),
+            DELAY,
+            requests.clone(),
+        ));
+        let chunks = vec![ChunkOffset::new(10, 5), ChunkOffset::new(15, 5)];
+        let expect = vec![
+            Bytes::from(data[10..15].to_vec()),
+            Bytes::from(data[15..20].to_vec()),
+        ];
+        let mut reader = new_reader(port);
+
+        let start = std::time::Instant::now();
+        let read: Vec<Bytes> = reader
+            .read_chunks(chunks.clone())
+            .map(|v| v.expect("item"))
+            .collect()
+            .await;
+        let without_prefetch = start.elapsed();
+        assert_eq!(read, expect);
+
+        reader.prefetch(&chunks).await;
+        // Do something else while the chunks are fetched.
+        tokio::time::sleep(DELAY + Duration::from_millis(100)).await;
+        let start = std::time::Instant::now();
+        let read: Vec<Bytes> = reader
+            .read_chunks(chunks)
+            .map(|v| v.expect("item"))
+            .collect()
+            .await;
+        let with_prefetch = start.elapsed();
+        assert_eq!(read, expect);
+
+        assert!(without_prefetch >= DELAY);
+        assert!(
+            with_prefetch < DELAY,
+            "read took {:?} with prefetch, {:?} without",
+            with_prefetch,
+            without_prefetch
+        );
+        assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn prefetch_other_chunks() {
+        let data: Vec<u8> = (0..100).collect();
+        let (listener, port) = new_listener().await;
+        let requests = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        tokio::spawn(new_delayed_server(
+            listener,
+            data.clone(),
+            Duration::from_millis(0),
+            requests.clone(),
+        ));
+        let mut reader = new_reader(port);
+        // Prefetching covers the second group only.
+        reader
+            .prefetch(&[ChunkOffset::new(50, 10), ChunkOffset::new(60, 10)])
+            .await;
+        let read: Vec<Bytes> = reader
+            .read_chunks(vec![ChunkOffset::new(0, 5), ChunkOffset::new(55, 10)])
+            .map(|v| v.expect("item"))
+            .collect()
+            .await;
+        assert_eq!(
+            read,
+            vec![
+                Bytes::from(data[0..5].to_vec()),
+                Bytes::from(data[55..65].to_vec())
+            ]
+        );
+        assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        // The prefetched data is not kept for later reads.
+        reader.prefetch(&[ChunkOffset::new(0, 10)]).await;
+        let _ = reader.read_chunks(vec![]).collect::<Vec<_>>().await;
+        let read: Vec<Bytes> = reader
+            .read_chunks(vec![ChunkOffset::new(0, 10)])
+            .map(|v| v.expect("item"))
+            .collect()
+            .await;
+        assert_eq!(read, vec![Bytes::from(data[0..10].to_vec())]);
+    }
+
+    #[test]
+    fn client_config() {
+        let config = HttpClientConfig::default()
+            .http2(true)
+            .connect_timeout(Duration::from_secs(3))
+            .pool_idle_timeout(Duration::from_secs(5))
+            .pool_max_idle_per_host(2)
+            .tcp_keepalive(Duration::from_secs(30));
+        assert!(config.http2);
+        assert_eq!(config.connect_timeout, Some(Duration::from_secs(3)));
+        assert_eq!(config.pool_idle_timeout, Some(Duration::from_secs(5)));
+        assert_eq!(config.pool_max_idle_per_host, Some(2));
+        assert_eq!(config.tcp_keepalive, Some(Duration::from_secs(30)));
+        config.build().unwrap();
+    }
+
+    #[tokio::test]
+    async fn http2_multiplex_scattered_reads() {
+        const READS: u64 = 8;
+        const DELAY: Duration = Duration::from_millis(200);
+        let (listener, port) = new_listener().await;
+        let data: Vec<u8> = (0..READS as usize * 1000).map(|v| v as u8).collect();
+        let accepts = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let server = new_delayed_http2_server(listener, data.clone(), DELAY, accepts.clone());
+        let client = HttpClientConfig::default().http2(true).build().unwrap();
+        let url = Url::parse(&format!("http://127.0.0.1:{}", port)).unwrap();
+        let reads = async {
+            // Establish the connection before the concurrent reads.
+            HttpReader::from_client_url(&client, url.clone())
+                .read_at(0, 10)
+                .await
+                .unwrap();
+            let start = std::time::Instant::now();
+            let results = futures_util::future::join_all((0..READS).map(|i| {
+                let mut reader = HttpReader::from_client_url(&client, url.clone());
+                async move { reader.read_at(i * 1000 + 10, 100).await.unwrap() }
+            }))
+            .await;
+            (start.elapsed(), results)
+        };
+        let (elapsed, results) = tokio::select! {
+            _ = server => panic!("server ended"),
+            result = reads => result,
+        };
+        for (i, read) in results.iter().enumerate() {
+            let offset = i * 1000 + 10;
+            assert_eq!(&read[..], &data[offset..offset + 100]);
+        }
+        // All requests were multiplexed on a single connection and served in parallel.
+        assert_eq!(accepts.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert!(elapsed < DELAY * READS as u32 / 2, "took {:?}", elapsed);
+    }
+
     #[tokio::test]
     async fn connection_timeout() {
         let (listener, port) = new_listener().await;
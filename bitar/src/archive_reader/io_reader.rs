@@ -3,25 +3,103 @@ use bytes::{Bytes, BytesMut};
 use core::pin::Pin;
 use core::task::{Context, Poll};
 use futures_util::{ready, stream::Stream};
+use std::future::Future;
 use std::io;
+use std::time::Duration;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, ReadBuf};
+use tokio::time::{sleep, Sleep};
 
-use crate::archive_reader::ArchiveReader;
+use crate::archive_reader::{coalesced_reads, ArchiveReader, RetryPolicy};
 use crate::ChunkOffset;
 
 /// Wrapper which implements ArchiveReader for any type which implements
 /// tokio AsyncRead and AsyncSeek.
-pub struct IoReader<T>(T);
+///
+/// Adjacent chunks are read using a single seek and read, to keep the number of
+/// syscalls down on storage with high per-operation latency (like NFS).
+pub struct IoReader<T> {
+    inner: T,
+    max_coalesce: usize,
+    retry_count: u32,
+    retry_policy: RetryPolicy,
+}
 
 impl<T> IoReader<T> {
+    /// Default max number of bytes read at once when coalescing adjacent chunks.
+    pub const DEFAULT_MAX_COALESCE: usize = 4 * 1024 * 1024;
+
     pub fn new(inner: T) -> Self {
-        Self(inner)
+        Self {
+            inner,
+            max_coalesce: Self::DEFAULT_MAX_COALESCE,
+            retry_count: 0,
+            retry_policy: RetryPolicy::default(),
+        }
+    }
+    /// Set the max number of bytes to read at once when coalescing adjacent chunks.
+    ///
+    /// Chunks bigger than this are still read as a whole. Set to 0 to read each chunk
+    /// separately.
+    #[must_use]
+    pub fn max_coalesce(mut self, size: usize) -> Self {
+        self.max_coalesce = size;
+        self
+    }
+    /// Set number of times to retry a read on failure.
+    ///
+    /// Meant for files on network filesystems (like SMB or NFS) occasionally failing reads.
+    /// Reads continue from where the failure occurred. Only transient errors (timeouts,
+    /// interrupted reads, broken pipes and I/O errors) are retried, other errors fail at once.
+    #[must_use]
+    pub fn retries(mut self, retry_count: u32) -> Self {
+        self.retry_count = retry_count;
+        self
+    }
+    /// Set a delay between attempts to read.
+    #[must_use]
+    pub fn retry_delay(mut self, retry_delay: Duration) -> Self {
+        self.retry_policy = RetryPolicy::Fixed(retry_delay);
+        self
+    }
+    /// Set the policy for how long to wait between attempts to read.
+    ///
+    /// Replaces any delay set by `retry_delay`.
+    #[must_use]
+    pub fn retry_backoff(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+}
+
+// Errors which may go away when reading again.
+fn is_transient(err: &io::Error) -> bool {
+    #[cfg(target_os = "linux")]
+    if rustix::io::Errno::from_io_error(err) == Some(rustix::io::Errno::IO) {
+        return true;
     }
+    matches!(
+        err.kind(),
+        io::ErrorKind::TimedOut | io::ErrorKind::Interrupted | io::ErrorKind::BrokenPipe
+    )
+}
+
+// Read from offset until buf holds size bytes.
+async fn read_into<T>(inner: &mut T, offset: u64, size: usize, buf: &mut BytesMut) -> io::Result<()>
+where
+    T: AsyncRead + AsyncSeek + Unpin,
+{
+    inner.seek(io::SeekFrom::Start(offset)).await?;
+    while buf.len() < size {
+        if inner.read_buf(buf).await? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+    }
+    Ok(())
 }
 
 impl<T> From<T> for IoReader<T> {
     fn from(inner: T) -> Self {
-        Self(inner)
+        Self::new(inner)
     }
 }
 
@@ -33,21 +111,43 @@ where
     type Error = io::Error;
 
     async fn read_at(&mut self, offset: u64, size: usize) -> Result<Bytes, io::Error> {
-        self.0.seek(io::SeekFrom::Start(offset)).await?;
         let mut buf = BytesMut::with_capacity(size);
-        while buf.len() < size {
-            if self.0.read_buf(&mut buf).await? == 0 {
-                return Err(io::ErrorKind::UnexpectedEof.into());
+        let mut retry_attempt = 0;
+        loop {
+            // Continue from what has been read so far.
+            let read_offset = offset + buf.len() as u64;
+            match read_into(&mut self.inner, read_offset, size, &mut buf).await {
+                Ok(()) => return Ok(buf.freeze()),
+                Err(err) if retry_attempt < self.retry_count && is_transient(&err) => {
+                    let delay = self.retry_policy.delay(retry_attempt);
+                    log::warn!("read failed (retrying in {:?}): {}", delay, err);
+                    sleep(delay).await;
+                    retry_attempt += 1;
+                }
+                Err(err) => return Err(err),
             }
         }
-        Ok(buf.freeze())
     }
 
     fn read_chunks<'a>(
         &'a mut self,
         chunks: Vec<ChunkOffset>,
     ) -> Pin<Box<dyn Stream<Item = Result<Bytes, io::Error>> + Send + 'a>> {
-        Box::pin(IoChunkReader::new(&mut self.0, chunks))
+        Box::pin(IoChunkReader::new(
+            &mut self.inner,
+            chunks,
+            self.max_coalesce,
+            self.retry_count,
+            self.retry_policy,
+        ))
+    }
+
+    async fn read_tail(&mut self, size: usize) -> Result<Option<Bytes>, io::Error> {
+        let end = self.inner.seek(io::SeekFrom::End(0)).await?;
+        let offset = end
+            .checked_sub(size as u64)
+            .ok_or(io::ErrorKind::UnexpectedEof)?;
+        self.read_at(offset, size).await.map(Some)
     }
 }
 
@@ -55,6 +155,8 @@ enum IoChunkReaderState {
     Seek,
     PollSeek,
     Read,
+    // Waiting to seek back to where a read failed.
+    Retry(Pin<Box<Sleep>>),
 }
 
 struct IoChunkReader<'a, R>
@@ -64,27 +166,63 @@ where
     state: IoChunkReaderState,
     chunks: Vec<ChunkOffset>,
     chunk_index: usize,
+    max_coalesce: usize,
+    // Chunks left to split from the buffer of the current read.
+    group_chunks: usize,
     buf: BytesMut,
     buf_offset: usize,
     reader: &'a mut R,
+    retry_count: u32,
+    retry_policy: RetryPolicy,
+    // Retry attempt of the current read.
+    retry_attempt: u32,
 }
 
 impl<'a, R> IoChunkReader<'a, R>
 where
     R: AsyncRead + AsyncSeekExt + Unpin + Send + ?Sized,
 {
-    fn new(reader: &'a mut R, chunks: Vec<ChunkOffset>) -> Self {
-        let first = chunks
-            .first()
-            .cloned()
-            .unwrap_or(ChunkOffset { offset: 0, size: 0 });
+    fn new(
+        reader: &'a mut R,
+        chunks: Vec<ChunkOffset>,
+        max_coalesce: usize,
+        retry_count: u32,
+        retry_policy: RetryPolicy,
+    ) -> Self {
         Self {
             reader,
             state: IoChunkReaderState::Seek,
             chunk_index: 0,
-            buf: BytesMut::with_capacity(first.size),
+            max_coalesce,
+            group_chunks: 0,
+            buf: BytesMut::new(),
             chunks,
             buf_offset: 0,
+            retry_count,
+            retry_policy,
+            retry_attempt: 0,
+        }
+    }
+
+    // Wait and retry the current read if the error is transient and retries are left.
+    fn retry(&mut self, err: io::Error) -> Result<(), io::Error> {
+        if self.retry_attempt >= self.retry_count || !is_transient(&err) {
+            return Err(err);
+        }
+        let delay = self.retry_policy.delay(self.retry_attempt);
+        log::warn!("read failed (retrying in {:?}): {}", delay, err);
+        self.retry_attempt += 1;
+        self.state = IoChunkReaderState::Retry(Box::pin(sleep(delay)));
+        Ok(())
+    }
+
+    fn start_seek(&mut self, offset: u64) -> Result<(), io::Error> {
+        match Pin::new(&mut self.reader).start_seek(io::SeekFrom::Start(offset)) {
+            Ok(()) => {
+                self.state = IoChunkReaderState::PollSeek;
+                Ok(())
+            }
+            Err(err) => self.retry(err),
         }
     }
 
@@ -94,31 +232,51 @@ where
         Self: Unpin + Send,
     {
         while self.chunk_index < self.chunks.len() {
-            let read_at = &self.chunks[self.chunk_index];
-            if self.buf_offset >= read_at.size {
-                self.buf_offset = 0;
-                self.chunk_index += 1;
-                self.state = IoChunkReaderState::Seek;
-                let chunk = self.buf.clone();
-                return Poll::Ready(Some(Ok(chunk.freeze())));
-            }
             match self.state {
                 IoChunkReaderState::Seek => {
-                    match Pin::new(&mut self.reader).start_seek(io::SeekFrom::Start(read_at.offset))
-                    {
-                        Ok(()) => self.state = IoChunkReaderState::PollSeek,
-                        Err(err) => return Poll::Ready(Some(Err(err))),
+                    // Read all adjacent chunks (up to the coalesce limit) at once.
+                    let chunks = &self.chunks[self.chunk_index..];
+                    self.group_chunks = coalesced_reads(chunks, self.max_coalesce);
+                    let offset = chunks[0].offset;
+                    let size = (chunks[self.group_chunks - 1].end() - offset) as usize;
+                    self.buf.clear();
+                    self.buf.resize(size, 0);
+                    self.buf_offset = 0;
+                    self.retry_attempt = 0;
+                    if let Err(err) = self.start_seek(offset) {
+                        return Poll::Ready(Some(Err(err)));
                     }
                 }
                 IoChunkReaderState::PollSeek => {
                     match ready!(Pin::new(&mut self.reader).poll_complete(cx)) {
                         Ok(_rc) => self.state = IoChunkReaderState::Read,
-                        Err(err) => return Poll::Ready(Some(Err(err))),
+                        Err(err) => {
+                            if let Err(err) = self.retry(err) {
+                                return Poll::Ready(Some(Err(err)));
+                            }
+                        }
+                    }
+                }
+                IoChunkReaderState::Retry(ref mut delay) => {
+                    ready!(delay.as_mut().poll(cx));
+                    // Continue from what has been read of the current chunk.
+                    let offset = self.chunks[self.chunk_index].offset + self.buf_offset as u64;
+                    if let Err(err) = self.start_seek(offset) {
+                        return Poll::Ready(Some(Err(err)));
                     }
                 }
                 IoChunkReaderState::Read => {
-                    if self.buf.len() != read_at.size {
-                        self.buf.resize(read_at.size, 0);
+                    // Pass on chunks as soon as they have been read.
+                    let chunk_size = self.chunks[self.chunk_index].size;
+                    if self.buf_offset >= chunk_size {
+                        let chunk = self.buf.split_to(chunk_size);
+                        self.buf_offset -= chunk_size;
+                        self.chunk_index += 1;
+                        self.group_chunks -= 1;
+                        if self.group_chunks == 0 {
+                            self.state = IoChunkReaderState::Seek;
+                        }
+                        return Poll::Ready(Some(Ok(chunk.freeze())));
                     }
                     let mut buf = ReadBuf::new(&mut self.buf[self.buf_offset..]);
                     match ready!(Pin::new(&mut self.reader).poll_read(cx, &mut buf)) {
@@ -129,7 +287,11 @@ where
                             ))));
                         }
                         Ok(()) => self.buf_offset += buf.filled().len(),
-                        Err(err) => return Poll::Ready(Some(Err(err))),
+                        Err(err) => {
+                            if let Err(err) = self.retry(err) {
+                                return Poll::Ready(Some(Err(err)));
+                            }
+                        }
                     }
                 }
             }
@@ -167,7 +329,7 @@ mod tests {
         let mut file = NamedTempFile::new().unwrap();
         let expected: Vec<u8> = b"hello file".to_vec();
         file.write_all(&expected).unwrap();
-        let reader = IoReader(File::open(&file.path()).await.unwrap());
+        let reader = IoReader::new(File::open(&file.path()).await.unwrap());
         pin_mut!(reader);
         let read_back = reader.read_at(0, expected.len()).await.unwrap();
         assert_eq!(read_back, expected);
@@ -178,12 +340,23 @@ mod tests {
         let mut file = NamedTempFile::new().unwrap();
         let expected: Vec<u8> = (0..10 * 1024 * 1024).map(|v| v as u8).collect();
         file.write_all(&expected).unwrap();
-        let reader = IoReader(File::open(&file.path()).await.unwrap());
+        let reader = IoReader::new(File::open(&file.path()).await.unwrap());
         pin_mut!(reader);
         let read_back = reader.read_at(0, expected.len()).await.unwrap();
         assert_eq!(read_back, expected);
     }
 
+    #[tokio::test]
+    async fn local_read_tail() {
+        let mut file = NamedTempFile::new().unwrap();
+        let data: Vec<u8> = (0..100).collect();
+        file.write_all(&data).unwrap();
+        let mut reader = IoReader::new(File::open(&file.path()).await.unwrap());
+        let tail = reader.read_tail(10).await.unwrap().unwrap();
+        assert_eq!(tail, &data[90..]);
+        assert!(reader.read_tail(101).await.is_err());
+    }
+
     #[tokio::test]
     async fn local_read_chunks() {
         let mut file = NamedTempFile::new().unwrap();
@@ -198,7 +371,7 @@ mod tests {
             ChunkOffset::new(760, 8 * 1024 * 1024),
         ];
         file.write_all(&expected).unwrap();
-        let mut reader = IoReader(File::open(&file.path()).await.unwrap());
+        let mut reader = IoReader::new(File::open(&file.path()).await.unwrap());
         let stream = reader.read_chunks(chunks.clone());
         {
             pin_mut!(stream);
@@ -214,4 +387,181 @@ mod tests {
             assert_eq!(chunk_count, chunks.len());
         }
     }
+
+    // Reader counting the number of seeks made.
+    struct SeekCounter {
+        inner: std::io::Cursor<Vec<u8>>,
+        seeks: usize,
+    }
+
+    impl AsyncRead for SeekCounter {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncSeek for SeekCounter {
+        fn start_seek(mut self: Pin<&mut Self>, position: io::SeekFrom) -> io::Result<()> {
+            self.seeks += 1;
+            Pin::new(&mut self.inner).start_seek(position)
+        }
+        fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+            Pin::new(&mut self.inner).poll_complete(cx)
+        }
+    }
+
+    async fn read_chunks_count_seeks(chunks: &[ChunkOffset], max_coalesce: usize) -> usize {
+        let data: Vec<u8> = (0..1000).map(|v| v as u8).collect();
+        let mut reader = IoReader::new(SeekCounter {
+            inner: std::io::Cursor::new(data.clone()),
+            seeks: 0,
+        })
+        .max_coalesce(max_coalesce);
+        let read: Vec<Bytes> = reader
+            .read_chunks(chunks.to_vec())
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await;
+        for (chunk, expected) in read.iter().zip(chunks) {
+            assert_eq!(
+                chunk,
+                &data[expected.offset as usize..expected.end() as usize]
+            );
+        }
+        assert_eq!(read.len(), chunks.len());
+        reader.inner.seeks
+    }
+
+    #[tokio::test]
+    async fn local_read_chunks_coalesced() {
+        let chunks = [
+            ChunkOffset::new(0, 10),
+            ChunkOffset::new(10, 20),
+            ChunkOffset::new(30, 30),
+            ChunkOffset::new(100, 50),
+            ChunkOffset::new(150, 50),
+            ChunkOffset::new(500, 10),
+        ];
+        // Three groups of adjacent chunks.
+        assert_eq!(read_chunks_count_seeks(&chunks, 1000).await, 3);
+        // Max coalesce splits the first two groups.
+        assert_eq!(read_chunks_count_seeks(&chunks, 50).await, 5);
+        // No coalescing.
+        assert_eq!(read_chunks_count_seeks(&chunks, 0).await, 6);
+    }
+
+    // Reader failing the given number of reads with an error of kind, reading half the
+    // requested data on reads succeeding.
+    struct FlakyReader {
+        inner: std::io::Cursor<Vec<u8>>,
+        failures: usize,
+        kind: io::ErrorKind,
+        reads: usize,
+    }
+
+    impl AsyncRead for FlakyReader {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            self.reads += 1;
+            // Fail the second read, after some data has been read.
+            if self.reads == 2 && self.failures > 0 {
+                self.failures -= 1;
+                self.reads = 0;
+                return Poll::Ready(Err(self.kind.into()));
+            }
+            let len = (buf.remaining() + 1) / 2;
+            let mut half = ReadBuf::new(&mut buf.initialize_unfilled()[..len]);
+            ready!(Pin::new(&mut self.inner).poll_read(cx, &mut half))?;
+            let read = half.filled().len();
+            buf.advance(read);
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncSeek for FlakyReader {
+        fn start_seek(mut self: Pin<&mut Self>, position: io::SeekFrom) -> io::Result<()> {
+            Pin::new(&mut self.inner).start_seek(position)
+        }
+        fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+            Pin::new(&mut self.inner).poll_complete(cx)
+        }
+    }
+
+    fn flaky_reader(data: &[u8], failures: usize, kind: io::ErrorKind) -> IoReader<FlakyReader> {
+        IoReader::new(FlakyReader {
+            inner: std::io::Cursor::new(data.to_vec()),
+            failures,
+            kind,
+            reads: 0,
+        })
+        .retries(1)
+        .retry_delay(Duration::from_millis(1))
+    }
+
+    #[tokio::test]
+    async fn local_read_retries_transient_error() {
+        let data: Vec<u8> = (0..1000).map(|v| v as u8).collect();
+        let mut reader = flaky_reader(&data, 1, io::ErrorKind::TimedOut);
+        assert_eq!(reader.read_at(100, 800).await.unwrap(), &data[100..900]);
+
+        let chunks = vec![
+            ChunkOffset::new(0, 100),
+            ChunkOffset::new(100, 300),
+            ChunkOffset::new(600, 400),
+        ];
+        // Fails twice reading the first group of adjacent chunks.
+        let mut reader = flaky_reader(&data, 2, io::ErrorKind::Interrupted).retries(2);
+        let read: Vec<Bytes> = reader
+            .read_chunks(chunks.clone())
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await;
+        for (chunk, expected) in read.iter().zip(&chunks) {
+            assert_eq!(
+                chunk,
+                &data[expected.offset as usize..expected.end() as usize]
+            );
+        }
+        assert_eq!(read.len(), chunks.len());
+    }
+
+    #[tokio::test]
+    async fn local_read_fails_after_retries() {
+        let data: Vec<u8> = (0..1000).map(|v| v as u8).collect();
+        // More failures than retries.
+        let mut reader = flaky_reader(&data, 2, io::ErrorKind::TimedOut);
+        assert_eq!(
+            reader.read_at(0, 1000).await.unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+        let mut reader = flaky_reader(&data, 2, io::ErrorKind::TimedOut);
+        let mut stream = reader.read_chunks(vec![ChunkOffset::new(0, 1000)]);
+        assert_eq!(
+            stream.next().await.unwrap().unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+    }
+
+    #[tokio::test]
+    async fn local_read_permanent_error_not_retried() {
+        let data: Vec<u8> = (0..1000).map(|v| v as u8).collect();
+        let mut reader = flaky_reader(&data, 1, io::ErrorKind::PermissionDenied);
+        assert_eq!(
+            reader.read_at(0, 1000).await.unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+        let mut reader = flaky_reader(&data, 1, io::ErrorKind::NotFound);
+        let mut stream = reader.read_chunks(vec![ChunkOffset::new(0, 1000)]);
+        assert_eq!(
+            stream.next().await.unwrap().unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
 }
@@ -1,19 +1,34 @@
+mod bounded_reader;
+mod caching_reader;
 mod http_range_request;
 mod http_reader;
 mod io_reader;
+#[cfg(feature = "object-store")]
+mod object_store_reader;
+mod split_reader;
+mod tracing_reader;
 
 use async_trait::async_trait;
 use bytes::Bytes;
 use core::pin::Pin;
-use futures_util::stream::Stream;
+use futures_util::{future::BoxFuture, stream::Stream};
 
 // Re-export archive reader implementations.
-pub use http_reader::{HttpReader, HttpReaderError};
+pub use bounded_reader::{BoundedReader, BoundedReaderError};
+pub use caching_reader::{CachingReader, ReadCache};
+pub use http_reader::{HttpClientConfig, HttpReader, HttpReaderError, RetryPolicy, TokenProvider};
 pub use io_reader::IoReader;
+#[cfg(feature = "object-store")]
+pub use object_store_reader::{ObjectStoreReader, ObjectStoreReaderError};
+pub use split_reader::{SplitReader, SplitReaderError};
+pub use tracing_reader::{ReadTrace, TracedRead, TracingReader};
 
 use crate::ChunkOffset;
 
 /// Trait may be implemented for any type to be read as an archive.
+///
+/// The optional methods have their default implementations written out, rather than as
+/// `async fn`, so that calling them doesn't require the reader to be `Send`.
 #[async_trait]
 pub trait ArchiveReader {
     type Error;
@@ -26,4 +41,103 @@ pub trait ArchiveReader {
         &'a mut self,
         chunks: Vec<ChunkOffset>,
     ) -> Pin<Box<dyn Stream<Item = Result<Bytes, Self::Error>> + Send + 'a>>;
+
+    /// Read the last `size` bytes of the archive.
+    ///
+    /// Needed to open archives with the header at the end. Returns None if the reader can't
+    /// read from the end of the archive, which is the default.
+    fn read_tail<'life0, 'async_trait>(
+        &'life0 mut self,
+        _size: usize,
+    ) -> BoxFuture<'async_trait, Result<Option<Bytes>, Self::Error>>
+    where
+        'life0: 'async_trait,
+        Self: 'async_trait,
+    {
+        Box::pin(async { Ok(None) })
+    }
+
+    /// Hint that the given chunks are about to be read.
+    ///
+    /// A reader may start fetching the chunks in the background to have them ready for the
+    /// next call to `read_chunks`. Any failure is reported by the actual read, not here.
+    /// The default implementation does nothing.
+    fn prefetch<'life0, 'life1, 'async_trait>(
+        &'life0 mut self,
+        _chunks: &'life1 [ChunkOffset],
+    ) -> BoxFuture<'async_trait, ()>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        Self: 'async_trait,
+    {
+        Box::pin(async {})
+    }
+}
+
+/// Get the number of chunks at the start of the given list which are directly
+/// adjacent to each other and hence can be fetched using a single read.
+pub(crate) fn adjacent_reads(chunks: &[ChunkOffset]) -> usize {
+    coalesced_reads(chunks, usize::MAX)
+}
+
+/// Like `adjacent_reads` but stop before the total size of the read would exceed
+/// `max_size`. A single chunk is always read, even if bigger than `max_size`.
+pub(crate) fn coalesced_reads(chunks: &[ChunkOffset], max_size: usize) -> usize {
+    let mut size = chunks.first().map_or(0, |chunk| chunk.size);
+    chunks
+        .windows(2)
+        .take_while(|p| {
+            size = size.saturating_add(p[1].size);
+            p[0].end() == p[1].offset && size <= max_size
+        })
+        .count()
+        + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_adjacent_reads() {
+        let chunks = [ChunkOffset::new(0, 1), ChunkOffset::new(10, 1)];
+        assert_eq!(adjacent_reads(&chunks[..]), 1);
+    }
+
+    #[test]
+    fn two_adjacent_reads() {
+        let chunks = [
+            ChunkOffset::new(0, 1),
+            ChunkOffset::new(1, 3),
+            ChunkOffset::new(10, 3),
+        ];
+        assert_eq!(adjacent_reads(&chunks[..]), 2);
+    }
+
+    #[test]
+    fn multiple_adjacent_reads() {
+        let chunks = [
+            ChunkOffset::new(0, 1),
+            ChunkOffset::new(1, 3),
+            ChunkOffset::new(4, 3),
+            ChunkOffset::new(7, 3),
+            ChunkOffset::new(50, 3),
+        ];
+        assert_eq!(adjacent_reads(&chunks[..]), 4);
+    }
+
+    #[test]
+    fn coalesced_reads_max_size() {
+        let chunks = [
+            ChunkOffset::new(0, 4),
+            ChunkOffset::new(4, 4),
+            ChunkOffset::new(8, 4),
+            ChunkOffset::new(12, 4),
+        ];
+        assert_eq!(coalesced_reads(&chunks[..], 16), 4);
+        assert_eq!(coalesced_reads(&chunks[..], 11), 2);
+        assert_eq!(coalesced_reads(&chunks[..], 8), 2);
+        assert_eq!(coalesced_reads(&chunks[..], 0), 1);
+    }
 }
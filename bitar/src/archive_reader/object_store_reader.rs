@@ -0,0 +1,275 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use core::pin::Pin;
+use futures_util::{stream, stream::Stream, StreamExt, TryStreamExt};
+use object_store::{path::Path, GetOptions, GetRange, ObjectStore, ObjectStoreScheme};
+use reqwest::Url;
+use std::{fmt, sync::Arc};
+
+use crate::archive_reader::{adjacent_reads, ArchiveReader};
+use crate::ChunkOffset;
+
+/// Read an archive stored in an object store (S3, GCS or Azure Blob Storage).
+pub struct ObjectStoreReader {
+    store: Arc<dyn ObjectStore>,
+    location: Path,
+}
+
+impl ObjectStoreReader {
+    /// Create an object store archive reader from a store and the location of the archive in it.
+    pub fn new(store: Arc<dyn ObjectStore>, location: Path) -> Self {
+        Self { store, location }
+    }
+
+    /// Create an object store archive reader from an URL.
+    ///
+    /// Supports `s3://`, `gs://` and `az://` URLs. Credentials and other store options are
+    /// taken from the environment variables of the store's provider, `AWS_*` for S3,
+    /// `GOOGLE_*` for GCS and `AZURE_*` for Azure (eg `AWS_ACCESS_KEY_ID`,
+    /// `GOOGLE_SERVICE_ACCOUNT` or `AZURE_STORAGE_ACCOUNT_NAME`).
+    pub fn from_url(url: &Url) -> Result<Self, ObjectStoreReaderError> {
+        let (scheme, _) = ObjectStoreScheme::parse(url).map_err(object_store::Error::from)?;
+        let options = store_options(&scheme, std::env::vars());
+        let (store, location) = object_store::parse_url_opts(url, options)?;
+        Ok(Self::new(Arc::from(store), location))
+    }
+
+    async fn read_range(
+        store: &dyn ObjectStore,
+        location: &Path,
+        offset: u64,
+        size: usize,
+    ) -> Result<Bytes, ObjectStoreReaderError> {
+        if size == 0 {
+            return Ok(Bytes::new());
+        }
+        let start = usize::try_from(offset).map_err(|_| ObjectStoreReaderError::OutOfRange)?;
+        let end = start
+            .checked_add(size)
+            .ok_or(ObjectStoreReaderError::OutOfRange)?;
+        let data = store.get_range(location, start..end).await?;
+        if data.len() < size {
+            return Err(ObjectStoreReaderError::UnexpectedEnd);
+        }
+        Ok(data)
+    }
+}
+
+// Store options from the environment variables of the scheme's provider, with the keys in
+// lower case as expected by the store builders.
+fn store_options<I>(scheme: &ObjectStoreScheme, vars: I) -> Vec<(String, String)>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let prefix = match scheme {
+        ObjectStoreScheme::AmazonS3 => "AWS_",
+        ObjectStoreScheme::GoogleCloudStorage => "GOOGLE_",
+        ObjectStoreScheme::MicrosoftAzure => "AZURE_",
+        _ => return Vec::new(),
+    };
+    vars.into_iter()
+        .filter(|(key, _)| key.starts_with(prefix))
+        .map(|(key, value)| (key.to_ascii_lowercase(), value))
+        .collect()
+}
+
+#[async_trait]
+impl ArchiveReader for ObjectStoreReader {
+    type Error = ObjectStoreReaderError;
+
+    async fn read_at(&mut self, offset: u64, size: usize) -> Result<Bytes, ObjectStoreReaderError> {
+        Self::read_range(self.store.as_ref(), &self.location, offset, size).await
+    }
+
+    fn read_chunks<'a>(
+        &'a mut self,
+        chunks: Vec<ChunkOffset>,
+    ) -> Pin<Box<dyn Stream<Item = Result<Bytes, ObjectStoreReaderError>> + Send + 'a>> {
+        // Group adjacent chunks so that each group can be fetched using a single ranged get.
+        let mut groups: Vec<Vec<ChunkOffset>> = Vec::new();
+        let mut remaining = &chunks[..];
+        while !remaining.is_empty() {
+            let (group, rest) = remaining.split_at(adjacent_reads(remaining));
+            groups.push(group.to_vec());
+            remaining = rest;
+        }
+        let store = self.store.as_ref();
+        let location = &self.location;
+        Box::pin(
+            stream::iter(groups)
+                .then(move |group| async move {
+                    let offset = group[0].offset;
+                    let size = (group[group.len() - 1].end() - offset) as usize;
+                    let mut data = Self::read_range(store, location, offset, size).await?;
+                    Ok::<_, ObjectStoreReaderError>(stream::iter(
+                        group
+                            .into_iter()
+                            .map(move |chunk| Ok(data.split_to(chunk.size)))
+                            .collect::<Vec<_>>(),
+                    ))
+                })
+                .try_flatten(),
+        )
+    }
+
+    async fn read_tail(&mut self, size: usize) -> Result<Option<Bytes>, ObjectStoreReaderError> {
+        let options = GetOptions {
+            range: Some(GetRange::Suffix(size)),
+            ..Default::default()
+        };
+        let data = self
+            .store
+            .get_opts(&self.location, options)
+            .await?
+            .bytes()
+            .await?;
+        if data.len() < size {
+            return Err(ObjectStoreReaderError::UnexpectedEnd);
+        }
+        Ok(Some(data))
+    }
+}
+
+#[derive(Debug)]
+pub enum ObjectStoreReaderError {
+    UnexpectedEnd,
+    /// The range to read is not addressable on this platform.
+    OutOfRange,
+    ObjectStore(object_store::Error),
+}
+
+impl std::error::Error for ObjectStoreReaderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ObjectStoreReaderError::ObjectStore(err) => Some(err),
+            ObjectStoreReaderError::UnexpectedEnd | ObjectStoreReaderError::OutOfRange => None,
+        }
+    }
+}
+
+impl fmt::Display for ObjectStoreReaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedEnd => write!(f, "unexpected end"),
+            Self::OutOfRange => write!(f, "range out of bounds"),
+            Self::ObjectStore(_) => write!(f, "object store error"),
+        }
+    }
+}
+
+impl From<object_store::Error> for ObjectStoreReaderError {
+    fn from(e: object_store::Error) -> Self {
+        Self::ObjectStore(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::{memory::InMemory, PutPayload};
+
+    async fn new_reader(data: Vec<u8>) -> ObjectStoreReader {
+        let store = InMemory::new();
+        let location = Path::from("archive.cba");
+        store.put(&location, PutPayload::from(data)).await.unwrap();
+        ObjectStoreReader::new(Arc::new(store), location)
+    }
+
+    #[tokio::test]
+    async fn read_single() {
+        let expect = vec![1, 2, 3, 4, 5, 6];
+        let mut reader = new_reader(expect.clone()).await;
+        assert_eq!(reader.read_at(0, expect.len()).await.unwrap(), expect);
+        assert_eq!(reader.read_at(2, 3).await.unwrap(), &expect[2..5]);
+    }
+
+    #[tokio::test]
+    async fn read_single_zero() {
+        let mut reader = new_reader(vec![1, 2, 3, 4, 5, 6]).await;
+        assert_eq!(&reader.read_at(1, 0).await.unwrap()[..], &[0u8; 0]);
+    }
+
+    #[tokio::test]
+    async fn unexpected_end() {
+        let mut reader = new_reader(vec![1, 2, 3, 4, 5, 6]).await;
+        assert!(reader.read_at(0, 10).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn read_chunks() {
+        let data: Vec<u8> = (1..=20).collect();
+        let mut reader = new_reader(data).await;
+        let chunks = vec![
+            ChunkOffset::new(2, 4),
+            ChunkOffset::new(6, 10),
+            ChunkOffset::new(17, 3),
+        ];
+        let read: Vec<Bytes> = reader
+            .read_chunks(chunks)
+            .map(|v| v.expect("item"))
+            .collect()
+            .await;
+        assert_eq!(
+            read,
+            vec![
+                Bytes::from(vec![3, 4, 5, 6]),
+                Bytes::from(vec![7, 8, 9, 10, 11, 12, 13, 14, 15, 16]),
+                Bytes::from(vec![18, 19, 20]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn read_tail() {
+        let mut reader = new_reader(vec![1, 2, 3, 4, 5, 6]).await;
+        assert_eq!(reader.read_tail(2).await.unwrap().unwrap(), &[5u8, 6][..]);
+        assert!(reader.read_tail(10).await.is_err());
+    }
+
+    #[test]
+    fn from_url() {
+        let reader =
+            ObjectStoreReader::from_url(&Url::parse("s3://bucket/path/archive.cba").unwrap())
+                .unwrap();
+        assert_eq!(reader.location, Path::from("path/archive.cba"));
+    }
+
+    #[test]
+    fn store_options_of_provider() {
+        let vars = || {
+            [
+                ("AWS_REGION", "eu-north-1"),
+                ("AZURE_STORAGE_ACCOUNT_NAME", "account"),
+                ("GOOGLE_BUCKET", "bucket"),
+                ("HOME", "/root"),
+                ("SECRET_TOKEN", "secret"),
+            ]
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+        };
+        assert_eq!(
+            store_options(&ObjectStoreScheme::AmazonS3, vars()),
+            vec![("aws_region".to_string(), "eu-north-1".to_string())]
+        );
+        assert_eq!(
+            store_options(&ObjectStoreScheme::MicrosoftAzure, vars()),
+            vec![(
+                "azure_storage_account_name".to_string(),
+                "account".to_string()
+            )]
+        );
+        assert_eq!(
+            store_options(&ObjectStoreScheme::GoogleCloudStorage, vars()),
+            vec![("google_bucket".to_string(), "bucket".to_string())]
+        );
+        assert!(store_options(&ObjectStoreScheme::Memory, vars()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn read_beyond_addressable_range() {
+        let mut reader = new_reader(vec![1, 2, 3, 4, 5, 6]).await;
+        assert!(matches!(
+            reader.read_at(u64::MAX, 2).await,
+            Err(ObjectStoreReaderError::OutOfRange)
+        ));
+    }
+}
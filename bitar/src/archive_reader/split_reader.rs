@@ -0,0 +1,233 @@
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use core::pin::Pin;
+use futures_util::stream::{self, Stream};
+use reqwest::Url;
+use std::fmt;
+
+use crate::archive_reader::{ArchiveReader, HttpReader};
+use crate::ChunkOffset;
+
+struct Part<R> {
+    reader: R,
+    offset: u64,
+    size: u64,
+}
+
+/// Read an archive split into multiple consecutive parts.
+///
+/// Each part is read using its own reader, eg one `HttpReader` per part URL. A read spanning
+/// multiple parts is split into one read per part and the result concatenated.
+pub struct SplitReader<R> {
+    parts: Vec<Part<R>>,
+}
+
+impl<R> SplitReader<R> {
+    /// Create a split archive reader from an ordered list of part readers and their sizes.
+    pub fn new(parts: Vec<(R, u64)>) -> Self {
+        let mut offset = 0;
+        Self {
+            parts: parts
+                .into_iter()
+                .map(|(reader, size)| {
+                    let part = Part {
+                        reader,
+                        offset,
+                        size,
+                    };
+                    offset += size;
+                    part
+                })
+                .collect(),
+        }
+    }
+
+    /// Total size of all parts.
+    pub fn total_size(&self) -> u64 {
+        self.parts.iter().map(|part| part.size).sum()
+    }
+}
+
+impl SplitReader<HttpReader> {
+    /// Create a split archive reader from an ordered list of part URLs and their sizes.
+    pub fn from_urls(parts: Vec<(Url, u64)>) -> Self {
+        Self::new(
+            parts
+                .into_iter()
+                .map(|(url, size)| (HttpReader::from_url(url), size))
+                .collect(),
+        )
+    }
+}
+
+#[async_trait]
+impl<R> ArchiveReader for SplitReader<R>
+where
+    R: ArchiveReader + Send,
+{
+    type Error = SplitReaderError<R::Error>;
+
+    async fn read_at(&mut self, offset: u64, size: usize) -> Result<Bytes, Self::Error> {
+        let end = offset + size as u64;
+        if end > self.total_size() {
+            return Err(SplitReaderError::UnexpectedEnd);
+        }
+        let mut reads = Vec::new();
+        for part in self
+            .parts
+            .iter_mut()
+            .filter(|part| part.offset < end && part.offset + part.size > offset)
+        {
+            let read_start = std::cmp::max(offset, part.offset);
+            let read_end = std::cmp::min(end, part.offset + part.size);
+            reads.push(
+                part.reader
+                    .read_at(read_start - part.offset, (read_end - read_start) as usize)
+                    .await
+                    .map_err(SplitReaderError::PartError)?,
+            );
+        }
+        match reads.len() {
+            0 => Ok(Bytes::new()),
+            1 => Ok(reads.remove(0)),
+            _ => {
+                let mut buf = BytesMut::with_capacity(size);
+                reads.iter().for_each(|read| buf.extend_from_slice(read));
+                Ok(buf.freeze())
+            }
+        }
+    }
+
+    fn read_chunks<'a>(
+        &'a mut self,
+        chunks: Vec<ChunkOffset>,
+    ) -> Pin<Box<dyn Stream<Item = Result<Bytes, Self::Error>> + Send + 'a>> {
+        Box::pin(stream::unfold(
+            (self, chunks.into_iter()),
+            |(reader, mut chunks)| async move {
+                let chunk = chunks.next()?;
+                let result = reader.read_at(chunk.offset, chunk.size).await;
+                Some((result, (reader, chunks)))
+            },
+        ))
+    }
+
+    async fn read_tail(&mut self, size: usize) -> Result<Option<Bytes>, Self::Error> {
+        let offset = self
+            .total_size()
+            .checked_sub(size as u64)
+            .ok_or(SplitReaderError::UnexpectedEnd)?;
+        self.read_at(offset, size).await.map(Some)
+    }
+}
+
+#[derive(Debug)]
+pub enum SplitReaderError<E> {
+    UnexpectedEnd,
+    PartError(E),
+}
+
+impl<E> std::error::Error for SplitReaderError<E>
+where
+    E: std::error::Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SplitReaderError::PartError(err) => Some(err),
+            SplitReaderError::UnexpectedEnd => None,
+        }
+    }
+}
+
+impl<E> fmt::Display for SplitReaderError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedEnd => write!(f, "unexpected end"),
+            Self::PartError(_) => write!(f, "failed to read part"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive_reader::IoReader;
+    use futures_util::StreamExt;
+    use std::io::Cursor;
+
+    fn new_reader(parts: &[&[u8]]) -> SplitReader<IoReader<Cursor<Vec<u8>>>> {
+        SplitReader::new(
+            parts
+                .iter()
+                .map(|part| (IoReader::new(Cursor::new(part.to_vec())), part.len() as u64))
+                .collect(),
+        )
+    }
+
+    #[tokio::test]
+    async fn read_within_part() {
+        let mut reader = new_reader(&[&[1, 2, 3, 4], &[5, 6, 7, 8]]);
+        assert_eq!(&reader.read_at(1, 2).await.unwrap()[..], &[2, 3]);
+        assert_eq!(&reader.read_at(4, 4).await.unwrap()[..], &[5, 6, 7, 8]);
+    }
+
+    #[tokio::test]
+    async fn read_spanning_parts() {
+        let mut reader = new_reader(&[&[1, 2, 3], &[4, 5], &[6, 7, 8]]);
+        assert_eq!(&reader.read_at(2, 5).await.unwrap()[..], &[3, 4, 5, 6, 7]);
+        assert_eq!(
+            &reader.read_at(0, 8).await.unwrap()[..],
+            &[1, 2, 3, 4, 5, 6, 7, 8]
+        );
+    }
+
+    #[tokio::test]
+    async fn read_zero() {
+        let mut reader = new_reader(&[&[1, 2, 3], &[4, 5]]);
+        assert_eq!(&reader.read_at(3, 0).await.unwrap()[..], &[0u8; 0]);
+    }
+
+    #[tokio::test]
+    async fn read_beyond_end() {
+        let mut reader = new_reader(&[&[1, 2, 3], &[4, 5]]);
+        assert!(matches!(
+            reader.read_at(3, 3).await,
+            Err(SplitReaderError::UnexpectedEnd)
+        ));
+    }
+
+    #[tokio::test]
+    async fn last_part_shorter_than_expected() {
+        let mut reader = SplitReader::new(vec![
+            (IoReader::new(Cursor::new(vec![1, 2, 3])), 3),
+            (IoReader::new(Cursor::new(vec![4, 5])), 4),
+        ]);
+        assert!(matches!(
+            reader.read_at(2, 5).await,
+            Err(SplitReaderError::PartError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn read_chunks() {
+        let mut reader = new_reader(&[&[1, 2, 3, 4], &[5, 6], &[7, 8, 9, 10]]);
+        let chunks = vec![
+            ChunkOffset::new(0, 3),
+            ChunkOffset::new(3, 4),
+            ChunkOffset::new(8, 2),
+        ];
+        let read: Vec<Bytes> = reader
+            .read_chunks(chunks)
+            .map(|v| v.expect("item"))
+            .collect()
+            .await;
+        assert_eq!(
+            read,
+            vec![
+                Bytes::from(vec![1, 2, 3]),
+                Bytes::from(vec![4, 5, 6, 7]),
+                Bytes::from(vec![9, 10]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,176 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use core::pin::Pin;
+use futures_util::stream::Stream;
+use std::sync::{Arc, Mutex};
+
+use crate::archive_reader::{adjacent_reads, ArchiveReader};
+use crate::ChunkOffset;
+
+/// A read requested from a [`TracingReader`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TracedRead {
+    /// Call to `read_at`.
+    ReadAt(ChunkOffset),
+    /// Call to `read_chunks`, with the chunks in the order requested.
+    ReadChunks(Vec<ChunkOffset>),
+    /// Call to `read_tail`, with the size requested.
+    ReadTail(usize),
+    /// Call to `prefetch`, with the chunks hinted.
+    Prefetch(Vec<ChunkOffset>),
+}
+
+impl TracedRead {
+    /// Get the requested chunks with directly adjacent chunks merged into one.
+    ///
+    /// That is the reads needed by a reader which coalesces adjacent chunks. Empty for
+    /// `ReadTail`, as its offset depends on the size of the archive.
+    pub fn adjacent_runs(&self) -> Vec<ChunkOffset> {
+        let chunks = match self {
+            TracedRead::ReadAt(chunk) => std::slice::from_ref(chunk),
+            TracedRead::ReadChunks(chunks) | TracedRead::Prefetch(chunks) => &chunks[..],
+            TracedRead::ReadTail(_) => &[],
+        };
+        let mut runs = Vec::new();
+        let mut rest = chunks;
+        while !rest.is_empty() {
+            let count = adjacent_reads(rest);
+            let size = rest[..count].iter().map(|chunk| chunk.size).sum();
+            runs.push(ChunkOffset::new(rest[0].offset, size));
+            rest = &rest[count..];
+        }
+        runs
+    }
+}
+
+/// Trace of the reads of a [`TracingReader`], in the order requested.
+pub type ReadTrace = Arc<Mutex<Vec<TracedRead>>>;
+
+/// Record every read requested from the inner reader.
+///
+/// Useful to see which parts of an archive are read, and in what order, eg when writing a
+/// custom reader or asserting the number and shape of reads in tests. Every read is also
+/// logged at trace level, and then passed on to the inner reader as is.
+pub struct TracingReader<R> {
+    inner: R,
+    trace: ReadTrace,
+}
+
+impl<R> TracingReader<R> {
+    /// Create a reader recording the reads of inner.
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            trace: ReadTrace::default(),
+        }
+    }
+
+    /// Get the trace of reads, which is still updated by the reader.
+    pub fn trace(&self) -> ReadTrace {
+        self.trace.clone()
+    }
+
+    /// Get back the inner reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn record(&self, read: TracedRead) {
+        log::trace!(
+            "Archive read {:?} ({} adjacent runs)",
+            read,
+            read.adjacent_runs().len()
+        );
+        self.trace.lock().unwrap().push(read);
+    }
+}
+
+#[async_trait]
+impl<R> ArchiveReader for TracingReader<R>
+where
+    R: ArchiveReader + Send,
+{
+    type Error = R::Error;
+
+    async fn read_at(&mut self, offset: u64, size: usize) -> Result<Bytes, Self::Error> {
+        self.record(TracedRead::ReadAt(ChunkOffset::new(offset, size)));
+        self.inner.read_at(offset, size).await
+    }
+
+    fn read_chunks<'a>(
+        &'a mut self,
+        chunks: Vec<ChunkOffset>,
+    ) -> Pin<Box<dyn Stream<Item = Result<Bytes, Self::Error>> + Send + 'a>> {
+        self.record(TracedRead::ReadChunks(chunks.clone()));
+        self.inner.read_chunks(chunks)
+    }
+
+    async fn read_tail(&mut self, size: usize) -> Result<Option<Bytes>, Self::Error> {
+        self.record(TracedRead::ReadTail(size));
+        self.inner.read_tail(size).await
+    }
+
+    async fn prefetch(&mut self, chunks: &[ChunkOffset]) {
+        self.record(TracedRead::Prefetch(chunks.to_vec()));
+        self.inner.prefetch(chunks).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive_reader::IoReader;
+    use futures_util::StreamExt;
+    use std::io::Cursor;
+
+    #[tokio::test]
+    async fn records_reads() {
+        let mut reader =
+            TracingReader::new(IoReader::new(Cursor::new((0..16).collect::<Vec<u8>>())));
+        let trace = reader.trace();
+        assert_eq!(&reader.read_at(2, 3).await.unwrap()[..], &[2, 3, 4]);
+        let chunks = vec![ChunkOffset::new(0, 2), ChunkOffset::new(8, 4)];
+        let read: Vec<Bytes> = reader
+            .read_chunks(chunks.clone())
+            .map(|v| v.expect("item"))
+            .collect()
+            .await;
+        assert_eq!(
+            read,
+            vec![Bytes::from(vec![0, 1]), Bytes::from(vec![8, 9, 10, 11])]
+        );
+        assert_eq!(
+            &reader.read_tail(4).await.unwrap().unwrap()[..],
+            &[12, 13, 14, 15]
+        );
+        assert_eq!(
+            *trace.lock().unwrap(),
+            vec![
+                TracedRead::ReadAt(ChunkOffset::new(2, 3)),
+                TracedRead::ReadChunks(chunks),
+                TracedRead::ReadTail(4),
+            ]
+        );
+    }
+
+    #[test]
+    fn adjacent_runs() {
+        let read = TracedRead::ReadChunks(vec![
+            ChunkOffset::new(0, 2),
+            ChunkOffset::new(2, 3),
+            ChunkOffset::new(5, 1),
+            ChunkOffset::new(10, 4),
+            ChunkOffset::new(14, 1),
+            ChunkOffset::new(20, 1),
+        ]);
+        assert_eq!(
+            read.adjacent_runs(),
+            vec![
+                ChunkOffset::new(0, 6),
+                ChunkOffset::new(10, 5),
+                ChunkOffset::new(20, 1),
+            ]
+        );
+        assert_eq!(TracedRead::ReadTail(8).adjacent_runs(), vec![]);
+    }
+}
@@ -1,10 +1,10 @@
 #![allow(clippy::len_without_is_empty)]
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use std::fmt;
 
 #[cfg(feature = "compress")]
 use crate::Compression;
-use crate::{CompressionAlgorithm, CompressionError, HashSum};
+use crate::{CompressionAlgorithm, CompressionError, HashAlgorithm, HashSum};
 
 /// A single chunk.
 ///
@@ -37,6 +37,11 @@ impl Chunk {
     pub fn verify(self) -> VerifiedChunk {
         VerifiedChunk::new(self)
     }
+    /// Create a verified chunk by calculating a hash sum for it using the given algorithm.
+    #[inline]
+    pub fn verify_with(self, algorithm: HashAlgorithm) -> VerifiedChunk {
+        VerifiedChunk::with_algorithm(self, algorithm)
+    }
     #[cfg(feature = "compress")]
     /// Create a compressed chunk.
     #[inline]
@@ -46,12 +51,62 @@ impl Chunk {
     ) -> Result<CompressedChunk, CompressionError> {
         CompressedChunk::try_compress(compression, self)
     }
+    #[cfg(feature = "compress")]
+    /// Compress the chunk with each of the given compressions and keep the smallest result.
+    ///
+    /// Returns the compression used, or None if no compression made the chunk smaller and it
+    /// is left uncompressed. Chunks which look like random data (like already compressed data)
+    /// are left uncompressed without trying any compression.
+    pub fn compress_smallest(
+        self,
+        compressions: &[Compression],
+    ) -> Result<(Option<Compression>, CompressedChunk), CompressionError> {
+        let mut smallest = (None, CompressedChunk::try_compress(None, self.clone())?);
+        if self.entropy() >= INCOMPRESSIBLE_ENTROPY {
+            return Ok(smallest);
+        }
+        for &compression in compressions {
+            let compressed = CompressedChunk::try_compress(Some(compression), self.clone())?;
+            if compressed.len() < smallest.1.len() {
+                smallest = (Some(compression), compressed);
+            }
+        }
+        Ok(smallest)
+    }
+    /// Shannon entropy of the chunk byte values, in bits per byte (0 to 8).
+    ///
+    /// A cheap estimate of how compressible the chunk is, data close to 8 bits per byte (like
+    /// already compressed data) is unlikely to compress.
+    pub fn entropy(&self) -> f64 {
+        byte_entropy(self.data())
+    }
     #[inline]
     pub fn into_inner(self) -> Bytes {
         self.0
     }
 }
 
+// Bits per byte above which data is considered not worth trying to compress.
+#[cfg(feature = "compress")]
+const INCOMPRESSIBLE_ENTROPY: f64 = 7.99;
+
+// Shannon entropy of the byte values in data, in bits per byte.
+fn byte_entropy(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for &b in data {
+        counts[b as usize] += 1;
+    }
+    let len = data.len() as f64;
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
 /// A chunk with verified hash sum.
 #[derive(Debug, Clone)]
 pub struct VerifiedChunk {
@@ -62,8 +117,12 @@ pub struct VerifiedChunk {
 impl VerifiedChunk {
     /// Create a new verified chunk by calculating a hash of it.
     pub fn new(chunk: Chunk) -> Self {
+        Self::with_algorithm(chunk, HashAlgorithm::Blake2b)
+    }
+    /// Create a new verified chunk by calculating a hash of it using the given algorithm.
+    pub fn with_algorithm(chunk: Chunk, algorithm: HashAlgorithm) -> Self {
         Self {
-            hash_sum: HashSum::b2_digest(chunk.data()),
+            hash_sum: algorithm.digest(chunk.data()),
             chunk,
         }
     }
@@ -103,6 +162,20 @@ pub struct CompressedChunk {
 }
 
 impl CompressedChunk {
+    /// Create a chunk from data already compressed with the given algorithm.
+    ///
+    /// Source size is the size of the chunk once decompressed.
+    pub fn new(
+        compression: Option<CompressionAlgorithm>,
+        data: Bytes,
+        source_size: usize,
+    ) -> CompressedChunk {
+        CompressedChunk {
+            data,
+            source_size,
+            compression,
+        }
+    }
     /// Create a compressed chunk.
     #[cfg(feature = "compress")]
     pub fn try_compress(
@@ -141,6 +214,22 @@ impl CompressedChunk {
             None => Chunk::from(self.data),
         })
     }
+    /// Decompress the chunk into buffer.
+    ///
+    /// Like `decompress` but decompressing into the allocation of buffer, which is reused for
+    /// the next chunk once the returned chunk has been dropped. Any data already in buffer is
+    /// discarded.
+    pub fn decompress_with(self, buffer: &mut BytesMut) -> Result<Chunk, CompressionError> {
+        Ok(match self.compression {
+            Some(compression) => {
+                buffer.clear();
+                compression.decompress_into(&self.data, self.source_size, buffer)?;
+                Chunk::from(buffer.split().freeze())
+            }
+            // Chunk not compressed.
+            None => Chunk::from(self.data),
+        })
+    }
     /// Compression used for chunk.
     #[inline]
     pub fn compression(&self) -> Option<CompressionAlgorithm> {
@@ -159,6 +248,8 @@ impl CompressedChunk {
 pub struct CompressedArchiveChunk {
     pub(crate) chunk: CompressedChunk,
     pub(crate) expected_hash: HashSum,
+    pub(crate) hash_algorithm: HashAlgorithm,
+    pub(crate) archive_offset: u64,
 }
 
 impl CompressedArchiveChunk {
@@ -166,13 +257,121 @@ impl CompressedArchiveChunk {
     pub fn len(&self) -> usize {
         self.chunk.len()
     }
+    /// Hash the chunk is expected to have once decompressed.
+    pub fn expected_hash(&self) -> &HashSum {
+        &self.expected_hash
+    }
+    /// Offset of the chunk in the archive.
+    pub fn archive_offset(&self) -> u64 {
+        self.archive_offset
+    }
     /// Decompress the chunk.
     pub fn decompress(self) -> Result<ArchiveChunk, CompressionError> {
         Ok(ArchiveChunk {
             chunk: self.chunk.decompress()?,
             expected_hash: self.expected_hash,
+            hash_algorithm: self.hash_algorithm,
         })
     }
+    /// Decompress the chunk into buffer, see [`CompressedChunk::decompress_with`].
+    pub fn decompress_with(self, buffer: &mut BytesMut) -> Result<ArchiveChunk, CompressionError> {
+        Ok(ArchiveChunk {
+            chunk: self.chunk.decompress_with(buffer)?,
+            expected_hash: self.expected_hash,
+            hash_algorithm: self.hash_algorithm,
+        })
+    }
+    /// Decompress and verify the chunk.
+    ///
+    /// Same as `decompress` followed by `verify`, but failing with an error which tells the
+    /// expected hash and archive offset of the chunk.
+    pub fn into_verified(self) -> Result<VerifiedChunk, ArchiveChunkError> {
+        self.into_verified_with(&mut BytesMut::new())
+    }
+    /// Decompress the chunk into buffer and verify it, see [`CompressedChunk::decompress_with`]
+    /// and [`CompressedArchiveChunk::into_verified`].
+    pub fn into_verified_with(
+        self,
+        buffer: &mut BytesMut,
+    ) -> Result<VerifiedChunk, ArchiveChunkError> {
+        let (expected_hash, archive_offset) = (self.expected_hash.clone(), self.archive_offset);
+        match self.decompress_with(buffer) {
+            Ok(chunk) => chunk
+                .verify()
+                .map_err(|source| ArchiveChunkError::VerifyError {
+                    expected_hash,
+                    archive_offset,
+                    source: Box::new(source),
+                }),
+            Err(source) => Err(ArchiveChunkError::DecompressError {
+                expected_hash,
+                archive_offset,
+                source,
+            }),
+        }
+    }
+}
+
+/// Error from [`CompressedArchiveChunk::into_verified`].
+#[derive(Debug)]
+pub enum ArchiveChunkError {
+    /// Failed to decompress the chunk
+    DecompressError {
+        expected_hash: HashSum,
+        archive_offset: u64,
+        source: CompressionError,
+    },
+    /// The decompressed chunk did not match its expected hash
+    VerifyError {
+        expected_hash: HashSum,
+        archive_offset: u64,
+        source: Box<HashSumMismatchError>,
+    },
+}
+
+impl ArchiveChunkError {
+    /// Hash the chunk was expected to have.
+    pub fn expected_hash(&self) -> &HashSum {
+        match self {
+            Self::DecompressError { expected_hash, .. } => expected_hash,
+            Self::VerifyError { expected_hash, .. } => expected_hash,
+        }
+    }
+    /// Offset of the chunk in the archive.
+    pub fn archive_offset(&self) -> u64 {
+        match self {
+            Self::DecompressError { archive_offset, .. } => *archive_offset,
+            Self::VerifyError { archive_offset, .. } => *archive_offset,
+        }
+    }
+}
+
+impl std::error::Error for ArchiveChunkError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::DecompressError { source, .. } => Some(source),
+            Self::VerifyError { source, .. } => Some(source.as_ref()),
+        }
+    }
+}
+
+impl fmt::Display for ArchiveChunkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DecompressError { .. } => write!(
+                f,
+                "failed to decompress chunk {} at archive offset {}",
+                self.expected_hash(),
+                self.archive_offset()
+            ),
+            Self::VerifyError { .. } => write!(
+                f,
+                "failed to verify chunk {} at archive offset {}",
+                self.expected_hash(),
+                self.archive_offset()
+            ),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -193,6 +392,7 @@ impl fmt::Display for HashSumMismatchError {
 pub struct ArchiveChunk {
     pub(crate) chunk: Chunk,
     pub(crate) expected_hash: HashSum,
+    pub(crate) hash_algorithm: HashAlgorithm,
 }
 
 impl ArchiveChunk {
@@ -204,8 +404,9 @@ impl ArchiveChunk {
     ///
     /// Results in a verified chunk or an error if the chunk hash sum doesn't
     /// match with the expected one.
+    #[allow(clippy::result_large_err)]
     pub fn verify(self) -> Result<VerifiedChunk, HashSumMismatchError> {
-        let mut hash_sum = HashSum::b2_digest(self.chunk.data());
+        let mut hash_sum = self.hash_algorithm.digest(self.chunk.data());
         hash_sum.truncate(self.expected_hash.len());
         if hash_sum != self.expected_hash {
             Err(HashSumMismatchError {
@@ -17,6 +17,14 @@ pub struct ChunkDescriptor {
     /// Size of uncompressed chunk data
     #[prost(uint32, tag = "5")]
     pub source_size: u32,
+    /// Compression of this chunk, only set when it differs from the archive
+    /// chunk_compression
+    #[prost(message, optional, tag = "6")]
+    pub compression: ::core::option::Option<ChunkCompression>,
+    /// Chunk data is not stored in this archive but in the base archive, only
+    /// set in delta archives
+    #[prost(bool, tag = "7")]
+    pub in_base: bool,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -25,7 +33,8 @@ pub struct ChunkerParameters {
     pub chunk_filter_bits: u32,
     #[prost(uint32, tag = "2")]
     pub min_chunk_size: u32,
-    /// max_chunk_size is also the fixed chunk size when FIXED_SIZE is set
+    /// max_chunk_size is also the fixed chunk size when FIXED_SIZE is set,
+    /// 0 means unbounded
     #[prost(uint32, tag = "3")]
     pub max_chunk_size: u32,
     #[prost(uint32, tag = "4")]
@@ -34,6 +43,12 @@ pub struct ChunkerParameters {
     pub chunk_hash_length: u32,
     #[prost(enumeration = "chunker_parameters::ChunkingAlgorithm", tag = "6")]
     pub chunking_algorithm: i32,
+    /// Only set when BUZHASH is used with a non-default seed
+    #[prost(uint32, optional, tag = "7")]
+    pub buzhash_seed: ::core::option::Option<u32>,
+    /// Algorithm used to hash chunks, the source checksum is always blake2
+    #[prost(enumeration = "chunker_parameters::ChunkHashAlgorithm", tag = "8")]
+    pub chunk_hash_algorithm: i32,
 }
 /// Nested message and enum types in `ChunkerParameters`.
 pub mod chunker_parameters {
@@ -53,6 +68,8 @@ pub mod chunker_parameters {
         Buzhash = 0,
         Rollsum = 1,
         FixedSize = 2,
+        /// Split at boundaries given by the user, which are not stored
+        Explicit = 3,
     }
     impl ChunkingAlgorithm {
         /// String value of the enum field names used in the ProtoBuf definition.
@@ -64,6 +81,7 @@ pub mod chunker_parameters {
                 ChunkingAlgorithm::Buzhash => "BUZHASH",
                 ChunkingAlgorithm::Rollsum => "ROLLSUM",
                 ChunkingAlgorithm::FixedSize => "FIXED_SIZE",
+                ChunkingAlgorithm::Explicit => "EXPLICIT",
             }
         }
         /// Creates an enum from field names used in the ProtoBuf definition.
@@ -72,6 +90,46 @@ pub mod chunker_parameters {
                 "BUZHASH" => Some(Self::Buzhash),
                 "ROLLSUM" => Some(Self::Rollsum),
                 "FIXED_SIZE" => Some(Self::FixedSize),
+                "EXPLICIT" => Some(Self::Explicit),
+                _ => None,
+            }
+        }
+    }
+    #[derive(
+        Clone,
+        Copy,
+        Debug,
+        PartialEq,
+        Eq,
+        Hash,
+        PartialOrd,
+        Ord,
+        ::prost::Enumeration
+    )]
+    #[repr(i32)]
+    pub enum ChunkHashAlgorithm {
+        Blake2b = 0,
+        Xxh3 = 1,
+        Blake2s = 2,
+    }
+    impl ChunkHashAlgorithm {
+        /// String value of the enum field names used in the ProtoBuf definition.
+        ///
+        /// The values are not transformed in any way and thus are considered stable
+        /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+        pub fn as_str_name(&self) -> &'static str {
+            match self {
+                ChunkHashAlgorithm::Blake2b => "BLAKE2B",
+                ChunkHashAlgorithm::Xxh3 => "XXH3",
+                ChunkHashAlgorithm::Blake2s => "BLAKE2S",
+            }
+        }
+        /// Creates an enum from field names used in the ProtoBuf definition.
+        pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+            match value {
+                "BLAKE2B" => Some(Self::Blake2b),
+                "XXH3" => Some(Self::Xxh3),
+                "BLAKE2S" => Some(Self::Blake2s),
                 _ => None,
             }
         }
@@ -104,6 +162,7 @@ pub mod chunk_compression {
         Lzma = 1,
         Zstd = 2,
         Brotli = 3,
+        Lz4 = 4,
     }
     impl CompressionType {
         /// String value of the enum field names used in the ProtoBuf definition.
@@ -116,6 +175,7 @@ pub mod chunk_compression {
                 CompressionType::Lzma => "LZMA",
                 CompressionType::Zstd => "ZSTD",
                 CompressionType::Brotli => "BROTLI",
+                CompressionType::Lz4 => "LZ4",
             }
         }
         /// Creates an enum from field names used in the ProtoBuf definition.
@@ -125,6 +185,7 @@ pub mod chunk_compression {
                 "LZMA" => Some(Self::Lzma),
                 "ZSTD" => Some(Self::Zstd),
                 "BROTLI" => Some(Self::Brotli),
+                "LZ4" => Some(Self::Lz4),
                 _ => None,
             }
         }
@@ -160,4 +221,8 @@ pub struct ChunkDictionary {
         ::prost::alloc::string::String,
         ::prost::alloc::vec::Vec<u8>,
     >,
+    /// Header checksum of the base archive when this is a delta archive, empty
+    /// otherwise
+    #[prost(bytes = "vec", tag = "9")]
+    pub base_header_checksum: ::prost::alloc::vec::Vec<u8>,
 }
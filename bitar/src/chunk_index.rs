@@ -1,6 +1,8 @@
 use std::borrow::Borrow;
 use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{self, Read, Write};
 
 use crate::ChunkOffset;
 use crate::{chunk_location_map::ChunkLocationMap, HashSum};
@@ -34,6 +36,17 @@ pub enum ReorderOp<'a> {
     },
 }
 
+/// Cost of transforming one source file into another using [`ChunkIndex::reorder_ops`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReorderCost {
+    /// Total size of the chunks copied into place.
+    pub bytes_copied: u64,
+    /// Total size of the chunks which have to be stored in memory while reordering.
+    pub bytes_store_in_mem: u64,
+    /// Maximum number of chunks held in memory at the same time.
+    pub peak_mem_chunks: usize,
+}
+
 #[derive(Eq, PartialEq)]
 struct MoveChunk<'a> {
     hash: &'a HashSum,
@@ -53,6 +66,61 @@ impl PartialOrd for MoveChunk<'_> {
     }
 }
 
+/// Error returned by [`ChunkIndex::validate`] for an index not covering its source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The chunk at offset overlaps the chunk before it, which ends at `previous_end`.
+    Overlap { offset: u64, previous_end: u64 },
+    /// No chunk covers the bytes from start to end.
+    Gap { start: u64, end: u64 },
+    /// The chunk at offset with size ends beyond the end of the source.
+    OutOfBounds { offset: u64, size: u64 },
+}
+impl std::error::Error for ValidationError {}
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::Overlap {
+                offset,
+                previous_end,
+            } => write!(
+                f,
+                "chunk at offset {} overlaps chunk ending at {}",
+                offset, previous_end
+            ),
+            ValidationError::Gap { start, end } => {
+                write!(f, "no chunk covers offset {} to {}", start, end)
+            }
+            ValidationError::OutOfBounds { offset, size } => write!(
+                f,
+                "chunk at offset {} of size {} ends beyond the source",
+                offset, size
+            ),
+        }
+    }
+}
+
+/// Identifies a serialized chunk index, see [`ChunkIndex::write_to`].
+const SERIALIZED_MAGIC: &[u8; 4] = b"BCIX";
+/// Version of the serialized chunk index format.
+const SERIALIZED_VERSION: u8 = 1;
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
 /// Size and offsets of a chunk in a file.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct ChunkLocation {
@@ -117,15 +185,15 @@ impl HashSumKey for TruncatedHashSum<'_> {
     }
 }
 
-impl Eq for (dyn HashSumKey + '_) {}
+impl Eq for dyn HashSumKey + '_ {}
 
-impl PartialEq for (dyn HashSumKey + '_) {
+impl PartialEq for dyn HashSumKey + '_ {
     fn eq(&self, other: &dyn HashSumKey) -> bool {
         self.sum() == other.sum()
     }
 }
 
-impl std::hash::Hash for (dyn HashSumKey + '_) {
+impl std::hash::Hash for dyn HashSumKey + '_ {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.sum().hash(state)
     }
@@ -188,6 +256,28 @@ impl ChunkIndex {
     pub fn keys(&self) -> impl Iterator<Item = &HashSum> {
         self.map.keys()
     }
+    /// Get an index of the chunks in self which are not present in other.
+    pub fn difference(&self, other: &ChunkIndex) -> ChunkIndex {
+        Self {
+            map: self
+                .map
+                .iter()
+                .filter(|(hash, _)| !other.contains(hash))
+                .map(|(hash, location)| (hash.clone(), location.clone()))
+                .collect(),
+            hash_length: self.hash_length,
+        }
+    }
+    /// Get an index of the chunks present in either self or other.
+    ///
+    /// The offsets of chunks present in both are merged.
+    pub fn union(&self, other: &ChunkIndex) -> ChunkIndex {
+        let mut union = self.clone();
+        for (hash, location) in &other.map {
+            union.add_chunk(hash.clone(), location.size, &location.offsets);
+        }
+        union
+    }
     /// Filter the given chunk index for chunks which are already in place in self
     ///
     /// Returns the number of chunks filtered and total size of them.
@@ -295,7 +385,7 @@ impl ChunkIndex {
     /// The transformation is done by reordering the chunks of a file in place trying to match the new
     /// order. Only the chunks present in both the current index and the new index will be reordered,
     /// while chunks that are not present in the current index still has to be fetched from elsewhere.
-    pub fn reorder_ops(&self, new_order: &ChunkIndex) -> Vec<ReorderOp> {
+    pub fn reorder_ops(&self, new_order: &ChunkIndex) -> Vec<ReorderOp<'_>> {
         // Generate an intersection between the two chunk sets to find which chunks that should be moved.
         // Also generate a layout of the source where we can go from offset+size to which chunks are
         // located within that range.
@@ -353,12 +443,149 @@ impl ChunkIndex {
         });
         ops
     }
+    /// Get the cost of transforming one source file into another, without executing it.
+    ///
+    /// Walks the same operations as returned by [`ChunkIndex::reorder_ops`], keeping track of
+    /// which chunks would be held in memory at each step.
+    pub fn reorder_cost(&self, new_order: &ChunkIndex) -> ReorderCost {
+        let mut cost = ReorderCost::default();
+        let mut in_mem: HashSet<&HashSum> = HashSet::new();
+        for op in self.reorder_ops(new_order) {
+            match op {
+                ReorderOp::Copy { hash, size, .. } => {
+                    in_mem.remove(hash);
+                    cost.bytes_copied += size as u64;
+                }
+                ReorderOp::StoreInMem { hash, size, .. } => {
+                    if in_mem.insert(hash) {
+                        cost.bytes_store_in_mem += size as u64;
+                        cost.peak_mem_chunks = cost.peak_mem_chunks.max(in_mem.len());
+                    }
+                }
+            }
+        }
+        cost
+    }
+    /// Check that the chunks of the index tile a source of the given size.
+    ///
+    /// Every byte of the source must be covered by exactly one chunk and no chunk may end
+    /// beyond the source. This holds for the index of a complete source, like the one from
+    /// [`crate::Archive::build_source_index`], but not for an index of only some of the
+    /// chunks.
+    pub fn validate(&self, source_total_size: u64) -> Result<(), ValidationError> {
+        let mut chunks: Vec<(u64, u64)> = self
+            .map
+            .values()
+            .flat_map(|location| {
+                location
+                    .offsets
+                    .iter()
+                    .map(move |&offset| (offset, location.size as u64))
+            })
+            .collect();
+        chunks.sort_unstable();
+        let mut position = 0;
+        for (offset, size) in chunks {
+            if offset > source_total_size || size > source_total_size - offset {
+                return Err(ValidationError::OutOfBounds { offset, size });
+            }
+            match offset.cmp(&position) {
+                Ordering::Less => {
+                    return Err(ValidationError::Overlap {
+                        offset,
+                        previous_end: position,
+                    })
+                }
+                Ordering::Greater => {
+                    return Err(ValidationError::Gap {
+                        start: position,
+                        end: offset,
+                    })
+                }
+                Ordering::Equal => position = offset + size,
+            }
+        }
+        if position < source_total_size {
+            return Err(ValidationError::Gap {
+                start: position,
+                end: source_total_size,
+            });
+        }
+        Ok(())
+    }
     /// Iterate all chunks in the index.
     ///
     /// Chunks are returned in undefined order.
     pub fn iter_chunks(&self) -> impl Iterator<Item = (&HashSum, &ChunkLocation)> {
         self.map.iter()
     }
+    /// Write the index in a compact binary format, readable by [`ChunkIndex::read_from`].
+    ///
+    /// The format starts with a magic, a version byte and the hash length of the index,
+    /// followed by the hash, size and offsets of every chunk. All integers are little
+    /// endian. Chunks are written in order of their first offset.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut chunks: Vec<(&HashSum, &ChunkLocation)> = self.map.iter().collect();
+        chunks.sort_by_key(|(_hash, location)| location.offsets.first().copied());
+        writer.write_all(SERIALIZED_MAGIC)?;
+        writer.write_all(&[SERIALIZED_VERSION, self.hash_length as u8])?;
+        writer.write_all(&(chunks.len() as u64).to_le_bytes())?;
+        for (hash, location) in chunks {
+            writer.write_all(&[hash.len() as u8])?;
+            writer.write_all(hash.slice())?;
+            writer.write_all(&(location.size as u64).to_le_bytes())?;
+            writer.write_all(&(location.offsets.len() as u64).to_le_bytes())?;
+            for offset in &location.offsets {
+                writer.write_all(&offset.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+    /// Read an index written by [`ChunkIndex::write_to`].
+    ///
+    /// Fails with [`io::ErrorKind::InvalidData`] if the data is not a serialized index, is
+    /// of an unknown version or if the index hash length is not `hash_length`.
+    pub fn read_from<R: Read>(reader: &mut R, hash_length: usize) -> io::Result<Self> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != SERIALIZED_MAGIC {
+            return Err(invalid_data("not a serialized chunk index".to_string()));
+        }
+        let version = read_u8(reader)?;
+        if version != SERIALIZED_VERSION {
+            return Err(invalid_data(format!(
+                "unsupported chunk index version {}",
+                version
+            )));
+        }
+        let index_hash_length = read_u8(reader)? as usize;
+        if index_hash_length != hash_length {
+            return Err(invalid_data(format!(
+                "chunk index hash length {} (expected {})",
+                index_hash_length, hash_length
+            )));
+        }
+        let num_chunks = read_u64(reader)?;
+        let mut index = Self::new_empty(hash_length);
+        for _ in 0..num_chunks {
+            let len = read_u8(reader)? as usize;
+            if len > HashSum::MAX_LEN {
+                return Err(invalid_data(format!("invalid chunk hash length {}", len)));
+            }
+            let mut hash = [0u8; HashSum::MAX_LEN];
+            reader.read_exact(&mut hash[..len])?;
+            let size = read_u64(reader)? as usize;
+            let num_offsets = read_u64(reader)?;
+            let offsets = (0..num_offsets)
+                .map(|_| read_u64(reader))
+                .collect::<io::Result<Vec<u64>>>()?;
+            if offsets.is_empty() {
+                return Err(invalid_data("chunk without offsets".to_string()));
+            }
+            index.add_chunk(HashSum::from(&hash[..len]), size, &offsets);
+        }
+        Ok(index)
+    }
 }
 
 #[cfg(test)]
@@ -455,6 +682,49 @@ mod tests {
         );
     }
     #[test]
+    fn reorder_cost_of_swap() {
+        let mut current_index = ChunkIndex::new_empty(HashSum::MAX_LEN);
+        current_index.add_chunk(HashSum::from(&[1]), 10, &[0]);
+        current_index.add_chunk(HashSum::from(&[2]), 15, &[10]);
+        let mut target_index = ChunkIndex::new_empty(HashSum::MAX_LEN);
+        target_index.add_chunk(HashSum::from(&[2]), 15, &[0]);
+        target_index.add_chunk(HashSum::from(&[1]), 10, &[15]);
+        let ops = current_index.reorder_ops(&target_index);
+        assert_eq!(
+            ops[0],
+            ReorderOp::StoreInMem {
+                hash: &HashSum::from(&[1]),
+                size: 10,
+                source: 0,
+            }
+        );
+        assert_eq!(
+            current_index.reorder_cost(&target_index),
+            ReorderCost {
+                bytes_copied: 25,
+                bytes_store_in_mem: 10,
+                peak_mem_chunks: 1,
+            }
+        );
+    }
+    #[test]
+    fn reorder_cost_without_loop() {
+        let mut current_index = ChunkIndex::new_empty(HashSum::MAX_LEN);
+        current_index.add_chunk(HashSum::from(&[1]), 10, &[0]);
+        current_index.add_chunk(HashSum::from(&[2]), 20, &[10]);
+        let mut target_index = ChunkIndex::new_empty(HashSum::MAX_LEN);
+        target_index.add_chunk(HashSum::from(&[1]), 10, &[30]);
+        target_index.add_chunk(HashSum::from(&[2]), 20, &[40]);
+        assert_eq!(
+            current_index.reorder_cost(&target_index),
+            ReorderCost {
+                bytes_copied: 30,
+                bytes_store_in_mem: 0,
+                peak_mem_chunks: 0,
+            }
+        );
+    }
+    #[test]
     fn reorder_but_do_not_copy_to_self() {
         let mut current_index = ChunkIndex::new_empty(HashSum::MAX_LEN);
         current_index.add_chunk(HashSum::from(&[1]), 10, &[0, 20]);
@@ -533,10 +803,181 @@ mod tests {
         );
     }
     #[test]
+    fn difference() {
+        let mut a = ChunkIndex::new_empty(HashSum::MAX_LEN);
+        a.add_chunk(HashSum::from(&[1]), 10, &[0]);
+        a.add_chunk(HashSum::from(&[2]), 20, &[10]);
+        let mut b = ChunkIndex::new_empty(HashSum::MAX_LEN);
+        b.add_chunk(HashSum::from(&[2]), 20, &[0]);
+        b.add_chunk(HashSum::from(&[3]), 5, &[20, 25]);
+        let diff = b.difference(&a);
+        assert_eq!(diff.len(), 1);
+        assert_eq!(
+            diff.get(&HashSum::from(&[3])).unwrap(),
+            &ChunkLocation {
+                size: 5,
+                offsets: vec![20, 25],
+            }
+        );
+        assert_eq!(a.difference(&a).len(), 0);
+    }
+    #[test]
+    fn union() {
+        let mut a = ChunkIndex::new_empty(HashSum::MAX_LEN);
+        a.add_chunk(HashSum::from(&[1]), 10, &[0]);
+        a.add_chunk(HashSum::from(&[2]), 20, &[10]);
+        let mut b = ChunkIndex::new_empty(HashSum::MAX_LEN);
+        b.add_chunk(HashSum::from(&[2]), 20, &[0]);
+        b.add_chunk(HashSum::from(&[3]), 5, &[20]);
+        let union = a.union(&b);
+        assert_eq!(union.len(), 3);
+        assert_eq!(
+            union.get(&HashSum::from(&[2])).unwrap(),
+            &ChunkLocation {
+                size: 20,
+                offsets: vec![0, 10],
+            }
+        );
+        assert_eq!(union.offsets(&HashSum::from(&[1])).unwrap().count(), 1);
+    }
+    #[test]
     fn lookup_truncated_hash_sum() {
         let mut index = ChunkIndex::new_empty(4);
         index.add_chunk(HashSum::from([1, 2, 3, 4, 99, 99]), 10, &[0]);
         assert!(index.contains(&HashSum::from([1, 2, 3, 4, 5, 6])));
         index.remove(&HashSum::from([1, 2, 3, 4, 5, 6])).unwrap();
     }
+    #[test]
+    fn validate_complete_index() {
+        let mut index = ChunkIndex::new_empty(HashSum::MAX_LEN);
+        index.add_chunk(HashSum::from(&[1]), 10, &[0, 30]);
+        index.add_chunk(HashSum::from(&[2]), 20, &[10]);
+        assert_eq!(index.validate(40), Ok(()));
+        assert_eq!(ChunkIndex::new_empty(HashSum::MAX_LEN).validate(0), Ok(()));
+    }
+    #[test]
+    fn validate_overlap() {
+        let mut index = ChunkIndex::new_empty(HashSum::MAX_LEN);
+        index.add_chunk(HashSum::from(&[1]), 10, &[0]);
+        index.add_chunk(HashSum::from(&[2]), 20, &[5]);
+        assert_eq!(
+            index.validate(25),
+            Err(ValidationError::Overlap {
+                offset: 5,
+                previous_end: 10
+            })
+        );
+    }
+    #[test]
+    fn validate_gap() {
+        let mut index = ChunkIndex::new_empty(HashSum::MAX_LEN);
+        index.add_chunk(HashSum::from(&[1]), 10, &[0]);
+        index.add_chunk(HashSum::from(&[2]), 20, &[15]);
+        assert_eq!(
+            index.validate(35),
+            Err(ValidationError::Gap { start: 10, end: 15 })
+        );
+        // Source not covered to its end.
+        index.add_chunk(HashSum::from(&[3]), 5, &[10]);
+        assert_eq!(index.validate(35), Ok(()));
+        assert_eq!(
+            index.validate(40),
+            Err(ValidationError::Gap { start: 35, end: 40 })
+        );
+    }
+    #[test]
+    fn validate_out_of_bounds() {
+        let mut index = ChunkIndex::new_empty(HashSum::MAX_LEN);
+        index.add_chunk(HashSum::from(&[1]), 10, &[0]);
+        index.add_chunk(HashSum::from(&[2]), 20, &[10]);
+        assert_eq!(
+            index.validate(25),
+            Err(ValidationError::OutOfBounds {
+                offset: 10,
+                size: 20
+            })
+        );
+        index.add_chunk(HashSum::from(&[3]), 10, &[u64::MAX - 5]);
+        assert_eq!(
+            index.validate(u64::MAX),
+            Err(ValidationError::OutOfBounds {
+                offset: u64::MAX - 5,
+                size: 10
+            })
+        );
+    }
+
+    // An index of a pseudo random source with repeated and truncated chunks.
+    fn nontrivial_index(hash_length: usize) -> ChunkIndex {
+        let mut index = ChunkIndex::new_empty(hash_length);
+        let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut offset = 0u64;
+        for n in 0..500u64 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let size = 1 + (state % 100_000) as usize;
+            // Every seventh chunk repeats an earlier one.
+            let id = if n % 7 == 6 { n / 2 } else { n };
+            let hash = HashSum::b2_digest(&id.to_le_bytes());
+            index.add_chunk(hash, size, &[offset]);
+            offset += size as u64;
+        }
+        index
+    }
+
+    fn assert_index_eq(a: &ChunkIndex, b: &ChunkIndex) {
+        assert_eq!(a.len(), b.len());
+        for (hash, location) in a.iter_chunks() {
+            assert_eq!(b.get(hash), Some(location), "{}", hash);
+        }
+    }
+
+    #[test]
+    fn serialize_round_trip() {
+        for hash_length in [4, 16, HashSum::MAX_LEN] {
+            let index = nontrivial_index(hash_length);
+            let mut buf = vec![];
+            index.write_to(&mut buf).unwrap();
+            let read = ChunkIndex::read_from(&mut &buf[..], hash_length).unwrap();
+            assert_index_eq(&index, &read);
+            // The format is deterministic.
+            let mut rewritten = vec![];
+            read.write_to(&mut rewritten).unwrap();
+            assert_eq!(buf, rewritten);
+        }
+    }
+
+    #[test]
+    fn serialize_round_trip_empty() {
+        let mut buf = vec![];
+        ChunkIndex::new_empty(32).write_to(&mut buf).unwrap();
+        assert!(ChunkIndex::read_from(&mut &buf[..], 32).unwrap().is_empty());
+    }
+
+    #[test]
+    fn serialize_reject_other_hash_length() {
+        let mut buf = vec![];
+        nontrivial_index(16).write_to(&mut buf).unwrap();
+        let err = ChunkIndex::read_from(&mut &buf[..], 32).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn serialize_reject_other_version() {
+        let mut buf = vec![];
+        nontrivial_index(16).write_to(&mut buf).unwrap();
+        buf[SERIALIZED_MAGIC.len()] += 1;
+        let err = ChunkIndex::read_from(&mut &buf[..], 16).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn serialize_reject_truncated() {
+        let mut buf = vec![];
+        nontrivial_index(16).write_to(&mut buf).unwrap();
+        buf.truncate(buf.len() - 1);
+        let err = ChunkIndex::read_from(&mut &buf[..], 16).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
 }
@@ -1,9 +1,12 @@
-use std::io;
+use std::{fmt, io};
 
-use futures_util::Stream;
+use futures_util::{stream, Stream};
 use tokio::io::AsyncRead;
 
-use super::{fixed_size::FixedSizeChunker, rolling_hash::RollingHashChunker, StreamingChunker};
+use super::{
+    explicit::ExplicitChunker, fixed_size::FixedSizeChunker, rolling_hash::RollingHashChunker,
+    StreamingChunker, DEFAULT_REFILL_SIZE,
+};
 use crate::{
     rolling_hash::{BuzHash, RollSum},
     Chunk,
@@ -54,9 +57,14 @@ pub struct FilterConfig {
     /// No chunks smaller than `min_chunk_size`.
     pub min_chunk_size: usize,
     /// No chunks bigger than `max_chunk_size`.
+    ///
+    /// Set to 0 to only split on boundaries found by the rolling hash. Chunks are then
+    /// still limited to `UNBOUNDED_CHUNK_SIZE`, the largest size an archive can describe.
     pub max_chunk_size: usize,
     /// Number of bytes kept in the rolling hash window while scanning.
     pub window_size: usize,
+    /// Seed of the BuzHash rolling hash (not used by RollSum).
+    pub buzhash_seed: u32,
 }
 
 impl Default for FilterConfig {
@@ -66,19 +74,118 @@ impl Default for FilterConfig {
             min_chunk_size: 16 * 1024,
             max_chunk_size: 16 * 1024 * 1024,
             window_size: 64,
+            buzhash_seed: BuzHash::DEFAULT_SEED,
         }
     }
 }
 
+impl FilterConfig {
+    /// Set the number of bytes kept in the rolling hash window.
+    #[must_use]
+    pub fn with_window_size(mut self, window_size: usize) -> Self {
+        self.window_size = window_size;
+        self
+    }
+    /// Set the seed of the BuzHash rolling hash.
+    ///
+    /// The seed is stored in the archive, and used when scanning seeds while cloning.
+    #[must_use]
+    pub fn with_buzhash_seed(mut self, seed: u32) -> Self {
+        self.buzhash_seed = seed;
+        self
+    }
+}
+
+/// Largest chunk size used when the max chunk size is set to 0 (unbounded).
+pub const UNBOUNDED_CHUNK_SIZE: usize = u32::MAX as usize;
+
 /// Algorithm and configuration to use while scanning for chunk boundaries.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Config {
     BuzHash(FilterConfig),
     RollSum(FilterConfig),
+    /// Chunks of a fixed size, or 0 for a single chunk (of at most `UNBOUNDED_CHUNK_SIZE`).
+    ///
+    /// The input is only split every N bytes, without any rolling hash, which makes this the
+    /// fastest chunker. Chunking is then mostly limited by reading the input, roughly 2.5
+    /// times the throughput of RollSum and BuzHash when reading from file (see the
+    /// `chunker-throughput` example). Inserting or removing data shifts every following chunk
+    /// boundary though, so only data at the same offset is deduplicated.
     FixedSize(usize),
+    /// Chunks split at the given offsets of the input, eg boundaries found by an external
+    /// chunker splitting on file or record boundaries.
+    ///
+    /// The offsets must be ascending, and greater than 0, or the chunker fails with an
+    /// `io::ErrorKind::InvalidInput` error. Data after the last offset becomes a single chunk.
+    /// Chunks are still matched by hash only, but the boundaries are not stored in the
+    /// archive. The config of an archive read back has no boundaries, so seeds scanned with
+    /// it are not split at all. Deduplicating chunks between archives, or against seeds,
+    /// requires the same external chunker to give consistent boundaries for all of them.
+    Explicit(Vec<u64>),
 }
 
 impl Config {
+    /// Check that the configuration is sane.
+    ///
+    /// Creating a chunker checks explicit boundaries, other configurations are only checked
+    /// in debug builds. Can be called by anyone wanting to validate a configuration.
+    pub fn validate(&self) -> Result<(), InvalidConfigError> {
+        match self {
+            Config::BuzHash(filter) | Config::RollSum(filter) => {
+                if filter.filter_bits.bits() == 0 || filter.filter_bits.bits() > 30 {
+                    return Err(InvalidConfigError("filter bits must be in range 1-30"));
+                }
+                if filter.window_size == 0 {
+                    return Err(InvalidConfigError("window size must be greater than 0"));
+                }
+                if filter.max_chunk_size == 0 {
+                    // Unbounded chunk size.
+                    return Ok(());
+                }
+                if filter.min_chunk_size > filter.max_chunk_size {
+                    return Err(InvalidConfigError(
+                        "min chunk size must not be greater than max chunk size",
+                    ));
+                }
+                if filter.filter_bits.chunk_target_average() as usize > filter.max_chunk_size {
+                    return Err(InvalidConfigError(
+                        "average chunk size must not be greater than max chunk size",
+                    ));
+                }
+            }
+            Config::FixedSize(_) => {}
+            Config::Explicit(boundaries) => {
+                if boundaries.first() == Some(&0) {
+                    return Err(InvalidConfigError(
+                        "chunk boundaries must be greater than 0",
+                    ));
+                }
+                if boundaries.windows(2).any(|pair| pair[0] >= pair[1]) {
+                    return Err(InvalidConfigError(
+                        "chunk boundaries must be in ascending order",
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the size of the largest chunk the configuration can produce.
+    ///
+    /// A max chunk size of 0 (unbounded) gives `UNBOUNDED_CHUNK_SIZE`.
+    pub fn max_chunk_size(&self) -> usize {
+        let size = match self {
+            Config::BuzHash(filter) | Config::RollSum(filter) => filter.max_chunk_size,
+            Config::FixedSize(size) => *size,
+            // The chunk after the last boundary has no limit.
+            Config::Explicit(_) => 0,
+        };
+        match size {
+            0 => UNBOUNDED_CHUNK_SIZE,
+            size => size,
+        }
+    }
+
     /// Create an (async) stream of chunks from the given source using config.
     pub fn new_chunker<'r, R>(
         &self,
@@ -87,19 +194,70 @@ impl Config {
     where
         R: AsyncRead + Unpin + Send + 'r,
     {
+        self.new_chunker_with_refill_size(source, None)
+    }
+
+    /// Create an (async) stream of chunks from the given source using config, reading
+    /// `refill_size` bytes at a time from source.
+    ///
+    /// None uses the default refill size (`DEFAULT_REFILL_SIZE`). See
+    /// `StreamingChunker::with_capacity`.
+    pub fn new_chunker_with_refill_size<'r, R>(
+        &self,
+        source: R,
+        refill_size: Option<usize>,
+    ) -> Box<dyn Stream<Item = io::Result<(u64, Chunk)>> + Unpin + Send + 'r>
+    where
+        R: AsyncRead + Unpin + Send + 'r,
+    {
+        let refill_size = refill_size.unwrap_or(DEFAULT_REFILL_SIZE);
+        if let Config::Explicit(_) = self {
+            if let Err(err) = self.validate() {
+                let err = io::Error::new(io::ErrorKind::InvalidInput, err);
+                return Box::new(stream::iter([Err(err)]));
+            }
+        } else if cfg!(debug_assertions) {
+            if let Err(err) = self.validate() {
+                log::warn!("{}", err);
+            }
+        }
+        let log_forced = log::log_enabled!(log::Level::Debug);
         match self {
-            Config::BuzHash(filter) => Box::new(StreamingChunker::new(
-                RollingHashChunker::new(BuzHash::new(filter.window_size), filter),
+            Config::BuzHash(filter) => Box::new(StreamingChunker::with_capacity(
+                RollingHashChunker::new(
+                    BuzHash::with_seed(filter.window_size, filter.buzhash_seed),
+                    filter,
+                )
+                .log_forced_boundaries(log_forced),
                 source,
+                refill_size,
             )),
-            Config::RollSum(filter) => Box::new(StreamingChunker::new(
-                RollingHashChunker::new(RollSum::new(filter.window_size), filter),
+            Config::RollSum(filter) => Box::new(StreamingChunker::with_capacity(
+                RollingHashChunker::new(RollSum::new(filter.window_size), filter)
+                    .log_forced_boundaries(log_forced),
                 source,
+                refill_size,
             )),
-            Config::FixedSize(fixed_size) => Box::new(StreamingChunker::new(
+            Config::FixedSize(fixed_size) => Box::new(StreamingChunker::with_capacity(
                 FixedSizeChunker::new(*fixed_size),
                 source,
+                refill_size,
+            )),
+            Config::Explicit(boundaries) => Box::new(StreamingChunker::with_capacity(
+                ExplicitChunker::new(boundaries.clone()),
+                source,
+                refill_size,
             )),
         }
     }
 }
+
+/// Error returned when validating an invalid chunker configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidConfigError(&'static str);
+impl std::error::Error for InvalidConfigError {}
+impl fmt::Display for InvalidConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid chunker config: {}", self.0)
+    }
+}
@@ -0,0 +1,56 @@
+use bytes::BytesMut;
+
+use super::{Chunker, UNBOUNDED_CHUNK_SIZE};
+use crate::Chunk;
+
+pub struct ExplicitChunker<I> {
+    boundaries: I,
+    next_boundary: Option<u64>,
+    offset: u64,
+}
+
+impl<I> ExplicitChunker<I>
+where
+    I: Iterator<Item = u64>,
+{
+    /// Create a new chunker splitting the input at the given (ascending) offsets.
+    ///
+    /// Data after the last boundary becomes a single chunk. Chunks are never bigger than
+    /// `UNBOUNDED_CHUNK_SIZE`, a bigger gap between two boundaries is split at that size.
+    pub fn new(boundaries: impl IntoIterator<IntoIter = I>) -> Self {
+        let mut chunker = Self {
+            boundaries: boundaries.into_iter(),
+            next_boundary: None,
+            offset: 0,
+        };
+        chunker.advance();
+        chunker
+    }
+    // Skip to the first boundary after the current offset.
+    fn advance(&mut self) {
+        self.next_boundary = self.boundaries.by_ref().find(|&b| b > self.offset);
+    }
+}
+
+impl<I> Chunker for ExplicitChunker<I>
+where
+    I: Iterator<Item = u64>,
+{
+    fn next(&mut self, buf: &mut BytesMut) -> Option<Chunk> {
+        let chunk_size = match self.next_boundary {
+            Some(boundary) => (boundary - self.offset).min(UNBOUNDED_CHUNK_SIZE as u64) as usize,
+            None => UNBOUNDED_CHUNK_SIZE,
+        };
+        if buf.len() < chunk_size {
+            return None;
+        }
+        self.offset += chunk_size as u64;
+        if self
+            .next_boundary
+            .is_some_and(|boundary| boundary <= self.offset)
+        {
+            self.advance();
+        }
+        Some(Chunk(buf.split_to(chunk_size).freeze()))
+    }
+}
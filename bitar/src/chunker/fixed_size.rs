@@ -1,6 +1,6 @@
 use bytes::BytesMut;
 
-use super::Chunker;
+use super::{Chunker, UNBOUNDED_CHUNK_SIZE};
 use crate::Chunk;
 
 pub struct FixedSizeChunker {
@@ -8,14 +8,19 @@ pub struct FixedSizeChunker {
 }
 
 impl FixedSizeChunker {
+    /// Create a new chunker, a size of 0 makes the whole input a single chunk.
     pub fn new(fixed_size: usize) -> Self {
         Self {
-            chunk_size: fixed_size,
+            chunk_size: match fixed_size {
+                0 => UNBOUNDED_CHUNK_SIZE,
+                size => size,
+            },
         }
     }
 }
 
 impl Chunker for FixedSizeChunker {
+    // Nothing is scanned, the buffered data is split as soon as a whole chunk is available.
     fn next(&mut self, buf: &mut BytesMut) -> Option<Chunk> {
         if buf.len() >= self.chunk_size {
             Some(Chunk(buf.split_to(self.chunk_size).freeze()))
@@ -0,0 +1,85 @@
+use futures_util::{Stream, StreamExt};
+use std::io;
+use tokio::{
+    io::AsyncRead,
+    task::{spawn_blocking, JoinError},
+};
+
+use crate::{chunker::Config, HashAlgorithm, HashSum};
+
+/// Chunk and hash the data read from reader.
+///
+/// Returns a stream of the offset, hash and size of each chunk, in source order. Chunks are
+/// hashed using `hash_algorithm` and the hashes truncated to `hash_length`. Chunks are hashed
+/// in parallel on the blocking thread pool of the tokio runtime, so the stream must be polled
+/// from within a runtime.
+pub fn chunk_stream<'r, R>(
+    config: &Config,
+    reader: R,
+    hash_algorithm: HashAlgorithm,
+    hash_length: usize,
+) -> impl Stream<Item = io::Result<(u64, HashSum, usize)>> + Unpin + Send + 'r
+where
+    R: AsyncRead + Unpin + Send + 'r,
+{
+    let buffers = std::thread::available_parallelism().map_or(1, |n| n.get() * 2);
+    buffered_chunk_stream(config, reader, hash_algorithm, hash_length, buffers)
+        .map(|result| result.map_err(|err| io::Error::new(io::ErrorKind::Other, err))?)
+}
+
+// Like `chunk_stream` but hashing at most `buffers` chunks at once, and failing to hash a
+// chunk is reported separately from failing to read.
+pub(crate) fn buffered_chunk_stream<'r, R>(
+    config: &Config,
+    reader: R,
+    hash_algorithm: HashAlgorithm,
+    hash_length: usize,
+    buffers: usize,
+) -> impl Stream<Item = Result<io::Result<(u64, HashSum, usize)>, JoinError>> + Unpin + Send + 'r
+where
+    R: AsyncRead + Unpin + Send + 'r,
+{
+    config
+        .new_chunker(reader)
+        .map(move |result| {
+            spawn_blocking(move || {
+                result.map(|(offset, chunk)| {
+                    let (mut hash, chunk) = chunk.verify_with(hash_algorithm).into_parts();
+                    hash.truncate(hash_length);
+                    (offset, hash, chunk.len())
+                })
+            })
+        })
+        .buffered(buffers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Chunk;
+
+    #[tokio::test]
+    async fn hashes_fixed_size_chunks() {
+        let source: Vec<u8> = (0..250u8).collect();
+        let chunks: Vec<(u64, HashSum, usize)> = chunk_stream(
+            &Config::FixedSize(100),
+            &source[..],
+            HashAlgorithm::Blake2b,
+            8,
+        )
+        .map(|result| result.unwrap())
+        .collect()
+        .await;
+        let expected: Vec<(u64, HashSum, usize)> = source
+            .chunks(100)
+            .enumerate()
+            .map(|(index, data)| {
+                let mut hash = Chunk::from(data.to_vec()).verify().hash().clone();
+                hash.truncate(8);
+                ((index * 100) as u64, hash, data.len())
+            })
+            .collect();
+        assert_eq!(chunks, expected);
+        assert!(chunks.iter().all(|(_offset, hash, _size)| hash.len() == 8));
+    }
+}
@@ -1,13 +1,18 @@
 //! Chunker related functions and types.
 mod config;
+mod explicit;
 mod fixed_size;
+mod hashed;
 mod rolling_hash;
 mod streaming_chunker;
 
-pub use config::{Config, FilterBits, FilterConfig};
+pub use config::{Config, FilterBits, FilterConfig, InvalidConfigError, UNBOUNDED_CHUNK_SIZE};
+pub use explicit::ExplicitChunker;
 pub use fixed_size::FixedSizeChunker;
+pub(crate) use hashed::buffered_chunk_stream;
+pub use hashed::chunk_stream;
 pub use rolling_hash::RollingHashChunker;
-pub use streaming_chunker::StreamingChunker;
+pub use streaming_chunker::{StreamingChunker, DEFAULT_REFILL_SIZE};
 
 use bytes::BytesMut;
 
@@ -1,6 +1,6 @@
 use bytes::BytesMut;
 
-use super::{Chunker, FilterConfig};
+use super::{Chunker, FilterConfig, UNBOUNDED_CHUNK_SIZE};
 use crate::{rolling_hash::RollingHash, Chunk};
 
 pub struct RollingHashChunker<H> {
@@ -11,23 +11,53 @@ pub struct RollingHashChunker<H> {
     hash_input_limit: usize,
     // Offset in buffer.
     offset: usize,
+    log_forced_boundaries: bool,
+    // Bytes scanned since the last boundary found by the rolling hash.
+    bytes_since_hash_boundary: u64,
 }
 
 impl<H> RollingHashChunker<H> {
     pub fn new(hasher: H, config: &FilterConfig) -> Self {
         // Allow for chunk size less than the rolling hash window.
-        let hash_input_limit = if config.min_chunk_size >= config.window_size {
-            config.min_chunk_size - config.window_size
-        } else {
-            0
-        };
+        let hash_input_limit = config.min_chunk_size.saturating_sub(config.window_size);
         Self {
             filter_mask: config.filter_bits.mask(),
             min_chunk_size: config.min_chunk_size,
-            max_chunk_size: config.max_chunk_size,
+            max_chunk_size: match config.max_chunk_size {
+                0 => UNBOUNDED_CHUNK_SIZE,
+                size => size,
+            },
             hasher,
             hash_input_limit,
             offset: 0,
+            log_forced_boundaries: false,
+            bytes_since_hash_boundary: 0,
+        }
+    }
+
+    /// Log whenever a chunk is cut at `max_chunk_size` since the rolling hash didn't find a
+    /// boundary.
+    ///
+    /// Useful to diagnose inputs where chunk boundaries fail to resync after a change.
+    #[must_use]
+    pub fn log_forced_boundaries(mut self, enabled: bool) -> Self {
+        self.log_forced_boundaries = enabled;
+        self
+    }
+
+    fn track_boundary(&mut self, chunk_size: usize, found_by_hash: bool) {
+        if !self.log_forced_boundaries {
+            return;
+        }
+        self.bytes_since_hash_boundary += chunk_size as u64;
+        if found_by_hash {
+            self.bytes_since_hash_boundary = 0;
+        } else {
+            log::debug!(
+                "No chunk boundary found by rolling hash for {} bytes (max chunk size is {})",
+                self.bytes_since_hash_boundary,
+                self.max_chunk_size
+            );
         }
     }
 
@@ -54,8 +84,9 @@ impl<H> RollingHashChunker<H> {
     }
 
     // Scan until end of buffer, chunk boundary (hash sum match) or max chunk
-    // size reached.
-    fn scan_for_boundary(&mut self, buf: &[u8]) -> bool
+    // size reached. Returns None if no boundary was found, otherwise whether the
+    // boundary was found by the hash sum.
+    fn scan_for_boundary(&mut self, buf: &[u8]) -> Option<bool>
     where
         H: RollingHash,
     {
@@ -72,7 +103,13 @@ impl<H> RollingHashChunker<H> {
             })
             .any(|sum| sum | filter_mask == sum);
         self.offset = end_offset;
-        found_boundary || self.offset >= self.max_chunk_size
+        if found_boundary {
+            Some(true)
+        } else if self.offset >= self.max_chunk_size {
+            Some(false)
+        } else {
+            None
+        }
     }
 }
 
@@ -87,10 +124,10 @@ where
             self.offset += 1;
         }
         self.skip_min_chunk(&buf[..]);
-        let found_boundary = self.scan_for_boundary(buf);
-        if found_boundary {
+        if let Some(found_by_hash) = self.scan_for_boundary(buf) {
             let offset = self.offset;
             self.offset = 0;
+            self.track_boundary(offset, found_by_hash);
             return Some(Chunk(buf.split_to(offset).freeze()));
         }
         None
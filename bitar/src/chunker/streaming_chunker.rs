@@ -13,21 +13,35 @@ use tokio::{
 
 use crate::{chunker::Chunker, Chunk};
 
-const REFILL_SIZE: usize = 1024 * 1024;
+/// Default number of bytes to make room for each time the chunker reads from its source.
+pub const DEFAULT_REFILL_SIZE: usize = 1024 * 1024;
 
 /// A streaming chunker to use with any source which implements tokio AsyncRead.
 pub struct StreamingChunker<C, R> {
     chunk_start: u64,
     buf: BytesMut,
+    refill_size: usize,
     chunker: C,
     reader: R,
 }
 
 impl<C, R> StreamingChunker<C, R> {
     pub fn new(chunker: C, reader: R) -> Self {
+        Self::with_capacity(chunker, reader, DEFAULT_REFILL_SIZE)
+    }
+
+    /// Create a chunker reading up to `refill_size` bytes at a time from reader.
+    ///
+    /// A larger refill size means fewer reads, which may help throughput on fast storage,
+    /// while a smaller one keeps the buffer small on memory constrained devices. The
+    /// buffer still grows to hold a whole chunk if needed.
+    pub fn with_capacity(chunker: C, reader: R, refill_size: usize) -> Self {
+        // Reading into a full buffer would look like end of source.
+        let refill_size = refill_size.max(1);
         Self {
             chunk_start: 0,
-            buf: BytesMut::with_capacity(REFILL_SIZE),
+            buf: BytesMut::with_capacity(refill_size),
+            refill_size,
             chunker,
             reader,
         }
@@ -51,8 +65,8 @@ where
                 }
             }
             // Append more data to buffer since no chunk was found.
-            if me.buf.capacity() < me.buf.len() + REFILL_SIZE {
-                me.buf.reserve(REFILL_SIZE);
+            if me.buf.capacity() < me.buf.len() + me.refill_size {
+                me.buf.reserve(me.refill_size);
             }
             let read_f = me.reader.read_buf(&mut me.buf);
             pin!(read_f);
@@ -79,6 +93,7 @@ mod tests {
 
     use super::*;
     use crate::chunker::{Config, FilterBits, FilterConfig};
+    use crate::rolling_hash::BuzHash;
     use futures_util::StreamExt;
     use std::cmp;
     use tokio::io::ReadBuf;
@@ -137,12 +152,14 @@ mod tests {
                 min_chunk_size: 20,
                 max_chunk_size: 600,
                 window_size: 10,
+                buzhash_seed: BuzHash::DEFAULT_SEED,
             }),
             Config::BuzHash(FilterConfig {
                 filter_bits: FilterBits(10),
                 min_chunk_size: 20,
                 max_chunk_size: 600,
                 window_size: 10,
+                buzhash_seed: BuzHash::DEFAULT_SEED,
             }),
         ] {
             let source_data: Vec<u8> = {
@@ -179,6 +196,30 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn refill_size() {
+        let config = Config::BuzHash(FilterConfig {
+            filter_bits: FilterBits(10),
+            min_chunk_size: 20,
+            max_chunk_size: 600,
+            window_size: 10,
+            buzhash_seed: BuzHash::DEFAULT_SEED,
+        });
+        let source_data: Vec<u8> = (0..10000u32).map(|v| (v * 31 % 251) as u8).collect();
+        let offsets = |refill_size: Option<usize>| {
+            config
+                .new_chunker_with_refill_size(&source_data[..], refill_size)
+                .map(|result| result.unwrap().0)
+                .collect::<Vec<u64>>()
+        };
+        let expected_offsets = offsets(None).await;
+        assert!(expected_offsets.len() > 1);
+        // Chunk boundaries don't depend on how much is read at a time.
+        for refill_size in [0, 1, 7, 600, 100_000] {
+            assert_eq!(offsets(Some(refill_size)).await, expected_offsets);
+        }
+    }
+
     #[tokio::test]
     async fn zero_data() {
         for chunker_config in &[
@@ -187,12 +228,14 @@ mod tests {
                 min_chunk_size: 3,
                 max_chunk_size: 640,
                 window_size: 5,
+                buzhash_seed: BuzHash::DEFAULT_SEED,
             }),
             Config::BuzHash(FilterConfig {
                 filter_bits: FilterBits(5),
                 min_chunk_size: 3,
                 max_chunk_size: 640,
                 window_size: 5,
+                buzhash_seed: BuzHash::DEFAULT_SEED,
             }),
         ] {
             let expected_chunk_offsets: [u64; 0] = [0; 0];
@@ -220,12 +263,14 @@ mod tests {
                 min_chunk_size: 0,
                 max_chunk_size: 40,
                 window_size: 10,
+                buzhash_seed: BuzHash::DEFAULT_SEED,
             }),
             Config::BuzHash(FilterConfig {
                 filter_bits: FilterBits(5),
                 min_chunk_size: 0,
                 max_chunk_size: 40,
                 window_size: 10,
+                buzhash_seed: BuzHash::DEFAULT_SEED,
             }),
         ] {
             let expected_chunk_offsets: [u64; 1] = [0; 1];
@@ -253,12 +298,14 @@ mod tests {
                 min_chunk_size: 10,
                 max_chunk_size: 40,
                 window_size: 5,
+                buzhash_seed: BuzHash::DEFAULT_SEED,
             }),
             Config::BuzHash(FilterConfig {
                 filter_bits: FilterBits(5),
                 min_chunk_size: 10,
                 max_chunk_size: 40,
                 window_size: 5,
+                buzhash_seed: BuzHash::DEFAULT_SEED,
             }),
         ] {
             let expected_chunk_offsets: [u64; 1] = [0; 1];
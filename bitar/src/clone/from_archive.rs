@@ -0,0 +1,210 @@
+use bytes::BytesMut;
+use futures_util::{future, stream, StreamExt};
+use tokio::{
+    io::{AsyncSeek, AsyncWrite},
+    sync::mpsc,
+    task::spawn_blocking,
+};
+
+use crate::{
+    archive::ChunkDescriptor, archive_reader::ArchiveReader, clone::CloneError, clone::Options,
+    Archive, ArchiveChunkError, ChunkIndex, ChunkOffset, CloneOutput, VerifiedChunk,
+};
+
+/// Fetch all chunks still missing in output from the archive.
+///
+/// Fails with `CloneError::MissingBaseChunks`, before fetching anything, if chunks stored in
+/// the base of a delta archive are still missing in output (see `Archive::base_chunk_count`).
+/// If `opts.on_plan` is set it's called with the chunks to fetch before any is read, and
+/// `opts.on_fetch` with the bytes fetched so far after every chunk read. If
+/// `opts.on_invalid_chunk` is set chunks failing to decompress or verify are zero filled in
+/// output, instead of failing the clone.
+/// Returns the number of bytes fetched from the archive.
+pub async fn from_archive<R, C>(
+    opts: &Options,
+    archive: &mut Archive<R>,
+    output: &mut CloneOutput<C>,
+) -> Result<u64, CloneError<R::Error>>
+where
+    R: ArchiveReader,
+    C: AsyncWrite + AsyncSeek + Unpin + Send,
+{
+    from_archive_all(opts, archive, std::slice::from_mut(output)).await
+}
+
+/// Fetch the chunks still missing in any of the outputs from the archive, writing each
+/// chunk to every output missing it.
+///
+/// Every chunk is fetched once, however many outputs it's written to. The outputs may have
+/// different chunks left, eg after re-ordering each of them in place.
+/// Fails like `from_archive` if chunks stored in the base of a delta archive are missing.
+/// Returns the number of bytes fetched from the archive.
+pub async fn from_archive_all<R, C>(
+    opts: &Options,
+    archive: &mut Archive<R>,
+    outputs: &mut [CloneOutput<C>],
+) -> Result<u64, CloneError<R::Error>>
+where
+    R: ArchiveReader,
+    C: AsyncWrite + AsyncSeek + Unpin + Send,
+{
+    let union;
+    let chunks = match outputs {
+        [output] => output.chunks(),
+        _ => {
+            union = outputs.iter().fold(
+                ChunkIndex::new_empty(archive.chunk_hash_length()),
+                |chunks, output| chunks.union(output.chunks()),
+            );
+            &union
+        }
+    };
+    let base_chunks = archive.base_chunk_count(chunks);
+    if base_chunks > 0 {
+        return Err(CloneError::MissingBaseChunks(base_chunks));
+    }
+    let mut total_fetched = 0u64;
+    let mut total_written = 0u64;
+    let lenient = opts.on_invalid_chunk.is_some();
+    // Descriptors of the streamed chunks, to log where in the archive each chunk was read.
+    let descriptors: Vec<ChunkDescriptor> =
+        archive.stored_chunks(chunks).into_iter().cloned().collect();
+    if let Some(on_plan) = &opts.on_plan {
+        let plan: Vec<ChunkOffset> = descriptors
+            .iter()
+            .map(|cd| ChunkOffset::new(cd.archive_offset, cd.archive_size))
+            .collect();
+        on_plan(&plan);
+    }
+    // Chunks already read from the archive are decompressed and verified in groups, one
+    // blocking task per group, to keep the task overhead down when chunks are small. A group
+    // never waits for more chunks to arrive, so a slow reader only gives smaller groups.
+    let mut chunk_stream = archive
+        .chunk_stream(chunks)
+        .zip(stream::iter(descriptors))
+        .map(|(result, descriptor)| {
+            if let Ok(compressed) = &result {
+                total_fetched += compressed.len() as u64;
+                if let Some(on_fetch) = &opts.on_fetch {
+                    on_fetch(total_fetched);
+                }
+            }
+            result.map(|compressed| (compressed, descriptor))
+        })
+        .ready_chunks(opts.decompress_batch_size.max(1))
+        .map(|batch| async move {
+            // Chunks before a failed read are verified first, to fail on the first bad chunk
+            // in the group whether it failed to read or to verify.
+            let mut read_result = Ok(());
+            let mut compressed = Vec::with_capacity(batch.len());
+            for result in batch {
+                match result {
+                    Ok(chunk) => compressed.push(chunk),
+                    Err(err) => {
+                        read_result = Err(CloneError::ReaderError(err));
+                        break;
+                    }
+                }
+            }
+            let verified = spawn_blocking(move || {
+                let mut buffer = BytesMut::new();
+                compressed
+                    .into_iter()
+                    .map(|(compressed, descriptor)| {
+                        match compressed.into_verified_with(&mut buffer) {
+                            Ok(verified) => Ok(Ok((verified, descriptor))),
+                            // Passed on to be zero filled when lenient, else fails the clone.
+                            Err(err) if lenient => Ok(Err(err)),
+                            Err(err) => Err(err),
+                        }
+                    })
+                    .collect::<Result<Vec<_>, ArchiveChunkError>>()
+            })
+            .await
+            .map_err(CloneError::TaskError)??;
+            read_result.map(|()| verified)
+        })
+        .buffered(opts.max_buffered_chunks);
+    // Chunks are written as they arrive through a bounded channel, while chunks are fetched
+    // and decompressed, so a slow output doesn't stall the stream while being written to.
+    let (tx, mut rx) = mpsc::channel(opts.max_buffered_chunks.max(1));
+    let fetch = async move {
+        while let Some(result) = chunk_stream.next().await {
+            if tx.send(result?).await.is_err() {
+                break;
+            }
+        }
+        Ok::<_, CloneError<R::Error>>(())
+    };
+    let write = async {
+        let mut batch = Vec::new().into_iter();
+        while let Some(result) = next_verified(&mut batch, &mut rx).await {
+            let (verified, descriptor) = match result {
+                Ok(next) => next,
+                Err(err) => {
+                    let mut zero_filled = 0;
+                    for output in outputs.iter_mut() {
+                        zero_filled += output
+                            .zero_fill(err.expected_hash())
+                            .await
+                            .map_err(CloneError::OutputError)?;
+                    }
+                    if let Some(on_invalid_chunk) = &opts.on_invalid_chunk {
+                        on_invalid_chunk(&err, zero_filled as u64);
+                    }
+                    continue;
+                }
+            };
+            let offsets: Vec<u64> = if log::log_enabled!(log::Level::Debug) {
+                outputs
+                    .iter()
+                    .find_map(|output| output.chunks().offsets(verified.hash()))
+                    .map(|offsets| offsets.collect())
+                    .unwrap_or_default()
+            } else {
+                Vec::new()
+            };
+            let mut written = 0;
+            for output in outputs.iter_mut() {
+                written += output
+                    .feed(&verified)
+                    .await
+                    .map_err(CloneError::OutputError)?;
+            }
+            if written > 0 {
+                log::debug!(
+                    "Chunk '{}', size {} used (archive offset {}, size {}), written to {:?}",
+                    verified.hash(),
+                    verified.len(),
+                    descriptor.archive_offset,
+                    descriptor.archive_size,
+                    offsets
+                );
+            }
+            total_written += written as u64;
+        }
+        Ok::<_, CloneError<R::Error>>(())
+    };
+    future::try_join(fetch, write).await?;
+    log::debug!(
+        "Fetched {} bytes from archive and decompressed to {} bytes",
+        total_fetched,
+        total_written
+    );
+    Ok(total_fetched)
+}
+
+type VerifyResult = Result<(VerifiedChunk, ChunkDescriptor), ArchiveChunkError>;
+
+// Next chunk of the current group, or of the next group received if the current is done.
+async fn next_verified(
+    batch: &mut std::vec::IntoIter<VerifyResult>,
+    rx: &mut mpsc::Receiver<Vec<VerifyResult>>,
+) -> Option<VerifyResult> {
+    loop {
+        if let Some(next) = batch.next() {
+            return Some(next);
+        }
+        *batch = rx.recv().await?.into_iter();
+    }
+}
@@ -0,0 +1,126 @@
+use futures_util::StreamExt;
+use std::io::SeekFrom;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    archive_reader::ArchiveReader,
+    chunker,
+    clone::{from_archive, CloneError, Options},
+    Archive, ChunkIndex, CloneOutput, CloneOutputBuilder,
+};
+
+/// Output from the `in_place` function
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InPlaceResult {
+    /// Number of bytes reused from the output itself
+    pub moved: u64,
+    /// Number of bytes fetched from the archive
+    pub fetched: u64,
+}
+
+/// Update output in place to match the archive source.
+///
+/// Chunks already present in output are first moved to where they belong in the source, then
+/// all chunks still missing are fetched from the archive. The output is not truncated or
+/// extended to the source size, that is left to the caller.
+/// If the archive can't tell where output chunks start and end the whole source is fetched
+/// (see `Archive::can_scan_seeds`).
+pub async fn in_place<R, C>(
+    opts: &Options,
+    archive: &mut Archive<R>,
+    output: C,
+) -> Result<InPlaceResult, CloneError<R::Error>>
+where
+    R: ArchiveReader,
+    C: AsyncRead + AsyncWrite + AsyncSeek + Unpin + Send,
+{
+    let source_index = archive.build_source_index();
+    reorder_and_fetch(opts, archive, CloneOutput::builder(output, source_index)).await
+}
+
+/// Like `in_place` but moving chunks by reflink, if supported by the output file system.
+///
+/// `file` is another handle to the output file, eg from `try_clone`. Reflink support is
+/// detected on the first chunk moved and the chunks are read and written when not
+/// supported, see `CloneOutputBuilder::reflink`.
+#[cfg(target_os = "linux")]
+pub async fn in_place_reflink<R, C>(
+    opts: &Options,
+    archive: &mut Archive<R>,
+    output: C,
+    file: std::fs::File,
+) -> Result<InPlaceResult, CloneError<R::Error>>
+where
+    R: ArchiveReader,
+    C: AsyncRead + AsyncWrite + AsyncSeek + Unpin + Send,
+{
+    let source_index = archive.build_source_index();
+    reorder_and_fetch(
+        opts,
+        archive,
+        CloneOutput::builder(output, source_index).reflink(file),
+    )
+    .await
+}
+
+async fn reorder_and_fetch<R, C>(
+    opts: &Options,
+    archive: &mut Archive<R>,
+    output: CloneOutputBuilder<C>,
+) -> Result<InPlaceResult, CloneError<R::Error>>
+where
+    R: ArchiveReader,
+    C: AsyncRead + AsyncWrite + AsyncSeek + Unpin + Send,
+{
+    let mut output = output.build();
+    output
+        .inner
+        .seek(SeekFrom::Start(0))
+        .await
+        .map_err(CloneError::OutputError)?;
+    let output_index = build_output_index(opts, archive, &mut output.inner).await?;
+    // Any chunks held in memory while re-ordering are released before returning, so they are
+    // gone before we start fetching from the archive.
+    let moved = output
+        .reorder_in_place_limited(output_index, opts.max_reorder_mem)
+        .await
+        .map_err(CloneError::OutputError)?;
+    let fetched = from_archive(opts, archive, &mut output).await?;
+    // The output is dropped when returning, eg a file would not finish pending writes.
+    output
+        .inner
+        .flush()
+        .await
+        .map_err(CloneError::OutputError)?;
+    Ok(InPlaceResult { moved, fetched })
+}
+
+pub(crate) async fn build_output_index<R, C>(
+    opts: &Options,
+    archive: &Archive<R>,
+    output: &mut C,
+) -> Result<ChunkIndex, CloneError<R::Error>>
+where
+    R: ArchiveReader,
+    C: AsyncRead + Unpin + Send,
+{
+    let mut index = ChunkIndex::new_empty(archive.chunk_hash_length());
+    if !archive.can_scan_seeds() {
+        log::warn!("Archive chunk boundaries are not known, not scanning output");
+        return Ok(index);
+    }
+    let mut chunk_stream = chunker::buffered_chunk_stream(
+        archive.chunker_config(),
+        output,
+        archive.chunk_hash_algorithm(),
+        archive.chunk_hash_length(),
+        opts.max_buffered_chunks,
+    );
+    while let Some(result) = chunk_stream.next().await {
+        let (offset, hash, size) = result
+            .map_err(CloneError::TaskError)?
+            .map_err(CloneError::OutputError)?;
+        index.add_chunk(hash, size, &[offset]);
+    }
+    Ok(index)
+}
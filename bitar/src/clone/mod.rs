@@ -0,0 +1,169 @@
+//! Clone an archive's source into an output.
+mod from_archive;
+mod in_place;
+mod run;
+
+pub use from_archive::{from_archive, from_archive_all};
+#[cfg(target_os = "linux")]
+pub use in_place::in_place_reflink;
+pub use in_place::{in_place, InPlaceResult};
+pub use run::{run, run_in_place, CloneReport};
+
+use std::{fmt, io, sync::Arc};
+use tokio::task::JoinError;
+
+use crate::{ArchiveChunkError, ChunkOffset, CompressionError, HashSumMismatchError};
+
+/// Callback given the chunks about to be fetched from the archive, see [`Options::on_plan`].
+pub type PlanCallback = Arc<dyn Fn(&[ChunkOffset]) + Send + Sync>;
+
+/// Callback given the number of bytes fetched from the archive so far, see
+/// [`Options::on_fetch`].
+pub type FetchCallback = Arc<dyn Fn(u64) + Send + Sync>;
+
+/// Callback given a chunk which failed to decompress or verify and the number of bytes
+/// zero filled in its place, see [`Options::on_invalid_chunk`].
+pub type InvalidChunkCallback = Arc<dyn Fn(&ArchiveChunkError, u64) + Send + Sync>;
+
+/// Options for the clone functions
+#[derive(Clone)]
+pub struct Options {
+    /// Number of chunks to decompress and verify in parallel
+    ///
+    /// Also the number of decompressed chunks queued for writing to a slow output, so up to
+    /// about twice this many chunks are held in memory by `from_archive`.
+    pub max_buffered_chunks: usize,
+    /// Max number of chunks to decompress and verify in a single blocking task
+    ///
+    /// Chunks are grouped by their position in the stream of chunks read from the archive, not
+    /// by their location in the archive or source. A group holds the chunks already read when
+    /// it is formed, up to this many, so it may be smaller when the reader is slow. Each group
+    /// is one item of the buffered chunk stream, so up to `max_buffered_chunks` groups are
+    /// processed in parallel. Larger groups cut the task overhead for archives with many small
+    /// chunks, at the cost of keeping more chunks in memory.
+    pub decompress_batch_size: usize,
+    /// Max number of bytes to keep in memory while re-ordering output in place
+    ///
+    /// Chunks which have to be moved around in a circular fashion are kept in memory while
+    /// re-ordering. Chunks not fitting within this limit are left for the fetch from the
+    /// archive instead, trading memory use for download size.
+    pub max_reorder_mem: usize,
+    /// Called once by `from_archive` before streaming, with the archive offset and size of
+    /// every chunk it's about to read, in the order they are read
+    ///
+    /// Lets a custom `ArchiveReader` warm its cache ahead of the reads. Chunks stored in the
+    /// base of a delta archive are not part of the plan.
+    pub on_plan: Option<PlanCallback>,
+    /// Called by `from_archive` after every chunk read from the archive, with the total
+    /// number of bytes fetched so far
+    ///
+    /// Lets a caller report the progress of the fetch, the total to fetch is the sum of the
+    /// sizes given to `on_plan`.
+    pub on_fetch: Option<FetchCallback>,
+    /// Called by `from_archive` for every chunk read from the archive which failed to
+    /// decompress or verify, instead of failing the clone
+    ///
+    /// The locations of the chunk are zero filled in every output and the clone continues,
+    /// for when a partial output is preferred over none. The callback is given the error and
+    /// the number of bytes zero filled, counting every location.
+    pub on_invalid_chunk: Option<InvalidChunkCallback>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            max_buffered_chunks: std::thread::available_parallelism()
+                .map(|n| n.get() * 2)
+                .unwrap_or(1),
+            decompress_batch_size: 1,
+            max_reorder_mem: usize::MAX,
+            on_plan: None,
+            on_fetch: None,
+            on_invalid_chunk: None,
+        }
+    }
+}
+
+impl fmt::Debug for Options {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Options")
+            .field("max_buffered_chunks", &self.max_buffered_chunks)
+            .field("decompress_batch_size", &self.decompress_batch_size)
+            .field("max_reorder_mem", &self.max_reorder_mem)
+            .field(
+                "on_plan",
+                &self.on_plan.as_ref().map(|_| "Fn(&[ChunkOffset])"),
+            )
+            .field("on_fetch", &self.on_fetch.as_ref().map(|_| "Fn(u64)"))
+            .field(
+                "on_invalid_chunk",
+                &self
+                    .on_invalid_chunk
+                    .as_ref()
+                    .map(|_| "Fn(&ArchiveChunkError, u64)"),
+            )
+            .finish()
+    }
+}
+
+/// Error from the clone functions
+#[derive(Debug)]
+pub enum CloneError<R> {
+    /// Failed to read from the archive
+    ReaderError(R),
+    /// Failed to read or write the output
+    OutputError(io::Error),
+    /// Failed to read a seed
+    SeedError(io::Error),
+    /// Failed to decompress a chunk fetched from the archive
+    DecompressError(CompressionError),
+    /// A chunk fetched from the archive did not match its expected hash
+    VerifyError(Box<HashSumMismatchError>),
+    /// A chunk processing task failed
+    TaskError(JoinError),
+    /// Chunks stored in the base of a delta archive were missing in output, given as the
+    /// number of chunks
+    MissingBaseChunks(usize),
+}
+
+impl<R> std::error::Error for CloneError<R>
+where
+    R: std::error::Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CloneError::ReaderError(err) => Some(err),
+            CloneError::OutputError(err) => Some(err),
+            CloneError::SeedError(err) => Some(err),
+            CloneError::DecompressError(err) => Some(err),
+            CloneError::VerifyError(err) => Some(err.as_ref()),
+            CloneError::TaskError(err) => Some(err),
+            CloneError::MissingBaseChunks(_) => None,
+        }
+    }
+}
+
+impl<R> From<ArchiveChunkError> for CloneError<R> {
+    fn from(err: ArchiveChunkError) -> Self {
+        match err {
+            ArchiveChunkError::DecompressError { source, .. } => Self::DecompressError(source),
+            ArchiveChunkError::VerifyError { source, .. } => Self::VerifyError(source),
+        }
+    }
+}
+
+impl<R> fmt::Display for CloneError<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CloneError::ReaderError(_) => write!(f, "failed to read archive"),
+            CloneError::OutputError(_) => write!(f, "failed to access output"),
+            CloneError::SeedError(_) => write!(f, "failed to read seed"),
+            CloneError::DecompressError(_) => write!(f, "failed to decompress chunk"),
+            CloneError::VerifyError(_) => write!(f, "failed to verify chunk"),
+            CloneError::TaskError(_) => write!(f, "chunk task failed"),
+            CloneError::MissingBaseChunks(count) => {
+                write!(f, "{} chunks stored in base archive are missing", count)
+            }
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use futures_util::StreamExt;
+use std::io::SeekFrom;
+use std::time::{Duration, Instant};
+use tokio::{
+    io::{AsyncRead, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt},
+    task::spawn_blocking,
+};
+
+use crate::{
+    archive_reader::ArchiveReader,
+    clone::{from_archive, in_place::build_output_index, CloneError, Options},
+    Archive, CloneOutput,
+};
+
+/// Statistics from the `run` and `run_in_place` functions
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CloneReport {
+    /// Number of bytes written to output using chunks found in seeds
+    pub bytes_from_seed: u64,
+    /// Number of bytes fetched from the archive (possibly compressed)
+    pub bytes_from_archive: u64,
+    /// Number of bytes left to fetch from the archive once all seeds were scanned
+    pub bytes_to_fetch: u64,
+    /// Number of bytes reused from the output itself
+    pub bytes_moved_in_place: u64,
+    /// Number of chunks written to output, counting every location a chunk is written to
+    pub chunks_written: u64,
+    /// Time spent cloning
+    pub duration: Duration,
+}
+
+/// Clone the archive source into output.
+///
+/// Each seed is scanned for chunks present in the source, in the given order, then all
+/// chunks still missing are fetched from the archive. Seeds are not scanned if the archive
+/// can't tell where seed chunks start and end (see `Archive::can_scan_seeds`).
+pub async fn run<R, S, C>(
+    opts: &Options,
+    archive: &mut Archive<R>,
+    seeds: impl IntoIterator<Item = S>,
+    output: &mut CloneOutput<C>,
+) -> Result<CloneReport, CloneError<R::Error>>
+where
+    R: ArchiveReader,
+    S: AsyncRead + Unpin + Send,
+    C: AsyncWrite + AsyncSeek + Unpin + Send,
+{
+    let start = Instant::now();
+    let chunks_written = output.chunks_written;
+    let mut report = CloneReport::default();
+    for seed in seeds {
+        report.bytes_from_seed += from_seed(opts, archive, seed, output).await?;
+    }
+    report.bytes_to_fetch = archive.fetch_size(output.chunks());
+    log::debug!(
+        "Fetching {} chunks ({} bytes) from archive",
+        output.len(),
+        report.bytes_to_fetch
+    );
+    report.bytes_from_archive = from_archive(opts, archive, output).await?;
+    report.chunks_written = output.chunks_written - chunks_written;
+    report.duration = start.elapsed();
+    Ok(report)
+}
+
+/// Update output in place to match the archive source.
+///
+/// Like `in_place` but also scans the given seeds before fetching from the archive.
+/// Neither the output nor the seeds are scanned if the archive can't tell where their chunks
+/// start and end (see `Archive::can_scan_seeds`).
+/// The output is not truncated or extended to the source size, that is left to the caller.
+pub async fn run_in_place<R, S, C>(
+    opts: &Options,
+    archive: &mut Archive<R>,
+    seeds: impl IntoIterator<Item = S>,
+    output: C,
+) -> Result<CloneReport, CloneError<R::Error>>
+where
+    R: ArchiveReader,
+    S: AsyncRead + Unpin + Send,
+    C: AsyncRead + AsyncWrite + AsyncSeek + Unpin + Send,
+{
+    let start = Instant::now();
+    let mut output = output;
+    output
+        .seek(SeekFrom::Start(0))
+        .await
+        .map_err(CloneError::OutputError)?;
+    let output_index = build_output_index(opts, archive, &mut output).await?;
+    let mut output = CloneOutput::new(output, archive.build_source_index());
+    let moved = output
+        .reorder_in_place_limited(output_index, opts.max_reorder_mem)
+        .await
+        .map_err(CloneError::OutputError)?;
+    let mut report = run(opts, archive, seeds, &mut output).await?;
+    report.bytes_moved_in_place = moved;
+    report.chunks_written = output.chunks_written;
+    // The output is dropped when returning, eg a file would not finish pending writes.
+    output
+        .into_inner()
+        .flush()
+        .await
+        .map_err(CloneError::OutputError)?;
+    report.duration = start.elapsed();
+    Ok(report)
+}
+
+// Write chunks found in seed to output.
+//
+// Returns the number of bytes written to output.
+async fn from_seed<R, S, C>(
+    opts: &Options,
+    archive: &Archive<R>,
+    seed: S,
+    output: &mut CloneOutput<C>,
+) -> Result<u64, CloneError<R::Error>>
+where
+    R: ArchiveReader,
+    S: AsyncRead + Unpin + Send,
+    C: AsyncWrite + AsyncSeek + Unpin + Send,
+{
+    if !archive.can_scan_seeds() {
+        log::warn!("Archive chunk boundaries are not known, not scanning seed");
+        return Ok(0);
+    }
+    let hash_algorithm = archive.chunk_hash_algorithm();
+    let mut chunk_stream = archive
+        .chunker_config()
+        .new_chunker(seed)
+        .map(|result| {
+            spawn_blocking(move || result.map(|(_offset, chunk)| chunk.verify_with(hash_algorithm)))
+        })
+        .buffered(opts.max_buffered_chunks);
+    let mut total_written = 0u64;
+    while let Some(result) = chunk_stream.next().await {
+        let verified = result
+            .map_err(CloneError::TaskError)?
+            .map_err(CloneError::SeedError)?;
+        let written = output
+            .feed(&verified)
+            .await
+            .map_err(CloneError::OutputError)?;
+        if written > 0 {
+            log::debug!("Chunk '{}', size {} used", verified.hash(), verified.len());
+        }
+        total_written += written as u64;
+    }
+    Ok(total_written)
+}
@@ -1,46 +1,594 @@
-use bytes::BytesMut;
+use blake2::{Blake2b512, Digest};
+use bytes::{Bytes, BytesMut};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     io::{self, SeekFrom},
+    ops::Range,
 };
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
 
-use crate::{Chunk, ChunkIndex, HashSum, ReorderOp, VerifiedChunk};
+use crate::{Chunk, ChunkIndex, HashSum, OrderedWriter, ReorderOp, VerifiedChunk};
 
+/// Output of a clone, writing each chunk fed to it at its location(s) in the clone index.
 pub struct CloneOutput<T> {
     pub(crate) inner: T,
     pub(crate) clone_index: ChunkIndex,
+    pub(crate) chunks_written: u64,
+    bytes_written: u64,
+    base_offset: u64,
+    source_range: Option<Range<u64>>,
+    sparse: bool,
+    hasher: Option<OutputHasher>,
+    sequential: Option<SequentialWrites>,
+    unchanged: Option<UnchangedWrites>,
+    #[cfg(target_os = "linux")]
+    reflink: Option<std::fs::File>,
+}
+
+/// Builder of a [`CloneOutput`], see [`CloneOutput::builder`].
+pub struct CloneOutputBuilder<T> {
+    output: T,
+    clone_index: ChunkIndex,
+    base_offset: u64,
+    source_range: Option<Range<u64>>,
+    hash_output: bool,
+    sparse: bool,
+    sequential_write: Option<usize>,
+    skip_unchanged: Option<std::fs::File>,
+    #[cfg(target_os = "linux")]
+    reflink: Option<std::fs::File>,
+}
+
+impl<T> CloneOutputBuilder<T> {
+    /// Offset in output of the first byte of the clone, eg to clone into a region of a
+    /// larger device.
+    ///
+    /// Every seek in output is shifted by the base offset, while the offsets of the clone
+    /// index and of the output index given to [`CloneOutput::reorder_in_place`] are still
+    /// relative to the start of the clone.
+    #[must_use]
+    pub fn base_offset(mut self, offset: u64) -> Self {
+        self.base_offset = offset;
+        self
+    }
+    /// Only write the part of the clone within `range`, to output starting at the base offset.
+    ///
+    /// Used with a clone index of the chunks overlapping the range (see
+    /// [`crate::Archive::build_source_range_index`]) to clone part of a source. Chunks
+    /// starting or ending outside of the range are cut to it. The output isn't hashed and
+    /// can't be re-ordered in place, since it doesn't hold whole chunks.
+    #[must_use]
+    pub fn source_range(mut self, range: Range<u64>) -> Self {
+        self.source_range = Some(range);
+        self
+    }
+    /// Hash the output while it is written, see [`CloneOutput::output_checksum`].
+    ///
+    /// Chunks are hashed in output order, so chunks written ahead of the ones still missing
+    /// are kept in memory until the gap is filled.
+    #[must_use]
+    pub fn hash_output(mut self, hash_output: bool) -> Self {
+        self.hash_output = hash_output;
+        self
+    }
+    /// Skip writing chunks of only zeros, leaving holes in the output.
+    ///
+    /// Only use with an output which reads as zeros where not written, eg a new file which
+    /// is extended to the source size after cloning.
+    #[must_use]
+    pub fn sparse(mut self, sparse: bool) -> Self {
+        self.sparse = sparse;
+        self
+    }
+    /// Write chunks in ascending output offset order, buffering at most `max_buffered` bytes.
+    ///
+    /// A chunk fed to the output is usually written to all its locations at once, which
+    /// seeks back and forth when chunks repeat throughout the source. With sequential write
+    /// chunks are instead buffered and written in offset order as the chunks before them
+    /// arrive. When the buffer is full everything buffered is written, still in offset order.
+    /// Trades memory for fewer seeks, eg when cloning to a rotational disk.
+    #[must_use]
+    pub fn sequential_write(mut self, max_buffered: usize) -> Self {
+        self.sequential_write = Some(max_buffered);
+        self
+    }
+    /// Skip writing chunks which output already holds, read through `file`.
+    ///
+    /// `file` is another handle to the output file, eg from `try_clone`. Before each write
+    /// the bytes at its location are read and compared to the chunk, and the write is skipped
+    /// when equal. Costs a read per write, but saves wear of eg flash memory when chunks
+    /// happen to be in place already. Only supported on unix, elsewhere every chunk is
+    /// still written.
+    #[must_use]
+    pub fn skip_unchanged(mut self, file: std::fs::File) -> Self {
+        self.skip_unchanged = Some(file);
+        self
+    }
+    /// Move chunks by reflink when re-ordering in place, if supported by the file system.
+    ///
+    /// `file` is another handle to the output file, eg from `try_clone`. Chunks are then moved
+    /// by `copy_file_range`, which on file systems supporting it (eg btrfs and XFS) shares
+    /// their data extents and elsewhere copies the data within the kernel. Chunks overlapping
+    /// their destination, and all chunks where `copy_file_range` is not supported, are still
+    /// read and written. Not used when hashing the output, since that needs the data.
+    #[cfg(target_os = "linux")]
+    #[must_use]
+    pub fn reflink(mut self, file: std::fs::File) -> Self {
+        self.reflink = Some(file);
+        self
+    }
+    /// Build the clone output.
+    pub fn build(self) -> CloneOutput<T> {
+        let hasher = (self.hash_output && self.source_range.is_none())
+            .then(|| OutputHasher::new(&self.clone_index));
+        CloneOutput {
+            inner: self.output,
+            clone_index: self.clone_index,
+            chunks_written: 0,
+            bytes_written: 0,
+            base_offset: self.base_offset,
+            source_range: self.source_range,
+            sparse: self.sparse,
+            hasher,
+            sequential: self.sequential_write.map(SequentialWrites::new),
+            unchanged: self.skip_unchanged.map(UnchangedWrites::new),
+            #[cfg(target_os = "linux")]
+            reflink: self.reflink,
+        }
+    }
+}
+
+// Streaming hash of the output, hashing chunks in output order as they are written.
+struct OutputHasher {
+    hasher: Blake2b512,
+    // Output is hashed up to this offset.
+    offset: u64,
+    // Size of the output covered by the clone index.
+    size: u64,
+    // Chunks written ahead of offset, waiting to be hashed.
+    pending: BTreeMap<u64, Bytes>,
+}
+
+impl OutputHasher {
+    fn new(clone_index: &ChunkIndex) -> Self {
+        let size = clone_index
+            .iter_chunks()
+            .filter_map(|(_hash, location)| {
+                location
+                    .offsets()
+                    .last()
+                    .map(|offset| offset + location.size() as u64)
+            })
+            .max()
+            .unwrap_or(0);
+        Self {
+            hasher: Blake2b512::new(),
+            offset: 0,
+            size,
+            pending: BTreeMap::new(),
+        }
+    }
+    fn update(&mut self, offset: u64, data: &Bytes) {
+        if offset > self.offset {
+            self.pending.insert(offset, data.clone());
+            return;
+        }
+        if offset < self.offset {
+            // Already hashed.
+            return;
+        }
+        self.hasher.update(data);
+        self.offset += data.len() as u64;
+        while let Some(entry) = self.pending.first_entry() {
+            if *entry.key() > self.offset {
+                break;
+            }
+            let (offset, data) = entry.remove_entry();
+            if offset == self.offset {
+                self.hasher.update(&data);
+                self.offset += data.len() as u64;
+            }
+        }
+    }
+    fn checksum(&self) -> Option<HashSum> {
+        (self.offset == self.size && self.pending.is_empty())
+            .then(|| HashSum::from(&self.hasher.clone().finalize()[..]))
+    }
+}
+
+// Output position to write a chunk at offset of the clone, and the range of the chunk to write
+// there. None if no part of the chunk is within the source range.
+fn output_location(
+    base_offset: u64,
+    source_range: Option<&Range<u64>>,
+    offset: u64,
+    len: usize,
+) -> Option<(u64, Range<usize>)> {
+    let Some(source_range) = source_range else {
+        return Some((base_offset + offset, 0..len));
+    };
+    let start = source_range.start.max(offset);
+    let end = source_range.end.min(offset + len as u64);
+    (start < end).then(|| {
+        (
+            base_offset + start - source_range.start,
+            (start - offset) as usize..(end - offset) as usize,
+        )
+    })
+}
+
+// Output read back to skip writing chunks it already holds.
+struct UnchangedWrites {
+    file: std::fs::File,
+    buf: Vec<u8>,
+    // Bytes not written since already in output.
+    skipped: u64,
+}
+
+impl UnchangedWrites {
+    fn new(file: std::fs::File) -> Self {
+        Self {
+            file,
+            buf: Vec::new(),
+            skipped: 0,
+        }
+    }
+    // Whether the file holds data at position. Read without seeking, since the file position
+    // may be shared with the handle written to.
+    #[cfg(unix)]
+    fn holds(&mut self, position: u64, data: &[u8]) -> io::Result<bool> {
+        use std::os::unix::fs::FileExt;
+        self.buf.resize(data.len(), 0);
+        match self.file.read_exact_at(&mut self.buf, position) {
+            Ok(()) => Ok(self.buf == data),
+            // Output ends before the chunk.
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+    #[cfg(not(unix))]
+    fn holds(&mut self, _position: u64, _data: &[u8]) -> io::Result<bool> {
+        Ok(false)
+    }
+}
+
+// Whether writing data at position of output can be skipped, since it's already there.
+async fn is_unchanged<T>(
+    output: &mut T,
+    unchanged: Option<&mut UnchangedWrites>,
+    position: u64,
+    data: &[u8],
+) -> io::Result<bool>
+where
+    T: AsyncWrite + Unpin,
+{
+    let Some(unchanged) = unchanged else {
+        return Ok(false);
+    };
+    // Anything written through output must reach the file first.
+    output.flush().await?;
+    if !unchanged.holds(position, data)? {
+        return Ok(false);
+    }
+    unchanged.skipped += data.len() as u64;
+    Ok(true)
+}
+
+// Chunk writes buffered to be written in ascending offset order.
+struct SequentialWrites {
+    max_buffered: usize,
+    buffered: usize,
+    pending: BTreeMap<u64, Bytes>,
+    // Offsets of the chunks not yet fed, collected when the first chunk is buffered.
+    missing: Option<BTreeSet<u64>>,
+    // Output position after the last write, to skip seeking when already there.
+    position: Option<u64>,
+}
+
+impl SequentialWrites {
+    fn new(max_buffered: usize) -> Self {
+        Self {
+            max_buffered,
+            buffered: 0,
+            pending: BTreeMap::new(),
+            missing: None,
+            position: None,
+        }
+    }
+    // Add the locations of a chunk no longer in clone index, buffering data if given.
+    fn add(&mut self, clone_index: &ChunkIndex, offsets: &[u64], data: Option<&Bytes>) {
+        let missing = self.missing.get_or_insert_with(|| {
+            clone_index
+                .iter_chunks()
+                .flat_map(|(_hash, location)| location.offsets().iter().copied())
+                .collect()
+        });
+        for &offset in offsets {
+            missing.remove(&offset);
+            if let Some(data) = data {
+                if let Some(replaced) = self.pending.insert(offset, data.clone()) {
+                    self.buffered -= replaced.len();
+                }
+                self.buffered += data.len();
+            }
+        }
+    }
+    // Offset before which buffered chunks can be written, given whether all should be.
+    fn write_before(&self, all: bool) -> u64 {
+        if all || self.buffered > self.max_buffered {
+            return u64::MAX;
+        }
+        self.missing
+            .as_ref()
+            .and_then(|missing| missing.first().copied())
+            .unwrap_or(u64::MAX)
+    }
+}
+
+impl<W> CloneOutput<OrderedWriter<W>> {
+    /// Create a clone output appending to a writer which can't seek, eg an upload stream.
+    ///
+    /// The writer is wrapped in an [`OrderedWriter`], passing data on to it in source order.
+    /// Chunks should be fed in source order, as when cloning from an archive whose chunks
+    /// are stored in the order they first appear in the source. Data fed ahead of the output
+    /// position is kept in memory until the data before it has been fed, at most
+    /// `max_buffered` bytes. A chunk used at several locations is written to all of them
+    /// when fed, so its later locations are always buffered.
+    ///
+    /// Feeding a chunk which would take the buffer over `max_buffered` bytes fails, as does
+    /// writing behind the output position. So with `max_buffered` at zero every chunk must be
+    /// fed exactly in source order and used only once. Shut down the writer of
+    /// [`CloneOutput::into_inner`] when done, which fails if any data is still missing.
+    pub fn append_only(writer: W, clone_index: ChunkIndex, max_buffered: usize) -> Self {
+        Self::new(OrderedWriter::new(writer, max_buffered), clone_index)
+    }
 }
 
 impl<T> CloneOutput<T> {
+    /// Create a clone output writing to output, using the default options.
     pub fn new(output: T, clone_index: ChunkIndex) -> Self {
-        Self {
-            inner: output,
+        Self::builder(output, clone_index).build()
+    }
+    /// Get a builder of a clone output, writing the chunks of clone index to output.
+    ///
+    /// Clone into memory, hashing the output while it is written:
+    /// ```
+    /// use bitar::{Chunk, ChunkIndex, CloneOutput, HashSum};
+    /// use std::io::Cursor;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> std::io::Result<()> {
+    /// let first = Chunk::from(vec![1u8; 16]).verify();
+    /// let second = Chunk::from(vec![2u8; 16]).verify();
+    /// // Output is the second chunk followed by the first one.
+    /// let mut clone_index = ChunkIndex::new_empty(HashSum::MAX_LEN);
+    /// clone_index.add_chunk(second.hash().clone(), second.len(), &[0]);
+    /// clone_index.add_chunk(first.hash().clone(), first.len(), &[16]);
+    ///
+    /// let mut output = CloneOutput::builder(Cursor::new(Vec::new()), clone_index)
+    ///     .hash_output(true)
+    ///     .build();
+    /// output.feed(&first).await?;
+    /// output.feed(&second).await?;
+    /// assert!(output.is_empty());
+    ///
+    /// let checksum = output.output_checksum().unwrap();
+    /// let data = output.into_inner().into_inner();
+    /// assert_eq!(&data[..16], second.data());
+    /// assert_eq!(checksum, *Chunk::from(data).verify().hash());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn builder(output: T, clone_index: ChunkIndex) -> CloneOutputBuilder<T> {
+        CloneOutputBuilder {
+            output,
             clone_index,
+            base_offset: 0,
+            source_range: None,
+            hash_output: false,
+            sparse: false,
+            sequential_write: None,
+            skip_unchanged: None,
+            #[cfg(target_os = "linux")]
+            reflink: None,
+        }
+    }
+    /// Get the checksum of the output (Blake2), when hashing is enabled and every chunk has
+    /// been written.
+    ///
+    /// Chunks already in place when re-ordering in place are never written, hence there's no
+    /// checksum of output cloned in place.
+    pub fn output_checksum(&self) -> Option<HashSum> {
+        self.hasher.as_ref().and_then(OutputHasher::checksum)
+    }
+    // Write chunk to offsets, or buffer it when writing sequentially.
+    async fn write_offset(&mut self, offsets: &[u64], chunk: &Chunk) -> io::Result<usize>
+    where
+        T: AsyncWrite + AsyncSeek + Unpin + Send,
+    {
+        self.bytes_written += (chunk.len() * offsets.len()) as u64;
+        let Some(sequential) = &mut self.sequential else {
+            return self.write_offset_now(offsets, chunk).await;
+        };
+        let skip_write = self.sparse && chunk.data().iter().all(|&b| b == 0);
+        sequential.add(
+            &self.clone_index,
+            offsets,
+            (!skip_write).then_some(&chunk.0),
+        );
+        for &offset in offsets {
+            if let Some(hasher) = &mut self.hasher {
+                hasher.update(offset, &chunk.0);
+            }
+            self.chunks_written += 1;
+        }
+        self.write_buffered(self.clone_index.is_empty()).await?;
+        Ok(chunk.len() * offsets.len())
+    }
+    // Write the chunks buffered by sequential write which are next in order, or all of them.
+    async fn write_buffered(&mut self, all: bool) -> io::Result<()>
+    where
+        T: AsyncWrite + AsyncSeek + Unpin + Send,
+    {
+        let Some(sequential) = &mut self.sequential else {
+            return Ok(());
+        };
+        let write_before = sequential.write_before(all);
+        while let Some(entry) = sequential.pending.first_entry() {
+            if *entry.key() >= write_before {
+                break;
+            }
+            let (offset, data) = entry.remove_entry();
+            sequential.buffered -= data.len();
+            let Some((position, range)) = output_location(
+                self.base_offset,
+                self.source_range.as_ref(),
+                offset,
+                data.len(),
+            ) else {
+                continue;
+            };
+            if is_unchanged(
+                &mut self.inner,
+                self.unchanged.as_mut(),
+                position,
+                &data[range.clone()],
+            )
+            .await?
+            {
+                sequential.position = None;
+                continue;
+            }
+            if sequential.position != Some(position) {
+                self.inner.seek(SeekFrom::Start(position)).await?;
+            }
+            self.inner.write_all(&data[range.clone()]).await?;
+            sequential.position = Some(position + range.len() as u64);
         }
+        Ok(())
     }
-    async fn write_offset(&mut self, offsets: &[u64], verified: &VerifiedChunk) -> io::Result<usize>
+    /// Write any chunks buffered by sequential write.
+    ///
+    /// Buffered chunks are written once every chunk has been fed, only needed to get a
+    /// partial output when not all chunks will be fed.
+    pub async fn flush_buffered(&mut self) -> io::Result<()>
+    where
+        T: AsyncWrite + AsyncSeek + Unpin + Send,
+    {
+        self.write_buffered(true).await
+    }
+    async fn write_offset_now(&mut self, offsets: &[u64], chunk: &Chunk) -> io::Result<usize>
     where
         T: AsyncWrite + AsyncSeek + Unpin + Send,
     {
         let mut output_bytes = 0;
+        let skip_write = self.sparse && chunk.data().iter().all(|&b| b == 0);
         for &offset in offsets {
-            self.inner.seek(SeekFrom::Start(offset)).await?;
-            self.inner.write_all(verified.data()).await?;
-            output_bytes += verified.len();
+            if let (false, Some((position, range))) = (
+                skip_write,
+                output_location(
+                    self.base_offset,
+                    self.source_range.as_ref(),
+                    offset,
+                    chunk.len(),
+                ),
+            ) {
+                let data = &chunk.data()[range];
+                if !is_unchanged(&mut self.inner, self.unchanged.as_mut(), position, data).await? {
+                    self.inner.seek(SeekFrom::Start(position)).await?;
+                    self.inner.write_all(data).await?;
+                }
+            }
+            if let Some(hasher) = &mut self.hasher {
+                hasher.update(offset, &chunk.0);
+            }
+            output_bytes += chunk.len();
+            self.chunks_written += 1;
         }
         Ok(output_bytes)
     }
+    // Reflink the chunk at source to all dest offsets. Returns false if not possible, and
+    // then the chunk still has to be copied.
+    #[cfg(target_os = "linux")]
+    async fn reflink_offset(&mut self, source: u64, size: usize, dest: &[u64]) -> io::Result<bool>
+    where
+        T: AsyncWrite + Unpin,
+    {
+        if self.hasher.is_some() {
+            return Ok(false);
+        }
+        let Some(file) = &self.reflink else {
+            return Ok(false);
+        };
+        // Anything written through inner must reach the file first.
+        self.inner.flush().await?;
+        for &offset in dest {
+            if let Err(err) = crate::reflink::reflink_range(
+                file,
+                self.base_offset + source,
+                size as u64,
+                self.base_offset + offset,
+            ) {
+                if crate::reflink::is_unsupported(&err) {
+                    log::debug!("Output does not support reflink: {}", err);
+                    self.reflink = None;
+                }
+                return Ok(false);
+            }
+        }
+        self.chunks_written += dest.len() as u64;
+        Ok(true)
+    }
+    #[cfg(not(target_os = "linux"))]
+    async fn reflink_offset(
+        &mut self,
+        _source: u64,
+        _size: usize,
+        _dest: &[u64],
+    ) -> io::Result<bool> {
+        Ok(false)
+    }
     pub async fn feed(&mut self, verified: &VerifiedChunk) -> io::Result<usize>
     where
         T: AsyncWrite + AsyncSeek + Unpin + Send,
     {
         if let Some(location) = self.clone_index.remove(verified.hash()) {
-            Ok(self.write_offset(location.offsets(), verified).await?)
+            Ok(self
+                .write_offset(location.offsets(), verified.chunk())
+                .await?)
+        } else {
+            Ok(0)
+        }
+    }
+    /// Fill the locations of a chunk with zeros instead of the chunk data.
+    ///
+    /// For when a chunk can't be retrieved and a partial output is preferred over none.
+    /// The chunk is no longer waited for. Returns the number of bytes written.
+    pub async fn zero_fill(&mut self, hash: &HashSum) -> io::Result<usize>
+    where
+        T: AsyncWrite + AsyncSeek + Unpin + Send,
+    {
+        if let Some(location) = self.clone_index.remove(hash) {
+            let zeros = Chunk::from(vec![0; location.size()]);
+            self.write_offset(location.offsets(), &zeros).await
         } else {
             Ok(0)
         }
     }
+    /// Get the number of bytes of chunks fed or zero filled to the output, counting every
+    /// location a chunk is written to.
+    ///
+    /// Chunks re-ordered in place are not counted.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+    /// Get the number of bytes not written since output already held them, see
+    /// [`CloneOutputBuilder::skip_unchanged`].
+    pub fn bytes_unchanged(&self) -> u64 {
+        self.unchanged
+            .as_ref()
+            .map_or(0, |unchanged| unchanged.skipped)
+    }
     pub fn chunks(&self) -> &ChunkIndex {
         &self.clone_index
     }
@@ -58,6 +606,33 @@ impl<T> CloneOutput<T> {
     where
         T: AsyncRead + AsyncWrite + AsyncSeek + Unpin + Send,
     {
+        self.reorder_in_place_limited(output_index, usize::MAX)
+            .await
+    }
+    /// Re-order chunks of output in place, keeping at most `max_mem` bytes of chunks in memory.
+    ///
+    /// Chunks which would have to be kept in memory beyond the limit are not moved, but left
+    /// in the clone index to be fetched from elsewhere (eg the archive).
+    pub async fn reorder_in_place_limited(
+        &mut self,
+        output_index: ChunkIndex,
+        max_mem: usize,
+    ) -> io::Result<u64>
+    where
+        T: AsyncRead + AsyncWrite + AsyncSeek + Unpin + Send,
+    {
+        if self.source_range.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "can't re-order output of a source range in place",
+            ));
+        }
+        // Chunks are moved by immediate writes, anything buffered must be in place first.
+        self.write_buffered(true).await?;
+        if let Some(sequential) = &mut self.sequential {
+            sequential.missing = None;
+            sequential.position = None;
+        }
         let mut total_moved: u64 = 0;
         let (already_in_place, in_place_total_size) =
             output_index.strip_chunks_already_in_place(&mut self.clone_index);
@@ -68,6 +643,9 @@ impl<T> CloneOutput<T> {
         );
         let reorder_ops = output_index.reorder_ops(&self.clone_index);
         let mut temp_store: HashMap<&HashSum, VerifiedChunk> = HashMap::new();
+        let mut temp_store_size: usize = 0;
+        // Chunks not stored in memory due to the limit, hence not moved.
+        let mut not_stored: HashSet<&HashSum> = HashSet::new();
         let mut temp_buf = BytesMut::new();
         for op in reorder_ops {
             // Move chunks around internally in the output file
@@ -78,26 +656,47 @@ impl<T> CloneOutput<T> {
                     source,
                     dest,
                 } => {
+                    if not_stored.contains(hash) {
+                        // Source might already be overwritten.
+                        continue;
+                    }
                     if let Some(verified) = temp_store.remove(hash) {
-                        self.write_offset(&dest[..], &verified).await?;
+                        temp_store_size -= verified.len();
+                        self.write_offset_now(&dest[..], verified.chunk()).await?;
+                    } else if self.reflink_offset(source, size, &dest[..]).await? {
+                        log::trace!("Chunk '{}' moved by reflink", hash);
                     } else {
                         temp_buf.resize(size, 0);
-                        self.inner.seek(SeekFrom::Start(source)).await?;
+                        self.inner
+                            .seek(SeekFrom::Start(self.base_offset + source))
+                            .await?;
                         self.inner.read_exact(&mut temp_buf[..]).await?;
                         let verified = VerifiedChunk {
                             chunk: Chunk::from(temp_buf.clone().freeze()),
                             hash_sum: hash.clone(),
                         };
-                        self.write_offset(&dest[..], &verified).await?;
+                        self.write_offset_now(&dest[..], verified.chunk()).await?;
                     };
                     total_moved += size as u64;
                     self.clone_index.remove(hash);
                 }
                 ReorderOp::StoreInMem { hash, size, source } => {
                     if !temp_store.contains_key(hash) {
+                        if temp_store_size.saturating_add(size) > max_mem {
+                            log::debug!(
+                                "Chunk '{}' exceeds the re-order memory limit, not moved",
+                                hash
+                            );
+                            not_stored.insert(hash);
+                            continue;
+                        }
+                        not_stored.remove(hash);
+                        temp_store_size += size;
                         let mut buf = BytesMut::new();
                         buf.resize(size, 0);
-                        self.inner.seek(SeekFrom::Start(source)).await?;
+                        self.inner
+                            .seek(SeekFrom::Start(self.base_offset + source))
+                            .await?;
                         self.inner.read_exact(&mut buf[..]).await?;
                         temp_store.insert(
                             hash,
@@ -113,3 +712,401 @@ impl<T> CloneOutput<T> {
         Ok(total_moved + in_place_total_size)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn verified(value: u8) -> VerifiedChunk {
+        VerifiedChunk {
+            chunk: Chunk::from(vec![value; 8]),
+            hash_sum: HashSum::from(&[value]),
+        }
+    }
+
+    // Output with three chunks which should all be rotated one step, making a loop.
+    fn rotate_chunks() -> (CloneOutput<Cursor<Vec<u8>>>, ChunkIndex) {
+        let mut output_index = ChunkIndex::new_empty(HashSum::MAX_LEN);
+        let mut clone_index = ChunkIndex::new_empty(HashSum::MAX_LEN);
+        let mut data = Vec::new();
+        for (value, offset) in [(1u8, 0u64), (2, 8), (3, 16)] {
+            data.extend_from_slice(verified(value).data());
+            output_index.add_chunk(HashSum::from(&[value]), 8, &[offset]);
+            clone_index.add_chunk(HashSum::from(&[value]), 8, &[(offset + 16) % 24]);
+        }
+        (
+            CloneOutput::new(Cursor::new(data), clone_index),
+            output_index,
+        )
+    }
+
+    fn rotated() -> Vec<u8> {
+        [verified(2), verified(3), verified(1)]
+            .iter()
+            .flat_map(|verified| verified.data().to_vec())
+            .collect()
+    }
+
+    // Append only sink, an upload stream or the like.
+    struct Sink(Vec<u8>);
+
+    impl AsyncWrite for Sink {
+        fn poll_write(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> std::task::Poll<io::Result<usize>> {
+            self.get_mut().0.extend_from_slice(buf);
+            std::task::Poll::Ready(Ok(buf.len()))
+        }
+        fn poll_flush(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+        fn poll_shutdown(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn append_only_in_source_order() {
+        let mut clone_index = ChunkIndex::new_empty(HashSum::MAX_LEN);
+        clone_index.add_chunk(HashSum::from(&[1]), 8, &[0]);
+        clone_index.add_chunk(HashSum::from(&[2]), 8, &[8]);
+        let mut output = CloneOutput::append_only(Sink(Vec::new()), clone_index, 0);
+        output.feed(&verified(1)).await.unwrap();
+        output.feed(&verified(2)).await.unwrap();
+        assert!(output.is_empty());
+        let mut writer = output.into_inner();
+        writer.shutdown().await.unwrap();
+        assert_eq!(writer.into_inner().0, [[1u8; 8], [2u8; 8]].concat());
+    }
+
+    #[tokio::test]
+    async fn append_only_buffers_repeated_chunk() {
+        let mut clone_index = ChunkIndex::new_empty(HashSum::MAX_LEN);
+        clone_index.add_chunk(HashSum::from(&[1]), 8, &[0, 16]);
+        clone_index.add_chunk(HashSum::from(&[2]), 8, &[8]);
+        // The second location of the first chunk doesn't fit without buffering.
+        let mut output = CloneOutput::append_only(Sink(Vec::new()), clone_index.clone(), 0);
+        assert!(output.feed(&verified(1)).await.is_err());
+
+        let mut output = CloneOutput::append_only(Sink(Vec::new()), clone_index, 8);
+        output.feed(&verified(1)).await.unwrap();
+        assert_eq!(output.inner.buffered(), 8);
+        output.feed(&verified(2)).await.unwrap();
+        let mut writer = output.into_inner();
+        writer.shutdown().await.unwrap();
+        assert_eq!(
+            writer.into_inner().0,
+            [[1u8; 8], [2u8; 8], [1u8; 8]].concat()
+        );
+    }
+
+    #[tokio::test]
+    async fn append_only_out_of_order() {
+        let mut clone_index = ChunkIndex::new_empty(HashSum::MAX_LEN);
+        clone_index.add_chunk(HashSum::from(&[1]), 8, &[0]);
+        clone_index.add_chunk(HashSum::from(&[2]), 8, &[8]);
+        let mut output = CloneOutput::append_only(Sink(Vec::new()), clone_index, 0);
+        assert!(output.feed(&verified(2)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn reorder_in_place_loop() {
+        let (mut output, output_index) = rotate_chunks();
+        let moved = output.reorder_in_place(output_index).await.unwrap();
+        assert_eq!(moved, 24);
+        assert!(output.is_empty());
+        assert_eq!(output.into_inner().into_inner(), rotated());
+    }
+
+    #[tokio::test]
+    async fn reorder_in_place_loop_exceeding_mem_limit() {
+        let (mut output, output_index) = rotate_chunks();
+        let moved = output
+            .reorder_in_place_limited(output_index, 4)
+            .await
+            .unwrap();
+        // The chunk which should have been kept in memory is left to fetch elsewhere.
+        assert_eq!(moved, 16);
+        assert_eq!(output.len(), 1);
+        let (hash, _location) = output.chunks().iter_chunks().next().unwrap();
+        let missing = verified(hash.slice()[0]);
+        assert_eq!(output.feed(&missing).await.unwrap(), 8);
+        assert!(output.is_empty());
+        assert_eq!(output.into_inner().into_inner(), rotated());
+    }
+
+    #[tokio::test]
+    async fn base_offset_shifts_output() {
+        let (output, output_index) = rotate_chunks();
+        let clone_index = output.chunks().clone();
+        let data = [vec![0xffu8; 4], output.into_inner().into_inner()].concat();
+        let mut output = CloneOutput::builder(Cursor::new(data), clone_index)
+            .base_offset(4)
+            .build();
+        let moved = output.reorder_in_place(output_index).await.unwrap();
+        assert_eq!(moved, 24);
+        assert!(output.is_empty());
+        assert_eq!(
+            output.into_inner().into_inner(),
+            [vec![0xffu8; 4], rotated()].concat()
+        );
+    }
+
+    #[tokio::test]
+    async fn source_range_cuts_chunks() {
+        // Chunks 1, 2, 3 at 0, 8, 16 while cloning source range 4..20, at base offset 2.
+        let mut clone_index = ChunkIndex::new_empty(HashSum::MAX_LEN);
+        for (value, offset) in [(1u8, 0u64), (2, 8), (3, 16)] {
+            clone_index.add_chunk(HashSum::from(&[value]), 8, &[offset]);
+        }
+        let expected = [vec![0xffu8; 2], vec![1; 4], vec![2; 8], vec![3; 4]].concat();
+        for sequential in [false, true] {
+            let mut output =
+                CloneOutput::builder(Cursor::new(vec![0xffu8; 2]), clone_index.clone())
+                    .base_offset(2)
+                    .source_range(4..20)
+                    .hash_output(true);
+            if sequential {
+                output = output.sequential_write(1024);
+            }
+            let mut output = output.build();
+            for value in [3, 1, 2] {
+                assert_eq!(output.feed(&verified(value)).await.unwrap(), 8);
+            }
+            assert!(output.is_empty());
+            assert_eq!(output.output_checksum(), None);
+            assert_eq!(output.into_inner().into_inner(), expected);
+        }
+        let (output, output_index) = rotate_chunks();
+        let mut output = CloneOutput::builder(output.into_inner(), clone_index)
+            .source_range(4..20)
+            .build();
+        assert!(output.reorder_in_place(output_index).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn sparse_output_skips_zero_chunks() {
+        let zeros = Chunk::from(vec![0u8; 8]).verify();
+        let ones = Chunk::from(vec![1u8; 8]).verify();
+        let mut clone_index = ChunkIndex::new_empty(HashSum::MAX_LEN);
+        clone_index.add_chunk(ones.hash().clone(), 8, &[0]);
+        clone_index.add_chunk(zeros.hash().clone(), 8, &[8]);
+        let mut output = CloneOutput::builder(Cursor::new(Vec::new()), clone_index)
+            .sparse(true)
+            .build();
+        assert_eq!(output.feed(&zeros).await.unwrap(), 8);
+        assert_eq!(output.feed(&ones).await.unwrap(), 8);
+        assert!(output.is_empty());
+        assert_eq!(output.into_inner().into_inner(), vec![1u8; 8]);
+    }
+
+    #[tokio::test]
+    async fn zero_fill_chunk() {
+        let mut clone_index = ChunkIndex::new_empty(HashSum::MAX_LEN);
+        clone_index.add_chunk(HashSum::from(&[1]), 8, &[0, 16]);
+        clone_index.add_chunk(HashSum::from(&[2]), 8, &[8]);
+        let mut output = CloneOutput::new(Cursor::new(Vec::new()), clone_index);
+        assert_eq!(output.feed(&verified(2)).await.unwrap(), 8);
+        assert_eq!(output.zero_fill(&HashSum::from(&[1])).await.unwrap(), 16);
+        assert!(output.is_empty());
+        // Nothing left to fill.
+        assert_eq!(output.zero_fill(&HashSum::from(&[1])).await.unwrap(), 0);
+        assert_eq!(
+            output.into_inner().into_inner(),
+            [vec![0u8; 8], vec![2u8; 8], vec![0u8; 8]].concat()
+        );
+    }
+
+    #[tokio::test]
+    async fn output_checksum_out_of_order() {
+        let (output, _output_index) = rotate_chunks();
+        let clone_index = output.chunks().clone();
+        let mut output = CloneOutput::builder(Cursor::new(Vec::new()), clone_index)
+            .hash_output(true)
+            .build();
+        for value in [3, 1] {
+            output.feed(&verified(value)).await.unwrap();
+            assert!(output.output_checksum().is_none());
+        }
+        output.feed(&verified(2)).await.unwrap();
+        assert_eq!(
+            output.output_checksum().unwrap(),
+            HashSum::b2_digest(&rotated())
+        );
+        // Without hashing there's no checksum.
+        let (output, _output_index) = rotate_chunks();
+        assert!(output.output_checksum().is_none());
+    }
+
+    // Clone chunk 1 at offsets 0 and 16 and chunk 2 at offset 8 into a file already holding
+    // chunk 1 at offset 0, skipping unchanged writes.
+    #[cfg(unix)]
+    async fn skip_unchanged(max_buffered: Option<usize>) {
+        let file = tempfile::tempfile().unwrap();
+        std::io::Write::write_all(&mut &file, &[vec![1u8; 8], vec![9u8; 16]].concat()).unwrap();
+        let mut clone_index = ChunkIndex::new_empty(HashSum::MAX_LEN);
+        clone_index.add_chunk(HashSum::from(&[1]), 8, &[0, 16]);
+        clone_index.add_chunk(HashSum::from(&[2]), 8, &[8]);
+        let mut builder = CloneOutput::builder(
+            tokio::fs::File::from_std(file.try_clone().unwrap()),
+            clone_index,
+        )
+        .skip_unchanged(file.try_clone().unwrap());
+        if let Some(max_buffered) = max_buffered {
+            builder = builder.sequential_write(max_buffered);
+        }
+        let mut output = builder.build();
+        assert_eq!(output.feed(&verified(1)).await.unwrap(), 16);
+        assert_eq!(output.feed(&verified(2)).await.unwrap(), 8);
+        output.inner.flush().await.unwrap();
+        assert_eq!(output.bytes_unchanged(), 8);
+        let mut data = Vec::new();
+        std::io::Seek::rewind(&mut &file).unwrap();
+        std::io::Read::read_to_end(&mut &file, &mut data).unwrap();
+        assert_eq!(data, [vec![1u8; 8], vec![2u8; 8], vec![1u8; 8]].concat());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn skip_unchanged_writes() {
+        skip_unchanged(None).await;
+        skip_unchanged(Some(1024)).await;
+    }
+
+    // Output keeping track of the offset of each write.
+    struct WriteLog {
+        inner: Cursor<Vec<u8>>,
+        writes: Vec<u64>,
+    }
+
+    impl AsyncWrite for WriteLog {
+        fn poll_write(
+            self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> std::task::Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            this.writes.push(this.inner.position());
+            std::pin::Pin::new(&mut this.inner).poll_write(cx, buf)
+        }
+        fn poll_flush(
+            self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            std::pin::Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+        }
+        fn poll_shutdown(
+            self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            std::pin::Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+        }
+    }
+
+    impl AsyncSeek for WriteLog {
+        fn start_seek(self: std::pin::Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+            std::pin::Pin::new(&mut self.get_mut().inner).start_seek(position)
+        }
+        fn poll_complete(
+            self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<io::Result<u64>> {
+            std::pin::Pin::new(&mut self.get_mut().inner).poll_complete(cx)
+        }
+    }
+
+    // Clone chunk 1 at offsets 0 and 16 and chunk 2 at offset 8, feeding chunk 1 first.
+    async fn sequential_write(max_buffered: Option<usize>) -> WriteLog {
+        let mut clone_index = ChunkIndex::new_empty(HashSum::MAX_LEN);
+        clone_index.add_chunk(HashSum::from(&[1]), 8, &[0, 16]);
+        clone_index.add_chunk(HashSum::from(&[2]), 8, &[8]);
+        let output = WriteLog {
+            inner: Cursor::new(Vec::new()),
+            writes: Vec::new(),
+        };
+        let mut output = match max_buffered {
+            Some(max_buffered) => CloneOutput::builder(output, clone_index)
+                .sequential_write(max_buffered)
+                .build(),
+            None => CloneOutput::new(output, clone_index),
+        };
+        assert_eq!(output.feed(&verified(1)).await.unwrap(), 16);
+        assert_eq!(output.feed(&verified(2)).await.unwrap(), 8);
+        assert!(output.is_empty());
+        let output = output.into_inner();
+        assert_eq!(
+            output.inner.get_ref(),
+            &[vec![1u8; 8], vec![2u8; 8], vec![1u8; 8]].concat()
+        );
+        output
+    }
+
+    #[tokio::test]
+    async fn sequential_write_in_offset_order() {
+        assert_eq!(sequential_write(None).await.writes, [0, 16, 8]);
+        assert_eq!(sequential_write(Some(1024)).await.writes, [0, 8, 16]);
+        // With a full buffer chunks are written as fed, still in offset order.
+        assert_eq!(sequential_write(Some(0)).await.writes, [0, 16, 8]);
+    }
+
+    #[tokio::test]
+    async fn sequential_write_flush_buffered() {
+        let mut clone_index = ChunkIndex::new_empty(HashSum::MAX_LEN);
+        clone_index.add_chunk(HashSum::from(&[1]), 8, &[8]);
+        clone_index.add_chunk(HashSum::from(&[2]), 8, &[0]);
+        let mut output = CloneOutput::builder(Cursor::new(Vec::new()), clone_index)
+            .sequential_write(1024)
+            .build();
+        output.feed(&verified(1)).await.unwrap();
+        // Waiting for the chunk at offset 0.
+        assert!(output.inner.get_ref().is_empty());
+        output.flush_buffered().await.unwrap();
+        assert_eq!(
+            output.into_inner().into_inner(),
+            [vec![0u8; 8], vec![1u8; 8]].concat()
+        );
+    }
+
+    // Moving block aligned chunks in a file, by reflink where the file system supports it
+    // and by an in-kernel copy elsewhere (eg tmpfs and ext4).
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn reorder_in_place_reflink() {
+        const SIZE: usize = 4096;
+        let mut output_index = ChunkIndex::new_empty(HashSum::MAX_LEN);
+        let mut clone_index = ChunkIndex::new_empty(HashSum::MAX_LEN);
+        let mut data = Vec::new();
+        let mut expected = vec![0u8; 3 * SIZE];
+        for (value, offset) in [(1u8, 0u64), (2, 4096), (3, 8192)] {
+            let chunk = Chunk::from(vec![value; SIZE]).verify();
+            let dest = (offset + 8192) % 12288;
+            data.extend_from_slice(chunk.data());
+            expected[dest as usize..dest as usize + SIZE].copy_from_slice(chunk.data());
+            output_index.add_chunk(chunk.hash().clone(), SIZE, &[offset]);
+            clone_index.add_chunk(chunk.hash().clone(), SIZE, &[dest]);
+        }
+        let mut file = tempfile::tempfile().unwrap();
+        std::io::Write::write_all(&mut file, &data).unwrap();
+        let reflink = file.try_clone().unwrap();
+        let mut output = CloneOutput::builder(tokio::fs::File::from_std(file), clone_index)
+            .reflink(reflink)
+            .build();
+        let moved = output.reorder_in_place(output_index).await.unwrap();
+        assert_eq!(moved, 3 * SIZE as u64);
+        assert!(output.is_empty());
+        let mut file = output.into_inner();
+        file.seek(SeekFrom::Start(0)).await.unwrap();
+        let mut result = Vec::new();
+        file.read_to_end(&mut result).await.unwrap();
+        assert_eq!(result, expected);
+    }
+}
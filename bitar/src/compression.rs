@@ -1,4 +1,4 @@
-use bytes::Bytes;
+use bytes::{BufMut, Bytes, BytesMut};
 use std::fmt;
 
 use crate::chunk_dictionary as dict;
@@ -8,6 +8,8 @@ pub enum CompressionError {
     Io(std::io::Error),
     #[cfg(feature = "lzma-compression")]
     LZMA(lzma::LzmaError),
+    #[cfg(feature = "lz4-compression")]
+    LZ4(lz4_flex::block::DecompressError),
 }
 impl std::error::Error for CompressionError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
@@ -15,6 +17,8 @@ impl std::error::Error for CompressionError {
             CompressionError::Io(err) => Some(err),
             #[cfg(feature = "lzma-compression")]
             CompressionError::LZMA(err) => Some(err),
+            #[cfg(feature = "lz4-compression")]
+            CompressionError::LZ4(err) => Some(err),
         }
     }
 }
@@ -24,6 +28,8 @@ impl fmt::Display for CompressionError {
             Self::Io(_) => write!(f, "i/o error"),
             #[cfg(feature = "lzma-compression")]
             Self::LZMA(_) => write!(f, "LZMA error"),
+            #[cfg(feature = "lz4-compression")]
+            Self::LZ4(_) => write!(f, "LZ4 error"),
         }
     }
 }
@@ -38,6 +44,12 @@ impl From<lzma::LzmaError> for CompressionError {
         Self::LZMA(e)
     }
 }
+#[cfg(feature = "lz4-compression")]
+impl From<lz4_flex::block::DecompressError> for CompressionError {
+    fn from(e: lz4_flex::block::DecompressError) -> Self {
+        Self::LZ4(e)
+    }
+}
 
 #[derive(Debug)]
 pub struct CompressionLevelOutOfRangeError(CompressionAlgorithm);
@@ -59,6 +71,8 @@ pub enum CompressionAlgorithm {
     Lzma,
     #[cfg(feature = "zstd-compression")]
     Zstd,
+    #[cfg(feature = "lz4-compression")]
+    Lz4,
     Brotli,
 }
 
@@ -72,6 +86,9 @@ impl CompressionAlgorithm {
             CompressionAlgorithm::Zstd => {
                 u32::try_from(*zstd::compression_level_range().end()).unwrap()
             }
+            // LZ4 has no compression levels to speak of.
+            #[cfg(feature = "lz4-compression")]
+            CompressionAlgorithm::Lz4 => 1,
             CompressionAlgorithm::Brotli => 11,
         }
     }
@@ -81,26 +98,48 @@ impl CompressionAlgorithm {
         compressed: Bytes,
         size_hint: usize,
     ) -> Result<Bytes, CompressionError> {
-        let mut output = Vec::with_capacity(size_hint);
+        let mut output = BytesMut::new();
+        self.decompress_into(&compressed, size_hint, &mut output)?;
+        Ok(output.freeze())
+    }
+    /// Decompress a block of data using the set compression, appending it to output.
+    ///
+    /// Data is decompressed straight into output, which grows by at least `size_hint`.
+    /// Lets the caller reuse the allocation of output across chunks.
+    pub(crate) fn decompress_into(
+        self,
+        compressed: &[u8],
+        size_hint: usize,
+        output: &mut BytesMut,
+    ) -> Result<(), CompressionError> {
+        output.reserve(size_hint);
         match self {
             #[cfg(feature = "lzma-compression")]
             CompressionAlgorithm::Lzma => {
                 use lzma::LzmaWriter;
                 use std::io::prelude::*;
-                let mut f = LzmaWriter::new_decompressor(&mut output)?;
-                f.write_all(&compressed)?;
+                let mut f = LzmaWriter::new_decompressor(output.writer())?;
+                f.write_all(compressed)?;
                 f.finish()?;
             }
             #[cfg(feature = "zstd-compression")]
             CompressionAlgorithm::Zstd => {
-                zstd::stream::copy_decode(&compressed[..], &mut output)?;
+                zstd::stream::copy_decode(compressed, output.writer())?;
+            }
+            #[cfg(feature = "lz4-compression")]
+            CompressionAlgorithm::Lz4 => {
+                let (size, compressed) = lz4_flex::block::uncompressed_size(compressed)?;
+                let start = output.len();
+                output.resize(start + size, 0);
+                let written = lz4_flex::block::decompress_into(compressed, &mut output[start..])?;
+                output.truncate(start + written);
             }
             CompressionAlgorithm::Brotli => {
-                let mut input_slice = &compressed[..];
-                brotli_decompressor::BrotliDecompress(&mut input_slice, &mut output)?;
+                let mut input_slice = compressed;
+                brotli_decompressor::BrotliDecompress(&mut input_slice, &mut output.writer())?;
             }
         }
-        Ok(Bytes::from(output))
+        Ok(())
     }
 }
 
@@ -111,6 +150,8 @@ impl fmt::Display for CompressionAlgorithm {
             CompressionAlgorithm::Lzma => "LZMA",
             #[cfg(feature = "zstd-compression")]
             CompressionAlgorithm::Zstd => "zstd",
+            #[cfg(feature = "lz4-compression")]
+            CompressionAlgorithm::Lz4 => "LZ4",
             CompressionAlgorithm::Brotli => "Brotli",
         };
         write!(f, "{}", algorithm_name)
@@ -122,6 +163,7 @@ impl fmt::Display for CompressionAlgorithm {
 pub struct Compression {
     pub(crate) algorithm: CompressionAlgorithm,
     pub(crate) level: u32,
+    pub(crate) zstd_long_distance: bool,
 }
 
 impl Compression {
@@ -133,7 +175,11 @@ impl Compression {
         if level < 1 || level > algorithm.max_level() {
             return Err(CompressionLevelOutOfRangeError(algorithm));
         }
-        Ok(Compression { algorithm, level })
+        Ok(Compression {
+            algorithm,
+            level,
+            zstd_long_distance: false,
+        })
     }
     /// Create a new brotli compression of given level.
     pub fn brotli(level: u32) -> Result<Compression, CompressionLevelOutOfRangeError> {
@@ -149,6 +195,37 @@ impl Compression {
     pub fn zstd(level: u32) -> Result<Compression, CompressionLevelOutOfRangeError> {
         Self::try_new(CompressionAlgorithm::Zstd, level)
     }
+    #[cfg(feature = "lz4-compression")]
+    /// Create a new lz4 compression of given level.
+    pub fn lz4(level: u32) -> Result<Compression, CompressionLevelOutOfRangeError> {
+        Self::try_new(CompressionAlgorithm::Lz4, level)
+    }
+    /// Get the compression algorithm.
+    pub fn algorithm(&self) -> CompressionAlgorithm {
+        self.algorithm
+    }
+    /// Get the compression level.
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+    /// Create the same compression with another level.
+    pub fn with_level(self, level: u32) -> Result<Compression, CompressionLevelOutOfRangeError> {
+        Ok(Compression {
+            zstd_long_distance: self.zstd_long_distance,
+            ..Self::try_new(self.algorithm, level)?
+        })
+    }
+    #[cfg(feature = "zstd-compression")]
+    /// Enable zstd long distance matching when compressing.
+    ///
+    /// Lets zstd find matches further back within large chunks, eg repeated content of log
+    /// like data. Does not affect other algorithms, and chunks are decompressed as any other
+    /// zstd chunk.
+    #[must_use]
+    pub fn with_zstd_long_distance(mut self, enabled: bool) -> Self {
+        self.zstd_long_distance = enabled;
+        self
+    }
     /// Compress a block of data with set compression.
     #[cfg(feature = "compress")]
     pub(crate) fn compress(self, chunk: Bytes) -> Result<Bytes, CompressionError> {
@@ -166,7 +243,19 @@ impl Compression {
             }
             #[cfg(feature = "zstd-compression")]
             CompressionAlgorithm::Zstd => {
-                zstd::stream::copy_encode(&chunk[..], &mut output, self.level as i32)?;
+                let mut encoder = zstd::stream::Encoder::new(&mut output, self.level as i32)?;
+                if self.zstd_long_distance {
+                    encoder.long_distance_matching(true)?;
+                    // The window of long distance matching is sized to the chunk, and not to
+                    // its max, when the size is known.
+                    encoder.set_pledged_src_size(Some(chunk.len() as u64))?;
+                }
+                encoder.write_all(&chunk)?;
+                encoder.finish()?;
+            }
+            #[cfg(feature = "lz4-compression")]
+            CompressionAlgorithm::Lz4 => {
+                output = lz4_flex::block::compress_prepend_size(&chunk);
             }
             CompressionAlgorithm::Brotli => {
                 let params = BrotliEncoderParams {
@@ -185,7 +274,11 @@ impl Compression {
 
 impl fmt::Display for Compression {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{} (level {})", self.algorithm, self.level)
+        write!(f, "{} (level {})", self.algorithm, self.level)?;
+        if self.zstd_long_distance {
+            write!(f, " with long distance matching")?;
+        }
+        Ok(())
     }
 }
 
@@ -196,15 +289,24 @@ impl From<Option<Compression>> for dict::ChunkCompression {
             Some(Compression {
                 algorithm: CompressionAlgorithm::Lzma,
                 level,
+                ..
             }) => (dict::chunk_compression::CompressionType::Lzma, level),
             #[cfg(feature = "zstd-compression")]
             Some(Compression {
                 algorithm: CompressionAlgorithm::Zstd,
                 level,
+                ..
             }) => (dict::chunk_compression::CompressionType::Zstd, level),
+            #[cfg(feature = "lz4-compression")]
+            Some(Compression {
+                algorithm: CompressionAlgorithm::Lz4,
+                level,
+                ..
+            }) => (dict::chunk_compression::CompressionType::Lz4, level),
             Some(Compression {
                 algorithm: CompressionAlgorithm::Brotli,
                 level,
+                ..
             }) => (dict::chunk_compression::CompressionType::Brotli, level),
             None => (dict::chunk_compression::CompressionType::None, 0),
         };
@@ -1,8 +1,11 @@
-use blake2::{Blake2b512, Digest};
+use blake2::{Blake2b512, Blake2s256, Digest};
 use std::{
     cmp, fmt,
     hash::{Hash, Hasher},
 };
+use xxhash_rust::xxh3::xxh3_128;
+
+use crate::chunk_dictionary::chunker_parameters::ChunkHashAlgorithm;
 
 /// Holds a hash sum.
 ///
@@ -53,6 +56,68 @@ impl HashSum {
     }
 }
 
+/// Algorithm used to hash chunks, identifying the chunks of an archive.
+///
+/// The checksum of the archive source is always a blake2 hash.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum HashAlgorithm {
+    /// BLAKE2b, 512 bits.
+    #[default]
+    Blake2b,
+    /// XXH3, 128 bits. Much faster than blake2 but not cryptographic, chunks with colliding
+    /// hashes are easily made. Only use with trusted archives and seeds.
+    Xxh3,
+    /// BLAKE2s, 256 bits. Operates on 32-bit words, faster than BLAKE2b on 32-bit targets
+    /// while BLAKE2b is faster on 64-bit targets.
+    Blake2s,
+}
+
+impl HashAlgorithm {
+    /// Length of the hash sum in bytes.
+    pub fn hash_length(self) -> usize {
+        match self {
+            HashAlgorithm::Blake2b => HashSum::MAX_LEN,
+            HashAlgorithm::Xxh3 => 16,
+            HashAlgorithm::Blake2s => 32,
+        }
+    }
+    /// True if it's a cryptographic hash, ie chunks with colliding hashes can't be made.
+    pub fn is_cryptographic(self) -> bool {
+        match self {
+            HashAlgorithm::Blake2b | HashAlgorithm::Blake2s => true,
+            HashAlgorithm::Xxh3 => false,
+        }
+    }
+    /// Hash data.
+    pub fn digest(self, data: &[u8]) -> HashSum {
+        match self {
+            HashAlgorithm::Blake2b => HashSum::b2_digest(data),
+            HashAlgorithm::Xxh3 => HashSum::from(xxh3_128(data).to_be_bytes()),
+            HashAlgorithm::Blake2s => HashSum::from(Blake2s256::digest(data)),
+        }
+    }
+}
+
+impl From<HashAlgorithm> for ChunkHashAlgorithm {
+    fn from(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Blake2b => ChunkHashAlgorithm::Blake2b,
+            HashAlgorithm::Xxh3 => ChunkHashAlgorithm::Xxh3,
+            HashAlgorithm::Blake2s => ChunkHashAlgorithm::Blake2s,
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HashAlgorithm::Blake2b => write!(f, "blake2b"),
+            HashAlgorithm::Xxh3 => write!(f, "xxh3"),
+            HashAlgorithm::Blake2s => write!(f, "blake2s"),
+        }
+    }
+}
+
 impl<T> From<T> for HashSum
 where
     T: AsRef<[u8]>,
@@ -119,6 +184,30 @@ impl fmt::Debug for HashSum {
 mod tests {
     use super::*;
 
+    #[test]
+    fn digest_length() {
+        for algorithm in [
+            HashAlgorithm::Blake2b,
+            HashAlgorithm::Xxh3,
+            HashAlgorithm::Blake2s,
+        ] {
+            let hash = algorithm.digest(b"bita");
+            assert_eq!(hash.len(), algorithm.hash_length());
+            assert_eq!(hash, algorithm.digest(b"bita"));
+            assert_ne!(hash, algorithm.digest(b"bitar"));
+        }
+        // Canonical (big endian) XXH3-128 of no data.
+        assert_eq!(
+            HashAlgorithm::Xxh3.digest(&[]).to_string(),
+            "99aa06d3014798d86001c324468d497f"
+        );
+        // BLAKE2s-256 test vector of "abc" (RFC 7693).
+        assert_eq!(
+            HashAlgorithm::Blake2s.digest(b"abc").to_string(),
+            "508c5e8c327c14e2e1a72ba34eeb452f37458b209ed63a294d999b4c86675982"
+        );
+    }
+
     #[test]
     fn zero_length() {
         let zero_length_hash = HashSum::from(&[]);
@@ -7,8 +7,31 @@
 //! |     14 |    n | Protobuf encoded dictionary.                                        |
 //! |      n |    8 | Chunk data offset in archive, absolute from archive start (u64 le). |
 //! |  n + 8 |   64 | Full header checksum (blake2), from offset 0 to n + 8.              |
+//! | n + 72 |   64 | Optional header signature (ed25519), from offset 0 to n + 72.       |
+//!
+//! The signature is only present in signed archives. An unsigned archive's chunk data starts
+//! directly after the header checksum.
+//!
+//! Archives written by early bita versions (eg 0.1.1) start with the legacy file magic
+//! \0BITA1 and are otherwise laid out the same.
+//!
+//! An archive with the file magic BITA1C instead stores the dictionary brotli compressed, the
+//! dictionary size is then the compressed size.
+//!
+//! An archive may also be written with the header at the end (trailer archive), which lets a
+//! producer stream chunk data straight to the output:
+//!
+//! |     Offset | Size | Description                                                     |
+//! |------------|------|-----------------------------------------------------------------|
+//! |          0 |    6 | Trailer archive file magic (BITA1T).                            |
+//! |          6 |    n | Chunk data.                                                     |
+//! |      n + 6 |    h | Archive header as above, with chunk data offset 6.              |
+//! |  n + h + 6 |    8 | Header offset in archive, n + 6 (u64 le).                       |
+//! | n + h + 14 |    8 | Header size h, including any signature (u64 le).                |
+//! | n + h + 22 |    6 | Trailer archive file magic (BITA1T).                            |
 
 use blake2::{Blake2b512, Digest};
+use ed25519_dalek::Signer;
 use prost::Message;
 
 use crate::chunk_dictionary::ChunkDictionary;
@@ -16,22 +39,86 @@ use crate::chunk_dictionary::ChunkDictionary;
 /// Archive file magic
 pub const ARCHIVE_MAGIC: &[u8; 6] = b"BITA1\0";
 
+/// Archive file magic of an archive with a compressed dictionary.
+pub const COMPRESSED_ARCHIVE_MAGIC: &[u8; 6] = b"BITA1C";
+
+/// File magic at start and end of an archive with the header at the end.
+pub const TRAILER_MAGIC: &[u8; 6] = b"BITA1T";
+
+/// Size of the footer ending an archive with the header at the end.
+pub const FOOTER_SIZE: usize = 8 + 8 + TRAILER_MAGIC.len();
+
 /// Pre header is the file magic + the size of the dictionary length value (u64)
 pub const PRE_HEADER_SIZE: usize = 6 + std::mem::size_of::<u64>();
 
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+
+/// Size of the optional header signature.
+pub const SIGNATURE_SIZE: usize = ed25519_dalek::SIGNATURE_LENGTH;
+
 /// Build an archive header from dictionary.
+///
+/// If a signing key is given the header is signed and the signature is appended to the header.
 pub fn build(
     dictionary: &ChunkDictionary,
     chunk_data_offset: Option<u64>,
+    signing_key: Option<&SigningKey>,
 ) -> Result<Vec<u8>, std::io::Error> {
-    let mut header: Vec<u8> = vec![];
-    let mut hasher = Blake2b512::new();
     let mut dictionary_buf: Vec<u8> = Vec::new();
-
     dictionary.encode(&mut dictionary_buf)?;
+    build_header(
+        ARCHIVE_MAGIC,
+        dictionary_buf,
+        chunk_data_offset,
+        signing_key,
+    )
+}
+
+/// Build an archive header from dictionary, with the dictionary brotli compressed.
+///
+/// Shrinks the header of archives with many chunks, which is read in full before cloning.
+/// Chunk hashes hardly compress, the savings come from the chunk offsets, sizes and the
+/// rebuild order.
+/// Archives with a compressed dictionary can't be read by bitar versions not knowing of
+/// [`COMPRESSED_ARCHIVE_MAGIC`].
+#[cfg(feature = "compress")]
+pub fn build_compressed(
+    dictionary: &ChunkDictionary,
+    chunk_data_offset: Option<u64>,
+    signing_key: Option<&SigningKey>,
+) -> Result<Vec<u8>, std::io::Error> {
+    use brotli::enc::backward_references::BrotliEncoderParams;
+    use std::io::Write;
+    let mut dictionary_buf: Vec<u8> = Vec::new();
+    {
+        let params = BrotliEncoderParams {
+            quality: 6,
+            magic_number: false,
+            ..Default::default()
+        };
+        let mut writer =
+            brotli::CompressorWriter::with_params(&mut dictionary_buf, 1024 * 1024, &params);
+        writer.write_all(&dictionary.encode_to_vec())?;
+    }
+    build_header(
+        COMPRESSED_ARCHIVE_MAGIC,
+        dictionary_buf,
+        chunk_data_offset,
+        signing_key,
+    )
+}
+
+fn build_header(
+    magic: &[u8],
+    dictionary_buf: Vec<u8>,
+    chunk_data_offset: Option<u64>,
+    signing_key: Option<&SigningKey>,
+) -> Result<Vec<u8>, std::io::Error> {
+    let mut header: Vec<u8> = vec![];
+    let mut hasher = Blake2b512::new();
 
     // File magic indicating bita archive version 1
-    header.extend(ARCHIVE_MAGIC);
+    header.extend(magic);
 
     // Chunk dictionary size
     header.extend((dictionary_buf.len() as u64).to_le_bytes());
@@ -42,7 +129,14 @@ pub fn build(
     // Start of archive chunk data, absolute to the archive start
     let offset = match chunk_data_offset {
         Some(o) => o,
-        None => header.len() as u64 + 8 + 64,
+        None => {
+            let signature_size = if signing_key.is_some() {
+                SIGNATURE_SIZE
+            } else {
+                0
+            };
+            (header.len() + 8 + 64 + signature_size) as u64
+        }
     };
     header.extend(offset.to_le_bytes());
 
@@ -50,5 +144,68 @@ pub fn build(
     hasher.update(&header);
     header.extend(hasher.finalize());
 
+    // Sign the full header, including the checksum
+    if let Some(signing_key) = signing_key {
+        let signature = signing_key.sign(&header);
+        header.extend(signature.to_bytes());
+    }
+
     Ok(header)
 }
+
+// Decompress a dictionary of an archive with `COMPRESSED_ARCHIVE_MAGIC`, failing if it
+// decompresses to more than `max_size` bytes.
+pub(crate) fn decompress_dictionary(
+    compressed: &[u8],
+    max_size: usize,
+) -> Result<Vec<u8>, std::io::Error> {
+    use std::io::Read;
+    let mut dictionary_buf = Vec::new();
+    brotli_decompressor::Decompressor::new(compressed, 64 * 1024)
+        .take((max_size as u64).saturating_add(1))
+        .read_to_end(&mut dictionary_buf)?;
+    if dictionary_buf.len() > max_size {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "decompressed dictionary exceeds max header size {}",
+                max_size
+            ),
+        ));
+    }
+    Ok(dictionary_buf)
+}
+
+/// Build the header and footer to append to an archive with the header at the end.
+///
+/// The archive is expected to start with `TRAILER_MAGIC` directly followed by
+/// `chunk_data_size` bytes of chunk data.
+pub fn build_trailer(
+    dictionary: &ChunkDictionary,
+    chunk_data_size: u64,
+    signing_key: Option<&SigningKey>,
+) -> Result<Vec<u8>, std::io::Error> {
+    let header = build(dictionary, Some(TRAILER_MAGIC.len() as u64), signing_key)?;
+    Ok(append_footer(header, chunk_data_size))
+}
+
+/// Build the header and footer to append to an archive with the header at the end, with the
+/// dictionary brotli compressed as by [`build_compressed`].
+#[cfg(feature = "compress")]
+pub fn build_compressed_trailer(
+    dictionary: &ChunkDictionary,
+    chunk_data_size: u64,
+    signing_key: Option<&SigningKey>,
+) -> Result<Vec<u8>, std::io::Error> {
+    let header = build_compressed(dictionary, Some(TRAILER_MAGIC.len() as u64), signing_key)?;
+    Ok(append_footer(header, chunk_data_size))
+}
+
+fn append_footer(mut trailer: Vec<u8>, chunk_data_size: u64) -> Vec<u8> {
+    let chunk_data_offset = TRAILER_MAGIC.len() as u64;
+    let header_size = trailer.len() as u64;
+    trailer.extend((chunk_data_offset + chunk_data_size).to_le_bytes());
+    trailer.extend(header_size.to_le_bytes());
+    trailer.extend(TRAILER_MAGIC);
+    trailer
+}
@@ -7,25 +7,31 @@ mod chunk_offset;
 mod clone_output;
 mod compression;
 mod hashsum;
-mod rolling_hash;
+mod ordered_writer;
+#[cfg(target_os = "linux")]
+mod reflink;
 
+pub mod analyze;
 pub mod api;
 
 pub mod archive_reader;
 #[rustfmt::skip]
 pub mod chunk_dictionary;
 pub mod chunker;
+pub mod clone;
 pub mod header;
+pub mod rolling_hash;
 
-pub use archive::{Archive, ArchiveError};
+pub use archive::{Archive, ArchiveBuilder, ArchiveError};
 pub use chunk::{
-    ArchiveChunk, Chunk, CompressedArchiveChunk, CompressedChunk, HashSumMismatchError,
-    VerifiedChunk,
+    ArchiveChunk, ArchiveChunkError, Chunk, CompressedArchiveChunk, CompressedChunk,
+    HashSumMismatchError, VerifiedChunk,
 };
-pub use chunk_index::{ChunkIndex, ChunkLocation, ReorderOp};
+pub use chunk_index::{ChunkIndex, ChunkLocation, ReorderCost, ReorderOp, ValidationError};
 pub use chunk_offset::ChunkOffset;
-pub use clone_output::CloneOutput;
+pub use clone_output::{CloneOutput, CloneOutputBuilder};
 pub use compression::{
     Compression, CompressionAlgorithm, CompressionError, CompressionLevelOutOfRangeError,
 };
-pub use hashsum::HashSum;
+pub use hashsum::{HashAlgorithm, HashSum};
+pub use ordered_writer::OrderedWriter;
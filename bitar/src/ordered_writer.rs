@@ -0,0 +1,234 @@
+use bytes::Bytes;
+use std::{
+    collections::BTreeMap,
+    io::{self, SeekFrom},
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+use tokio::io::{AsyncSeek, AsyncWrite};
+
+/// Writer making it possible to clone into a non-seekable output, like a pipe or stdout.
+///
+/// While cloning chunks are written to the output in whatever order they become
+/// available. The ordered writer buffers data written ahead of the current output
+/// position and passes it on to the inner writer in offset order, dropping buffers as
+/// they are written.
+///
+/// Seeking/writing behind the current output position, or buffering more data than
+/// the given cap, results in an error. Overlapping writes are not supported.
+pub struct OrderedWriter<W> {
+    inner: W,
+    position: u64,
+    written: u64,
+    pending: BTreeMap<u64, Bytes>,
+    pending_size: usize,
+    max_pending: usize,
+}
+
+impl<W> OrderedWriter<W> {
+    /// Create a new ordered writer buffering at most `max_buffered` bytes.
+    pub fn new(inner: W, max_buffered: usize) -> Self {
+        Self {
+            inner,
+            position: 0,
+            written: 0,
+            pending: BTreeMap::new(),
+            pending_size: 0,
+            max_pending: max_buffered,
+        }
+    }
+    /// Number of bytes passed on to the inner writer.
+    pub fn bytes_written(&self) -> u64 {
+        self.written
+    }
+    /// Number of bytes currently buffered, waiting to be written.
+    pub fn buffered(&self) -> usize {
+        self.pending_size
+    }
+    /// Get the inner writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W> OrderedWriter<W>
+where
+    W: AsyncWrite + Unpin,
+{
+    // Pass on buffered data which is next in order to the inner writer.
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while let Some(entry) = self.pending.first_entry() {
+            if *entry.key() != self.written {
+                break;
+            }
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, entry.get()))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            let data = entry.remove();
+            self.written += n as u64;
+            self.pending_size -= n;
+            if n < data.len() {
+                self.pending.insert(self.written, data.slice(n..));
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W> AsyncWrite for OrderedWriter<W>
+where
+    W: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.position < this.written {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "write at offset {} which is behind output position {}",
+                    this.position, this.written
+                ),
+            )));
+        }
+        let drained = this.poll_drain(cx)?;
+        if drained.is_ready() && this.position == this.written {
+            // Data is next in order, no need to buffer it.
+            let n = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+            this.written += n as u64;
+            this.position += n as u64;
+            return Poll::Ready(Ok(n));
+        }
+        if this.pending_size + buf.len() > this.max_pending {
+            if drained.is_pending() {
+                // Wait for the inner writer to make room.
+                return Poll::Pending;
+            }
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!(
+                    "buffered data would exceed {} bytes, waiting for data at offset {}",
+                    this.max_pending, this.written
+                ),
+            )));
+        }
+        if let Some(replaced) = this
+            .pending
+            .insert(this.position, Bytes::copy_from_slice(buf))
+        {
+            this.pending_size -= replaced.len();
+        }
+        this.pending_size += buf.len();
+        this.position += buf.len() as u64;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        if !this.pending.is_empty() {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("output is missing data at offset {}", this.written),
+            )));
+        }
+        // Not all writers flush on shutdown (eg tokio's stdout).
+        ready!(Pin::new(&mut this.inner).poll_flush(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+impl<W> AsyncSeek for OrderedWriter<W>
+where
+    W: Unpin,
+{
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+        this.position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => this.position.checked_add_signed(delta),
+            SeekFrom::End(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "seek from end is not supported",
+                ))
+            }
+        }
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid seek position"))?;
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.position))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+    async fn write_at(writer: &mut OrderedWriter<Vec<u8>>, offset: u64, data: &[u8]) {
+        writer.seek(SeekFrom::Start(offset)).await.unwrap();
+        writer.write_all(data).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn in_order() {
+        let mut writer = OrderedWriter::new(Vec::new(), 0);
+        write_at(&mut writer, 0, &[1, 2, 3]).await;
+        write_at(&mut writer, 3, &[4, 5]).await;
+        assert_eq!(writer.buffered(), 0);
+        writer.shutdown().await.unwrap();
+        assert_eq!(writer.into_inner(), vec![1, 2, 3, 4, 5]);
+    }
+
+    #[tokio::test]
+    async fn out_of_order() {
+        let mut writer = OrderedWriter::new(Vec::new(), 100);
+        write_at(&mut writer, 5, &[6, 7]).await;
+        write_at(&mut writer, 2, &[3, 4, 5]).await;
+        assert_eq!(writer.buffered(), 5);
+        assert_eq!(writer.bytes_written(), 0);
+        write_at(&mut writer, 0, &[1, 2]).await;
+        writer.flush().await.unwrap();
+        assert_eq!(writer.buffered(), 0);
+        assert_eq!(writer.bytes_written(), 7);
+        writer.shutdown().await.unwrap();
+        assert_eq!(writer.into_inner(), vec![1, 2, 3, 4, 5, 6, 7]);
+    }
+
+    #[tokio::test]
+    async fn buffer_cap_exceeded() {
+        let mut writer = OrderedWriter::new(Vec::new(), 4);
+        write_at(&mut writer, 4, &[1, 2, 3]).await;
+        writer.seek(SeekFrom::Start(7)).await.unwrap();
+        assert!(writer.write_all(&[4, 5]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn write_behind_position() {
+        let mut writer = OrderedWriter::new(Vec::new(), 100);
+        write_at(&mut writer, 0, &[1, 2, 3]).await;
+        writer.seek(SeekFrom::Start(1)).await.unwrap();
+        assert!(writer.write_all(&[1]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn shutdown_with_gap() {
+        let mut writer = OrderedWriter::new(Vec::new(), 100);
+        write_at(&mut writer, 0, &[1, 2]).await;
+        write_at(&mut writer, 3, &[4]).await;
+        assert!(writer.shutdown().await.is_err());
+    }
+}
@@ -0,0 +1,41 @@
+//! Copy ranges within a file by reflink, sharing the data extents rather than copying them.
+use rustix::io::Errno;
+use std::fs::File;
+use std::io;
+
+/// Reflink `len` bytes at `src_offset` of file to `dest_offset` of the same file.
+///
+/// Uses `copy_file_range`, which shares the data extents on file systems supporting reflinks
+/// (eg btrfs and XFS) and otherwise copies the data within the kernel. Fails if the ranges
+/// overlap.
+pub(crate) fn reflink_range(
+    file: &File,
+    mut src_offset: u64,
+    len: u64,
+    mut dest_offset: u64,
+) -> io::Result<()> {
+    let end = src_offset + len;
+    while src_offset < end {
+        let remaining = usize::try_from(end - src_offset).unwrap_or(usize::MAX);
+        let copied = rustix::fs::copy_file_range(
+            file,
+            Some(&mut src_offset),
+            file,
+            Some(&mut dest_offset),
+            remaining,
+        )?;
+        if copied == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if err tells that the file doesn't support reflinks at all, rather than
+/// the specific range not possible to reflink.
+pub(crate) fn is_unsupported(err: &io::Error) -> bool {
+    matches!(
+        Errno::from_io_error(err),
+        Some(Errno::OPNOTSUPP | Errno::NOTTY | Errno::XDEV | Errno::NOSYS | Errno::BADF)
+    )
+}
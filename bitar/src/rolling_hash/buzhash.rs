@@ -1,7 +1,5 @@
 use crate::rolling_hash::RollingHash;
 
-const BUZHASH_SEED: u32 = 0x1032_4195;
-
 #[allow(clippy::unreadable_literal)]
 static BUZHASH_TABLE: &[u32] = &[
     0xa40cc360, 0xbb785af8, 0x32c790bc, 0x6c64cd34, 0x83b4aa73, 0x36b691a5, 0x4631ad79, 0x5e49d231,
@@ -52,14 +50,24 @@ pub struct BuzHash {
 }
 
 impl BuzHash {
-    /// Create a new instance of BuzHash with the given window size.
+    /// Seed used by the chunker unless another one is given.
+    pub const DEFAULT_SEED: u32 = 0x1032_4195;
+
+    /// Create a new instance of BuzHash with the given window size, using the default seed.
     pub fn new(window: usize) -> Self {
+        Self::with_seed(window, Self::DEFAULT_SEED)
+    }
+    /// Create a new instance of BuzHash with the given window size and seed.
+    ///
+    /// The seed scrambles the byte-to-hash table, so hashers with different seeds produce
+    /// different sums for the same input.
+    pub fn with_seed(window: usize, seed: u32) -> Self {
         BuzHash {
             index: 0,
             buf: vec![0; window],
             window,
             hash_sum: 0,
-            buzhash_table: Self::generate_seeded_table(BUZHASH_SEED),
+            buzhash_table: Self::generate_seeded_table(seed),
             window_full: false,
             last_input: 0,
             repeated_input: 0,
@@ -69,6 +77,8 @@ impl BuzHash {
         BUZHASH_TABLE.iter().map(|x| x ^ seed).collect()
     }
     /// Should be used for processing input until hash is valid.
+    ///
+    /// The hash is valid once window size bytes have been processed.
     pub fn init(&mut self, in_val: u8) {
         if !self.window_full {
             let in_val = self.buzhash_table[in_val as usize];
@@ -106,7 +116,7 @@ impl BuzHash {
             }
         }
     }
-    /// Get current hash sum.
+    /// Get current hash sum of the bytes in the window.
     pub fn sum(&self) -> u32 {
         self.hash_sum
     }
@@ -202,4 +212,17 @@ mod tests {
         };
         assert_eq!(sums[9], 1_406_929_643);
     }
+    #[test]
+    fn seed_changes_sum() {
+        let mut h1 = BuzHash::new(4);
+        let mut h2 = BuzHash::with_seed(4, BuzHash::DEFAULT_SEED);
+        let mut h3 = BuzHash::with_seed(4, 1);
+        for v in [1, 2, 3, 4] {
+            h1.init(v);
+            h2.init(v);
+            h3.init(v);
+        }
+        assert_eq!(h1.sum(), h2.sum());
+        assert_ne!(h1.sum(), h3.sum());
+    }
 }
@@ -1,3 +1,4 @@
+//! Rolling hash algorithms used for finding chunk boundaries.
 mod buzhash;
 mod rollsum;
 
@@ -5,10 +6,19 @@ pub use buzhash::BuzHash;
 pub use rollsum::RollSum;
 
 /// Rolling hash.
+///
+/// A rolling hash keeps a hash sum of the last window size bytes of its input, updated for
+/// every byte pushed through it. The same window of input bytes always gives the same sum,
+/// independent of what came before it.
 pub trait RollingHash {
     /// Returns true if hasher has been initialized.
     fn init_done(&self) -> bool;
+    /// Process a byte while initializing, until `init_done` returns true.
     fn init(&mut self, value: u8);
+    /// Process a byte, rolling the oldest byte out of the window.
     fn input(&mut self, value: u8);
+    /// Get the hash sum of the bytes currently in the window.
+    ///
+    /// Only valid once the hasher has been initialized.
     fn sum(&self) -> u32;
 }
@@ -13,7 +13,7 @@ pub struct RollSum {
 }
 
 impl RollSum {
-    /// Create a new instance of BuzHash with the given window size.
+    /// Create a new instance of RollSum with the given window size.
     pub fn new(window_size: usize) -> Self {
         Self {
             s1: window_size as u32 * CHAR_OFFSET,
@@ -41,7 +41,9 @@ impl RollSum {
             self.offset = 0;
         }
     }
-    /// Get current hash sum.
+    /// Get current hash sum of the bytes in the window.
+    ///
+    /// The window starts out filled with zeros, so no initialization is needed.
     pub fn sum(&self) -> u32 {
         (self.s1 << 16) | (self.s2 & 0xffff)
     }
@@ -52,8 +54,8 @@ impl RollingHash for RollSum {
         // No init needed.
         true
     }
-    fn init(&mut self, _value: u8) {
-        unimplemented!("not used")
+    fn init(&mut self, value: u8) {
+        self.input(value)
     }
     fn input(&mut self, value: u8) {
         self.input(value)
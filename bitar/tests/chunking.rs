@@ -1,6 +1,7 @@
 use std::path::Path;
 
 use bitar::chunker::{Config, FilterBits, FilterConfig};
+use bitar::rolling_hash::BuzHash;
 use blake2::{Blake2b512, Digest};
 use futures_util::stream::StreamExt;
 
@@ -22,6 +23,7 @@ async fn buzhash() {
                     min_chunk_size: min,
                     max_chunk_size: max,
                     window_size: win,
+                    buzhash_seed: BuzHash::DEFAULT_SEED,
                 }),
                 source,
                 format!(
@@ -52,6 +54,7 @@ async fn rollsum() {
                     min_chunk_size: min,
                     max_chunk_size: max,
                     window_size: win,
+                    buzhash_seed: BuzHash::DEFAULT_SEED,
                 }),
                 source,
                 format!(
@@ -82,6 +85,153 @@ async fn fixed_size() {
     }
 }
 
+#[tokio::test]
+async fn resync_after_single_byte_insert() {
+    let source = std::fs::read("tests/resources/random.img").unwrap();
+    for config in &[
+        Config::BuzHash(FilterConfig {
+            filter_bits: FilterBits::from_size(512),
+            min_chunk_size: 20,
+            max_chunk_size: 1200,
+            window_size: 20,
+            buzhash_seed: BuzHash::DEFAULT_SEED,
+        }),
+        Config::RollSum(FilterConfig {
+            filter_bits: FilterBits::from_size(512),
+            min_chunk_size: 20,
+            max_chunk_size: 1200,
+            window_size: 20,
+            buzhash_seed: BuzHash::DEFAULT_SEED,
+        }),
+    ] {
+        let boundaries = chunk_boundaries(config, &source).await;
+        // Insert a byte right before a known boundary.
+        let insert_at = boundaries[10] - 1;
+        let mut modified = source.clone();
+        modified.insert(insert_at as usize, 0xaa);
+        let modified_boundaries = chunk_boundaries(config, &modified).await;
+
+        // Boundaries before the insert are untouched.
+        assert_eq!(&boundaries[..10], &modified_boundaries[..10]);
+        // Boundaries after the insert should, shifted by one byte, realign within two chunks.
+        let shifted: Vec<u64> = boundaries[10..].iter().map(|offset| offset + 1).collect();
+        let realigned = shifted[..3]
+            .iter()
+            .position(|offset| modified_boundaries.contains(offset))
+            .unwrap_or_else(|| panic!("{:?} did not resync within two chunks", config));
+        let resync_offset = shifted[realigned];
+        assert_eq!(
+            &shifted[realigned..],
+            &modified_boundaries[modified_boundaries
+                .iter()
+                .position(|&offset| offset == resync_offset)
+                .unwrap()..]
+        );
+    }
+}
+
+#[tokio::test]
+async fn unbounded_max_chunk_size() {
+    // A uniform source never matches the filter, so only the max chunk size splits it.
+    let source = vec![0u8; 4 * 1024 * 1024];
+    for config in &[
+        Config::BuzHash(FilterConfig {
+            max_chunk_size: 0,
+            ..FilterConfig::default()
+        }),
+        Config::RollSum(FilterConfig {
+            max_chunk_size: 0,
+            ..FilterConfig::default()
+        }),
+        Config::FixedSize(0),
+    ] {
+        assert_eq!(
+            chunk_boundaries(config, &source).await,
+            vec![source.len() as u64],
+            "{:?}",
+            config
+        );
+    }
+    // While a bounded chunker splits it.
+    assert!(
+        chunk_boundaries(
+            &Config::BuzHash(FilterConfig {
+                max_chunk_size: 1024 * 1024,
+                ..FilterConfig::default()
+            }),
+            &source
+        )
+        .await
+        .len()
+            > 1
+    );
+}
+
+#[test]
+fn validate_config() {
+    assert!(Config::BuzHash(FilterConfig::default()).validate().is_ok());
+    assert!(Config::RollSum(FilterConfig::default()).validate().is_ok());
+    assert!(Config::FixedSize(1024).validate().is_ok());
+    assert!(Config::FixedSize(0).validate().is_ok());
+    assert!(Config::BuzHash(FilterConfig {
+        min_chunk_size: 2048,
+        max_chunk_size: 0,
+        ..FilterConfig::default()
+    })
+    .validate()
+    .is_ok());
+    assert!(Config::RollSum(FilterConfig {
+        min_chunk_size: 2048,
+        max_chunk_size: 1024,
+        ..FilterConfig::default()
+    })
+    .validate()
+    .is_err());
+    assert!(Config::BuzHash(FilterConfig {
+        window_size: 0,
+        ..FilterConfig::default()
+    })
+    .validate()
+    .is_err());
+}
+
+#[tokio::test]
+async fn buzhash_seed() {
+    let source = std::fs::read("tests/resources/random.img").unwrap();
+    let config = |seed| {
+        Config::BuzHash(FilterConfig {
+            filter_bits: FilterBits::from_size(512),
+            min_chunk_size: 20,
+            max_chunk_size: 1200,
+            window_size: 20,
+            buzhash_seed: seed,
+        })
+    };
+    let default_seed = chunk_boundaries(&config(BuzHash::DEFAULT_SEED), &source).await;
+    assert_eq!(
+        chunk_boundaries(&config(BuzHash::DEFAULT_SEED), &source).await,
+        default_seed
+    );
+    let other_seed = chunk_boundaries(&config(0xdead_beef), &source).await;
+    assert_eq!(
+        chunk_boundaries(&config(0xdead_beef), &source).await,
+        other_seed
+    );
+    assert_ne!(default_seed, other_seed);
+}
+
+// Get the end offset of each chunk when chunking data.
+async fn chunk_boundaries(config: &Config, data: &[u8]) -> Vec<u64> {
+    config
+        .new_chunker(data)
+        .map(|result| {
+            let (offset, chunk) = result.unwrap();
+            offset + chunk.len() as u64
+        })
+        .collect()
+        .await
+}
+
 // Chunk a source file and verify the hash sums for each chunk against the sums
 // in the expected sums file.
 async fn verify_chunks<P1: AsRef<Path>, P2: AsRef<Path>>(
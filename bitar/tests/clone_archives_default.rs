@@ -1,10 +1,18 @@
 mod common;
 
 use std::io::ErrorKind;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
 
-use bitar::{archive_reader::IoReader, Archive};
+use bitar::{
+    archive_reader::{BoundedReader, HttpReader, IoReader, TracedRead, TracingReader},
+    Archive,
+};
 use futures_util::stream::StreamExt;
-use tokio::fs::File;
+use reqwest::Url;
+use tokio::{fs::File, io::AsyncWriteExt, net::TcpListener};
 
 use common::*;
 
@@ -26,6 +34,40 @@ async fn clone_local_v0_1_1_zstd_not_supported() {
     ))
 }
 
+#[tokio::test]
+async fn open_v0_1_1_legacy_magic() {
+    let magic = &std::fs::read(ARCHIVE_0_1_1_NONE).unwrap()[..6];
+    assert_eq!(magic, b"\0BITA1");
+    let archive = Archive::try_init(IoReader::new(File::open(ARCHIVE_0_1_1_NONE).await.unwrap()))
+        .await
+        .unwrap();
+    assert_eq!(archive.built_with_version(), "0.1.1");
+    assert_eq!(archive.total_chunks(), 5);
+    assert_eq!(archive.chunk_data_offset(), 588);
+    assert_eq!(archive.chunk_compression(), None);
+    assert_eq!(archive.total_source_size(), 262144);
+}
+
+#[tokio::test]
+async fn open_v0_7_1_header() {
+    let magic = &std::fs::read(ARCHIVE_0_7_1_BROTLI).unwrap()[..6];
+    assert_eq!(magic, b"BITA1\0");
+    let archive = Archive::try_init(IoReader::new(
+        File::open(ARCHIVE_0_7_1_BROTLI).await.unwrap(),
+    ))
+    .await
+    .unwrap();
+    assert_eq!(archive.built_with_version(), "0.7.1");
+    assert_eq!(archive.total_chunks(), 8);
+    assert_eq!(archive.unique_chunks(), 1);
+    assert_eq!(archive.chunk_data_offset(), 274);
+    assert_eq!(
+        archive.chunk_compression().map(|c| c.algorithm()),
+        Some(bitar::CompressionAlgorithm::Brotli)
+    );
+    assert_eq!(archive.total_source_size(), 134217728);
+}
+
 #[tokio::test]
 async fn clone_local_v0_1_1_none() {
     clone_local_expect_checksum(ARCHIVE_0_1_1_NONE, RAND_B2SUM).await;
@@ -46,6 +88,42 @@ async fn clone_remote_v0_7_1_brotli() {
     clone_remote_expect_checksum(ARCHIVE_0_7_1_BROTLI, ZERO_B2SUM).await;
 }
 
+#[tokio::test]
+async fn clone_local_bounded_in_container() {
+    // Archive stored as an entry with data before and after it, like in a tar file.
+    let archive = std::fs::read(ARCHIVE_0_7_1_BROTLI).unwrap();
+    let container = [&[0xffu8; 512][..], &archive[..], &[0xffu8; 1024][..]].concat();
+    let reader = BoundedReader::new(
+        IoReader::new(std::io::Cursor::new(container)),
+        512,
+        archive.len() as u64,
+    );
+    clone_expect_checksum(Archive::try_init(reader).await.unwrap(), ZERO_B2SUM).await;
+}
+
+#[tokio::test]
+async fn clone_remote_shared_client() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+    let accepts = Arc::new(AtomicUsize::new(0));
+    let server = serve_archive_counting_accepts(listener, ARCHIVE_0_1_1_NONE, accepts.clone());
+    let client = reqwest::Client::new();
+    let clone_task = async {
+        for _ in 0..3 {
+            let archive = Archive::try_init(HttpReader::from_client_url(&client, url.clone()))
+                .await
+                .unwrap();
+            clone_expect_checksum(archive, RAND_B2SUM).await;
+        }
+    };
+    tokio::select! {
+        _ = server => panic!("server ended"),
+        _ = clone_task => {},
+    };
+    // All requests reused the same pooled connection.
+    assert_eq!(accepts.load(Ordering::SeqCst), 1);
+}
+
 #[tokio::test]
 async fn clone_local_v0_7_1_corrupt_header() {
     assert!(matches!(
@@ -79,6 +157,30 @@ async fn clone_local_v0_7_1_corrupt_chunk() {
     panic!("no hashsum mismatch error?!");
 }
 
+#[tokio::test]
+async fn clone_local_v0_7_1_corrupt_chunk_into_verified() {
+    let mut archive = Archive::try_init(IoReader::new(
+        File::open(ARCHIVE_0_7_1_CORRUPT_CHUNK).await.unwrap(),
+    ))
+    .await
+    .unwrap();
+    let mut chunk_stream = archive.chunk_stream(&archive.build_source_index());
+    while let Some(result) = chunk_stream.next().await {
+        let compressed = result.expect("chunk");
+        let (hash, offset) = (
+            compressed.expected_hash().clone(),
+            compressed.archive_offset(),
+        );
+        if let Err(err) = compressed.into_verified() {
+            assert!(matches!(err, bitar::ArchiveChunkError::VerifyError { .. }));
+            assert_eq!(*err.expected_hash(), hash);
+            assert_eq!(err.archive_offset(), offset);
+            return;
+        }
+    }
+    panic!("no verify error?!");
+}
+
 #[tokio::test]
 async fn clone_local_v0_11_0_brotli_expect_unexpected_end() {
     let mut archive = Archive::try_init(IoReader::new(
@@ -102,3 +204,485 @@ async fn clone_local_v0_11_0_brotli_expect_unexpected_end() {
     assert!(chunk_stream.next().await.is_none());
     assert!(chunk_stream.next().await.is_none());
 }
+
+#[tokio::test]
+async fn clone_in_place_v0_1_1_none() {
+    let mut archive =
+        Archive::try_init(IoReader::new(File::open(ARCHIVE_0_1_1_NONE).await.unwrap()))
+            .await
+            .unwrap();
+    let source = clone_to_memory(
+        Archive::try_init(IoReader::new(File::open(ARCHIVE_0_1_1_NONE).await.unwrap()))
+            .await
+            .unwrap(),
+    )
+    .await;
+    // Output holds the source with its first two chunks cut off, followed by zeros. So some
+    // chunks must be moved while the rest is fetched from the archive.
+    let (cut_offset, _) = archive.iter_source_chunks().nth(2).unwrap();
+    let mut output_buf = source[cut_offset as usize..].to_vec();
+    output_buf.resize(source.len(), 0);
+    let result = bitar::clone::in_place(
+        &bitar::clone::Options::default(),
+        &mut archive,
+        std::io::Cursor::new(&mut output_buf),
+    )
+    .await
+    .unwrap();
+    assert!(result.moved > 0);
+    assert!(result.fetched > 0);
+    assert!(result.moved + result.fetched <= source.len() as u64);
+    assert_eq!(output_buf, source);
+}
+
+// Reflink is used on file systems supporting it, others (eg tmpfs and ext4) fall back to
+// an in-kernel copy.
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn clone_in_place_reflink_v0_1_1_none() {
+    use std::io::{Read, Seek, Write};
+    let mut archive =
+        Archive::try_init(IoReader::new(File::open(ARCHIVE_0_1_1_NONE).await.unwrap()))
+            .await
+            .unwrap();
+    let source = clone_to_memory(
+        Archive::try_init(IoReader::new(File::open(ARCHIVE_0_1_1_NONE).await.unwrap()))
+            .await
+            .unwrap(),
+    )
+    .await;
+    let (cut_offset, _) = archive.iter_source_chunks().nth(2).unwrap();
+    let mut output_file = tempfile::tempfile().unwrap();
+    output_file
+        .write_all(&source[cut_offset as usize..])
+        .unwrap();
+    output_file.set_len(source.len() as u64).unwrap();
+    let result = bitar::clone::in_place_reflink(
+        &bitar::clone::Options::default(),
+        &mut archive,
+        File::from_std(output_file.try_clone().unwrap()),
+        output_file.try_clone().unwrap(),
+    )
+    .await
+    .unwrap();
+    assert!(result.moved > 0);
+    assert!(result.fetched > 0);
+    let mut output = Vec::new();
+    output_file.rewind().unwrap();
+    output_file.read_to_end(&mut output).unwrap();
+    assert_eq!(output, source);
+}
+
+#[tokio::test]
+async fn clone_ordered_stream_v0_1_1_none() {
+    let source = clone_to_memory(
+        Archive::try_init(IoReader::new(File::open(ARCHIVE_0_1_1_NONE).await.unwrap()))
+            .await
+            .unwrap(),
+    )
+    .await;
+    let mut archive =
+        Archive::try_init(IoReader::new(File::open(ARCHIVE_0_1_1_NONE).await.unwrap()))
+            .await
+            .unwrap();
+    let clone_index = archive.build_source_index();
+    let mut output = bitar::CloneOutput::new(
+        bitar::OrderedWriter::new(Vec::new(), source.len()),
+        clone_index,
+    );
+    bitar::clone::from_archive(&bitar::clone::Options::default(), &mut archive, &mut output)
+        .await
+        .unwrap();
+    let mut writer = output.into_inner();
+    writer.shutdown().await.unwrap();
+    assert_eq!(writer.into_inner(), source);
+}
+
+// Output failing every write.
+struct FailingOutput;
+
+impl tokio::io::AsyncWrite for FailingOutput {
+    fn poll_write(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+        _buf: &[u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        std::task::Poll::Ready(Err(ErrorKind::Other.into()))
+    }
+    fn poll_flush(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+    fn poll_shutdown(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+}
+
+impl tokio::io::AsyncSeek for FailingOutput {
+    fn start_seek(
+        self: std::pin::Pin<&mut Self>,
+        _position: std::io::SeekFrom,
+    ) -> std::io::Result<()> {
+        Ok(())
+    }
+    fn poll_complete(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<u64>> {
+        std::task::Poll::Ready(Ok(0))
+    }
+}
+
+#[tokio::test]
+async fn clone_output_error_stops_fetching() {
+    let mut archive =
+        Archive::try_init(IoReader::new(File::open(ARCHIVE_0_1_1_NONE).await.unwrap()))
+            .await
+            .unwrap();
+    let clone_index = archive.build_source_index();
+    assert!(clone_index.len() > 2);
+    let mut output = bitar::CloneOutput::new(FailingOutput, clone_index);
+    // The write fails while more chunks are fetched than fit in the buffer.
+    let opts = bitar::clone::Options {
+        max_buffered_chunks: 1,
+        ..Default::default()
+    };
+    assert!(matches!(
+        bitar::clone::from_archive(&opts, &mut archive, &mut output).await,
+        Err(bitar::clone::CloneError::OutputError(_))
+    ));
+}
+
+#[tokio::test]
+async fn clone_decompress_batch_v0_1_1_none() {
+    let source = clone_to_memory(
+        Archive::try_init(IoReader::new(File::open(ARCHIVE_0_1_1_NONE).await.unwrap()))
+            .await
+            .unwrap(),
+    )
+    .await;
+    let mut archive =
+        Archive::try_init(IoReader::new(File::open(ARCHIVE_0_1_1_NONE).await.unwrap()))
+            .await
+            .unwrap();
+    // The last group is smaller than the others.
+    assert!(archive.total_chunks() % 3 != 0);
+    let mut output =
+        bitar::CloneOutput::new(std::io::Cursor::new(vec![]), archive.build_source_index());
+    let opts = bitar::clone::Options {
+        decompress_batch_size: 3,
+        ..Default::default()
+    };
+    bitar::clone::from_archive(&opts, &mut archive, &mut output)
+        .await
+        .unwrap();
+    assert!(output.is_empty());
+    assert_eq!(output.into_inner().into_inner(), source);
+}
+
+#[tokio::test]
+async fn clone_decompress_batch_v0_7_1_corrupt_chunk() {
+    let mut archive = Archive::try_init(IoReader::new(
+        File::open(ARCHIVE_0_7_1_CORRUPT_CHUNK).await.unwrap(),
+    ))
+    .await
+    .unwrap();
+    let mut output =
+        bitar::CloneOutput::new(std::io::Cursor::new(vec![]), archive.build_source_index());
+    let opts = bitar::clone::Options {
+        decompress_batch_size: 4,
+        ..Default::default()
+    };
+    assert!(matches!(
+        bitar::clone::from_archive(&opts, &mut archive, &mut output).await,
+        Err(bitar::clone::CloneError::VerifyError(_))
+    ));
+}
+
+#[tokio::test]
+async fn clone_run_with_seed_v0_1_1_none() {
+    let source = clone_to_memory(
+        Archive::try_init(IoReader::new(File::open(ARCHIVE_0_1_1_NONE).await.unwrap()))
+            .await
+            .unwrap(),
+    )
+    .await;
+    let mut archive =
+        Archive::try_init(IoReader::new(File::open(ARCHIVE_0_1_1_NONE).await.unwrap()))
+            .await
+            .unwrap();
+    // Seed holds the first half of the source, the rest is fetched from the archive.
+    let (half_offset, _) = archive
+        .iter_source_chunks()
+        .nth(archive.total_chunks() / 2)
+        .unwrap();
+    let seed = std::io::Cursor::new(source[..half_offset as usize].to_vec());
+    let mut output_buf = vec![];
+    let mut output = bitar::CloneOutput::new(
+        std::io::Cursor::new(&mut output_buf),
+        archive.build_source_index(),
+    );
+    let report = bitar::clone::run(
+        &bitar::clone::Options::default(),
+        &mut archive,
+        [seed],
+        &mut output,
+    )
+    .await
+    .unwrap();
+    drop(output);
+    assert_eq!(report.bytes_from_seed, half_offset);
+    assert!(report.bytes_from_archive > 0);
+    assert_eq!(report.bytes_to_fetch, report.bytes_from_archive);
+    assert!(report.bytes_to_fetch < archive.compressed_size());
+    assert_eq!(report.bytes_moved_in_place, 0);
+    assert_eq!(report.chunks_written, archive.total_chunks() as u64);
+    assert_eq!(output_buf, source);
+}
+
+#[tokio::test]
+async fn clone_from_archive_all_v0_1_1_none() {
+    let source = clone_to_memory(
+        Archive::try_init(IoReader::new(File::open(ARCHIVE_0_1_1_NONE).await.unwrap()))
+            .await
+            .unwrap(),
+    )
+    .await;
+    let mut archive =
+        Archive::try_init(IoReader::new(File::open(ARCHIVE_0_1_1_NONE).await.unwrap()))
+            .await
+            .unwrap();
+    let (half_offset, _) = archive
+        .iter_source_chunks()
+        .nth(archive.total_chunks() / 2)
+        .unwrap();
+    let seed = std::io::Cursor::new(source[..half_offset as usize].to_vec());
+    let mut outputs = vec![
+        bitar::CloneOutput::new(std::io::Cursor::new(vec![]), archive.build_source_index()),
+        bitar::CloneOutput::new(std::io::Cursor::new(vec![]), archive.build_source_index()),
+    ];
+    // Only the second output has the first half of the source from seed.
+    let mut seed_chunks = archive.chunker_config().new_chunker(seed);
+    while let Some(result) = seed_chunks.next().await {
+        let (_offset, chunk) = result.unwrap();
+        outputs[1]
+            .feed(&chunk.verify_with(archive.chunk_hash_algorithm()))
+            .await
+            .unwrap();
+    }
+    let seed_written = outputs[1].bytes_written();
+    assert_eq!(seed_written, half_offset);
+
+    // Both outputs are completed by fetching every chunk once.
+    let mut single_output =
+        bitar::CloneOutput::new(std::io::Cursor::new(vec![]), archive.build_source_index());
+    let fetched_single = bitar::clone::from_archive(
+        &bitar::clone::Options::default(),
+        &mut archive,
+        &mut single_output,
+    )
+    .await
+    .unwrap();
+    let fetched = bitar::clone::from_archive_all(
+        &bitar::clone::Options::default(),
+        &mut archive,
+        &mut outputs,
+    )
+    .await
+    .unwrap();
+    assert_eq!(fetched, fetched_single);
+    assert_eq!(outputs[0].bytes_written(), source.len() as u64);
+    assert_eq!(
+        outputs[1].bytes_written() - seed_written,
+        source.len() as u64 - half_offset
+    );
+    for output in outputs {
+        assert!(output.is_empty());
+        assert_eq!(output.into_inner().into_inner(), source);
+    }
+}
+
+#[tokio::test]
+async fn clone_run_on_plan_v0_1_1_none() {
+    let source = clone_to_memory(
+        Archive::try_init(IoReader::new(File::open(ARCHIVE_0_1_1_NONE).await.unwrap()))
+            .await
+            .unwrap(),
+    )
+    .await;
+    let reader = TracingReader::new(IoReader::new(File::open(ARCHIVE_0_1_1_NONE).await.unwrap()));
+    let trace = reader.trace();
+    let mut archive = Archive::try_init(reader).await.unwrap();
+    trace.lock().unwrap().clear();
+    let (half_offset, _) = archive
+        .iter_source_chunks()
+        .nth(archive.total_chunks() / 2)
+        .unwrap();
+    let seed = std::io::Cursor::new(source[..half_offset as usize].to_vec());
+
+    let plans = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let opts = bitar::clone::Options {
+        on_plan: Some({
+            let plans = plans.clone();
+            Arc::new(move |plan: &[bitar::ChunkOffset]| plans.lock().unwrap().push(plan.to_vec()))
+        }),
+        ..Default::default()
+    };
+    let mut output_buf = vec![];
+    let mut output = bitar::CloneOutput::new(
+        std::io::Cursor::new(&mut output_buf),
+        archive.build_source_index(),
+    );
+    bitar::clone::run(&opts, &mut archive, [seed], &mut output)
+        .await
+        .unwrap();
+    drop(output);
+    assert_eq!(output_buf, source);
+
+    // Called once, planning exactly the chunks then read, which the seed didn't provide.
+    let plans = plans.lock().unwrap();
+    assert_eq!(plans.len(), 1);
+    assert!(!plans[0].is_empty());
+    assert!(plans[0].len() < archive.total_chunks());
+    assert_eq!(
+        *trace.lock().unwrap(),
+        vec![TracedRead::ReadChunks(plans[0].clone())]
+    );
+}
+
+#[tokio::test]
+async fn clone_on_fetch_v0_1_1_none() {
+    let mut archive =
+        Archive::try_init(IoReader::new(File::open(ARCHIVE_0_1_1_NONE).await.unwrap()))
+            .await
+            .unwrap();
+    let fetched = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let opts = bitar::clone::Options {
+        on_fetch: Some({
+            let fetched = fetched.clone();
+            Arc::new(move |bytes| fetched.lock().unwrap().push(bytes))
+        }),
+        ..Default::default()
+    };
+    let mut output =
+        bitar::CloneOutput::new(std::io::Cursor::new(vec![]), archive.build_source_index());
+    let total_fetched = bitar::clone::from_archive(&opts, &mut archive, &mut output)
+        .await
+        .unwrap();
+
+    // Called once per chunk read, counting up to the total fetched.
+    let fetched = fetched.lock().unwrap();
+    assert_eq!(fetched.len(), archive.unique_chunks());
+    assert!(fetched.windows(2).all(|w| w[0] < w[1]));
+    assert_eq!(fetched.last(), Some(&total_fetched));
+}
+
+#[tokio::test]
+async fn clone_adjacent_chunks_read_at_once_v0_1_1_none() {
+    let reader = TracingReader::new(IoReader::new(File::open(ARCHIVE_0_1_1_NONE).await.unwrap()));
+    let trace = reader.trace();
+    let mut archive = Archive::try_init(reader).await.unwrap();
+    trace.lock().unwrap().clear();
+    assert!(archive.is_contiguous());
+    let stored: Vec<bitar::ChunkOffset> = archive
+        .iter_archive_order()
+        .map(|cd| bitar::ChunkOffset::new(cd.archive_offset, cd.archive_size))
+        .collect();
+    let mut output =
+        bitar::CloneOutput::new(std::io::Cursor::new(vec![]), archive.build_source_index());
+    bitar::clone::from_archive(&bitar::clone::Options::default(), &mut archive, &mut output)
+        .await
+        .unwrap();
+    assert!(output.is_empty());
+
+    // Every chunk is requested in a single call, in archive order, and as the chunks are
+    // stored back to back they form a single adjacent run.
+    let trace = trace.lock().unwrap();
+    assert_eq!(*trace, vec![TracedRead::ReadChunks(stored.clone())]);
+    assert!(stored.len() > 1);
+    assert_eq!(
+        trace[0].adjacent_runs(),
+        vec![bitar::ChunkOffset::new(
+            stored[0].offset,
+            stored.iter().map(|chunk| chunk.size).sum()
+        )]
+    );
+}
+
+#[tokio::test]
+async fn clone_run_in_place_v0_1_1_none() {
+    let mut archive =
+        Archive::try_init(IoReader::new(File::open(ARCHIVE_0_1_1_NONE).await.unwrap()))
+            .await
+            .unwrap();
+    let source = clone_to_memory(
+        Archive::try_init(IoReader::new(File::open(ARCHIVE_0_1_1_NONE).await.unwrap()))
+            .await
+            .unwrap(),
+    )
+    .await;
+    let (cut_offset, _) = archive.iter_source_chunks().nth(2).unwrap();
+    let mut output_buf = source[cut_offset as usize..].to_vec();
+    output_buf.resize(source.len(), 0);
+    let report = bitar::clone::run_in_place(
+        &bitar::clone::Options::default(),
+        &mut archive,
+        Vec::<File>::new(),
+        std::io::Cursor::new(&mut output_buf),
+    )
+    .await
+    .unwrap();
+    assert!(report.bytes_moved_in_place > 0);
+    assert!(report.bytes_from_archive > 0);
+    assert_eq!(report.bytes_to_fetch, report.bytes_from_archive);
+    assert_eq!(report.bytes_from_seed, 0);
+    assert!(report.chunks_written > 0);
+    assert_eq!(output_buf, source);
+}
+
+#[tokio::test]
+async fn read_source_range_v0_1_1_none() {
+    let open = || async {
+        Archive::try_init(IoReader::new(File::open(ARCHIVE_0_1_1_NONE).await.unwrap()))
+            .await
+            .unwrap()
+    };
+    let source = clone_to_memory(open().await).await;
+    let mut archive = open().await;
+    let (first_offset, first) = archive.iter_source_chunks().next().unwrap();
+    assert_eq!(first_offset, 0);
+    let first_size = u64::from(first.source_size);
+    let total_size = archive.total_source_size();
+    assert_eq!(source.len() as u64, total_size);
+    for (offset, len) in [
+        // Within a single chunk.
+        (10, 100),
+        // Starting mid-chunk and spanning into the next one.
+        (first_size - 10, 20),
+        // Spanning many chunks.
+        (first_size / 2, total_size / 2),
+        // The whole source.
+        (0, total_size),
+        // Extending past the end of source.
+        (total_size - 1000, 5000),
+        (0, u64::MAX),
+        // Empty or outside source.
+        (100, 0),
+        (total_size, 10),
+        (total_size + 10, 10),
+    ] {
+        let mut read = Vec::new();
+        let mut stream = archive.read_source_range(offset, len);
+        while let Some(result) = stream.next().await {
+            read.extend_from_slice(&result.unwrap());
+        }
+        let start = (offset as usize).min(source.len());
+        let end = (offset.saturating_add(len) as usize).min(source.len());
+        assert_eq!(read, &source[start..end], "offset {} len {}", offset, len);
+    }
+}
@@ -0,0 +1,211 @@
+#![cfg(feature = "compress")]
+
+use std::future::Future;
+use std::io::{self, Cursor, SeekFrom};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bitar::{archive_reader::ArchiveReader, archive_reader::IoReader, chunker, clone, Archive};
+use bitar::{ChunkOffset, CloneOutput};
+use bytes::Bytes;
+use futures_util::stream::{Stream, StreamExt};
+use tokio::io::{AsyncSeek, AsyncWrite};
+use tokio::task::{spawn_blocking, JoinHandle};
+
+const CHUNK_SIZE: usize = 1000;
+
+#[derive(Default)]
+struct Counts {
+    read: AtomicUsize,
+    written: AtomicUsize,
+    peak_unwritten: AtomicUsize,
+}
+
+// Reader counting the chunks read, and the most chunks read but not yet written to output.
+struct CountingReader {
+    inner: IoReader<Cursor<Vec<u8>>>,
+    counts: Arc<Counts>,
+}
+
+#[async_trait]
+impl ArchiveReader for CountingReader {
+    type Error = io::Error;
+
+    async fn read_at(&mut self, offset: u64, size: usize) -> Result<Bytes, io::Error> {
+        self.inner.read_at(offset, size).await
+    }
+
+    fn read_chunks<'a>(
+        &'a mut self,
+        chunks: Vec<ChunkOffset>,
+    ) -> Pin<Box<dyn Stream<Item = Result<Bytes, io::Error>> + Send + 'a>> {
+        let counts = self.counts.clone();
+        Box::pin(self.inner.read_chunks(chunks).inspect(move |_| {
+            let read = counts.read.fetch_add(1, Ordering::SeqCst) + 1;
+            let written = counts.written.load(Ordering::SeqCst) / CHUNK_SIZE;
+            counts
+                .peak_unwritten
+                .fetch_max(read - written, Ordering::SeqCst);
+        }))
+    }
+}
+
+// Output where every write takes a while, on a blocking thread like a tokio file.
+struct SlowOutput {
+    counts: Arc<Counts>,
+    pending: Option<JoinHandle<()>>,
+    position: u64,
+}
+
+impl AsyncWrite for SlowOutput {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let handle = self
+            .pending
+            .get_or_insert_with(|| spawn_blocking(|| std::thread::sleep(Duration::from_millis(2))));
+        match Pin::new(handle).poll(cx) {
+            Poll::Ready(result) => {
+                self.pending = None;
+                result.map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+                self.position += buf.len() as u64;
+                self.counts.written.fetch_add(buf.len(), Ordering::SeqCst);
+                Poll::Ready(Ok(buf.len()))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for SlowOutput {
+    fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        match position {
+            SeekFrom::Start(position) => {
+                self.position = position;
+                Ok(())
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "only seeking from start is supported",
+            )),
+        }
+    }
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.position))
+    }
+}
+
+// Archive of 64 different uncompressed chunks.
+async fn create_archive() -> (Vec<u8>, Vec<u8>) {
+    let source: Vec<u8> = (0..64 * CHUNK_SIZE as u32)
+        .map(|v| (v / CHUNK_SIZE as u32 + v % 251) as u8)
+        .collect();
+    let mut output = Vec::new();
+    let options = bitar::api::compress::CreateArchiveOptions {
+        chunker_config: chunker::Config::FixedSize(CHUNK_SIZE),
+        compression: None,
+        ..Default::default()
+    };
+    bitar::api::compress::create_archive(&source[..], &mut output, &options)
+        .await
+        .unwrap();
+    (output, source)
+}
+
+async fn peak_unwritten_chunks(opts: &clone::Options) -> usize {
+    let (archive_buf, source) = create_archive().await;
+    let counts = Arc::new(Counts::default());
+    let mut archive = Archive::try_init(CountingReader {
+        inner: IoReader::new(Cursor::new(archive_buf)),
+        counts: counts.clone(),
+    })
+    .await
+    .unwrap();
+    assert_eq!(archive.unique_chunks(), 64);
+    let mut output = CloneOutput::new(
+        SlowOutput {
+            counts: counts.clone(),
+            pending: None,
+            position: 0,
+        },
+        archive.build_source_index(),
+    );
+    clone::from_archive(opts, &mut archive, &mut output)
+        .await
+        .unwrap();
+    assert!(output.is_empty());
+    assert_eq!(counts.written.load(Ordering::SeqCst), source.len());
+    counts.peak_unwritten.load(Ordering::SeqCst)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn clone_buffers_at_most_twice_max_buffered_chunks() {
+    let opts = clone::Options {
+        max_buffered_chunks: 4,
+        ..Default::default()
+    };
+    let peak = peak_unwritten_chunks(&opts).await;
+    assert!(peak <= 2 * (4 + 1), "{} chunks read but not written", peak);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn clone_buffers_at_most_twice_max_buffered_groups() {
+    let opts = clone::Options {
+        max_buffered_chunks: 2,
+        decompress_batch_size: 4,
+        ..Default::default()
+    };
+    let peak = peak_unwritten_chunks(&opts).await;
+    // Plus the group being filled.
+    assert!(
+        peak <= 2 * (2 + 1) * 4 + 3,
+        "{} chunks read but not written",
+        peak
+    );
+}
+
+#[tokio::test]
+async fn clone_zero_fills_invalid_chunks() {
+    let (mut archive_buf, source) = create_archive().await;
+    let archive = Archive::try_init(IoReader::new(Cursor::new(archive_buf.clone())))
+        .await
+        .unwrap();
+    let (offset, descriptor) = archive.iter_source_chunks().nth(1).unwrap();
+    archive_buf[descriptor.archive_offset as usize] ^= 0xff;
+    let mut archive = Archive::try_init(IoReader::new(Cursor::new(archive_buf)))
+        .await
+        .unwrap();
+    let invalid = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let opts = clone::Options {
+        decompress_batch_size: 4,
+        on_invalid_chunk: Some({
+            let invalid = invalid.clone();
+            Arc::new(move |err, bytes| invalid.lock().unwrap().push((err.archive_offset(), bytes)))
+        }),
+        ..Default::default()
+    };
+    let mut output = CloneOutput::new(Cursor::new(Vec::new()), archive.build_source_index());
+    clone::from_archive(&opts, &mut archive, &mut output)
+        .await
+        .unwrap();
+    assert!(output.is_empty());
+    assert_eq!(
+        *invalid.lock().unwrap(),
+        [(descriptor.archive_offset, CHUNK_SIZE as u64)]
+    );
+    let mut expected = source;
+    expected[offset as usize..offset as usize + CHUNK_SIZE].fill(0);
+    assert_eq!(output.into_inner().into_inner(), expected);
+}
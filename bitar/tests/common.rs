@@ -14,7 +14,11 @@ use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 
 use bitar::archive_reader::{HttpReader, IoReader};
 use bitar::{Archive, CloneOutput};
-use tokio::net::TcpListener;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use tokio::net::{TcpListener, TcpStream};
 
 // Checksum of the rand archive source file.
 pub static RAND_B2SUM: &[u8] = &[
@@ -70,27 +74,23 @@ pub async fn clone_remote_expect_checksum(path: &str, b2sum: &'static [u8]) {
     };
 }
 
-async fn clone_expect_checksum<R: bitar::archive_reader::ArchiveReader>(
+pub async fn clone_expect_checksum<R: bitar::archive_reader::ArchiveReader>(
     mut archive: Archive<R>,
     b2sum: &[u8],
 ) where
     R::Error: std::fmt::Debug,
 {
+    let source_index = archive.build_source_index();
+    source_index
+        .validate(archive.total_source_size())
+        .expect("valid source index");
     let mut output_buf = vec![];
     {
-        let mut output =
-            CloneOutput::new(Cursor::new(&mut output_buf), archive.build_source_index());
+        let mut output = CloneOutput::new(Cursor::new(&mut output_buf), source_index);
         let mut chunk_stream = archive.chunk_stream(output.chunks());
         while let Some(result) = chunk_stream.next().await {
             output
-                .feed(
-                    &result
-                        .expect("chunk")
-                        .decompress()
-                        .expect("decompress")
-                        .verify()
-                        .expect("verify"),
-                )
+                .feed(&result.expect("chunk").into_verified().expect("verify"))
                 .await
                 .unwrap();
         }
@@ -102,8 +102,26 @@ async fn clone_expect_checksum<R: bitar::archive_reader::ArchiveReader>(
 }
 
 async fn serve_archive(listener: TcpListener, path: &str) {
-    let archive_data = tokio::fs::read(path).await.unwrap();
+    let archive_data = Arc::new(tokio::fs::read(path).await.unwrap());
     let (stream, _) = listener.accept().await.unwrap();
+    serve_archive_connection(stream, archive_data).await;
+}
+
+// Serve an archive on any number of connections, counting every accepted connection.
+pub async fn serve_archive_counting_accepts(
+    listener: TcpListener,
+    path: &str,
+    accepts: Arc<AtomicUsize>,
+) {
+    let archive_data = Arc::new(tokio::fs::read(path).await.unwrap());
+    loop {
+        let (stream, _) = listener.accept().await.unwrap();
+        accepts.fetch_add(1, Ordering::SeqCst);
+        tokio::spawn(serve_archive_connection(stream, archive_data.clone()));
+    }
+}
+
+async fn serve_archive_connection(stream: TcpStream, archive_data: Arc<Vec<u8>>) {
     let io = TokioIo::new(stream);
     http1::Builder::new()
         .serve_connection(
@@ -139,21 +157,17 @@ pub async fn clone_to_memory<R: bitar::archive_reader::ArchiveReader>(
 where
     R::Error: std::fmt::Debug,
 {
+    let source_index = archive.build_source_index();
+    source_index
+        .validate(archive.total_source_size())
+        .expect("valid source index");
     let mut output_buf = vec![];
     {
-        let mut output =
-            CloneOutput::new(Cursor::new(&mut output_buf), archive.build_source_index());
+        let mut output = CloneOutput::new(Cursor::new(&mut output_buf), source_index);
         let mut chunk_stream = archive.chunk_stream(output.chunks());
         while let Some(result) = chunk_stream.next().await {
             output
-                .feed(
-                    &result
-                        .expect("chunk")
-                        .decompress()
-                        .expect("decompress")
-                        .verify()
-                        .expect("verify"),
-                )
+                .feed(&result.expect("chunk").into_verified().expect("verify"))
                 .await
                 .unwrap();
         }
@@ -209,3 +223,28 @@ pub async fn compress_archive(
         .await
         .unwrap();
 }
+
+// Decompress chunks of the given compression into a single buffer, checking that the buffer
+// allocation is reused once the previous chunk has been dropped.
+#[cfg(feature = "compress")]
+pub fn check_decompress_with(algorithm: bitar::CompressionAlgorithm) {
+    let compression = bitar::Compression::try_new(algorithm, 1).unwrap();
+    let mut buffer = bytes::BytesMut::new();
+    let mut previous_ptr = None;
+    for seed in 0..3u8 {
+        let data: Vec<u8> = (0..64 * 1024)
+            .map(|i: usize| (i % 251) as u8 ^ seed)
+            .collect();
+        let compressed = bitar::CompressedChunk::try_compress(
+            Some(compression),
+            bitar::Chunk::from(data.clone()),
+        )
+        .unwrap();
+        let chunk = compressed.decompress_with(&mut buffer).unwrap();
+        assert_eq!(chunk.data(), &data[..]);
+        if let Some(ptr) = previous_ptr {
+            assert_eq!(chunk.data().as_ptr(), ptr);
+        }
+        previous_ptr = Some(chunk.data().as_ptr());
+    }
+}
@@ -4,6 +4,7 @@ mod common;
 use bitar::chunker;
 
 use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 
 use common::*;
 
@@ -216,3 +217,1109 @@ async fn compress_random_bytes_buzhash_brotli() {
 
     check_archive_equals_source(&mut output, &mut input).await;
 }
+
+// ============================================================================
+// Metadata
+// ============================================================================
+#[tokio::test]
+async fn compress_metadata_round_trip() {
+    let mut input = File::from_std(tempfile::tempfile().unwrap());
+    let mut output = File::from_std(tempfile::tempfile().unwrap());
+
+    write_random_bytes(&mut input, 8096).await;
+
+    let mut metadata = std::collections::BTreeMap::new();
+    metadata.insert("signature".to_string(), vec![0, 1, 2, 255]);
+    metadata.insert("version".to_string(), b"1.2.3".to_vec());
+    let options = bitar::api::compress::CreateArchiveOptions {
+        chunker_config: chunker::Config::FixedSize(1024),
+        metadata: metadata.clone(),
+        ..Default::default()
+    };
+    bitar::api::compress::create_archive(&mut input, &mut output, &options)
+        .await
+        .unwrap();
+
+    output.rewind().await.unwrap();
+    let archive = bitar::Archive::try_init(bitar::archive_reader::IoReader::new(output))
+        .await
+        .unwrap();
+    assert_eq!(archive.metadata(), &metadata);
+    assert_eq!(archive.metadata_value("version"), Some(&b"1.2.3"[..]));
+    assert_eq!(
+        archive.metadata_value("signature"),
+        Some(&[0u8, 1, 2, 255][..])
+    );
+    assert_eq!(archive.metadata_value("missing"), None);
+}
+
+#[tokio::test]
+async fn compress_buzhash_seed_round_trip() {
+    let mut input = File::from_std(tempfile::tempfile().unwrap());
+    let mut output = File::from_std(tempfile::tempfile().unwrap());
+
+    write_random_bytes(&mut input, 8096).await;
+
+    let chunker_config = chunker::Config::BuzHash(
+        chunker::FilterConfig::default()
+            .with_window_size(16)
+            .with_buzhash_seed(0xdead_beef),
+    );
+    let options = bitar::api::compress::CreateArchiveOptions {
+        chunker_config: chunker_config.clone(),
+        ..Default::default()
+    };
+    bitar::api::compress::create_archive(&mut input, &mut output, &options)
+        .await
+        .unwrap();
+
+    output.rewind().await.unwrap();
+    let archive = bitar::Archive::try_init(bitar::archive_reader::IoReader::new(output))
+        .await
+        .unwrap();
+    assert_eq!(archive.chunker_config(), &chunker_config);
+}
+
+#[tokio::test]
+async fn compress_truncated_hash_collision() {
+    // More different chunks than there are 1 byte hashes, hence some share a truncated hash.
+    let source: Vec<u8> = (0..300u32)
+        .flat_map(|i| i.to_le_bytes().repeat(16))
+        .collect();
+    let options = |chunk_hash_length| bitar::api::compress::CreateArchiveOptions {
+        chunker_config: chunker::Config::FixedSize(64),
+        chunk_hash_length,
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    match bitar::api::compress::create_archive(&source[..], &mut output, &options(1)).await {
+        Err(bitar::api::compress::CreateArchiveError::ChunkHashCollision(hash)) => {
+            assert_eq!(hash.len(), 1)
+        }
+        other => panic!("unexpected result {:?}", other.map(|_| ())),
+    }
+    // The same chunk repeated is not a collision, even with a short hash.
+    let repeated = source[..64].repeat(300);
+    bitar::api::compress::create_archive(&repeated[..], Vec::new(), &options(1))
+        .await
+        .unwrap();
+
+    let mut output = Vec::new();
+    bitar::api::compress::create_archive(&source[..], &mut output, &options(8))
+        .await
+        .unwrap();
+    let archive = bitar::Archive::from_bytes(output).await.unwrap();
+    assert_eq!(archive.chunk_hash_length(), 8);
+    assert_eq!(archive.chunk_descriptors().len(), 300);
+}
+
+#[tokio::test]
+async fn compress_unbounded_max_chunk_size_round_trip() {
+    let mut input = File::from_std(tempfile::tempfile().unwrap());
+    let mut output = File::from_std(tempfile::tempfile().unwrap());
+
+    input.write_all(&[0u8; 1024 * 1024]).await.unwrap();
+    input.rewind().await.unwrap();
+
+    let chunker_config = chunker::Config::BuzHash(chunker::FilterConfig {
+        max_chunk_size: 0,
+        ..Default::default()
+    });
+    let options = bitar::api::compress::CreateArchiveOptions {
+        chunker_config: chunker_config.clone(),
+        ..Default::default()
+    };
+    bitar::api::compress::create_archive(&mut input, &mut output, &options)
+        .await
+        .unwrap();
+
+    output.rewind().await.unwrap();
+    let archive = bitar::Archive::try_init(bitar::archive_reader::IoReader::new(output))
+        .await
+        .unwrap();
+    assert_eq!(archive.chunker_config(), &chunker_config);
+    assert_eq!(archive.total_chunks(), 1);
+}
+
+#[tokio::test]
+async fn compress_skip_entropy_same_archive() {
+    // Mix of incompressible and very compressible data.
+    let mut source = vec![0u8; 256 * 1024];
+    let mut random = File::from_std(tempfile::tempfile().unwrap());
+    write_random_bytes(&mut random, 256 * 1024).await;
+    random.read_to_end(&mut source).await.unwrap();
+    source.extend_from_slice(&[0u8; 128 * 1024]);
+
+    let chunker_config = chunker::Config::FixedSize(16 * 1024);
+    let mut archives = Vec::new();
+    for compress_skip_entropy in [None, Some(7.5)] {
+        let options = bitar::api::compress::CreateArchiveOptions {
+            chunker_config: chunker_config.clone(),
+            compress_skip_entropy,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        bitar::api::compress::create_archive(&source[..], &mut output, &options)
+            .await
+            .unwrap();
+        archives.push(output);
+    }
+    // Skipping incompressible chunks only saves time, the archive is the same.
+    assert_eq!(archives[0], archives[1]);
+
+    let archive = bitar::Archive::try_init(bitar::archive_reader::IoReader::new(
+        std::io::Cursor::new(archives.pop().unwrap()),
+    ))
+    .await
+    .unwrap();
+    for (offset, descriptor) in archive.iter_source_chunks() {
+        let random = (256 * 1024..512 * 1024).contains(&offset);
+        assert_eq!(
+            descriptor.compression.is_none(),
+            random,
+            "offset {}",
+            offset
+        );
+    }
+    assert_eq!(clone_to_memory(archive).await, source);
+}
+
+// Reader returning some data and then failing.
+struct FailingReader {
+    remaining: usize,
+}
+
+impl tokio::io::AsyncRead for FailingReader {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        if self.remaining == 0 {
+            return std::task::Poll::Ready(Err(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "input went away",
+            )));
+        }
+        let n = self.remaining.min(buf.remaining());
+        buf.put_slice(&vec![0xaa; n]);
+        self.remaining -= n;
+        std::task::Poll::Ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn compress_input_read_error() {
+    let output = File::from_std(tempfile::tempfile().unwrap());
+    let result = bitar::api::compress::create_archive(
+        FailingReader {
+            remaining: 1024 * 1024,
+        },
+        output,
+        &Default::default(),
+    )
+    .await;
+    match result {
+        Err(bitar::api::compress::CreateArchiveError::ChunkerRead(err)) => {
+            assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe)
+        }
+        Err(err) => panic!("unexpected error {}", err),
+        Ok(_) => panic!("expected an error"),
+    }
+}
+
+// ============================================================================
+// Header signature
+// ============================================================================
+async fn compress_signed(
+    input: &mut File,
+    output: &mut File,
+    signing_key: Option<bitar::header::SigningKey>,
+) {
+    let options = bitar::api::compress::CreateArchiveOptions {
+        chunker_config: chunker::Config::FixedSize(1024),
+        signing_key,
+        ..Default::default()
+    };
+    bitar::api::compress::create_archive(&mut *input, &mut *output, &options)
+        .await
+        .unwrap();
+    output.rewind().await.unwrap();
+}
+
+#[tokio::test]
+async fn compress_signed_verify() {
+    let mut input = File::from_std(tempfile::tempfile().unwrap());
+    let mut output = File::from_std(tempfile::tempfile().unwrap());
+    write_random_bytes(&mut input, 8096).await;
+    let signing_key = bitar::header::SigningKey::from_bytes(&[7; 32]);
+    compress_signed(&mut input, &mut output, Some(signing_key.clone())).await;
+
+    let archive = bitar::Archive::try_init_with_signature(
+        bitar::archive_reader::IoReader::new(&mut output),
+        &signing_key.verifying_key(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(archive.total_source_size(), 8096);
+
+    // Signed archives can still be read without verifying the signature.
+    check_archive_equals_source(&mut output, &mut input).await;
+}
+
+#[tokio::test]
+async fn compress_signed_wrong_key() {
+    let mut input = File::from_std(tempfile::tempfile().unwrap());
+    let mut output = File::from_std(tempfile::tempfile().unwrap());
+    write_random_bytes(&mut input, 8096).await;
+    let signing_key = bitar::header::SigningKey::from_bytes(&[7; 32]);
+    compress_signed(&mut input, &mut output, Some(signing_key)).await;
+
+    let other_key = bitar::header::SigningKey::from_bytes(&[8; 32]);
+    assert!(matches!(
+        bitar::Archive::try_init_with_signature(
+            bitar::archive_reader::IoReader::new(&mut output),
+            &other_key.verifying_key(),
+        )
+        .await,
+        Err(bitar::ArchiveError::InvalidArchive(_))
+    ));
+}
+
+#[tokio::test]
+async fn compress_unsigned_verify() {
+    let mut input = File::from_std(tempfile::tempfile().unwrap());
+    let mut output = File::from_std(tempfile::tempfile().unwrap());
+    write_random_bytes(&mut input, 8096).await;
+    compress_signed(&mut input, &mut output, None).await;
+
+    let signing_key = bitar::header::SigningKey::from_bytes(&[7; 32]);
+    assert!(matches!(
+        bitar::Archive::try_init_with_signature(
+            bitar::archive_reader::IoReader::new(&mut output),
+            &signing_key.verifying_key(),
+        )
+        .await,
+        Err(bitar::ArchiveError::InvalidArchive(_))
+    ));
+}
+
+// ============================================================================
+// Header at end of archive
+// ============================================================================
+async fn compress_header_at_end(
+    input: &mut File,
+    output: &mut File,
+    signing_key: Option<bitar::header::SigningKey>,
+) {
+    let options = bitar::api::compress::CreateArchiveOptions {
+        chunker_config: chunker::Config::FixedSize(1024),
+        compression: Some(bitar::Compression::brotli(6).unwrap()),
+        signing_key,
+        header_at_end: true,
+        ..Default::default()
+    };
+    bitar::api::compress::create_archive(&mut *input, &mut *output, &options)
+        .await
+        .unwrap();
+    output.rewind().await.unwrap();
+}
+
+#[tokio::test]
+async fn compress_header_at_end_clone() {
+    let mut input = File::from_std(tempfile::tempfile().unwrap());
+    let mut output = File::from_std(tempfile::tempfile().unwrap());
+    write_random_bytes(&mut input, 100_000).await;
+    compress_header_at_end(&mut input, &mut output, None).await;
+
+    let mut magic = [0u8; 6];
+    output.read_exact(&mut magic).await.unwrap();
+    assert_eq!(&magic, bitar::header::TRAILER_MAGIC);
+
+    check_archive_equals_source(&mut output, &mut input).await;
+}
+
+#[tokio::test]
+async fn compress_header_at_end_signed_verify() {
+    let mut input = File::from_std(tempfile::tempfile().unwrap());
+    let mut output = File::from_std(tempfile::tempfile().unwrap());
+    write_random_bytes(&mut input, 8096).await;
+    let signing_key = bitar::header::SigningKey::from_bytes(&[7; 32]);
+    compress_header_at_end(&mut input, &mut output, Some(signing_key.clone())).await;
+
+    let archive = bitar::Archive::try_init_with_signature(
+        bitar::archive_reader::IoReader::new(&mut output),
+        &signing_key.verifying_key(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(archive.total_source_size(), 8096);
+
+    let other_key = bitar::header::SigningKey::from_bytes(&[8; 32]);
+    assert!(matches!(
+        bitar::Archive::try_init_with_signature(
+            bitar::archive_reader::IoReader::new(&mut output),
+            &other_key.verifying_key(),
+        )
+        .await,
+        Err(bitar::ArchiveError::InvalidArchive(_))
+    ));
+}
+
+#[tokio::test]
+async fn compress_header_at_end_unsigned_verify() {
+    let mut input = File::from_std(tempfile::tempfile().unwrap());
+    let mut output = File::from_std(tempfile::tempfile().unwrap());
+    write_random_bytes(&mut input, 8096).await;
+    compress_header_at_end(&mut input, &mut output, None).await;
+
+    let signing_key = bitar::header::SigningKey::from_bytes(&[7; 32]);
+    assert!(matches!(
+        bitar::Archive::try_init_with_signature(
+            bitar::archive_reader::IoReader::new(&mut output),
+            &signing_key.verifying_key(),
+        )
+        .await,
+        Err(bitar::ArchiveError::InvalidArchive(_))
+    ));
+}
+
+// ============================================================================
+// Append to archive
+// ============================================================================
+async fn create_appendable_archive(
+    source: &[u8],
+    path: &std::path::Path,
+    signing_key: Option<bitar::header::SigningKey>,
+) {
+    let options = bitar::api::compress::CreateArchiveOptions {
+        chunker_config: chunker::Config::BuzHash(chunker::FilterConfig {
+            filter_bits: chunker::FilterBits::from_size(1024),
+            min_chunk_size: 256,
+            max_chunk_size: 8192,
+            window_size: 16,
+            ..Default::default()
+        }),
+        compression: Some(bitar::Compression::brotli(6).unwrap()),
+        signing_key,
+        header_at_end: true,
+        ..Default::default()
+    };
+    bitar::api::compress::create_archive(source, File::create(path).await.unwrap(), &options)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn append_archive_equals_source() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("archive.cba");
+    let mut source = File::from_std(tempfile::tempfile().unwrap());
+    write_random_bytes(&mut source, 50_000).await;
+    let mut source_bytes = Vec::new();
+    source.read_to_end(&mut source_bytes).await.unwrap();
+    create_appendable_archive(&source_bytes, &path, None).await;
+    let chunks_before = bitar::Archive::try_init(bitar::archive_reader::IoReader::new(
+        File::open(&path).await.unwrap(),
+    ))
+    .await
+    .unwrap()
+    .unique_chunks();
+
+    // Appended data repeats the start of source, which should be deduplicated.
+    let mut tail = source_bytes[..20_000].to_vec();
+    let mut random = File::from_std(tempfile::tempfile().unwrap());
+    write_random_bytes(&mut random, 10_000).await;
+    random.read_to_end(&mut tail).await.unwrap();
+    let result = bitar::api::append::append_archive(&path, &tail[..], &Default::default())
+        .await
+        .unwrap();
+    source.write_all(&tail).await.unwrap();
+    assert_eq!(result.source_length, 80_000);
+
+    let archive = bitar::Archive::try_init(bitar::archive_reader::IoReader::new(
+        File::open(&path).await.unwrap(),
+    ))
+    .await
+    .unwrap();
+    assert_eq!(archive.total_source_size(), 80_000);
+    assert_eq!(archive.source_checksum(), &result.source_hash);
+    assert!(archive.unique_chunks() < chunks_before * 2);
+    check_archive_equals_source(&mut File::open(&path).await.unwrap(), &mut source).await;
+
+    // Same source checksum as when compressing the whole source at once.
+    let full_path = dir.path().join("full.cba");
+    source_bytes.extend_from_slice(&tail);
+    create_appendable_archive(&source_bytes, &full_path, None).await;
+    let full = bitar::Archive::try_init(bitar::archive_reader::IoReader::new(
+        File::open(&full_path).await.unwrap(),
+    ))
+    .await
+    .unwrap();
+    assert_eq!(archive.source_checksum(), full.source_checksum());
+}
+
+#[tokio::test]
+async fn append_archive_resigns_header() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("archive.cba");
+    create_appendable_archive(&[1u8; 10_000], &path, None).await;
+    let signing_key = bitar::header::SigningKey::from_bytes(&[7; 32]);
+    let options = bitar::api::append::AppendArchiveOptions {
+        signing_key: Some(signing_key.clone()),
+        ..Default::default()
+    };
+    bitar::api::append::append_archive(&path, &[2u8; 5_000][..], &options)
+        .await
+        .unwrap();
+    let archive = bitar::Archive::try_init_with_signature(
+        bitar::archive_reader::IoReader::new(File::open(&path).await.unwrap()),
+        &signing_key.verifying_key(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(archive.total_source_size(), 15_000);
+
+    // Appending without a key leaves a shorter, unsigned, trailer.
+    bitar::api::append::append_archive(&path, &[3u8; 10][..], &Default::default())
+        .await
+        .unwrap();
+    let mut expected = vec![1u8; 10_000];
+    expected.extend_from_slice(&[2u8; 5_000]);
+    expected.extend_from_slice(&[3u8; 10]);
+    let archive = bitar::Archive::try_init(bitar::archive_reader::IoReader::new(
+        File::open(&path).await.unwrap(),
+    ))
+    .await
+    .unwrap();
+    assert_eq!(clone_to_memory(archive).await, expected);
+}
+
+#[tokio::test]
+async fn append_archive_header_at_start() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("archive.cba");
+    bitar::api::compress::create_archive(
+        &[1u8; 1000][..],
+        File::create(&path).await.unwrap(),
+        &Default::default(),
+    )
+    .await
+    .unwrap();
+    assert!(matches!(
+        bitar::api::append::append_archive(&path, &[2u8; 10][..], &Default::default()).await,
+        Err(bitar::api::append::AppendArchiveError::HeaderNotAtEnd)
+    ));
+}
+// ============================================================================
+// Auto compression
+// ============================================================================
+#[tokio::test]
+async fn compress_auto_compression() {
+    let mut input = File::from_std(tempfile::tempfile().unwrap());
+    let mut output = File::from_std(tempfile::tempfile().unwrap());
+    // Random (incompressible) data followed by compressible text.
+    write_random_bytes(&mut input, 64 * 1024).await;
+    input.seek(std::io::SeekFrom::End(0)).await.unwrap();
+    let text: Vec<u8> = (0..20_000)
+        .flat_map(|v| format!("{} ", v).into_bytes())
+        .collect();
+    input.write_all(&text).await.unwrap();
+    input.rewind().await.unwrap();
+
+    let fast = bitar::Compression::brotli(1).unwrap();
+    let best = bitar::Compression::brotli(11).unwrap();
+    let options = bitar::api::compress::CreateArchiveOptions {
+        chunker_config: chunker::Config::FixedSize(16 * 1024),
+        compression: Some(fast),
+        auto_compression: vec![fast, best],
+        ..Default::default()
+    };
+    bitar::api::compress::create_archive(&mut input, &mut output, &options)
+        .await
+        .unwrap();
+    output.rewind().await.unwrap();
+
+    let archive = bitar::Archive::try_init(bitar::archive_reader::IoReader::new(&mut output))
+        .await
+        .unwrap();
+    assert_eq!(archive.chunk_compression(), Some(fast));
+    let descriptors = archive.chunk_descriptors();
+    // Random data is left uncompressed while text is stored with the best compression.
+    assert!(descriptors[..4]
+        .iter()
+        .all(|cd| cd.compression.is_none() && cd.archive_size == cd.source_size as usize));
+    assert!(descriptors[4..]
+        .iter()
+        .any(|cd| cd.compression == Some(best)));
+
+    check_archive_equals_source(&mut output, &mut input).await;
+}
+
+// ============================================================================
+// Archive order
+// ============================================================================
+#[tokio::test]
+async fn archive_order_is_contiguous() {
+    for header_at_end in [false, true] {
+        let mut input = File::from_std(tempfile::tempfile().unwrap());
+        let mut output = File::from_std(tempfile::tempfile().unwrap());
+        write_random_bytes(&mut input, 100_000).await;
+        let options = bitar::api::compress::CreateArchiveOptions {
+            chunker_config: chunker::Config::FixedSize(1024),
+            header_at_end,
+            ..Default::default()
+        };
+        bitar::api::compress::create_archive(&mut input, &mut output, &options)
+            .await
+            .unwrap();
+        output.rewind().await.unwrap();
+
+        let archive = bitar::Archive::try_init(bitar::archive_reader::IoReader::new(&mut output))
+            .await
+            .unwrap();
+        assert!(archive.is_contiguous());
+        let descriptors: Vec<_> = archive.iter_archive_order().collect();
+        assert_eq!(descriptors.len(), archive.unique_chunks());
+        assert_eq!(descriptors[0].archive_offset, archive.chunk_data_offset());
+        assert!(descriptors
+            .windows(2)
+            .all(|pair| pair[0].archive_end_offset() == pair[1].archive_offset));
+    }
+}
+
+// ============================================================================
+// Export index
+// ============================================================================
+#[tokio::test]
+async fn export_caibx_index() {
+    let mut input = File::from_std(tempfile::tempfile().unwrap());
+    let mut output = File::from_std(tempfile::tempfile().unwrap());
+    write_random_bytes(&mut input, 10_000).await;
+    let options = bitar::api::compress::CreateArchiveOptions {
+        chunker_config: chunker::Config::FixedSize(4096),
+        ..Default::default()
+    };
+    bitar::api::compress::create_archive(&mut input, &mut output, &options)
+        .await
+        .unwrap();
+    output.rewind().await.unwrap();
+    let archive = bitar::Archive::try_init(bitar::archive_reader::IoReader::new(&mut output))
+        .await
+        .unwrap();
+
+    let index = bitar::api::export_index::build_caibx(&archive).unwrap();
+    let u64_at = |offset: usize| u64::from_le_bytes(index[offset..offset + 8].try_into().unwrap());
+    // Index header followed by table header, 3 items and table tail.
+    assert_eq!(index.len(), 48 + 16 + 3 * 40 + 40);
+    assert_eq!(u64_at(0), 48);
+    assert_eq!(u64_at(8), 0x96824d9c7b129ff9);
+    assert_eq!((u64_at(24), u64_at(32), u64_at(40)), (4096, 4096, 4096));
+    assert_eq!(u64_at(48), u64::MAX);
+    assert_eq!(u64_at(56), 0xe75b9e112f17417d);
+    let item_end_offsets: Vec<u64> = (0..3).map(|item| u64_at(64 + item * 40)).collect();
+    assert_eq!(item_end_offsets, [4096, 8192, 10_000]);
+    for (item, (_offset, descriptor)) in archive.iter_source_chunks().enumerate() {
+        let id = &index[64 + item * 40 + 8..64 + (item + 1) * 40];
+        assert_eq!(id, &descriptor.checksum.slice()[..32]);
+    }
+    let tail = index.len() - 40;
+    assert_eq!(u64_at(tail + 16), 48);
+    assert_eq!(u64_at(tail + 24), 16 + 3 * 40 + 40);
+    assert_eq!(u64_at(tail + 32), 0x4b4f050e5549ecd1);
+}
+
+#[tokio::test]
+async fn export_caibx_index_short_hash() {
+    let mut input = File::from_std(tempfile::tempfile().unwrap());
+    let mut output = File::from_std(tempfile::tempfile().unwrap());
+    write_random_bytes(&mut input, 10_000).await;
+    let options = bitar::api::compress::CreateArchiveOptions {
+        chunk_hash_length: 16,
+        ..Default::default()
+    };
+    bitar::api::compress::create_archive(&mut input, &mut output, &options)
+        .await
+        .unwrap();
+    output.rewind().await.unwrap();
+    let archive = bitar::Archive::try_init(bitar::archive_reader::IoReader::new(&mut output))
+        .await
+        .unwrap();
+    assert!(matches!(
+        bitar::api::export_index::build_caibx(&archive),
+        Err(bitar::api::export_index::ExportIndexError::HashTooShort { .. })
+    ));
+}
+
+// ============================================================================
+// Header read from a sidecar
+// ============================================================================
+
+// Create an archive and split it into a header sidecar and the chunk data.
+async fn create_split_archive(source: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let mut archive = Vec::new();
+    let options = bitar::api::compress::CreateArchiveOptions {
+        chunker_config: chunker::Config::FixedSize(1000),
+        compression: Some(bitar::Compression::brotli(6).unwrap()),
+        ..Default::default()
+    };
+    bitar::api::compress::create_archive(source, &mut archive, &options)
+        .await
+        .unwrap();
+    let chunk_data_offset = bitar::Archive::try_init(bitar::archive_reader::IoReader::new(
+        std::io::Cursor::new(archive.clone()),
+    ))
+    .await
+    .unwrap()
+    .chunk_data_offset() as usize;
+    let header = archive[..chunk_data_offset].to_vec();
+    (header, archive)
+}
+
+fn memory_reader(data: &[u8]) -> bitar::archive_reader::IoReader<std::io::Cursor<Vec<u8>>> {
+    bitar::archive_reader::IoReader::new(std::io::Cursor::new(data.to_vec()))
+}
+
+#[tokio::test]
+async fn header_sidecar_with_whole_archive() {
+    let source: Vec<u8> = (0..20_000u32).map(|v| (v * 7 % 251) as u8).collect();
+    let (header, archive) = create_split_archive(&source).await;
+    let archive =
+        bitar::Archive::try_init_with_header(memory_reader(&header), memory_reader(&archive))
+            .await
+            .unwrap();
+    assert_eq!(clone_to_memory(archive).await, source);
+}
+
+#[tokio::test]
+async fn header_sidecar_with_chunk_data_only() {
+    let source: Vec<u8> = (0..20_000u32).map(|v| (v * 7 % 251) as u8).collect();
+    let (header, archive) = create_split_archive(&source).await;
+    let data = &archive[header.len()..];
+    let archive = bitar::Archive::try_init_with_header(memory_reader(&header), memory_reader(data))
+        .await
+        .unwrap();
+    assert_eq!(archive.chunk_data_offset(), 0);
+    assert_eq!(clone_to_memory(archive).await, source);
+}
+
+#[tokio::test]
+async fn header_sidecar_with_other_chunk_data() {
+    let source: Vec<u8> = (0..20_000u32).map(|v| (v * 7 % 251) as u8).collect();
+    let other: Vec<u8> = (0..20_000u32).map(|v| (v * 11 % 241) as u8).collect();
+    let (header, _archive) = create_split_archive(&source).await;
+    let (_other_header, other_archive) = create_split_archive(&other).await;
+    let err =
+        bitar::Archive::try_init_with_header(memory_reader(&header), memory_reader(&other_archive))
+            .await
+            .err()
+            .unwrap();
+    assert!(matches!(err, bitar::ArchiveError::InvalidArchive(_)));
+}
+
+#[tokio::test]
+async fn header_sidecar_with_truncated_chunk_data() {
+    let source: Vec<u8> = (0..20_000u32).map(|v| (v * 7 % 251) as u8).collect();
+    let (header, archive) = create_split_archive(&source).await;
+    let truncated = &archive[..archive.len() - 10];
+    assert!(
+        bitar::Archive::try_init_with_header(memory_reader(&header), memory_reader(truncated))
+            .await
+            .is_err()
+    );
+}
+
+// ============================================================================
+// Chunk data streamed apart from the header
+// ============================================================================
+
+#[tokio::test]
+async fn streaming_matches_create_archive() {
+    use futures_util::StreamExt;
+    // Repeating source, to have chunks which are only stored once.
+    let source: Vec<u8> = (0..30_000u32).map(|v| (v % 7000 * 7 % 251) as u8).collect();
+    let options = bitar::api::compress::CreateArchiveOptions {
+        chunker_config: chunker::Config::FixedSize(1000),
+        ..Default::default()
+    };
+    let mut archive = Vec::new();
+    bitar::api::compress::create_archive(&source[..], &mut archive, &options)
+        .await
+        .unwrap();
+
+    let (mut chunk_data, dictionary) =
+        bitar::api::compress::create_archive_streaming(&source[..], &options);
+    let mut data = Vec::new();
+    while let Some(result) = chunk_data.next().await {
+        data.extend_from_slice(&result.unwrap());
+    }
+    let dictionary = dictionary.await.unwrap();
+    assert!(dictionary.chunk_descriptors.len() < dictionary.rebuild_order.len());
+    let header = bitar::header::build(&dictionary, None, None).unwrap();
+    assert_eq!([&header[..], &data[..]].concat(), archive);
+
+    let archive =
+        bitar::Archive::try_init_with_header(memory_reader(&header), memory_reader(&data))
+            .await
+            .unwrap();
+    assert_eq!(clone_to_memory(archive).await, source);
+}
+
+#[tokio::test]
+async fn streaming_dropped_before_end() {
+    use futures_util::StreamExt;
+    let source: Vec<u8> = (0..30_000u32).map(|v| (v * 7 % 251) as u8).collect();
+    let options = bitar::api::compress::CreateArchiveOptions {
+        chunker_config: chunker::Config::FixedSize(1000),
+        ..Default::default()
+    };
+    let (mut chunk_data, dictionary) =
+        bitar::api::compress::create_archive_streaming(&source[..], &options);
+    chunk_data.next().await.unwrap().unwrap();
+    drop(chunk_data);
+    assert!(matches!(
+        dictionary.await,
+        Err(bitar::api::compress::CreateArchiveError::Incomplete)
+    ));
+}
+
+// ============================================================================
+// Chunks hashed using xxh3
+// ============================================================================
+
+#[tokio::test]
+async fn compress_xxh3_chunk_hash() {
+    let source: Vec<u8> = (0..30_000u32).map(|v| (v % 7000 * 7 % 251) as u8).collect();
+    let options = bitar::api::compress::CreateArchiveOptions {
+        chunker_config: chunker::Config::FixedSize(1000),
+        chunk_hash_algorithm: bitar::HashAlgorithm::Xxh3,
+        ..Default::default()
+    };
+    let mut archive = Vec::new();
+    bitar::api::compress::create_archive(&source[..], &mut archive, &options)
+        .await
+        .unwrap();
+    let archive = bitar::Archive::try_init(memory_reader(&archive))
+        .await
+        .unwrap();
+    assert_eq!(archive.chunk_hash_algorithm(), bitar::HashAlgorithm::Xxh3);
+    // Truncated to the length of a xxh3 hash.
+    assert_eq!(archive.chunk_hash_length(), 16);
+    assert_eq!(
+        archive.source_checksum(),
+        &bitar::Chunk::from(source.clone()).verify().hash().clone()
+    );
+    let coverage = bitar::analyze::seed_coverage(&archive, &source[..])
+        .await
+        .unwrap();
+    assert_eq!(coverage.matched_bytes, source.len() as u64);
+    assert_eq!(clone_to_memory(archive).await, source);
+}
+
+// ============================================================================
+// Chunks hashed using blake2s
+// ============================================================================
+
+#[tokio::test]
+async fn compress_blake2s_chunk_hash() {
+    let source: Vec<u8> = (0..30_000u32).map(|v| (v % 7000 * 7 % 251) as u8).collect();
+    let options = bitar::api::compress::CreateArchiveOptions {
+        chunker_config: chunker::Config::FixedSize(1000),
+        chunk_hash_algorithm: bitar::HashAlgorithm::Blake2s,
+        ..Default::default()
+    };
+    let mut archive = Vec::new();
+    bitar::api::compress::create_archive(&source[..], &mut archive, &options)
+        .await
+        .unwrap();
+    let archive = bitar::Archive::try_init(memory_reader(&archive))
+        .await
+        .unwrap();
+    assert_eq!(
+        archive.chunk_hash_algorithm(),
+        bitar::HashAlgorithm::Blake2s
+    );
+    // Truncated to the length of a blake2s hash.
+    assert_eq!(archive.chunk_hash_length(), 32);
+    let (_offset, descriptor) = archive.iter_source_chunks().next().unwrap();
+    assert_eq!(
+        descriptor.checksum,
+        bitar::HashAlgorithm::Blake2s.digest(&source[..1000])
+    );
+    let coverage = bitar::analyze::seed_coverage(&archive, &source[..])
+        .await
+        .unwrap();
+    assert_eq!(coverage.matched_bytes, source.len() as u64);
+    assert_eq!(clone_to_memory(archive).await, source);
+}
+
+#[tokio::test]
+async fn compress_and_clone_empty_source() {
+    use futures_util::StreamExt;
+    let mut archive = Vec::new();
+    bitar::api::compress::create_archive(
+        &[][..],
+        &mut archive,
+        &bitar::api::compress::CreateArchiveOptions::default(),
+    )
+    .await
+    .unwrap();
+    let mut archive = bitar::Archive::try_init(memory_reader(&archive))
+        .await
+        .unwrap();
+    assert_eq!(archive.total_source_size(), 0);
+    assert_eq!(archive.total_chunks(), 0);
+    assert_eq!(
+        archive.source_checksum(),
+        &bitar::Chunk::from(Vec::new()).verify().hash().clone()
+    );
+    let index = archive.build_source_index();
+    assert!(index.is_empty());
+    assert!(archive.chunk_stream(&index).next().await.is_none());
+    // Cloning writes nothing, but the output is still hashed.
+    let mut output_buf = Vec::new();
+    let mut output = bitar::CloneOutput::builder(std::io::Cursor::new(&mut output_buf), index)
+        .hash_output(true)
+        .build();
+    let fetched =
+        bitar::clone::from_archive(&bitar::clone::Options::default(), &mut archive, &mut output)
+            .await
+            .unwrap();
+    assert_eq!(fetched, 0);
+    assert_eq!(
+        output.output_checksum().as_ref(),
+        Some(archive.source_checksum())
+    );
+    drop(output);
+    assert!(output_buf.is_empty());
+}
+
+#[tokio::test]
+async fn compress_explicit_chunk_boundaries() {
+    let source: Vec<u8> = (0..3000u32).map(|v| (v % 251) as u8).collect();
+    let options = bitar::api::compress::CreateArchiveOptions {
+        chunker_config: chunker::Config::Explicit(vec![100, 1000, 1500]),
+        ..Default::default()
+    };
+    let mut archive = Vec::new();
+    bitar::api::compress::create_archive(&source[..], &mut archive, &options)
+        .await
+        .unwrap();
+    let archive = bitar::Archive::try_init(memory_reader(&archive))
+        .await
+        .unwrap();
+    // Boundaries are not stored in the archive.
+    assert_eq!(
+        archive.chunker_config(),
+        &chunker::Config::Explicit(Vec::new())
+    );
+    let chunks: Vec<(u64, u32)> = archive
+        .iter_source_chunks()
+        .map(|(offset, descriptor)| (offset, descriptor.source_size))
+        .collect();
+    assert_eq!(chunks, [(0, 100), (100, 900), (1000, 500), (1500, 1500)]);
+    assert_eq!(clone_to_memory(archive).await, source);
+}
+
+#[test]
+fn explicit_chunk_boundaries_must_ascend() {
+    assert!(chunker::Config::Explicit(vec![100, 1000])
+        .validate()
+        .is_ok());
+    assert!(chunker::Config::Explicit(vec![1000, 100])
+        .validate()
+        .is_err());
+    assert!(chunker::Config::Explicit(vec![100, 100])
+        .validate()
+        .is_err());
+    assert!(chunker::Config::Explicit(vec![0, 100]).validate().is_err());
+}
+
+#[tokio::test]
+async fn compress_unordered_explicit_chunk_boundaries() {
+    use futures_util::StreamExt;
+    let source = vec![1u8; 3000];
+    let config = chunker::Config::Explicit(vec![1000, 100]);
+    let options = bitar::api::compress::CreateArchiveOptions {
+        chunker_config: config.clone(),
+        ..Default::default()
+    };
+    assert!(matches!(
+        bitar::api::compress::create_archive(&source[..], Vec::new(), &options).await,
+        Err(bitar::api::compress::CreateArchiveError::InvalidChunkerConfig(_))
+    ));
+    let mut chunker = config.new_chunker(&source[..]);
+    let err = chunker.next().await.unwrap().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    assert!(chunker.next().await.is_none());
+}
+
+// ============================================================================
+// Compressed header dictionary
+// ============================================================================
+
+// Dictionary of a source made of many unique fixed size chunks, without any chunk data.
+fn large_dictionary(chunks: u32) -> bitar::chunk_dictionary::ChunkDictionary {
+    use bitar::chunk_dictionary as dict;
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let chunk_descriptors = (0..chunks)
+        .map(|index| dict::ChunkDescriptor {
+            checksum: (0..32)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state as u8
+                })
+                .collect(),
+            source_size: 4096,
+            archive_offset: u64::from(index) * 2000,
+            archive_size: 2000,
+            ..Default::default()
+        })
+        .collect();
+    dict::ChunkDictionary {
+        application_version: "test".to_string(),
+        source_checksum: vec![0; 64],
+        source_total_size: u64::from(chunks) * 4096,
+        chunker_params: Some(dict::ChunkerParameters {
+            max_chunk_size: 4096,
+            chunk_hash_length: 32,
+            chunking_algorithm: dict::chunker_parameters::ChunkingAlgorithm::FixedSize as i32,
+            ..Default::default()
+        }),
+        chunk_compression: Some(bitar::Compression::brotli(6).ok().into()),
+        rebuild_order: (0..chunks).collect(),
+        chunk_descriptors,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn compressed_header_large_dictionary() {
+    let dictionary = large_dictionary(50_000);
+    let header = bitar::header::build(&dictionary, None, None).unwrap();
+    let compressed_header = bitar::header::build_compressed(&dictionary, None, None).unwrap();
+    assert!(compressed_header.starts_with(bitar::header::COMPRESSED_ARCHIVE_MAGIC));
+    assert!(compressed_header.len() < header.len() * 4 / 5);
+
+    // Both the uncompressed and the compressed header describe the same archive.
+    let archive = bitar::Archive::try_init(memory_reader(&header))
+        .await
+        .unwrap();
+    let compressed = bitar::Archive::try_init(memory_reader(&compressed_header))
+        .await
+        .unwrap();
+    assert_eq!(compressed.header_size(), compressed_header.len());
+    assert_eq!(compressed.total_chunks(), 50_000);
+    assert_eq!(compressed.total_source_size(), archive.total_source_size());
+    assert!(compressed
+        .iter_source_chunks()
+        .map(|(offset, descriptor)| (offset, descriptor.checksum.clone()))
+        .eq(archive
+            .iter_source_chunks()
+            .map(|(offset, descriptor)| (offset, descriptor.checksum.clone()))));
+
+    // The decompressed dictionary is limited by the max header size.
+    let result = bitar::Archive::builder(memory_reader(&compressed_header))
+        .max_header_size(compressed_header.len() + 1024)
+        .try_init()
+        .await;
+    assert!(matches!(
+        result,
+        Err(bitar::ArchiveError::InvalidArchive(_))
+    ));
+}
+
+#[tokio::test]
+async fn compressed_header_clone() {
+    let source: Vec<u8> = (0..30_000u32).map(|v| (v % 7000 * 7 % 251) as u8).collect();
+    for header_at_end in [false, true] {
+        let options = bitar::api::compress::CreateArchiveOptions {
+            chunker_config: chunker::Config::FixedSize(1000),
+            compress_header: true,
+            header_at_end,
+            ..Default::default()
+        };
+        let mut archive = Vec::new();
+        bitar::api::compress::create_archive(&source[..], &mut archive, &options)
+            .await
+            .unwrap();
+        let archive = bitar::Archive::try_init(memory_reader(&archive))
+            .await
+            .unwrap();
+        assert_eq!(clone_to_memory(archive).await, source);
+    }
+}
+
+#[tokio::test]
+async fn reproducible_archive() {
+    let source: Vec<u8> = (0..300_000u32)
+        .map(|v| (v % 7000 * 7 % 251) as u8)
+        .collect();
+    let mut metadata = std::collections::BTreeMap::new();
+    metadata.insert("b".to_string(), b"2".to_vec());
+    metadata.insert("a".to_string(), b"1".to_vec());
+    for header_at_end in [false, true] {
+        let options = bitar::api::compress::CreateArchiveOptions {
+            metadata: metadata.clone(),
+            header_at_end,
+            reproducible: true,
+            ..Default::default()
+        };
+        let mut archives = Vec::new();
+        for _ in 0..2 {
+            let mut archive = Vec::new();
+            bitar::api::compress::create_archive(&source[..], &mut archive, &options)
+                .await
+                .unwrap();
+            archives.push(archive);
+        }
+        assert_eq!(archives[0], archives[1]);
+        let archive = bitar::Archive::try_init(memory_reader(&archives[0]))
+            .await
+            .unwrap();
+        assert_eq!(
+            archive.built_with_version(),
+            bitar::api::compress::REPRODUCIBLE_VERSION
+        );
+        assert_eq!(clone_to_memory(archive).await, source);
+    }
+}
+
+#[tokio::test]
+async fn append_keeps_header_compressed() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("archive.cba");
+    let source: Vec<u8> = (0..30_000u32).map(|v| (v % 7000 * 7 % 251) as u8).collect();
+    let options = bitar::api::compress::CreateArchiveOptions {
+        chunker_config: chunker::Config::FixedSize(1000),
+        compress_header: true,
+        header_at_end: true,
+        ..Default::default()
+    };
+    bitar::api::compress::create_archive(&source[..], File::create(&path).await.unwrap(), &options)
+        .await
+        .unwrap();
+    let tail = [7u8; 5_000];
+    bitar::api::append::append_archive(&path, &tail[..], &Default::default())
+        .await
+        .unwrap();
+
+    let archive = std::fs::read(&path).unwrap();
+    let footer = &archive[archive.len() - bitar::header::FOOTER_SIZE..];
+    let header_offset = u64::from_le_bytes(footer[0..8].try_into().unwrap()) as usize;
+    assert!(archive[header_offset..].starts_with(bitar::header::COMPRESSED_ARCHIVE_MAGIC));
+    let archive = bitar::Archive::try_init(memory_reader(&archive))
+        .await
+        .unwrap();
+    assert_eq!(
+        clone_to_memory(archive).await,
+        [&source[..], &tail[..]].concat()
+    );
+}
+
+#[test]
+fn decompress_with_reuses_buffer_brotli() {
+    check_decompress_with(bitar::CompressionAlgorithm::Brotli);
+}
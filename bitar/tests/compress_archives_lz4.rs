@@ -0,0 +1,118 @@
+#![cfg(all(feature = "compress", feature = "lz4-compression"))]
+mod common;
+
+use bitar::chunker;
+
+use tokio::fs::File;
+
+use common::*;
+
+#[tokio::test]
+async fn compress_zero_bytes_fixed_lz4() {
+    let mut input = File::from_std(tempfile::tempfile().unwrap());
+    let mut output = File::from_std(tempfile::tempfile().unwrap());
+
+    compress_archive(
+        &mut input,
+        &mut output,
+        chunker::Config::FixedSize(64),
+        Some(bitar::CompressionAlgorithm::Lz4),
+    )
+    .await;
+
+    check_archive_equals_source(&mut output, &mut input).await;
+}
+
+#[tokio::test]
+async fn compress_zero_bytes_rollsum_lz4() {
+    let mut input = File::from_std(tempfile::tempfile().unwrap());
+    let mut output = File::from_std(tempfile::tempfile().unwrap());
+
+    compress_archive(
+        &mut input,
+        &mut output,
+        chunker::Config::RollSum(chunker::FilterConfig::default()),
+        Some(bitar::CompressionAlgorithm::Lz4),
+    )
+    .await;
+
+    check_archive_equals_source(&mut output, &mut input).await;
+}
+
+#[tokio::test]
+async fn compress_zero_bytes_buzhash_lz4() {
+    let mut input = File::from_std(tempfile::tempfile().unwrap());
+    let mut output = File::from_std(tempfile::tempfile().unwrap());
+
+    compress_archive(
+        &mut input,
+        &mut output,
+        chunker::Config::BuzHash(chunker::FilterConfig::default()),
+        Some(bitar::CompressionAlgorithm::Lz4),
+    )
+    .await;
+
+    check_archive_equals_source(&mut output, &mut input).await;
+}
+
+// ============================================================================
+// Compress random bytes
+// ============================================================================
+#[tokio::test]
+async fn compress_random_bytes_fixed_lz4() {
+    let mut input = File::from_std(tempfile::tempfile().unwrap());
+    let mut output = File::from_std(tempfile::tempfile().unwrap());
+
+    write_random_bytes(&mut input, 8096).await;
+
+    compress_archive(
+        &mut input,
+        &mut output,
+        chunker::Config::FixedSize(64),
+        Some(bitar::CompressionAlgorithm::Lz4),
+    )
+    .await;
+
+    check_archive_equals_source(&mut output, &mut input).await;
+}
+
+#[tokio::test]
+async fn compress_random_bytes_rollsum_lz4() {
+    let mut input = File::from_std(tempfile::tempfile().unwrap());
+    let mut output = File::from_std(tempfile::tempfile().unwrap());
+
+    write_random_bytes(&mut input, 8096).await;
+
+    compress_archive(
+        &mut input,
+        &mut output,
+        chunker::Config::RollSum(chunker::FilterConfig::default()),
+        Some(bitar::CompressionAlgorithm::Lz4),
+    )
+    .await;
+
+    check_archive_equals_source(&mut output, &mut input).await;
+}
+
+#[tokio::test]
+async fn compress_random_bytes_buzhash_lz4() {
+    let mut input = File::from_std(tempfile::tempfile().unwrap());
+    let mut output = File::from_std(tempfile::tempfile().unwrap());
+
+    write_random_bytes(&mut input, 8096).await;
+
+    compress_archive(
+        &mut input,
+        &mut output,
+        chunker::Config::BuzHash(chunker::FilterConfig::default()),
+        Some(bitar::CompressionAlgorithm::Lz4),
+    )
+    .await;
+
+    check_archive_equals_source(&mut output, &mut input).await;
+}
+
+#[test]
+fn decompress_with_reuses_buffer_lz4() {
+    check_decompress_with(bitar::CompressionAlgorithm::Lz4);
+}
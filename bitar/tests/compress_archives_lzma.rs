@@ -115,3 +115,8 @@ async fn compress_random_bytes_buzhash_lzma() {
 
     check_archive_equals_source(&mut output, &mut input).await;
 }
+
+#[test]
+fn decompress_with_reuses_buffer_lzma() {
+    check_decompress_with(bitar::CompressionAlgorithm::Lzma);
+}
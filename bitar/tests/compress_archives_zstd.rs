@@ -4,6 +4,7 @@ mod common;
 use bitar::chunker;
 
 use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 
 use common::*;
 
@@ -111,3 +112,80 @@ async fn compress_random_bytes_buzhash_zstd() {
 
     check_archive_equals_source(&mut output, &mut input).await;
 }
+
+#[tokio::test]
+async fn compress_auto_compression_zstd() {
+    let mut input = File::from_std(tempfile::tempfile().unwrap());
+    let mut output = File::from_std(tempfile::tempfile().unwrap());
+    // Random (incompressible) data followed by compressible text.
+    write_random_bytes(&mut input, 256 * 1024).await;
+    input.seek(std::io::SeekFrom::End(0)).await.unwrap();
+    let text: Vec<u8> = (0..100_000)
+        .flat_map(|v| format!("{} ", v).into_bytes())
+        .collect();
+    input.write_all(&text).await.unwrap();
+    input.rewind().await.unwrap();
+
+    let candidates = vec![
+        bitar::Compression::brotli(6).unwrap(),
+        bitar::Compression::zstd(6).unwrap(),
+    ];
+    let options = bitar::api::compress::CreateArchiveOptions {
+        chunker_config: chunker::Config::FixedSize(8 * 1024),
+        compression: Some(candidates[0]),
+        auto_compression: candidates.clone(),
+        ..Default::default()
+    };
+    bitar::api::compress::create_archive(&mut input, &mut output, &options)
+        .await
+        .unwrap();
+
+    output.rewind().await.unwrap();
+    let archive = bitar::Archive::try_init(bitar::archive_reader::IoReader::new(&mut output))
+        .await
+        .unwrap();
+    assert!(archive
+        .chunk_descriptors()
+        .iter()
+        .all(|cd| cd.compression.is_none() || candidates.contains(&cd.compression.unwrap())));
+
+    check_archive_equals_source(&mut output, &mut input).await;
+}
+
+#[test]
+fn decompress_with_reuses_buffer_zstd() {
+    check_decompress_with(bitar::CompressionAlgorithm::Zstd);
+}
+
+// Compress input with zstd and return the archive size.
+async fn compress_zstd_archive_size(input: &mut File, zstd_long_distance: bool) -> u64 {
+    let mut output = File::from_std(tempfile::tempfile().unwrap());
+    let options = bitar::api::compress::CreateArchiveOptions {
+        chunker_config: chunker::Config::FixedSize(8 * 1024 * 1024),
+        compression: Some(
+            bitar::Compression::zstd(3)
+                .unwrap()
+                .with_zstd_long_distance(zstd_long_distance),
+        ),
+        ..Default::default()
+    };
+    input.rewind().await.unwrap();
+    bitar::api::compress::create_archive(&mut *input, &mut output, &options)
+        .await
+        .unwrap();
+    check_archive_equals_source(&mut output, input).await;
+    output.seek(std::io::SeekFrom::End(0)).await.unwrap()
+}
+
+#[tokio::test]
+async fn compress_long_distance_zstd() {
+    let mut input = File::from_std(tempfile::tempfile().unwrap());
+    // Random data repeated further apart than the zstd window of the compression level.
+    write_random_bytes(&mut input, 3 * 1024 * 1024).await;
+    let mut block = Vec::new();
+    input.read_to_end(&mut block).await.unwrap();
+    input.write_all(&block).await.unwrap();
+
+    assert!(compress_zstd_archive_size(&mut input, false).await > 6 * 1024 * 1024);
+    assert!(compress_zstd_archive_size(&mut input, true).await < 4 * 1024 * 1024);
+}
@@ -0,0 +1,146 @@
+use std::io::Cursor;
+
+use bitar::{
+    archive_reader::IoReader, chunk_dictionary as dict, clone, header, Archive, Chunk, CloneOutput,
+};
+
+// Blocks of the source, the first stored in the base archive and the second in the archive.
+fn blocks() -> (Vec<u8>, Vec<u8>) {
+    let block = |seed: u32| (0..1000u32).map(|v| (v % 251 + seed) as u8).collect();
+    (block(1), block(2))
+}
+
+// Delta archive of a 2000 byte source, where only the second 1000 byte chunk is stored.
+async fn delta_archive() -> Archive<IoReader<Cursor<Vec<u8>>>> {
+    let (base_block, stored_block) = blocks();
+    let checksum = |block: &Vec<u8>| Chunk::from(block.clone()).verify().hash().to_vec();
+    let dictionary = dict::ChunkDictionary {
+        application_version: "test".to_string(),
+        source_checksum: vec![0; 64],
+        source_total_size: 2000,
+        chunker_params: Some(dict::ChunkerParameters {
+            max_chunk_size: 1000,
+            chunk_hash_length: 64,
+            chunking_algorithm: dict::chunker_parameters::ChunkingAlgorithm::FixedSize as i32,
+            ..Default::default()
+        }),
+        chunk_compression: Some(None.into()),
+        rebuild_order: vec![0, 1],
+        chunk_descriptors: vec![
+            dict::ChunkDescriptor {
+                checksum: checksum(&base_block),
+                source_size: 1000,
+                archive_offset: 0,
+                archive_size: 0,
+                in_base: true,
+                ..Default::default()
+            },
+            dict::ChunkDescriptor {
+                checksum: checksum(&stored_block),
+                source_size: 1000,
+                archive_offset: 0,
+                archive_size: 1000,
+                ..Default::default()
+            },
+        ],
+        base_header_checksum: vec![2; 64],
+        ..Default::default()
+    };
+    let mut archive = header::build(&dictionary, None, None).unwrap();
+    archive.extend_from_slice(&stored_block);
+    Archive::try_init(IoReader::new(Cursor::new(archive)))
+        .await
+        .unwrap()
+}
+
+fn source() -> Vec<u8> {
+    let (base_block, stored_block) = blocks();
+    [base_block, stored_block].concat()
+}
+
+#[tokio::test]
+async fn count_base_chunks() {
+    let archive = delta_archive().await;
+    assert!(archive.base_header_checksum().is_some());
+    assert_eq!(archive.base_chunk_count(&archive.build_source_index()), 1);
+    assert_eq!(archive.fetch_size(&archive.build_source_index()), 1000);
+}
+
+#[tokio::test]
+async fn clone_without_base_fails() {
+    let mut archive = delta_archive().await;
+    let mut output_buf = Vec::new();
+    let mut output = CloneOutput::new(Cursor::new(&mut output_buf), archive.build_source_index());
+    assert!(matches!(
+        clone::from_archive(&clone::Options::default(), &mut archive, &mut output).await,
+        Err(clone::CloneError::MissingBaseChunks(1))
+    ));
+    assert!(matches!(
+        clone::run(
+            &clone::Options::default(),
+            &mut archive,
+            Vec::<&[u8]>::new(),
+            &mut output
+        )
+        .await,
+        Err(clone::CloneError::MissingBaseChunks(1))
+    ));
+    assert_eq!(output.len(), 2);
+    drop(output);
+    assert!(output_buf.is_empty());
+}
+
+#[tokio::test]
+async fn clone_with_base_source_as_seed() {
+    let mut archive = delta_archive().await;
+    let (base_block, _) = blocks();
+    let mut output_buf = Vec::new();
+    let mut output = CloneOutput::new(Cursor::new(&mut output_buf), archive.build_source_index());
+    let report = clone::run(
+        &clone::Options::default(),
+        &mut archive,
+        [&base_block[..]],
+        &mut output,
+    )
+    .await
+    .unwrap();
+    assert!(output.is_empty());
+    drop(output);
+    assert_eq!(report.bytes_from_seed, 1000);
+    assert_eq!(report.bytes_from_archive, 1000);
+    assert_eq!(output_buf, source());
+}
+
+#[tokio::test]
+async fn clone_in_place_without_base_fails() {
+    let mut archive = delta_archive().await;
+    let mut output_buf = vec![0; 2000];
+    assert!(matches!(
+        clone::run_in_place(
+            &clone::Options::default(),
+            &mut archive,
+            Vec::<&[u8]>::new(),
+            Cursor::new(&mut output_buf),
+        )
+        .await,
+        Err(clone::CloneError::MissingBaseChunks(1))
+    ));
+}
+
+#[tokio::test]
+async fn clone_in_place_with_base_chunk_in_output() {
+    let mut archive = delta_archive().await;
+    let (base_block, _) = blocks();
+    let mut output_buf = [vec![0; 1000], base_block].concat();
+    let report = clone::run_in_place(
+        &clone::Options::default(),
+        &mut archive,
+        Vec::<&[u8]>::new(),
+        Cursor::new(&mut output_buf),
+    )
+    .await
+    .unwrap();
+    assert_eq!(report.bytes_moved_in_place, 1000);
+    assert_eq!(report.bytes_from_archive, 1000);
+    assert_eq!(output_buf, source());
+}
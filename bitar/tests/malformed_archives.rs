@@ -0,0 +1,267 @@
+use std::io::Cursor;
+use std::pin::Pin;
+
+use async_trait::async_trait;
+use bitar::{
+    archive_reader::{ArchiveReader, IoReader},
+    chunk_dictionary as dict, header, Archive, ArchiveError, ChunkOffset,
+};
+use bytes::Bytes;
+use futures_util::stream::{self, Stream, StreamExt};
+
+type ArchiveResult = Result<Archive<IoReader<Cursor<Vec<u8>>>>, ArchiveError<std::io::Error>>;
+
+// Dictionary of a 20 byte source made of the same 10 byte chunk twice.
+fn dictionary() -> dict::ChunkDictionary {
+    dict::ChunkDictionary {
+        application_version: "test".to_string(),
+        source_checksum: vec![0; 64],
+        source_total_size: 20,
+        chunker_params: Some(dict::ChunkerParameters {
+            max_chunk_size: 10,
+            chunk_hash_length: 64,
+            chunking_algorithm: dict::chunker_parameters::ChunkingAlgorithm::FixedSize as i32,
+            ..Default::default()
+        }),
+        chunk_compression: Some(None.into()),
+        rebuild_order: vec![0, 0],
+        chunk_descriptors: vec![dict::ChunkDescriptor {
+            checksum: vec![1; 64],
+            source_size: 10,
+            archive_offset: 0,
+            archive_size: 10,
+            ..Default::default()
+        }],
+        ..Default::default()
+    }
+}
+
+fn header_at_end_archive(dictionary: &dict::ChunkDictionary) -> Vec<u8> {
+    let mut archive = header::TRAILER_MAGIC.to_vec();
+    archive.extend_from_slice(&[1; 10]);
+    archive.extend(header::build_trailer(dictionary, 10, None).unwrap());
+    archive
+}
+
+async fn try_init(archive: Vec<u8>) -> ArchiveResult {
+    Archive::try_init(IoReader::new(Cursor::new(archive))).await
+}
+
+// Reader returning whatever is left of a read past the end of the archive, like a server
+// cutting a range request at the end of the file.
+struct ShortReader(Bytes);
+
+#[async_trait]
+impl ArchiveReader for ShortReader {
+    type Error = std::io::Error;
+
+    async fn read_at(&mut self, offset: u64, size: usize) -> Result<Bytes, std::io::Error> {
+        let start = (offset as usize).min(self.0.len());
+        let end = start.saturating_add(size).min(self.0.len());
+        Ok(self.0.slice(start..end))
+    }
+
+    fn read_chunks<'a>(
+        &'a mut self,
+        chunks: Vec<ChunkOffset>,
+    ) -> Pin<Box<dyn Stream<Item = Result<Bytes, std::io::Error>> + Send + 'a>> {
+        Box::pin(stream::iter(chunks).then(move |chunk| {
+            let data = self.0.clone();
+            async move {
+                let mut reader = ShortReader(data);
+                reader.read_at(chunk.offset, chunk.size).await
+            }
+        }))
+    }
+}
+
+async fn try_init_short(
+    archive: Vec<u8>,
+) -> Result<Archive<ShortReader>, ArchiveError<std::io::Error>> {
+    Archive::try_init(ShortReader(Bytes::from(archive))).await
+}
+
+#[tokio::test]
+async fn crafted_archive() {
+    let archive = try_init(header_at_end_archive(&dictionary()))
+        .await
+        .unwrap();
+    assert_eq!(archive.total_source_size(), 20);
+    let mut archive = header::build(&dictionary(), None, None).unwrap();
+    archive.extend_from_slice(&[1; 10]);
+    try_init(archive).await.unwrap();
+}
+
+#[tokio::test]
+async fn huge_dictionary_size() {
+    let mut archive = header::ARCHIVE_MAGIC.to_vec();
+    archive.extend_from_slice(&(u64::MAX - 10).to_le_bytes());
+    archive.extend_from_slice(&[0; 100]);
+    assert!(matches!(
+        try_init(archive).await,
+        Err(ArchiveError::InvalidArchive(_))
+    ));
+}
+
+#[tokio::test]
+async fn dictionary_size_beyond_end_of_archive() {
+    let mut archive = header::ARCHIVE_MAGIC.to_vec();
+    archive.extend_from_slice(&(100 * 1024 * 1024u64).to_le_bytes());
+    archive.extend_from_slice(&[0; 100]);
+    assert!(matches!(
+        try_init(archive).await,
+        Err(ArchiveError::ReaderError(_))
+    ));
+}
+
+#[tokio::test]
+async fn truncated_header() {
+    let archive = header::build(&dictionary(), None, None).unwrap();
+    let truncated = archive[..archive.len() / 2].to_vec();
+    assert!(matches!(
+        try_init(truncated).await,
+        Err(ArchiveError::ReaderError(_))
+    ));
+}
+
+#[tokio::test]
+async fn dictionary_size_exceeds_trailer_header_size() {
+    let mut archive = header_at_end_archive(&dictionary());
+    // Grow the dictionary size of the header following the chunk data.
+    let size_offset = header::TRAILER_MAGIC.len() + 10 + header::ARCHIVE_MAGIC.len();
+    let size = u64::from_le_bytes(archive[size_offset..size_offset + 8].try_into().unwrap());
+    archive[size_offset..size_offset + 8].copy_from_slice(&(size + 1000).to_le_bytes());
+    assert!(matches!(
+        try_init(archive).await,
+        Err(ArchiveError::InvalidArchive(_))
+    ));
+}
+
+#[tokio::test]
+async fn max_header_size() {
+    let archive = header_at_end_archive(&dictionary());
+    assert!(matches!(
+        Archive::builder(IoReader::new(Cursor::new(archive.clone())))
+            .max_header_size(100)
+            .try_init()
+            .await,
+        Err(ArchiveError::InvalidArchive(_))
+    ));
+    Archive::builder(IoReader::new(Cursor::new(archive)))
+        .max_header_size(1000)
+        .try_init()
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn skip_header_checksum_verification() {
+    let mut archive = header::build(&dictionary(), None, None).unwrap();
+    // Corrupt the stored header checksum, which ends the header.
+    *archive.last_mut().unwrap() ^= 0xff;
+    archive.extend_from_slice(&[1; 10]);
+    assert!(matches!(
+        try_init(archive.clone()).await,
+        Err(ArchiveError::InvalidArchive(_))
+    ));
+    let archive = Archive::builder(IoReader::new(Cursor::new(archive)))
+        .verify_header_checksum(false)
+        .try_init()
+        .await
+        .unwrap();
+    assert_eq!(archive.total_source_size(), 20);
+}
+
+#[tokio::test]
+async fn source_refers_missing_chunk() {
+    let mut dictionary = dictionary();
+    dictionary.rebuild_order = vec![0, 1];
+    assert!(matches!(
+        try_init(header_at_end_archive(&dictionary)).await,
+        Err(ArchiveError::InvalidArchive(_))
+    ));
+}
+
+#[tokio::test]
+async fn source_size_mismatch() {
+    let mut dictionary = dictionary();
+    dictionary.source_total_size = 21;
+    assert!(matches!(
+        try_init(header_at_end_archive(&dictionary)).await,
+        Err(ArchiveError::InvalidArchive(_))
+    ));
+}
+
+#[tokio::test]
+async fn chunk_beyond_data_region() {
+    let mut dictionary = dictionary();
+    dictionary.chunk_descriptors[0].archive_offset = 1;
+    assert!(matches!(
+        try_init(header_at_end_archive(&dictionary)).await,
+        Err(ArchiveError::InvalidArchive(_))
+    ));
+    dictionary.chunk_descriptors[0].archive_offset = u64::MAX - 5;
+    let mut archive = header::build(&dictionary, None, None).unwrap();
+    archive.extend_from_slice(&[1; 10]);
+    assert!(matches!(
+        try_init(archive).await,
+        Err(ArchiveError::InvalidArchive(_))
+    ));
+}
+
+#[tokio::test]
+async fn truncated_pre_header() {
+    let archive = header::build(&dictionary(), None, None).unwrap();
+    for len in [
+        0,
+        3,
+        header::ARCHIVE_MAGIC.len(),
+        header::PRE_HEADER_SIZE - 1,
+    ] {
+        assert!(matches!(
+            try_init_short(archive[..len].to_vec()).await,
+            Err(ArchiveError::InvalidArchive(_))
+        ));
+    }
+}
+
+#[tokio::test]
+async fn truncated_dictionary() {
+    let mut archive = header::build(&dictionary(), None, None).unwrap();
+    archive.extend_from_slice(&[1; 10]);
+    try_init_short(archive.clone()).await.unwrap();
+    for len in [
+        header::PRE_HEADER_SIZE,
+        header::PRE_HEADER_SIZE + 10,
+        archive.len() - 10 - 64,
+        archive.len() - 10 - 1,
+    ] {
+        assert!(matches!(
+            try_init_short(archive[..len].to_vec()).await,
+            Err(ArchiveError::InvalidArchive(_))
+        ));
+    }
+}
+
+#[tokio::test]
+async fn dictionary_size_beyond_end_of_short_archive() {
+    let mut archive = header::ARCHIVE_MAGIC.to_vec();
+    archive.extend_from_slice(&(100 * 1024 * 1024u64).to_le_bytes());
+    archive.extend_from_slice(&[0; 100]);
+    assert!(matches!(
+        try_init_short(archive).await,
+        Err(ArchiveError::InvalidArchive(_))
+    ));
+}
+
+#[tokio::test]
+async fn chunk_within_header() {
+    // Chunk data offset pointing into the header.
+    let dictionary = dictionary();
+    let mut archive = header::build(&dictionary, Some(10), None).unwrap();
+    archive.extend_from_slice(&[1; 10]);
+    assert!(matches!(
+        try_init(archive).await,
+        Err(ArchiveError::InvalidArchive(_))
+    ));
+}
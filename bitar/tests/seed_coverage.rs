@@ -0,0 +1,129 @@
+#![cfg(feature = "compress")]
+
+use bitar::{analyze, archive_reader::IoReader, chunker, clone, Archive, CloneOutput};
+
+// Archive of a source made of ten different 1000 byte blocks, where the last block is
+// repeated.
+async fn create_archive() -> (Archive<IoReader<std::io::Cursor<Vec<u8>>>>, Vec<u8>) {
+    create_archive_chunked(chunker::Config::FixedSize(1000)).await
+}
+
+async fn create_archive_chunked(
+    chunker_config: chunker::Config,
+) -> (Archive<IoReader<std::io::Cursor<Vec<u8>>>>, Vec<u8>) {
+    let mut source: Vec<u8> = (0..10_000u32).map(|v| (v / 1000 + v % 13) as u8).collect();
+    source.extend_from_within(9000..);
+    let mut output = Vec::new();
+    let options = bitar::api::compress::CreateArchiveOptions {
+        chunker_config,
+        compression: None,
+        ..Default::default()
+    };
+    bitar::api::compress::create_archive(&source[..], &mut output, &options)
+        .await
+        .unwrap();
+    let archive = Archive::try_init(IoReader::new(std::io::Cursor::new(output)))
+        .await
+        .unwrap();
+    (archive, source)
+}
+
+#[tokio::test]
+async fn seed_coverage_of_source() {
+    let (archive, source) = create_archive().await;
+    let coverage = analyze::seed_coverage(&archive, &source[..]).await.unwrap();
+    assert_eq!(
+        coverage,
+        analyze::Coverage {
+            matched_chunks: 10,
+            matched_bytes: 11_000,
+            source_size: 11_000,
+        }
+    );
+    assert_eq!(coverage.fraction(), 1.0);
+}
+
+#[tokio::test]
+async fn seed_coverage_of_partial_seed() {
+    let (archive, source) = create_archive().await;
+    // Two blocks, in another order, and the repeated block.
+    let mut seed = Vec::new();
+    seed.extend_from_slice(&source[3000..4000]);
+    seed.extend_from_slice(&[0xff; 1000]);
+    seed.extend_from_slice(&source[1000..2000]);
+    seed.extend_from_slice(&source[9000..10_000]);
+    let coverage = analyze::seed_coverage(&archive, &seed[..]).await.unwrap();
+    assert_eq!(coverage.matched_chunks, 3);
+    assert_eq!(coverage.matched_bytes, 4000);
+    assert_eq!(coverage.fraction(), 4000.0 / 11_000.0);
+}
+
+#[tokio::test]
+async fn seed_coverage_of_unrelated_seed() {
+    let (archive, _source) = create_archive().await;
+    let coverage = analyze::seed_coverage(&archive, &[0u8; 5000][..])
+        .await
+        .unwrap();
+    assert_eq!(coverage.matched_chunks, 0);
+    assert_eq!(coverage.matched_bytes, 0);
+    assert_eq!(coverage.fraction(), 0.0);
+}
+
+// Archive chunked at the block boundaries, which are not stored in the archive.
+async fn create_explicit_archive() -> (Archive<IoReader<std::io::Cursor<Vec<u8>>>>, Vec<u8>) {
+    create_archive_chunked(chunker::Config::Explicit(
+        (1..11).map(|v| v * 1000).collect(),
+    ))
+    .await
+}
+
+#[tokio::test]
+async fn seed_coverage_of_explicit_archive() {
+    let (archive, source) = create_explicit_archive().await;
+    assert!(!archive.can_scan_seeds());
+    assert_eq!(archive.total_chunks(), 11);
+    let err = analyze::seed_coverage(&archive, &source[..])
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+}
+
+#[tokio::test]
+async fn clone_explicit_archive_ignores_seeds() {
+    let (mut archive, source) = create_explicit_archive().await;
+    let mut output_buf = Vec::new();
+    let mut output = CloneOutput::new(
+        std::io::Cursor::new(&mut output_buf),
+        archive.build_source_index(),
+    );
+    let report = clone::run(
+        &clone::Options::default(),
+        &mut archive,
+        [&source[..]],
+        &mut output,
+    )
+    .await
+    .unwrap();
+    drop(output);
+    assert_eq!(report.bytes_from_seed, 0);
+    assert_eq!(report.bytes_to_fetch, 10_000);
+    assert_eq!(output_buf, source);
+}
+
+#[tokio::test]
+async fn clone_explicit_archive_in_place_fetches_source() {
+    let (mut archive, source) = create_explicit_archive().await;
+    let mut output_buf = source.clone();
+    output_buf.rotate_left(1000);
+    let report = clone::run_in_place(
+        &clone::Options::default(),
+        &mut archive,
+        Vec::<&[u8]>::new(),
+        std::io::Cursor::new(&mut output_buf),
+    )
+    .await
+    .unwrap();
+    assert_eq!(report.bytes_moved_in_place, 0);
+    assert_eq!(report.bytes_from_archive, 10_000);
+    assert_eq!(output_buf, source);
+}
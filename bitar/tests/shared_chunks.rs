@@ -0,0 +1,69 @@
+#![cfg(feature = "compress")]
+
+use bitar::{analyze::SharedStats, archive_reader::IoReader, chunker, Archive, HashAlgorithm};
+
+async fn create_archive(
+    source: &[u8],
+    chunk_hash_length: usize,
+    chunk_hash_algorithm: HashAlgorithm,
+) -> Archive<IoReader<std::io::Cursor<Vec<u8>>>> {
+    let mut output = Vec::new();
+    let options = bitar::api::compress::CreateArchiveOptions {
+        chunker_config: chunker::Config::FixedSize(1000),
+        chunk_hash_length,
+        chunk_hash_algorithm,
+        compression: None,
+        ..Default::default()
+    };
+    bitar::api::compress::create_archive(source, &mut output, &options)
+        .await
+        .unwrap();
+    Archive::try_init(IoReader::new(std::io::Cursor::new(output)))
+        .await
+        .unwrap()
+}
+
+// Source of the given 1000 byte blocks, where each block is filled by its number.
+fn source(blocks: &[u8]) -> Vec<u8> {
+    blocks.iter().flat_map(|&block| [block; 1000]).collect()
+}
+
+#[tokio::test]
+async fn shared_chunks_of_archives() {
+    // Block 1 is repeated in a and each unique chunk only counted once.
+    let a = create_archive(&source(&[0, 1, 2, 1, 3]), 64, HashAlgorithm::Blake2b).await;
+    let b = create_archive(&source(&[1, 3, 4, 5, 6]), 64, HashAlgorithm::Blake2b).await;
+    let stats = a.shared_chunks(&b);
+    assert_eq!(
+        stats,
+        SharedStats {
+            shared_chunks: 2,
+            shared_bytes: 2000,
+            unique_bytes: 2000,
+            other_unique_bytes: 3000,
+        }
+    );
+    let reversed = b.shared_chunks(&a);
+    assert_eq!(reversed.shared_chunks, 2);
+    assert_eq!(reversed.unique_bytes, 3000);
+    assert_eq!(reversed.other_unique_bytes, 2000);
+}
+
+#[tokio::test]
+async fn shared_chunks_of_truncated_hashes() {
+    let a = create_archive(&source(&[0, 1, 2]), 64, HashAlgorithm::Blake2b).await;
+    let b = create_archive(&source(&[2, 1, 4]), 8, HashAlgorithm::Blake2b).await;
+    let stats = a.shared_chunks(&b);
+    assert_eq!(stats.shared_chunks, 2);
+    assert_eq!(stats.shared_bytes, 2000);
+}
+
+#[tokio::test]
+async fn shared_chunks_of_other_hash_algorithm() {
+    let a = create_archive(&source(&[0, 1, 2]), 16, HashAlgorithm::Blake2b).await;
+    let b = create_archive(&source(&[0, 1, 2]), 16, HashAlgorithm::Xxh3).await;
+    let stats = a.shared_chunks(&b);
+    assert_eq!(stats.shared_chunks, 0);
+    assert_eq!(stats.unique_bytes, 3000);
+    assert_eq!(stats.other_unique_bytes, 3000);
+}
@@ -0,0 +1,107 @@
+use anyhow::{bail, Result};
+use log::*;
+use tokio::fs::File;
+
+use crate::clone_cmd::InputArchive;
+use crate::{info_cmd, signature};
+use bitar::archive_reader::{ArchiveReader, IoReader};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// Local file or URL to read archive from.
+    pub input_archive: InputArchive,
+    /// Write the header checksum to the checksum file of the archive, `<archive>.b2`.
+    pub write: bool,
+    /// Overwrite an existing checksum file.
+    pub force_create: bool,
+}
+
+async fn checksum_archive<R>(opts: &Options, reader: R) -> Result<()>
+where
+    R: ArchiveReader,
+    R::Error: std::error::Error + Send + Sync + 'static,
+{
+    let archive = signature::init_archive(reader, None, true).await?;
+    println!("{}", archive.header_checksum());
+    if opts.write {
+        let path = info_cmd::write_checksum_file(
+            &opts.input_archive,
+            archive.header_checksum(),
+            opts.force_create,
+        )?;
+        info!("Wrote header checksum to {}", path.display());
+    }
+    Ok(())
+}
+
+pub async fn checksum_cmd(opts: Options) -> Result<()> {
+    match opts.input_archive.clone() {
+        InputArchive::Local(path) => {
+            checksum_archive(&opts, IoReader::new(File::open(path).await?)).await
+        }
+        _ if opts.write => bail!("Checksum file can only be written next to a local archive"),
+        InputArchive::Remote(input) => checksum_archive(&opts, input.reader()?).await,
+        #[cfg(feature = "object-store")]
+        InputArchive::ObjectStore(url) => {
+            checksum_archive(&opts, crate::clone_cmd::object_store_reader(&url)?).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitar::chunker;
+
+    #[tokio::test]
+    async fn write_checksum_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = dir.path().join("archive.cba");
+        bitar::api::compress::create_archive(
+            &[7u8; 10_000][..],
+            File::create(&archive_path).await.unwrap(),
+            &bitar::api::compress::CreateArchiveOptions {
+                chunker_config: chunker::Config::FixedSize(4096),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let opts = Options {
+            input_archive: InputArchive::Local(archive_path.clone()),
+            write: true,
+            force_create: false,
+        };
+        checksum_cmd(opts.clone()).await.unwrap();
+
+        let archive =
+            bitar::Archive::try_init(IoReader::new(File::open(&archive_path).await.unwrap()))
+                .await
+                .unwrap();
+        let (path, checksum) = info_cmd::read_checksum_file(&opts.input_archive)
+            .unwrap()
+            .unwrap();
+        assert_eq!(path, dir.path().join("archive.cba.b2"));
+        assert_eq!(&checksum, archive.header_checksum());
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .ends_with("  archive.cba\n"));
+
+        // An existing checksum file is only overwritten when forced.
+        std::fs::write(&path, "garbage").unwrap();
+        assert!(checksum_cmd(opts.clone()).await.is_err());
+        checksum_cmd(Options {
+            force_create: true,
+            ..opts.clone()
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            info_cmd::read_checksum_file(&opts.input_archive)
+                .unwrap()
+                .unwrap()
+                .1,
+            checksum
+        );
+    }
+}
@@ -0,0 +1,75 @@
+use log::*;
+
+use crate::human_size;
+
+/// Stages of compressing buffering chunks: hashing and compressing.
+pub const COMPRESS_STAGES: usize = 2;
+
+/// Stages of cloning buffering chunks: chunking a seed (or reading the archive), hashing (or
+/// decompressing) and queueing chunks for the output.
+pub const CLONE_STAGES: usize = 3;
+
+/// Lower the number of chunk buffers to keep the chunks buffered by every stage within
+/// max_chunk_memory bytes.
+///
+/// Each of the stages buffers up to the returned number of chunks, plus the chunk it's
+/// handing on to the next stage. Assumes each buffered chunk is of max chunk size. At least
+/// one chunk is always buffered.
+pub fn limit_chunk_buffers(
+    num_chunk_buffers: usize,
+    stages: usize,
+    max_chunk_size: usize,
+    max_chunk_memory: Option<usize>,
+) -> usize {
+    let Some(max_chunk_memory) = max_chunk_memory else {
+        return num_chunk_buffers;
+    };
+    let chunks_per_stage = max_chunk_memory / max_chunk_size.max(1) / stages.max(1);
+    let limit = chunks_per_stage.saturating_sub(1).max(1);
+    if num_chunk_buffers <= limit {
+        return num_chunk_buffers;
+    }
+    info!(
+        "Buffering at most {} chunks (instead of {}) in each of {} stages to keep chunks of up to {} within {}",
+        limit,
+        num_chunk_buffers,
+        stages,
+        human_size!(max_chunk_size),
+        human_size!(max_chunk_memory)
+    );
+    limit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limit_chunk_buffers_to_memory() {
+        assert_eq!(limit_chunk_buffers(128, 1, 16 * 1024 * 1024, None), 128);
+        assert_eq!(
+            limit_chunk_buffers(128, 1, 16 * 1024 * 1024, Some(256 * 1024 * 1024)),
+            15
+        );
+        assert_eq!(
+            limit_chunk_buffers(8, 1, 16 * 1024 * 1024, Some(1024 * 1024 * 1024)),
+            8
+        );
+        // Always room for a single chunk.
+        assert_eq!(limit_chunk_buffers(8, 1, 16 * 1024 * 1024, Some(1024)), 1);
+    }
+
+    #[test]
+    fn limit_chunk_buffers_of_every_stage() {
+        // 16 chunks fit, 5 per stage of which 1 is being handed on to the next stage.
+        assert_eq!(
+            limit_chunk_buffers(128, CLONE_STAGES, 1024, Some(16 * 1024)),
+            4
+        );
+        assert_eq!(
+            limit_chunk_buffers(128, COMPRESS_STAGES, 1024, Some(16 * 1024)),
+            7
+        );
+        assert_eq!(limit_chunk_buffers(128, CLONE_STAGES, 1024, Some(1024)), 1);
+    }
+}
@@ -1,39 +1,165 @@
 use anyhow::{anyhow, Context, Result};
 use blake2::{Blake2b512, Digest};
-use futures_util::StreamExt;
+use futures_util::{future, stream, Stream, StreamExt};
 use log::*;
 use reqwest::header::HeaderMap;
+use std::borrow::Cow;
+use std::collections::HashSet;
 use std::io::{IsTerminal, SeekFrom};
-use std::path::PathBuf;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tokio::fs::File;
 use tokio::{
-    io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite},
+    io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt},
+    sync::mpsc,
     task::spawn_blocking,
 };
 use url::Url;
 
-use crate::{human_size, info_cmd};
+use crate::progress::{self, Progress, ProgressFormat};
+use crate::{chunk_buffers, file_metadata, human_size, info_cmd, signature};
+#[cfg(feature = "object-store")]
+use bitar::archive_reader::ObjectStoreReader;
 use bitar::{
-    archive_reader::{ArchiveReader, HttpReader, IoReader},
-    chunker, Archive, ChunkIndex, CloneOutput, HashSum, VerifiedChunk,
+    archive_reader::{ArchiveReader, HttpClientConfig, HttpReader, IoReader},
+    chunker, clone, Archive, ArchiveChunkError, Chunk, ChunkIndex, CloneOutput, HashAlgorithm,
+    HashSum, OrderedWriter, ReorderOp, VerifiedChunk,
 };
 
+/// Chunk hashes shorter than this are not trusted to tell chunks apart.
+const MIN_SAFE_HASH_LENGTH: usize = 16;
+
+/// Clone failures with a distinct process exit status, see `crate::exit_code`.
+#[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
+pub enum CloneError {
+    /// The archive header checksum is not the one given by --verify-header, or the one of
+    /// the archive checksum file.
+    HeaderChecksumMismatch,
+    /// The output device, or output file not to be resized, is smaller than the archive
+    /// source at the output offset.
+    OutputSizeMismatch {
+        output_size: u64,
+        output_offset: u64,
+        source_size: u64,
+    },
+    /// The checksum of the cloned output is not the archive source checksum.
+    SourceChecksumMismatch {
+        output: String,
+        checksum: HashSum,
+        archive: String,
+        expected: HashSum,
+    },
+    /// Archive chunks failed to decompress or verify and were zero filled by --lenient.
+    InvalidChunksZeroFilled { count: usize, bytes: u64 },
+    /// The clone did not finish within --http-total-deadline.
+    DeadlineExceeded { deadline: Duration },
+}
+
+impl std::error::Error for CloneError {}
+
+impl std::fmt::Display for CloneError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CloneError::HeaderChecksumMismatch => write!(f, "Header checksum mismatch"),
+            CloneError::OutputSizeMismatch {
+                output_size,
+                output_offset: 0,
+                source_size,
+            } => write!(
+                f,
+                "Size of output ({}) is less than archive target file ({})",
+                human_size!(*output_size),
+                human_size!(*source_size)
+            ),
+            CloneError::OutputSizeMismatch {
+                output_size,
+                output_offset,
+                source_size,
+            } => write!(
+                f,
+                "Size of output ({}) is less than archive target file ({}) at offset {}",
+                human_size!(*output_size),
+                human_size!(*source_size),
+                output_offset
+            ),
+            CloneError::SourceChecksumMismatch {
+                output,
+                checksum,
+                archive,
+                expected,
+            } => write!(
+                f,
+                "Checksum mismatch ({}: {}, {}: {})",
+                output, checksum, archive, expected
+            ),
+            CloneError::InvalidChunksZeroFilled { count, bytes } => write!(
+                f,
+                "{} chunks ({}) failed to decompress or verify and were zero filled",
+                count,
+                human_size!(*bytes)
+            ),
+            CloneError::DeadlineExceeded { deadline } => write!(
+                f,
+                "Clone did not finish within the deadline of {}s",
+                deadline.as_secs()
+            ),
+        }
+    }
+}
+
+// Chunks zero filled in output by a lenient clone.
+#[derive(Debug, Default)]
+struct FailedChunks {
+    count: usize,
+    bytes: u64,
+}
+
+impl FailedChunks {
+    fn into_result(self) -> Result<()> {
+        if self.count > 0 {
+            return Err(CloneError::InvalidChunksZeroFilled {
+                count: self.count,
+                bytes: self.bytes,
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
+
 async fn file_size(file: &mut File) -> Result<u64, std::io::Error> {
     file.seek(SeekFrom::Start(0)).await?;
     file.seek(SeekFrom::End(0)).await
 }
 
-async fn file_checksum(file: &mut File) -> Result<HashSum, std::io::Error> {
-    file.seek(SeekFrom::Start(0)).await?;
+// Checksum of the size bytes at offset in file.
+async fn file_checksum(
+    file: &mut File,
+    offset: u64,
+    size: u64,
+    mut progress: Option<Progress>,
+) -> Result<HashSum, std::io::Error> {
+    file.seek(SeekFrom::Start(offset)).await?;
+    let mut file = file.take(size);
     let mut output_hasher = Blake2b512::new();
     let mut buffer: Vec<u8> = vec![0; 4 * 1024 * 1024];
+    let mut hashed = 0;
     loop {
         let rc = file.read(&mut buffer).await?;
         if rc == 0 {
             break;
         }
         output_hasher.update(&buffer[0..rc]);
+        hashed += rc as u64;
+        if let Some(progress) = &mut progress {
+            progress.update(hashed);
+        }
+    }
+    if let Some(progress) = &progress {
+        progress.finish();
     }
     Ok(HashSum::from(&output_hasher.finalize()[..]))
 }
@@ -60,97 +186,349 @@ async fn is_block_dev(_file: &File) -> Result<bool, std::io::Error> {
     Ok(false)
 }
 
-async fn feed_output<S, C>(output: &mut CloneOutput<C>, mut chunk_stream: S) -> Result<u64>
+// Check if path is a named pipe, which can only be written in order.
+#[cfg(unix)]
+fn is_fifo(path: &Path) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    std::fs::metadata(path).is_ok_and(|meta| meta.file_type().is_fifo())
+}
+
+#[cfg(not(unix))]
+fn is_fifo(_path: &Path) -> bool {
+    false
+}
+
+// Chunks still missing in any of the outputs.
+fn chunks_left<C>(outputs: &[CloneOutput<C>]) -> Cow<'_, ChunkIndex> {
+    match outputs {
+        [output] => Cow::Borrowed(output.chunks()),
+        [first, rest @ ..] => {
+            Cow::Owned(rest.iter().fold(first.chunks().clone(), |chunks, output| {
+                chunks.union(output.chunks())
+            }))
+        }
+        [] => Cow::Owned(ChunkIndex::new_empty(HashSum::MAX_LEN)),
+    }
+}
+
+fn all_complete<C>(outputs: &[CloneOutput<C>]) -> bool {
+    outputs.iter().all(CloneOutput::is_empty)
+}
+
+// Write chunks from stream to every output.
+//
+// Chunks are passed to the writing through a bounded channel, so the stream keeps producing
+// chunks while a slow output is written to. The channel is one of the stages accounted for
+// by `chunk_buffers::CLONE_STAGES`.
+async fn feed_output<S, C>(
+    outputs: &mut [CloneOutput<C>],
+    mut chunk_stream: S,
+    max_buffered_chunks: usize,
+) -> Result<u64>
 where
     S: StreamExt<Item = Result<VerifiedChunk>> + Unpin,
     C: AsyncWrite + AsyncSeek + Unpin + Send,
 {
-    let mut output_bytes = 0;
-    while let Some(result) = chunk_stream.next().await {
-        let verified = result?;
-        let wc = output.feed(&verified).await?;
-        if wc > 0 {
-            debug!("Chunk '{}', size {} used", verified.hash(), verified.len());
+    let (tx, mut rx) = mpsc::channel(max_buffered_chunks.max(1));
+    let produce = async move {
+        while let Some(result) = chunk_stream.next().await {
+            if tx.send(result).await.is_err() {
+                break;
+            }
         }
-        output_bytes += wc as u64;
-    }
+        Ok(())
+    };
+    let write = async move {
+        let mut output_bytes = 0;
+        while let Some(result) = rx.recv().await {
+            let verified = result?;
+            let mut wc = 0;
+            for output in outputs.iter_mut() {
+                wc += output.feed(&verified).await?;
+            }
+            if wc > 0 {
+                debug!("Chunk '{}', size {} used", verified.hash(), verified.len());
+            }
+            output_bytes += wc as u64;
+        }
+        Ok::<_, anyhow::Error>(output_bytes)
+    };
+    let ((), output_bytes) = future::try_join(produce, write).await?;
     Ok(output_bytes)
 }
 
+// Report the bytes of the chunks passing through chunk_stream as progress.
+fn report_chunks<'a, S>(
+    chunk_stream: S,
+    mut progress: Option<&'a mut Progress>,
+) -> impl Stream<Item = Result<VerifiedChunk>> + Unpin + 'a
+where
+    S: Stream<Item = Result<VerifiedChunk>> + Unpin + 'a,
+{
+    let mut processed = 0;
+    chunk_stream.inspect(move |result| {
+        if let (Some(progress), Ok(verified)) = (&mut progress, result) {
+            processed += verified.len() as u64;
+            progress.update(processed);
+        }
+    })
+}
+
+// Run the chunker on a task of its own, feeding chunks through a bounded channel.
+//
+// Keeps the chunker scanning the input while the consumer is busy waiting on (or
+// processing) hashed chunks, so chunking and hashing overlap.
+fn spawn_chunker<I>(
+    config: &chunker::Config,
+    input: I,
+    max_buffered_chunks: usize,
+) -> impl Stream<Item = std::io::Result<(u64, Chunk)>> + Unpin
+where
+    I: AsyncRead + Unpin + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(max_buffered_chunks.max(1));
+    let mut chunker = config.new_chunker(input);
+    tokio::spawn(async move {
+        while let Some(result) = chunker.next().await {
+            if tx.send(result).await.is_err() {
+                // Receiver is gone, no reason to continue chunking.
+                break;
+            }
+        }
+    });
+    Box::pin(stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|result| (result, rx))
+    }))
+}
+
 async fn clone_from_readable<I, C>(
     max_buffered_chunks: usize,
     config: &chunker::Config,
+    hash_algorithm: HashAlgorithm,
     input: I,
-    output: &mut CloneOutput<C>,
+    outputs: &mut [CloneOutput<C>],
+    mut progress: Option<Progress>,
 ) -> Result<u64>
 where
-    I: AsyncRead + Unpin + Send,
+    I: AsyncRead + Unpin + Send + 'static,
     C: AsyncWrite + AsyncSeek + Unpin + Send,
 {
-    let chunk_stream = config
-        .new_chunker(input)
-        .map(|r| spawn_blocking(|| r.map(|(_, chunk)| chunk.verify())))
+    let chunk_stream = spawn_chunker(config, input, max_buffered_chunks)
+        .map(|r| spawn_blocking(move || r.map(|(_, chunk)| chunk.verify_with(hash_algorithm))))
         .buffered(max_buffered_chunks)
         .map(|r| match r {
             Ok(inner) => Ok(inner?),
             Err(err) => Err(anyhow!(err)),
         });
-    feed_output(output, chunk_stream).await
+    let chunk_stream = report_chunks(chunk_stream, progress.as_mut());
+    let output_bytes = feed_output(outputs, chunk_stream, max_buffered_chunks).await?;
+    if let Some(progress) = &progress {
+        progress.finish();
+    }
+    Ok(output_bytes)
 }
 
+// Fetch all chunks still missing in any output from the archive, fetching each chunk once.
+//
+// If failed is given chunks failing to decompress or verify are zero filled and recorded in
+// failed, instead of failing the clone.
 async fn clone_from_archive<R, C>(
     max_buffered_chunks: usize,
     archive: &mut Archive<R>,
-    output: &mut CloneOutput<C>,
+    outputs: &mut [CloneOutput<C>],
+    failed: Option<&mut FailedChunks>,
+    progress: Option<Progress>,
 ) -> Result<u64>
 where
     R: ArchiveReader,
     R::Error: std::error::Error + Sync + Send + 'static,
     C: AsyncWrite + AsyncSeek + Unpin + Send,
 {
-    let mut total_fetched = 0u64;
-    let chunk_stream = archive
-        .chunk_stream(output.chunks())
-        .map(|r| {
-            if let Ok(compressed) = &r {
-                total_fetched += compressed.len() as u64;
+    let progress = progress.map(|progress| Arc::new(Mutex::new(progress)));
+    let zero_filled = Arc::new(Mutex::new(FailedChunks::default()));
+    let opts = clone::Options {
+        max_buffered_chunks,
+        on_fetch: progress.clone().map(|progress| {
+            Arc::new(move |fetched| progress.lock().unwrap().update(fetched))
+                as clone::FetchCallback
+        }),
+        on_invalid_chunk: failed.is_some().then(|| {
+            let zero_filled = zero_filled.clone();
+            Arc::new(move |err: &ArchiveChunkError, bytes: u64| {
+                error!(
+                    "Zero filling {} in place of invalid chunk: {}",
+                    human_size!(bytes),
+                    err
+                );
+                let mut zero_filled = zero_filled.lock().unwrap();
+                zero_filled.count += 1;
+                zero_filled.bytes += bytes;
+            }) as clone::InvalidChunkCallback
+        }),
+        ..Default::default()
+    };
+    let total_fetched = clone::from_archive_all(&opts, archive, outputs).await?;
+    if let Some(failed) = failed {
+        let zero_filled = zero_filled.lock().unwrap();
+        failed.count += zero_filled.count;
+        failed.bytes += zero_filled.bytes;
+    }
+    if let Some(progress) = &progress {
+        progress.lock().unwrap().finish();
+    }
+    info!("Fetched {} from archive.", human_size!(total_fetched));
+    Ok(total_fetched)
+}
+
+// Base content of a delta archive, given either as the base archive or its source.
+enum BaseSeed {
+    Archive(Box<Archive<IoReader<File>>>),
+    File(File),
+}
+
+// Open the base seed of a delta archive.
+//
+// A base archive is verified against the base header checksum stored in the delta archive.
+// Anything else is used as the base source.
+async fn open_base_seed<R>(opts: &Options, archive: &Archive<R>) -> Result<Option<BaseSeed>> {
+    let Some(base_checksum) = archive.base_header_checksum() else {
+        if let Some(path) = &opts.base_seed {
+            warn!(
+                "Archive is not a delta archive, ignoring base seed {}",
+                path.display()
+            );
+        }
+        return Ok(None);
+    };
+    let Some(path) = &opts.base_seed else {
+        return Err(anyhow!(
+            "Archive is a delta against base archive {}, a base seed is required",
+            base_checksum
+        ));
+    };
+    let open = || async {
+        File::open(path)
+            .await
+            .context(format!("Failed to open base seed {}", path.display()))
+    };
+    match Archive::try_init(IoReader::new(open().await?)).await {
+        Ok(base) => {
+            if base.header_checksum() != base_checksum {
+                return Err(anyhow!(
+                    "Base archive header checksum mismatch ({}: {}, expected: {})",
+                    path.display(),
+                    base.header_checksum(),
+                    base_checksum
+                ));
             }
-            spawn_blocking(move || -> Result<VerifiedChunk> {
-                let compressed = r.context("read archive")?;
-                let verified = compressed
-                    .decompress()
-                    .context("decompress chunk")?
-                    .verify()
-                    .context("verify chunk")?;
-                Ok(verified)
-            })
-        })
-        .buffered(max_buffered_chunks)
-        .map(|r| match r {
-            Ok(inner) => inner,
-            Err(err) => Err(anyhow!(err)),
-        });
-    let total_written = feed_output(output, chunk_stream).await?;
+            info!("Using {} as base archive", path.display());
+            Ok(Some(BaseSeed::Archive(Box::new(base))))
+        }
+        Err(_) if !archive.can_scan_seeds() => Err(anyhow!(
+            "Base seed {} is not an archive, can't scan it for chunks of an archive chunked at explicit boundaries",
+            path.display()
+        )),
+        Err(_) => {
+            info!("Using {} as base source", path.display());
+            Ok(Some(BaseSeed::File(open().await?)))
+        }
+    }
+}
+
+// Write chunks of the base seed to output.
+//
+// Returns the number of bytes written to output.
+async fn clone_from_base<R, C>(
+    opts: &Options,
+    archive: &Archive<R>,
+    base: Option<BaseSeed>,
+    outputs: &mut [CloneOutput<C>],
+) -> Result<u64>
+where
+    C: AsyncWrite + AsyncSeek + Unpin + Send,
+{
+    let (Some(base), Some(path)) = (base, &opts.base_seed) else {
+        return Ok(0);
+    };
+    if all_complete(outputs) {
+        info!("All chunks found, not scanning base {}", path.display());
+        return Ok(0);
+    }
     info!(
-        "Fetched {} from archive and decompressed to {}.",
-        human_size!(total_fetched),
-        human_size!(total_written)
+        "Scanning base {} for chunks ({} left to find)...",
+        path.display(),
+        chunks_left(outputs).len()
     );
-    Ok(total_fetched)
+    let bytes_to_output = match base {
+        BaseSeed::Archive(mut base) => {
+            let fetch_size = base.fetch_size(&chunks_left(outputs));
+            clone_from_archive(
+                opts.num_chunk_buffers,
+                &mut base,
+                outputs,
+                None,
+                opts.progress("seed-scan", Some(fetch_size)),
+            )
+            .await
+        }
+        BaseSeed::File(file) => {
+            let size = file.metadata().await.ok().map(|metadata| metadata.len());
+            clone_from_readable(
+                opts.num_chunk_buffers,
+                archive.chunker_config(),
+                archive.chunk_hash_algorithm(),
+                file,
+                outputs,
+                opts.progress("seed-scan", size),
+            )
+            .await
+        }
+    }
+    .context(format!("Failed to clone from base {}", path.display()))?;
+    info!(
+        "Used {} from base {}",
+        human_size!(bytes_to_output),
+        path.display()
+    );
+    Ok(bytes_to_output)
+}
+
+// Fail if chunks a delta archive leaves to the base were not found in the base seed, before
+// fetching the rest from the archive.
+fn ensure_base_complete<R, C>(
+    opts: &Options,
+    archive: &Archive<R>,
+    outputs: &[CloneOutput<C>],
+) -> Result<()> {
+    if let (Some(_), Some(path)) = (archive.base_header_checksum(), &opts.base_seed) {
+        let base_chunks = archive.base_chunk_count(&chunks_left(outputs));
+        if base_chunks > 0 {
+            return Err(anyhow!(
+                "{} chunks stored in base were not found in {}",
+                base_chunks,
+                path.display()
+            ));
+        }
+    }
+    Ok(())
 }
 
 async fn chunk_index_from_readable<R>(
+    hash_algorithm: HashAlgorithm,
     hash_length: usize,
     config: &chunker::Config,
     max_buffered_chunks: usize,
-    readable: &mut R,
+    readable: R,
 ) -> Result<ChunkIndex>
 where
-    R: AsyncRead + Unpin + Send,
+    R: AsyncRead + Unpin + Send + 'static,
 {
-    let mut chunk_stream = config
-        .new_chunker(readable)
-        .map(|r| spawn_blocking(|| r.map(|(offset, chunk)| (offset, chunk.verify()))))
+    let mut chunk_stream = spawn_chunker(config, readable, max_buffered_chunks)
+        .map(|r| {
+            spawn_blocking(move || {
+                r.map(|(offset, chunk)| (offset, chunk.verify_with(hash_algorithm)))
+            })
+        })
         .buffered(max_buffered_chunks);
     let mut index = ChunkIndex::new_empty(hash_length);
     while let Some(r) = chunk_stream.next().await {
@@ -161,228 +539,1289 @@ where
     Ok(index)
 }
 
-async fn clone_archive<R>(opts: Options, reader: R) -> Result<()>
+// Remove chunks found in readable from the index without writing them anywhere.
+//
+// Returns the number of bytes which would have been written to the output.
+async fn dry_run_from_readable<I>(
+    max_buffered_chunks: usize,
+    config: &chunker::Config,
+    hash_algorithm: HashAlgorithm,
+    input: I,
+    clone_index: &mut ChunkIndex,
+) -> Result<u64>
 where
-    R: ArchiveReader,
-    R::Error: std::error::Error + Send + Sync + 'static,
+    I: AsyncRead + Unpin + Send + 'static,
 {
-    let mut archive = Archive::try_init(reader).await.context(format!(
-        "Failed to read archive at {}",
-        opts.input_archive.source()
-    ))?;
-    let clone_index = archive.build_source_index();
-    let mut total_read_from_seed = 0u64;
-
-    info_cmd::print_archive(&archive);
-    println!();
-
-    // Verify the header checksum if requested
-    if let Some(ref expected_checksum) = opts.header_checksum {
-        if *expected_checksum != *archive.header_checksum() {
-            return Err(anyhow!("Header checksum mismatch"));
-        } else {
-            info!("Header checksum verified OK");
+    let mut chunk_stream = spawn_chunker(config, input, max_buffered_chunks)
+        .map(|r| spawn_blocking(move || r.map(|(_, chunk)| chunk.verify_with(hash_algorithm))))
+        .buffered(max_buffered_chunks);
+    let mut output_bytes = 0;
+    while let Some(r) = chunk_stream.next().await {
+        let verified = r??;
+        if let Some(location) = clone_index.remove(verified.hash()) {
+            output_bytes += (location.size() * location.offsets().len()) as u64;
         }
     }
-    info!(
-        "Cloning archive {} to {}...",
-        opts.input_archive.source(),
-        opts.output.display()
-    );
-
-    // Create or open output file
-    let mut output_file = tokio::fs::OpenOptions::new()
-        .write(true)
-        .read(opts.verify_output || opts.seed_output)
-        .create(opts.force_create || opts.seed_output)
-        .create_new(!opts.force_create && !opts.seed_output)
-        .open(&opts.output)
-        .await
-        .context(format!("Failed to open {}", opts.output.display()))?;
+    Ok(output_bytes)
+}
 
-    // Check if the given output file is a regular file or block device.
-    // If it is a block device we should check its size against the target size before
-    // writing. If a regular file then resize that file to target size.
-    let output_is_block_dev = is_block_dev(&output_file).await?;
-    if output_is_block_dev {
-        let size = file_size(&mut output_file).await?;
-        if size < archive.total_source_size() {
-            return Err(anyhow!(
-                "Size of output device ({}) is less than archive target file ({})",
-                human_size!(size),
-                human_size!(archive.total_source_size())
-            ));
+// Describe the operations re-ordering chunks of output in place, one line per operation in
+// the order executed, followed by a summary line.
+fn explain_reorder_ops(ops: &[ReorderOp]) -> Vec<String> {
+    let mut lines = Vec::with_capacity(ops.len() + 1);
+    let mut in_mem = HashSet::new();
+    let mut num_copies = 0;
+    let mut copied_size = 0u64;
+    let mut num_stored = 0;
+    let mut stored_size = 0u64;
+    for (n, op) in ops.iter().enumerate() {
+        match op {
+            ReorderOp::Copy {
+                hash,
+                size,
+                source,
+                dest,
+            } => {
+                let plural = if dest.len() > 1 { "s" } else { "" };
+                let dest = dest
+                    .iter()
+                    .map(u64::to_string)
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                let from = if in_mem.remove(hash) {
+                    "memory".to_string()
+                } else {
+                    format!("offset {}", source)
+                };
+                lines.push(format!(
+                    "{}: copy {} from {} to offset{} {}",
+                    n + 1,
+                    human_size!(*size),
+                    from,
+                    plural,
+                    dest
+                ));
+                num_copies += 1;
+                copied_size += *size as u64;
+            }
+            ReorderOp::StoreInMem { hash, size, source } => {
+                lines.push(format!(
+                    "{}: store {} from offset {} in memory",
+                    n + 1,
+                    human_size!(*size),
+                    source
+                ));
+                in_mem.insert(*hash);
+                num_stored += 1;
+                stored_size += *size as u64;
+            }
         }
     }
+    lines.push(format!(
+        "{} re-order operations: {} copies ({}), {} stored in memory ({})",
+        ops.len(),
+        num_copies,
+        human_size!(copied_size),
+        num_stored,
+        human_size!(stored_size)
+    ));
+    lines
+}
 
-    // Build an index of the output file's chunks
-    let output_index = if opts.seed_output {
-        info!("Building chunk index of {}...", opts.output.display());
-        Some(
-            chunk_index_from_readable(
-                archive.chunk_hash_length(),
-                archive.chunker_config(),
-                opts.num_chunk_buffers,
-                &mut output_file,
-            )
-            .await?,
-        )
-    } else {
-        None
-    };
+fn print_reorder_ops(ops: &[ReorderOp]) {
+    info!("Re-order operations:");
+    for line in explain_reorder_ops(ops) {
+        info!("  {}", line);
+    }
+}
 
-    let mut output = CloneOutput::new(output_file, clone_index);
-    if let Some(output_index) = output_index {
-        info!("Re-ordering chunks of {}...", opts.output.display());
-        let used_from_self = output
-            .reorder_in_place(output_index)
-            .await
-            .context("Failed to clone in place")?;
-        info!(
-            "Used {} from {}",
-            human_size!(used_from_self),
-            opts.output.display()
-        );
-        total_read_from_seed += used_from_self;
+// Report what a clone would use from seeds and archive, without touching the output.
+async fn dry_run_archive<R>(
+    opts: &Options,
+    archive: &Archive<R>,
+    base: Option<BaseSeed>,
+) -> Result<()> {
+    let mut clone_index = archive.build_source_index();
+    let mut total_read_from_seed = 0u64;
+    let mut num_reorder_ops = 0;
+
+    if opts.seed_output {
+        match File::open(&opts.output).await {
+            Ok(output_file) => {
+                info!("Building chunk index of {}...", opts.output.display());
+                let output_index = chunk_index_from_readable(
+                    archive.chunk_hash_algorithm(),
+                    archive.chunk_hash_length(),
+                    archive.chunker_config(),
+                    opts.num_chunk_buffers,
+                    output_file,
+                )
+                .await?;
+                let (_, in_place_size) =
+                    output_index.strip_chunks_already_in_place(&mut clone_index);
+                let reorder_ops = output_index.reorder_ops(&clone_index);
+                if opts.explain_reorder {
+                    print_reorder_ops(&reorder_ops);
+                }
+                let mut used_from_self = in_place_size;
+                for op in &reorder_ops {
+                    if let ReorderOp::Copy { hash, size, .. } = op {
+                        if clone_index.remove(hash).is_some() {
+                            used_from_self += *size as u64;
+                        }
+                    }
+                }
+                num_reorder_ops = reorder_ops.len();
+                info!(
+                    "Would use {} from {} ({} re-order operations)",
+                    human_size!(used_from_self),
+                    opts.output.display(),
+                    num_reorder_ops
+                );
+                total_read_from_seed += used_from_self;
+            }
+            Err(err) => info!(
+                "Unable to read {} ({}), not using it as seed",
+                opts.output.display(),
+                err
+            ),
+        }
     }
 
-    // Read chunks from seed files
-    if opts.seed_stdin && !std::io::stdin().is_terminal() {
+    let seed_paths = seed_paths(opts)?;
+    if opts.uses_seeds() && clone_index.is_empty() {
+        info!("All chunks found, skipping remaining seeds");
+    } else if opts.seed_stdin && !std::io::stdin().is_terminal() {
         info!(
             "Scanning stdin for chunks ({} left to find)...",
-            output.len()
+            clone_index.len()
         );
-        let bytes_to_output = clone_from_readable(
+        let bytes_to_output = dry_run_from_readable(
             opts.num_chunk_buffers,
             archive.chunker_config(),
-            &mut tokio::io::stdin(),
-            &mut output,
+            archive.chunk_hash_algorithm(),
+            tokio::io::stdin(),
+            &mut clone_index,
         )
         .await
-        .context("Failed to clone from stdin")?;
-        info!("Used {} bytes from stdin", human_size!(bytes_to_output));
+        .context("Failed to scan stdin")?;
+        info!("Would use {} from stdin", human_size!(bytes_to_output));
         total_read_from_seed += bytes_to_output;
     }
-    for seed_path in &opts.seed_files {
+    for seed_path in &seed_paths {
+        if clone_index.is_empty() {
+            break;
+        }
         let file = File::open(seed_path)
             .await
             .context(format!("Failed to open seed file {}", seed_path.display()))?;
         info!(
             "Scanning {} for chunks ({} left to find)...",
             seed_path.display(),
-            output.len()
+            clone_index.len()
         );
-        let bytes_to_output = clone_from_readable(
+        let bytes_to_output = dry_run_from_readable(
             opts.num_chunk_buffers,
             archive.chunker_config(),
+            archive.chunk_hash_algorithm(),
             file,
-            &mut output,
+            &mut clone_index,
         )
         .await
-        .context(format!("Failed to clone from {}", seed_path.display()))?;
+        .context(format!("Failed to scan {}", seed_path.display()))?;
         info!(
-            "Used {} bytes from {}",
+            "Would use {} from {}",
             human_size!(bytes_to_output),
             seed_path.display()
         );
         total_read_from_seed += bytes_to_output;
     }
-
-    // Read the rest from archive
-    info!(
-        "Fetching {} chunks from {}...",
-        output.len(),
-        opts.input_archive.source()
-    );
-
-    let total_read_from_remote =
-        clone_from_archive(opts.num_chunk_buffers, &mut archive, &mut output)
-            .await
-            .context(format!(
-                "Failed to clone from archive at {}",
-                opts.input_archive.source()
-            ))?;
-
-    let mut output_file = output.into_inner();
-    if !output_is_block_dev {
-        // Resize output file to same size as the archive source
-        output_file
-            .set_len(archive.total_source_size())
-            .await
-            .context(format!("Failed to resize {}", opts.output.display()))?;
-    }
-
-    if opts.verify_output {
-        info!("Verifying checksum of {}...", opts.output.display());
-        let sum = file_checksum(&mut output_file).await.context(format!(
-            "Failed to create checksum of {}",
-            opts.output.display()
-        ))?;
-        let expected_checksum = archive.source_checksum();
-        if sum == *expected_checksum {
-            info!("Checksum verified Ok");
+    if let (Some(base), Some(path)) = (base, &opts.base_seed) {
+        if clone_index.is_empty() {
+            info!("All chunks found, not scanning base {}", path.display());
         } else {
-            return Err(anyhow!(
-                "Checksum mismatch ({}: {}, {}: {})",
-                opts.output.display(),
-                sum,
-                opts.input_archive.source(),
-                expected_checksum
-            ));
+            info!(
+                "Scanning base {} for chunks ({} left to find)...",
+                path.display(),
+                clone_index.len()
+            );
+            let bytes_to_output = match base {
+                BaseSeed::Archive(base) => base
+                    .chunk_descriptors()
+                    .iter()
+                    .filter(|descriptor| !descriptor.in_base)
+                    .filter_map(|descriptor| clone_index.remove(&descriptor.checksum))
+                    .map(|location| (location.size() * location.offsets().len()) as u64)
+                    .sum(),
+                BaseSeed::File(file) => dry_run_from_readable(
+                    opts.num_chunk_buffers,
+                    archive.chunker_config(),
+                    archive.chunk_hash_algorithm(),
+                    file,
+                    &mut clone_index,
+                )
+                .await
+                .context(format!("Failed to scan base {}", path.display()))?,
+            };
+            info!(
+                "Would use {} from base {}",
+                human_size!(bytes_to_output),
+                path.display()
+            );
+            total_read_from_seed += bytes_to_output;
         }
     }
+    let missing_from_base = archive
+        .chunk_descriptors()
+        .iter()
+        .filter(|descriptor| descriptor.in_base && clone_index.contains(&descriptor.checksum))
+        .count();
+    if missing_from_base > 0 {
+        warn!(
+            "{} chunks stored in base would not be found",
+            missing_from_base
+        );
+    }
 
+    let total_read_from_remote: u64 = archive
+        .chunk_descriptors()
+        .iter()
+        .filter(|descriptor| clone_index.contains(&descriptor.checksum))
+        .map(|descriptor| descriptor.archive_size as u64)
+        .sum();
     info!(
-        "Successfully cloned archive using {} from archive and {} from seeds.",
-        human_size!(total_read_from_remote),
-        human_size!(total_read_from_seed)
+        "Dry run: would use {} from seeds ({} re-order operations) and fetch {} chunks ({}) from archive.",
+        human_size!(total_read_from_seed),
+        num_reorder_ops,
+        clone_index.len(),
+        human_size!(total_read_from_remote)
     );
-
     Ok(())
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct RemoteInput {
-    pub url: Url,
-    pub retries: u32,
-    pub retry_delay: Duration,
-    pub receive_timeout: Option<Duration>,
-    pub headers: HeaderMap,
+// Identifies a seed file state and the chunker used for a cached seed index.
+//
+// A cached index is only used if the seed has the same size and modification time, and the
+// archive the same chunker configuration and hash, as when the index was cached.
+fn seed_cache_key(
+    metadata: &std::fs::Metadata,
+    config: &chunker::Config,
+    hash_algorithm: HashAlgorithm,
+    hash_length: usize,
+) -> Result<Vec<u8>> {
+    let mtime = metadata
+        .modified()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut b2 = Blake2b512::new();
+    b2.update(metadata.len().to_le_bytes());
+    b2.update(mtime.as_nanos().to_le_bytes());
+    b2.update((hash_length as u64).to_le_bytes());
+    b2.update(hash_algorithm.to_string());
+    b2.update(format!("{:?}", config));
+    Ok(b2.finalize().to_vec())
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum InputArchive {
-    Local(std::path::PathBuf),
-    Remote(Box<RemoteInput>),
+// Path of the cached index of a seed, named by a hash of the seed path.
+fn seed_cache_path(cache_dir: &Path, seed_path: &Path) -> PathBuf {
+    let seed_path = std::fs::canonicalize(seed_path).unwrap_or_else(|_| seed_path.to_path_buf());
+    let mut b2 = Blake2b512::new();
+    b2.update(seed_path.to_string_lossy().as_bytes());
+    let name = HashSum::from(&b2.finalize()[..16]);
+    cache_dir.join(format!("{}.bcix", name))
 }
-impl InputArchive {
-    fn source(&self) -> String {
-        match self {
-            Self::Local(p) => format!("{}", p.display()),
-            Self::Remote(input) => input.url.to_string(),
-        }
+
+fn read_cached_seed_index(path: &Path, key: &[u8], hash_length: usize) -> Result<ChunkIndex> {
+    let cached = std::fs::read(path)?;
+    if !cached.starts_with(key) {
+        return Err(anyhow!("seed changed since cached"));
     }
+    Ok(ChunkIndex::read_from(
+        &mut &cached[key.len()..],
+        hash_length,
+    )?)
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Options {
-    pub force_create: bool,
-    pub input_archive: InputArchive,
-    pub header_checksum: Option<HashSum>,
-    pub output: PathBuf,
-    pub seed_stdin: bool,
-    pub seed_files: Vec<PathBuf>,
-    pub seed_output: bool,
-    pub verify_output: bool,
-    pub num_chunk_buffers: usize,
+fn write_cached_seed_index(path: &Path, key: &[u8], index: &ChunkIndex) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // Write to a temporary file first to never leave a partially written index behind.
+    let tmp_path = path.with_extension("bcix.tmp");
+    let mut writer = std::io::BufWriter::new(std::fs::File::create(&tmp_path)?);
+    std::io::Write::write_all(&mut writer, key)?;
+    index.write_to(&mut writer)?;
+    std::io::Write::flush(&mut writer)?;
+    drop(writer);
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
 }
 
-pub async fn clone_cmd(opts: Options) -> Result<()> {
-    match opts.input_archive.clone() {
-        InputArchive::Local(path) => {
-            clone_archive(
-                opts,
-                IoReader::new(
+// Get the chunk index of a seed file, from the cache if still valid or else by chunking
+// the seed and caching the result.
+async fn seed_index<R>(
+    opts: &Options,
+    archive: &Archive<R>,
+    seed_path: &Path,
+    cache_dir: &Path,
+) -> Result<ChunkIndex> {
+    let hash_length = archive.chunk_hash_length();
+    let metadata = tokio::fs::metadata(seed_path)
+        .await
+        .context(format!("Failed to stat seed file {}", seed_path.display()))?;
+    let key = seed_cache_key(
+        &metadata,
+        archive.chunker_config(),
+        archive.chunk_hash_algorithm(),
+        hash_length,
+    )?;
+    let cache_path = seed_cache_path(cache_dir, seed_path);
+    match read_cached_seed_index(&cache_path, &key, hash_length) {
+        Ok(index) => {
+            info!(
+                "Using cached index of {} ({} chunks)",
+                seed_path.display(),
+                index.len()
+            );
+            return Ok(index);
+        }
+        Err(err) => debug!(
+            "No usable cached index of {} at {} ({})",
+            seed_path.display(),
+            cache_path.display(),
+            err
+        ),
+    }
+    info!("Scanning {} for chunks...", seed_path.display());
+    let file = File::open(seed_path)
+        .await
+        .context(format!("Failed to open seed file {}", seed_path.display()))?;
+    let index = chunk_index_from_readable(
+        archive.chunk_hash_algorithm(),
+        hash_length,
+        archive.chunker_config(),
+        opts.num_chunk_buffers,
+        file,
+    )
+    .await
+    .context(format!("Failed to scan {}", seed_path.display()))?;
+    if let Err(err) = write_cached_seed_index(&cache_path, &key, &index) {
+        warn!(
+            "Failed to cache index of {} at {}: {:#}",
+            seed_path.display(),
+            cache_path.display(),
+            err
+        );
+    }
+    Ok(index)
+}
+
+// Read the chunks still missing from outputs at their location in seed, given the chunk
+// index of seed.
+//
+// Every chunk is verified before written, so a seed modified without its index being
+// updated only results in the modified chunks not being used.
+async fn clone_from_seed_index<I, C>(
+    max_buffered_chunks: usize,
+    hash_algorithm: HashAlgorithm,
+    seed_index: &ChunkIndex,
+    seed: I,
+    outputs: &mut [CloneOutput<C>],
+    mut progress: Option<Progress>,
+) -> Result<u64>
+where
+    I: AsyncRead + AsyncSeek + Unpin + Send,
+    C: AsyncWrite + AsyncSeek + Unpin + Send,
+{
+    let mut chunks: Vec<(u64, usize)> = chunks_left(outputs)
+        .iter_chunks()
+        .filter_map(|(hash, location)| {
+            let offset = seed_index.offsets(hash)?.next()?;
+            Some((offset, location.size()))
+        })
+        .collect();
+    chunks.sort_unstable();
+    if let Some(progress) = &mut progress {
+        progress.set_total(chunks.iter().map(|&(_, size)| size as u64).sum());
+    }
+    let chunk_stream = stream::unfold(
+        (seed, chunks.into_iter()),
+        |(mut seed, mut chunks)| async move {
+            let (offset, size) = chunks.next()?;
+            let mut buf = vec![0; size];
+            let result = async {
+                seed.seek(SeekFrom::Start(offset)).await?;
+                seed.read_exact(&mut buf).await?;
+                Ok::<_, std::io::Error>(Chunk::from(buf))
+            }
+            .await;
+            Some((result, (seed, chunks)))
+        },
+    )
+    .map(|r| spawn_blocking(move || r.map(|chunk| chunk.verify_with(hash_algorithm))))
+    .buffered(max_buffered_chunks)
+    .map(|r| match r {
+        Ok(inner) => Ok(inner?),
+        Err(err) => Err(anyhow!(err)),
+    });
+    let chunk_stream = report_chunks(Box::pin(chunk_stream), progress.as_mut());
+    let output_bytes = feed_output(outputs, chunk_stream, max_buffered_chunks).await?;
+    if let Some(progress) = &progress {
+        progress.finish();
+    }
+    Ok(output_bytes)
+}
+
+// Add the files in directory to files, sorted by path. Sub directories are descended into
+// if recursive, but not when reached through a symlink.
+fn seed_dir_files(directory: &Path, recursive: bool, files: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries = std::fs::read_dir(directory)
+        .and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<std::io::Result<Vec<PathBuf>>>()
+        })
+        .context(format!(
+            "Failed to read seed directory {}",
+            directory.display()
+        ))?;
+    entries.sort();
+    for path in entries {
+        let file_type = std::fs::symlink_metadata(&path)
+            .context(format!("Failed to stat {}", path.display()))?
+            .file_type();
+        if file_type.is_dir() {
+            if recursive {
+                seed_dir_files(&path, recursive, files)?;
+            }
+        } else if std::fs::metadata(&path).is_ok_and(|metadata| metadata.is_file()) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+// Get the seed files to scan, in order, with seed directories expanded to the regular files
+// within. A file given more than once, eg both by itself and through its directory, is only
+// scanned the first time. The output is never used from a seed directory.
+fn seed_paths(opts: &Options) -> Result<Vec<PathBuf>> {
+    let output = std::fs::canonicalize(&opts.output).ok();
+    let mut seen = std::collections::HashSet::new();
+    let mut seed_paths = Vec::new();
+    for seed_path in &opts.seed_files {
+        if !std::fs::metadata(seed_path).is_ok_and(|metadata| metadata.is_dir()) {
+            // Not a directory, let opening the seed report if it is missing.
+            let canonical = std::fs::canonicalize(seed_path).unwrap_or_else(|_| seed_path.clone());
+            if seen.insert(canonical) {
+                seed_paths.push(seed_path.clone());
+            }
+            continue;
+        }
+        let mut files = Vec::new();
+        seed_dir_files(seed_path, opts.seed_recursive, &mut files)?;
+        debug!(
+            "Found {} files in seed directory {}",
+            files.len(),
+            seed_path.display()
+        );
+        for file in files {
+            let canonical = std::fs::canonicalize(&file).unwrap_or_else(|_| file.clone());
+            if output.as_ref() != Some(&canonical) && seen.insert(canonical) {
+                seed_paths.push(file);
+            }
+        }
+    }
+    Ok(seed_paths)
+}
+
+// Read chunks from stdin and seed files into outputs.
+//
+// Returns the number of bytes written to outputs.
+async fn clone_from_seeds<R, C>(
+    opts: &Options,
+    archive: &Archive<R>,
+    outputs: &mut [CloneOutput<C>],
+) -> Result<u64>
+where
+    C: AsyncWrite + AsyncSeek + Unpin + Send,
+{
+    let mut total_read_from_seed = 0u64;
+    let seed_paths = seed_paths(opts)?;
+    if opts.uses_seeds() && all_complete(outputs) {
+        info!("All chunks found, skipping remaining seeds");
+        return Ok(0);
+    }
+    if opts.seed_stdin && !std::io::stdin().is_terminal() {
+        info!(
+            "Scanning stdin for chunks ({} left to find)...",
+            chunks_left(outputs).len()
+        );
+        let bytes_to_output = clone_from_readable(
+            opts.num_chunk_buffers,
+            archive.chunker_config(),
+            archive.chunk_hash_algorithm(),
+            tokio::io::stdin(),
+            outputs,
+            opts.progress("seed-scan", None),
+        )
+        .await
+        .context("Failed to clone from stdin")?;
+        info!("Used {} bytes from stdin", human_size!(bytes_to_output));
+        total_read_from_seed += bytes_to_output;
+    }
+    for (index, seed_path) in seed_paths.iter().enumerate() {
+        if all_complete(outputs) {
+            info!(
+                "All chunks found, skipping {} remaining seeds",
+                seed_paths.len() - index
+            );
+            break;
+        }
+        let file = File::open(seed_path)
+            .await
+            .context(format!("Failed to open seed file {}", seed_path.display()))?;
+        let bytes_to_output = if let Some(cache_dir) = &opts.seed_index_cache {
+            let seed_index = seed_index(opts, archive, seed_path, cache_dir).await?;
+            info!(
+                "Reading chunks from {} ({} left to find)...",
+                seed_path.display(),
+                chunks_left(outputs).len()
+            );
+            clone_from_seed_index(
+                opts.num_chunk_buffers,
+                archive.chunk_hash_algorithm(),
+                &seed_index,
+                file,
+                outputs,
+                opts.progress("seed-scan", None),
+            )
+            .await
+        } else {
+            info!(
+                "Scanning {} for chunks ({} left to find)...",
+                seed_path.display(),
+                chunks_left(outputs).len()
+            );
+            let size = file.metadata().await.ok().map(|metadata| metadata.len());
+            clone_from_readable(
+                opts.num_chunk_buffers,
+                archive.chunker_config(),
+                archive.chunk_hash_algorithm(),
+                file,
+                outputs,
+                opts.progress("seed-scan", size),
+            )
+            .await
+        }
+        .context(format!("Failed to clone from {}", seed_path.display()))?;
+        info!(
+            "Used {} bytes from {}",
+            human_size!(bytes_to_output),
+            seed_path.display()
+        );
+        total_read_from_seed += bytes_to_output;
+    }
+    Ok(total_read_from_seed)
+}
+
+// Clone archive to a non-seekable output, like stdout or a FIFO, writing the source in order
+// as chunks becomes available.
+async fn clone_to_stream<R, W>(
+    opts: &Options,
+    mut archive: Archive<R>,
+    clone_index: ChunkIndex,
+    base: Option<BaseSeed>,
+    stream: W,
+    name: &str,
+) -> Result<()>
+where
+    R: ArchiveReader,
+    R::Error: std::error::Error + Send + Sync + 'static,
+    W: AsyncWrite + Unpin + Send,
+{
+    let mut output = CloneOutput::builder(
+        OrderedWriter::new(stream, opts.max_stream_buffer),
+        clone_index,
+    )
+    .hash_output(opts.verify_output)
+    .build();
+    let outputs = std::slice::from_mut(&mut output);
+    let mut total_read_from_seed = clone_from_seeds(opts, &archive, outputs).await?;
+    total_read_from_seed += clone_from_base(opts, &archive, base, outputs).await?;
+    ensure_base_complete(opts, &archive, outputs)?;
+    let fetch_size = archive.fetch_size(outputs[0].chunks());
+    info!(
+        "Fetching {} chunks ({}) from {}...",
+        outputs[0].len(),
+        human_size!(fetch_size),
+        opts.input_archive.source()
+    );
+    let mut failed = FailedChunks::default();
+    let total_read_from_remote = clone_from_archive(
+        opts.num_chunk_buffers,
+        &mut archive,
+        outputs,
+        opts.lenient.then_some(&mut failed),
+        opts.progress("fetch", Some(fetch_size)),
+    )
+    .await
+    .context(format!(
+        "Failed to clone from archive at {}",
+        opts.input_archive.source()
+    ))?;
+    failed.into_result()?;
+    if let Some(sum) = output.output_checksum() {
+        let expected_checksum = archive.source_checksum();
+        if sum == *expected_checksum {
+            info!("Checksum verified Ok");
+        } else {
+            return Err(CloneError::SourceChecksumMismatch {
+                output: name.to_string(),
+                checksum: sum,
+                archive: opts.input_archive.source(),
+                expected: expected_checksum.clone(),
+            }
+            .into());
+        }
+    }
+    output
+        .into_inner()
+        .shutdown()
+        .await
+        .context(format!("Failed to write to {}", name))?;
+    info!(
+        "Successfully cloned archive using {} from archive and {} from seeds.",
+        human_size!(total_read_from_remote),
+        human_size!(total_read_from_seed)
+    );
+    progress::report_done(
+        opts.progress,
+        &[
+            ("bytes_from_archive", total_read_from_remote),
+            ("bytes_from_seeds", total_read_from_seed),
+        ],
+    );
+    Ok(())
+}
+
+async fn clone_archive<R>(mut opts: Options, reader: R) -> Result<()>
+where
+    R: ArchiveReader,
+    R::Error: std::error::Error + Send + Sync + 'static,
+{
+    let mut archive = signature::init_archive(
+        reader,
+        opts.verify_signature.as_deref(),
+        !opts.no_verify_header,
+    )
+    .await
+    .context(format!(
+        "Failed to read archive at {}",
+        opts.input_archive.source()
+    ))?;
+    if let Some(range) = &opts.source_range {
+        if range.end > archive.total_source_size() {
+            return Err(anyhow!(
+                "Source range {}:{} ends beyond the end of source ({})",
+                range.start,
+                range.end,
+                archive.total_source_size()
+            ));
+        }
+    }
+    let clone_index = match &opts.source_range {
+        Some(range) => archive.build_source_range_index(range.start, range.end - range.start),
+        None => archive.build_source_index(),
+    };
+    let mut total_read_from_seed = 0u64;
+
+    info_cmd::print_archive(&archive);
+    if !opts.output_is_stdout() {
+        println!();
+    }
+
+    // Verify the header checksum if requested
+    if let Some(ref expected_checksum) = opts.header_checksum {
+        if *expected_checksum != *archive.header_checksum() {
+            return Err(CloneError::HeaderChecksumMismatch.into());
+        } else {
+            info!("Header checksum verified OK");
+        }
+    } else {
+        let checksum_file = info_cmd::read_checksum_file(&opts.input_archive)?;
+        info_cmd::verify_checksum_file(&archive, checksum_file.as_ref(), opts.no_verify_header)?;
+    }
+    if opts.no_verify_header {
+        warn!("Header not verified against its checksum, the clone may not be the archived source");
+    }
+    info!(
+        "Cloning archive {} to {}...",
+        opts.input_archive.source(),
+        opts.outputs()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    if opts.strict_seeds && opts.uses_seeds() && !archive.chunk_hash_algorithm().is_cryptographic()
+    {
+        return Err(anyhow!(
+            "Archive chunks are hashed using {}, can't strictly verify seed chunks",
+            archive.chunk_hash_algorithm()
+        ));
+    }
+    if opts.strict_seeds
+        && opts.uses_seeds()
+        && archive.chunk_hash_length() < archive.chunk_hash_algorithm().hash_length()
+    {
+        return Err(anyhow!(
+            "Archive chunk hashes are truncated to {} bytes, can't strictly verify seed chunks",
+            archive.chunk_hash_length()
+        ));
+    }
+    if !archive.can_scan_seeds()
+        && (opts.seed_output || opts.seed_stdin || !opts.seed_files.is_empty())
+    {
+        return Err(anyhow!(
+            "Archive is chunked at explicit boundaries which are not stored in the archive, can't scan seeds for its chunks"
+        ));
+    }
+    if archive.chunk_hash_length() < MIN_SAFE_HASH_LENGTH
+        && !opts.verify_output
+        && opts.source_range.is_none()
+    {
+        warn!(
+            "Archive chunk hashes are truncated to {} bytes. Different chunks with the same \
+            truncated hash can't be told apart, which may silently corrupt the output. \
+            Verifying the output checksum. Compress using a hash length of at least {} bytes \
+            to avoid this.",
+            archive.chunk_hash_length(),
+            MIN_SAFE_HASH_LENGTH
+        );
+        opts.verify_output = true;
+    }
+
+    opts.num_chunk_buffers = chunk_buffers::limit_chunk_buffers(
+        opts.num_chunk_buffers,
+        chunk_buffers::CLONE_STAGES,
+        archive.chunker_config().max_chunk_size(),
+        opts.max_chunk_memory,
+    );
+
+    let base = open_base_seed(&opts, &archive).await?;
+    if opts.dry_run {
+        return dry_run_archive(&opts, &archive, base).await;
+    }
+    if !opts.extra_outputs.is_empty() {
+        if let Some(path) = opts
+            .outputs()
+            .find(|path| *path == Path::new("-") || is_fifo(path))
+        {
+            return Err(anyhow!(
+                "Can't clone to {} along with other outputs",
+                path.display()
+            ));
+        }
+    }
+    if opts.output_is_stdout() {
+        if opts.restore_metadata {
+            return Err(anyhow!(
+                "Can't restore file metadata when cloning to stdout"
+            ));
+        }
+        return clone_to_stream(
+            &opts,
+            archive,
+            clone_index,
+            base,
+            tokio::io::stdout(),
+            "stdout",
+        )
+        .await;
+    }
+    if is_fifo(&opts.output) {
+        if opts.seed_output
+            || opts.output_offset != 0
+            || opts.restore_metadata
+            || opts.source_range.is_some()
+            || opts.skip_unchanged_writes
+        {
+            return Err(anyhow!(
+                "Can't seed from, clone at an offset of, clone a source range to, skip unchanged writes to or restore file metadata of FIFO {}",
+                opts.output.display()
+            ));
+        }
+        // Opening blocks until there is a reader of the FIFO.
+        let fifo = tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(&opts.output)
+            .await
+            .context(format!("Failed to open {}", opts.output.display()))?;
+        let name = opts.output.display().to_string();
+        return clone_to_stream(&opts, archive, clone_index, base, fifo, &name).await;
+    }
+
+    let output_end = opts
+        .output_offset
+        .checked_add(opts.output_size(&archive))
+        .ok_or_else(|| anyhow!("Output offset {} is too large", opts.output_offset))?;
+
+    let mut outputs = Vec::new();
+    let mut output_is_block_dev = Vec::new();
+    for path in opts.outputs() {
+        let (output, is_block_dev, used_from_self) =
+            open_output(&opts, &archive, path, clone_index.clone()).await?;
+        outputs.push(output);
+        output_is_block_dev.push(is_block_dev);
+        total_read_from_seed += used_from_self;
+    }
+
+    // Read chunks from seed files
+    total_read_from_seed += clone_from_seeds(&opts, &archive, &mut outputs).await?;
+    total_read_from_seed += clone_from_base(&opts, &archive, base, &mut outputs).await?;
+    ensure_base_complete(&opts, &archive, &outputs)?;
+
+    // Read the rest from archive
+    let (num_chunks, fetch_size) = {
+        let chunks = chunks_left(&outputs);
+        (chunks.len(), archive.fetch_size(&chunks))
+    };
+    info!(
+        "Fetching {} chunks ({}) from {}...",
+        num_chunks,
+        human_size!(fetch_size),
+        opts.input_archive.source()
+    );
+
+    let mut failed = FailedChunks::default();
+    let total_read_from_remote = clone_from_archive(
+        opts.num_chunk_buffers,
+        &mut archive,
+        &mut outputs,
+        opts.lenient.then_some(&mut failed),
+        opts.progress("fetch", Some(fetch_size)),
+    )
+    .await
+    .context(format!(
+        "Failed to clone from archive at {}",
+        opts.input_archive.source()
+    ))?;
+
+    let mut output_files = Vec::new();
+    for ((mut output, path), is_block_dev) in outputs
+        .into_iter()
+        .zip(opts.outputs())
+        .zip(&output_is_block_dev)
+    {
+        output
+            .flush_buffered()
+            .await
+            .context(format!("Failed to write to {}", path.display()))?;
+        info!(
+            "Wrote {} to {}",
+            human_size!(output.bytes_written()),
+            path.display()
+        );
+        if opts.skip_unchanged_writes {
+            info!(
+                "Skipped writing {} already in {}",
+                human_size!(output.bytes_unchanged()),
+                path.display()
+            );
+        }
+        let output_file = output.into_inner();
+        if !is_block_dev && !opts.no_resize {
+            // Resize output file to end with the archive source
+            output_file
+                .set_len(output_end)
+                .await
+                .context(format!("Failed to resize {}", path.display()))?;
+        }
+        output_files.push(output_file);
+    }
+    // The output can't match the source checksum with chunks zero filled.
+    failed.into_result()?;
+
+    for ((mut output_file, path), is_block_dev) in output_files
+        .into_iter()
+        .zip(opts.outputs())
+        .zip(output_is_block_dev)
+    {
+        if opts.verify_output {
+            verify_output(&opts, &archive, path, &mut output_file).await?;
+        }
+        if opts.restore_metadata {
+            restore_metadata(&opts, &archive, path, is_block_dev)?;
+        }
+    }
+
+    info!(
+        "Successfully cloned archive using {} from archive and {} from seeds.",
+        human_size!(total_read_from_remote),
+        human_size!(total_read_from_seed)
+    );
+    progress::report_done(
+        opts.progress,
+        &[
+            ("bytes_from_archive", total_read_from_remote),
+            ("bytes_from_seeds", total_read_from_seed),
+        ],
+    );
+
+    Ok(())
+}
+
+// Open an output file of the clone and, if seeding from output, re-order its chunks in
+// place.
+//
+// Returns the clone output, if it's a block device and the number of bytes re-ordered.
+async fn open_output<R>(
+    opts: &Options,
+    archive: &Archive<R>,
+    path: &Path,
+    clone_index: ChunkIndex,
+) -> Result<(CloneOutput<File>, bool, u64)> {
+    // Create or open output file
+    let mut output_file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .read(opts.verify_output || opts.seed_output || opts.skip_unchanged_writes)
+        .create(opts.force_create || opts.seed_output)
+        .create_new(!opts.force_create && !opts.seed_output)
+        .open(path)
+        .await
+        .context(format!("Failed to open {}", path.display()))?;
+
+    // Check if the given output file is a regular file or block device.
+    // If it is a block device, or a file not to be resized, we should check its size against
+    // the target size, at the output offset, before writing. Otherwise the file is resized
+    // to target size after writing.
+    let output_is_block_dev = is_block_dev(&output_file).await?;
+    if output_is_block_dev || opts.no_resize {
+        let size = file_size(&mut output_file).await?;
+        if size.saturating_sub(opts.output_offset) < opts.output_size(archive) {
+            return Err(CloneError::OutputSizeMismatch {
+                output_size: size,
+                output_offset: opts.output_offset,
+                source_size: opts.output_size(archive),
+            }
+            .into());
+        }
+    }
+
+    // Build an index of the output file's chunks
+    let output_index = if opts.seed_output {
+        info!("Building chunk index of {}...", path.display());
+        let mut seed = output_file
+            .try_clone()
+            .await
+            .context(format!("Failed to read {}", path.display()))?;
+        seed.seek(SeekFrom::Start(opts.output_offset))
+            .await
+            .context(format!("Failed to read {}", path.display()))?;
+        Some(
+            chunk_index_from_readable(
+                archive.chunk_hash_algorithm(),
+                archive.chunk_hash_length(),
+                archive.chunker_config(),
+                opts.num_chunk_buffers,
+                seed,
+            )
+            .await?,
+        )
+    } else {
+        None
+    };
+
+    // Move chunks by reflink where supported by the output file system.
+    #[cfg(target_os = "linux")]
+    let reflink = match output_index {
+        Some(_) => output_file.try_clone().await.ok(),
+        None => None,
+    };
+    // Another handle to read output through, skipping writes of chunks already in place.
+    let unchanged = if opts.skip_unchanged_writes {
+        Some(
+            output_file
+                .try_clone()
+                .await
+                .context(format!("Failed to read {}", path.display()))?,
+        )
+    } else {
+        None
+    };
+    let mut output = CloneOutput::builder(output_file, clone_index).base_offset(opts.output_offset);
+    if let Some(range) = &opts.source_range {
+        output = output.source_range(range.clone());
+    }
+    if opts.sequential_write {
+        output = output.sequential_write(opts.max_stream_buffer);
+    }
+    if let Some(file) = unchanged {
+        output = output.skip_unchanged(file.into_std().await);
+    }
+    #[cfg(target_os = "linux")]
+    let output = match reflink {
+        Some(file) => output.reflink(file.into_std().await),
+        None => output,
+    };
+    let mut output = output.build();
+    let mut used_from_self = 0;
+    if let Some(output_index) = output_index {
+        if opts.explain_reorder {
+            // The same operations as planned by the re-order below.
+            let mut clone_index = output.chunks().clone();
+            output_index.strip_chunks_already_in_place(&mut clone_index);
+            print_reorder_ops(&output_index.reorder_ops(&clone_index));
+        }
+        info!("Re-ordering chunks of {}...", path.display());
+        let progress = opts.progress("in-place", None);
+        used_from_self = output
+            .reorder_in_place(output_index)
+            .await
+            .context("Failed to clone in place")?;
+        if let Some(mut progress) = progress {
+            progress.update(used_from_self);
+            progress.finish();
+        }
+        info!(
+            "Used {} from {}",
+            human_size!(used_from_self),
+            path.display()
+        );
+    }
+    Ok((output, output_is_block_dev, used_from_self))
+}
+
+// Verify the checksum of a cloned output file against the archive source checksum.
+async fn verify_output<R>(
+    opts: &Options,
+    archive: &Archive<R>,
+    path: &Path,
+    output_file: &mut File,
+) -> Result<()> {
+    info!("Verifying checksum of {}...", path.display());
+    let sum = file_checksum(
+        output_file,
+        opts.output_offset,
+        archive.total_source_size(),
+        opts.progress("verify", Some(archive.total_source_size())),
+    )
+    .await
+    .context(format!("Failed to create checksum of {}", path.display()))?;
+    let expected_checksum = archive.source_checksum();
+    if sum == *expected_checksum {
+        info!("Checksum verified Ok");
+        Ok(())
+    } else {
+        Err(CloneError::SourceChecksumMismatch {
+            output: path.display().to_string(),
+            checksum: sum,
+            archive: opts.input_archive.source(),
+            expected: expected_checksum.clone(),
+        }
+        .into())
+    }
+}
+
+fn restore_metadata<R>(
+    opts: &Options,
+    archive: &Archive<R>,
+    path: &Path,
+    is_block_dev: bool,
+) -> Result<()> {
+    if is_block_dev {
+        warn!(
+            "Not restoring file metadata of block device {}",
+            path.display()
+        );
+    } else if file_metadata::restore(path, archive.metadata())? {
+        info!("Restored file metadata of {}", path.display());
+    } else {
+        warn!(
+            "No file metadata stored in archive {}",
+            opts.input_archive.source()
+        );
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteInput {
+    pub url: Url,
+    pub retries: u32,
+    pub retry_delay: Duration,
+    pub receive_timeout: Option<Duration>,
+    /// How long to wait for a connection to be established, None for no limit.
+    pub connect_timeout: Option<Duration>,
+    pub headers: HeaderMap,
+    /// Use HTTP/2 without negotiating it first.
+    pub http2: bool,
+    /// How long to keep idle connections open, None for the default.
+    pub pool_idle_timeout: Option<Duration>,
+}
+
+impl RemoteInput {
+    /// Build the http client to read the archive with.
+    pub fn client(&self) -> Result<reqwest::Client> {
+        let mut config = HttpClientConfig::default().http2(self.http2);
+        if let Some(timeout) = self.connect_timeout {
+            config = config.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.pool_idle_timeout {
+            config = config.pool_idle_timeout(timeout);
+        }
+        config.build().context("Failed to create http client")
+    }
+    /// Build the http reader to read the archive with.
+    pub fn reader(&self) -> Result<HttpReader> {
+        let mut request = self
+            .client()?
+            .get(self.url.clone())
+            .headers(self.headers.clone());
+        if let Some(timeout) = self.receive_timeout {
+            request = request.timeout(timeout);
+        }
+        Ok(HttpReader::from_request(request)
+            .retries(self.retries)
+            .retry_delay(self.retry_delay))
+    }
+}
+
+/// Open the reader of an archive in an object store.
+#[cfg(feature = "object-store")]
+pub fn object_store_reader(url: &Url) -> Result<ObjectStoreReader> {
+    ObjectStoreReader::from_url(url).context(format!("Failed to open object store at {}", url))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputArchive {
+    Local(std::path::PathBuf),
+    Remote(Box<RemoteInput>),
+    #[cfg(feature = "object-store")]
+    ObjectStore(Url),
+}
+impl InputArchive {
+    pub fn source(&self) -> String {
+        match self {
+            Self::Local(p) => format!("{}", p.display()),
+            Self::Remote(input) => input.url.to_string(),
+            #[cfg(feature = "object-store")]
+            Self::ObjectStore(url) => url.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    pub force_create: bool,
+    pub input_archive: InputArchive,
+    pub header_checksum: Option<HashSum>,
+    pub verify_signature: Option<PathBuf>,
+    /// Skip verifying the header against its internal checksum.
+    ///
+    /// Best-effort recovery of a slightly corrupt archive, nothing guarantees the clone is
+    /// the archived source.
+    pub no_verify_header: bool,
+    pub output: PathBuf,
+    /// Further outputs to write the same clone to, fetching each chunk only once.
+    pub extra_outputs: Vec<PathBuf>,
+    /// Offset in output to clone to, eg a region of a larger device.
+    pub output_offset: u64,
+    /// Only clone this range of the source, to an output the size of the range.
+    pub source_range: Option<Range<u64>>,
+    pub seed_stdin: bool,
+    /// Seed files, or directories of seed files.
+    pub seed_files: Vec<PathBuf>,
+    /// Also use the files in sub directories of seed directories.
+    pub seed_recursive: bool,
+    /// Base archive, or its source file, providing the chunks a delta archive doesn't store.
+    pub base_seed: Option<PathBuf>,
+    /// Directory to cache the chunk index of seed files in.
+    pub seed_index_cache: Option<PathBuf>,
+    pub seed_output: bool,
+    pub verify_output: bool,
+    /// Only use seeds if the archive stores full length chunk hashes.
+    ///
+    /// Seed chunks are matched against the archive by hash. With truncated hashes (see
+    /// `--hash-length`) a colliding seed chunk could be written in place of the archive chunk.
+    pub strict_seeds: bool,
+    pub dry_run: bool,
+    /// Zero fill archive chunks failing to decompress or verify instead of aborting.
+    pub lenient: bool,
+    /// Max size of data buffered while writing to stdout, a FIFO or with sequential_write.
+    pub max_stream_buffer: usize,
+    /// Write chunks to output in ascending offset order, buffering up to max_stream_buffer.
+    pub sequential_write: bool,
+    /// Read output before writing a chunk and skip the write if the chunk is already there.
+    pub skip_unchanged_writes: bool,
+    /// Number of chunks buffered ahead while streaming.
+    pub num_chunk_buffers: usize,
+    /// Lower num_chunk_buffers to keep the buffered chunks within this many bytes.
+    pub max_chunk_memory: Option<usize>,
+    /// Size of the blocking thread pool hashing and decompressing chunks.
+    pub num_threads: usize,
+    /// Abort the clone if not finished within this time.
+    pub total_deadline: Option<Duration>,
+    /// Restore the mode and ownership stored in the archive metadata to the output.
+    pub restore_metadata: bool,
+    /// Print the operations re-ordering the chunks of output when seeding from output.
+    pub explain_reorder: bool,
+    /// Don't resize the output file, fail if it's smaller than the archive source and leave
+    /// anything after the source untouched, like for a block device.
+    pub no_resize: bool,
+    /// How to report the progress of every phase of the clone.
+    ///
+    /// Only JSON events are reported, the human readable log already tells when each phase
+    /// starts and ends.
+    pub progress: ProgressFormat,
+}
+
+impl Options {
+    pub fn output_is_stdout(&self) -> bool {
+        self.output == Path::new("-")
+    }
+    // Size of the cloned output, the source range if given or else the whole source.
+    fn output_size<R>(&self, archive: &Archive<R>) -> u64 {
+        match &self.source_range {
+            Some(range) => range.end - range.start,
+            None => archive.total_source_size(),
+        }
+    }
+    // The output followed by any extra outputs.
+    fn outputs(&self) -> impl Iterator<Item = &Path> {
+        std::iter::once(self.output.as_path())
+            .chain(self.extra_outputs.iter().map(PathBuf::as_path))
+    }
+    // Progress of a phase of the clone, reported as JSON events only.
+    fn progress(&self, phase: &'static str, total: Option<u64>) -> Option<Progress> {
+        (self.progress == ProgressFormat::Json)
+            .then(|| Progress::new(self.progress, phase, phase, total))
+    }
+    fn uses_seeds(&self) -> bool {
+        self.seed_output
+            || self.seed_stdin
+            || !self.seed_files.is_empty()
+            || self.base_seed.is_some()
+    }
+}
+
+pub async fn clone_cmd(opts: Options) -> Result<()> {
+    let Some(deadline) = opts.total_deadline else {
+        return clone_input_archive(opts).await;
+    };
+    // Dropping the clone aborts any transfer in progress, unlike the per request timeout
+    // which only fails a single transfer.
+    tokio::time::timeout(deadline, clone_input_archive(opts))
+        .await
+        .map_err(|_| CloneError::DeadlineExceeded { deadline })?
+}
+
+async fn clone_input_archive(opts: Options) -> Result<()> {
+    match opts.input_archive.clone() {
+        InputArchive::Local(path) => {
+            clone_archive(
+                opts,
+                IoReader::new(
                     File::open(&path)
                         .await
                         .context(format!("Failed to open {}", path.display()))?,
@@ -390,20 +1829,1175 @@ pub async fn clone_cmd(opts: Options) -> Result<()> {
             )
             .await
         }
-        InputArchive::Remote(input) => {
-            let mut request = reqwest::Client::new()
-                .get(input.url.clone())
-                .headers(input.headers.clone());
-            if let Some(timeout) = input.receive_timeout {
-                request = request.timeout(timeout);
-            }
-            clone_archive(
-                opts,
-                HttpReader::from_request(request)
-                    .retries(input.retries)
-                    .retry_delay(input.retry_delay),
+        InputArchive::Remote(input) => clone_archive(opts, input.reader()?).await,
+        #[cfg(feature = "object-store")]
+        InputArchive::ObjectStore(url) => clone_archive(opts, object_store_reader(&url)?).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitar::rolling_hash::BuzHash;
+    use futures_util::ready;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::task::{Context, Poll};
+    use tokio::io::ReadBuf;
+
+    // Options cloning the local archive to output, verifying the output. Tests override the
+    // options they exercise using struct update syntax.
+    fn test_options(archive: &Path, output: &Path) -> Options {
+        Options {
+            force_create: false,
+            input_archive: InputArchive::Local(archive.to_path_buf()),
+            header_checksum: None,
+            verify_signature: None,
+            no_verify_header: false,
+            output: output.to_path_buf(),
+            extra_outputs: Vec::new(),
+            seed_stdin: false,
+            base_seed: None,
+            seed_index_cache: None,
+            seed_files: Vec::new(),
+            seed_recursive: false,
+            seed_output: false,
+            verify_output: true,
+            strict_seeds: false,
+            dry_run: false,
+            lenient: false,
+            output_offset: 0,
+            source_range: None,
+            max_stream_buffer: 0,
+            sequential_write: false,
+            skip_unchanged_writes: false,
+            num_chunk_buffers: 2,
+            num_threads: 1,
+            max_chunk_memory: None,
+            total_deadline: None,
+            restore_metadata: false,
+            explain_reorder: false,
+            no_resize: false,
+            progress: ProgressFormat::Human,
+        }
+    }
+
+    async fn clone_with_strict_seeds(hash_length: usize) -> Result<()> {
+        let dir = tempfile::tempdir().unwrap();
+        let source: Vec<u8> = (0..100_000u32).map(|v| (v % 251) as u8).collect();
+        let archive_path = dir.path().join("archive.cba");
+        let seed_path = dir.path().join("seed");
+        std::fs::write(&seed_path, &source).unwrap();
+        let options = bitar::api::compress::CreateArchiveOptions {
+            chunk_hash_length: hash_length,
+            ..Default::default()
+        };
+        bitar::api::compress::create_archive(
+            &source[..],
+            File::create(&archive_path).await.unwrap(),
+            &options,
+        )
+        .await
+        .unwrap();
+        clone_cmd(Options {
+            seed_files: vec![seed_path],
+            strict_seeds: true,
+            num_chunk_buffers: 1,
+            ..test_options(&archive_path, &dir.path().join("output"))
+        })
+        .await
+    }
+
+    #[test]
+    fn explain_swapped_chunks_reorder() {
+        let mut output_index = ChunkIndex::new_empty(HashSum::MAX_LEN);
+        output_index.add_chunk(HashSum::from(&[1]), 10, &[0]);
+        output_index.add_chunk(HashSum::from(&[2]), 10, &[10]);
+        let mut clone_index = ChunkIndex::new_empty(HashSum::MAX_LEN);
+        clone_index.add_chunk(HashSum::from(&[2]), 10, &[0, 20]);
+        clone_index.add_chunk(HashSum::from(&[1]), 10, &[10]);
+        assert_eq!(
+            explain_reorder_ops(&output_index.reorder_ops(&clone_index)),
+            [
+                "1: store 10 bytes from offset 0 in memory",
+                "2: copy 10 bytes from offset 10 to offsets 0, 20",
+                "3: copy 10 bytes from memory to offset 10",
+                "3 re-order operations: 2 copies (20 bytes), 1 stored in memory (10 bytes)",
+            ]
+        );
+    }
+
+    fn pseudo_random_bytes(size: usize) -> Vec<u8> {
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        (0..size)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    // Create a base archive and a delta archive against it, returning their paths and the
+    // base source path.
+    async fn create_delta_archive(dir: &Path) -> (PathBuf, PathBuf, PathBuf) {
+        let chunker_config = chunker::Config::RollSum(chunker::FilterConfig {
+            filter_bits: chunker::FilterBits::from_size(4096),
+            min_chunk_size: 1024,
+            max_chunk_size: 16384,
+            window_size: 64,
+            buzhash_seed: BuzHash::DEFAULT_SEED,
+        });
+        let base_source = pseudo_random_bytes(256 * 1024);
+        let mut source = base_source.clone();
+        source[100_000..101_000].fill(0xff);
+        source.extend_from_slice(&pseudo_random_bytes(8 * 1024)[..]);
+        let base_source_path = dir.join("base.img");
+        let source_path = dir.join("source.img");
+        let base_path = dir.join("base.cba");
+        let delta_path = dir.join("delta.cba");
+        std::fs::write(&base_source_path, &base_source).unwrap();
+        std::fs::write(&source_path, &source).unwrap();
+        bitar::api::compress::create_archive(
+            &base_source[..],
+            File::create(&base_path).await.unwrap(),
+            &bitar::api::compress::CreateArchiveOptions {
+                chunker_config: chunker_config.clone(),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        crate::compress_cmd::compress_cmd(crate::compress_cmd::Options {
+            force_create: false,
+            inputs: vec![source_path],
+            output: delta_path.clone(),
+            temp_dir: None,
+            hash_length: 64,
+            hash_algorithm: HashAlgorithm::Blake2b,
+            chunker_config,
+            compression: None,
+            auto_compression: Vec::new(),
+            num_chunk_buffers: 2,
+            num_threads: 1,
+            metadata_files: Vec::new(),
+            metadata_strings: Vec::new(),
+            store_metadata: false,
+            sign_key: None,
+            header_at_end: false,
+            compress_header: false,
+            base: Some(base_path.clone()),
+            max_chunk_memory: None,
+            compress_time_budget: None,
+            reproducible: false,
+            progress: ProgressFormat::Human,
+        })
+        .await
+        .unwrap();
+        (base_path, delta_path, base_source_path)
+    }
+
+    async fn clone_delta(
+        archive: PathBuf,
+        base_seed: Option<PathBuf>,
+        output: PathBuf,
+    ) -> Result<()> {
+        clone_cmd(Options {
+            base_seed,
+            ..test_options(&archive, &output)
+        })
+        .await
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn clone_restores_metadata() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let source_path = dir.path().join("source.img");
+        let archive_path = dir.path().join("archive.cba");
+        std::fs::write(&source_path, pseudo_random_bytes(10_000)).unwrap();
+        std::fs::set_permissions(&source_path, std::fs::Permissions::from_mode(0o751)).unwrap();
+        crate::compress_cmd::compress_cmd(crate::compress_cmd::Options {
+            force_create: false,
+            inputs: vec![source_path],
+            output: archive_path.clone(),
+            temp_dir: None,
+            hash_length: 64,
+            hash_algorithm: HashAlgorithm::Blake2b,
+            chunker_config: chunker::Config::FixedSize(4096),
+            compression: None,
+            auto_compression: Vec::new(),
+            num_chunk_buffers: 2,
+            num_threads: 1,
+            metadata_files: Vec::new(),
+            metadata_strings: Vec::new(),
+            store_metadata: true,
+            sign_key: None,
+            header_at_end: false,
+            compress_header: false,
+            base: None,
+            max_chunk_memory: None,
+            compress_time_budget: None,
+            reproducible: false,
+            progress: ProgressFormat::Human,
+        })
+        .await
+        .unwrap();
+
+        let output_path = dir.path().join("output.img");
+        clone_cmd(Options {
+            restore_metadata: true,
+            ..test_options(&archive_path, &output_path)
+        })
+        .await
+        .unwrap();
+        let mode = std::fs::metadata(&output_path)
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o7777, 0o751);
+    }
+
+    #[tokio::test]
+    async fn clone_explicit_archive_rejects_seeds() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = pseudo_random_bytes(16 * 1024);
+        let archive_path = dir.path().join("archive.cba");
+        bitar::api::compress::create_archive(
+            &source[..],
+            File::create(&archive_path).await.unwrap(),
+            &bitar::api::compress::CreateArchiveOptions {
+                chunker_config: chunker::Config::Explicit(vec![4096, 10000, 16384]),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let seed_path = dir.path().join("seed.img");
+        std::fs::write(&seed_path, &source).unwrap();
+        let output_path = dir.path().join("output.img");
+        for options in [
+            Options {
+                seed_files: vec![seed_path.clone()],
+                ..test_options(&archive_path, &output_path)
+            },
+            Options {
+                force_create: true,
+                seed_output: true,
+                ..test_options(&archive_path, &output_path)
+            },
+        ] {
+            let err = clone_cmd(options).await.unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "Archive is chunked at explicit boundaries which are not stored in the archive, can't scan seeds for its chunks"
+            );
+        }
+        assert!(!output_path.exists());
+
+        clone_cmd(test_options(&archive_path, &output_path))
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&output_path).unwrap(), source);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn clone_to_fifo() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = pseudo_random_bytes(64 * 1024);
+        let archive_path = dir.path().join("archive.cba");
+        bitar::api::compress::create_archive(
+            &source[..],
+            File::create(&archive_path).await.unwrap(),
+            &bitar::api::compress::CreateArchiveOptions {
+                chunker_config: chunker::Config::FixedSize(4096),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        // Seed with the chunks in reverse order, so they can't be written as read.
+        let seed_path = dir.path().join("seed.img");
+        let seed: Vec<u8> = source.chunks(4096).rev().flatten().copied().collect();
+        std::fs::write(&seed_path, seed).unwrap();
+        let fifo_path = dir.path().join("output.fifo");
+        let status = std::process::Command::new("mkfifo")
+            .arg(&fifo_path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let options = |max_stream_buffer: usize, seed_output: bool| Options {
+            seed_files: vec![seed_path.clone()],
+            seed_output,
+            max_stream_buffer,
+            ..test_options(&archive_path, &fifo_path)
+        };
+
+        // Can't read back the output of a FIFO.
+        clone_cmd(options(usize::MAX, true)).await.unwrap_err();
+
+        let reader = {
+            let fifo_path = fifo_path.clone();
+            std::thread::spawn(move || std::fs::read(fifo_path).unwrap())
+        };
+        clone_cmd(options(usize::MAX, false)).await.unwrap();
+        assert_eq!(reader.join().unwrap(), source);
+
+        // Reordering seed chunks needs buffering beyond the limit.
+        let reader = {
+            let fifo_path = fifo_path.clone();
+            std::thread::spawn(move || std::fs::read(fifo_path).unwrap())
+        };
+        let err = clone_cmd(options(4096, false)).await.unwrap_err();
+        assert!(format!("{:?}", err).contains("buffered data would exceed 4096 bytes"));
+        assert!(reader.join().unwrap().len() < source.len());
+    }
+
+    #[tokio::test]
+    async fn clone_to_output_offset() {
+        let dir = tempfile::tempdir().unwrap();
+        let seed = pseudo_random_bytes(64 * 1024);
+        let mut source = seed.clone();
+        source[10_000..11_000].fill(0xff);
+        let archive_path = dir.path().join("archive.cba");
+        bitar::api::compress::create_archive(
+            &source[..],
+            File::create(&archive_path).await.unwrap(),
+            &bitar::api::compress::CreateArchiveOptions {
+                chunker_config: chunker::Config::FixedSize(4096),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        // Output with the seed in place after a header which must be left as is.
+        let header = vec![0xa5u8; 1000];
+        let output_path = dir.path().join("output.img");
+        std::fs::write(&output_path, [&header[..], &seed[..]].concat()).unwrap();
+
+        clone_cmd(Options {
+            output_offset: header.len() as u64,
+            seed_output: true,
+            ..test_options(&archive_path, &output_path)
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            std::fs::read(&output_path).unwrap(),
+            [&header[..], &source[..]].concat()
+        );
+    }
+
+    #[tokio::test]
+    async fn clone_no_resize() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = pseudo_random_bytes(64 * 1024);
+        let archive_path = dir.path().join("archive.cba");
+        bitar::api::compress::create_archive(
+            &source[..],
+            File::create(&archive_path).await.unwrap(),
+            &bitar::api::compress::CreateArchiveOptions {
+                chunker_config: chunker::Config::FixedSize(4096),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let opts = |output: PathBuf| Options {
+            force_create: true,
+            no_resize: true,
+            ..test_options(&archive_path, &output)
+        };
+
+        // Sparse output larger than the source, with data after the source left as is.
+        let output_path = dir.path().join("larger.img");
+        let output_size = 16 * 1024 * 1024;
+        {
+            let mut output = std::fs::File::create(&output_path).unwrap();
+            output.set_len(output_size).unwrap();
+            std::io::Seek::seek(&mut output, SeekFrom::End(-4)).unwrap();
+            std::io::Write::write_all(&mut output, b"tail").unwrap();
+        }
+        clone_cmd(opts(output_path.clone())).await.unwrap();
+        let output = std::fs::read(&output_path).unwrap();
+        assert_eq!(output.len() as u64, output_size);
+        assert_eq!(&output[..source.len()], &source[..]);
+        assert!(output[source.len()..output.len() - 4]
+            .iter()
+            .all(|&b| b == 0));
+        assert_eq!(&output[output.len() - 4..], b"tail");
+
+        // Output smaller than the source is not written to.
+        let output_path = dir.path().join("smaller.img");
+        std::fs::write(&output_path, vec![0xa5u8; 1000]).unwrap();
+        let err = clone_cmd(opts(output_path.clone())).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CloneError>(),
+            Some(CloneError::OutputSizeMismatch { .. })
+        ));
+        assert_eq!(std::fs::read(&output_path).unwrap(), vec![0xa5u8; 1000]);
+    }
+
+    #[tokio::test]
+    async fn clone_empty_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let input_path = dir.path().join("empty.img");
+        std::fs::write(&input_path, []).unwrap();
+        let archive_path = dir.path().join("archive.cba");
+        crate::compress_cmd::compress_cmd(crate::compress_cmd::Options {
+            force_create: false,
+            inputs: vec![input_path],
+            output: archive_path.clone(),
+            temp_dir: None,
+            hash_length: 64,
+            hash_algorithm: bitar::HashAlgorithm::Blake2b,
+            chunker_config: chunker::Config::RollSum(chunker::FilterConfig::default()),
+            compression: None,
+            auto_compression: Vec::new(),
+            num_chunk_buffers: 2,
+            num_threads: 1,
+            metadata_files: Vec::new(),
+            metadata_strings: Vec::new(),
+            store_metadata: false,
+            sign_key: None,
+            header_at_end: false,
+            compress_header: false,
+            base: None,
+            max_chunk_memory: None,
+            compress_time_budget: None,
+            reproducible: false,
+            progress: ProgressFormat::Human,
+        })
+        .await
+        .unwrap();
+        // An existing output is truncated to the empty source.
+        let output_path = dir.path().join("output.img");
+        std::fs::write(&output_path, vec![0xa5u8; 10_000]).unwrap();
+
+        clone_cmd(Options {
+            seed_output: true,
+            ..test_options(&archive_path, &output_path)
+        })
+        .await
+        .unwrap();
+        assert!(std::fs::read(&output_path).unwrap().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn clone_skip_unchanged_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = pseudo_random_bytes(64 * 1024);
+        let archive_path = dir.path().join("archive.cba");
+        bitar::api::compress::create_archive(
+            &source[..],
+            File::create(&archive_path).await.unwrap(),
+            &bitar::api::compress::CreateArchiveOptions {
+                chunker_config: chunker::Config::FixedSize(4096),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        // Output holding the source except for a single chunk.
+        let mut previous = source.clone();
+        previous[10_000..11_000].fill(0xff);
+        let output_path = dir.path().join("output.img");
+        std::fs::write(&output_path, &previous).unwrap();
+
+        clone_cmd(Options {
+            force_create: true,
+            skip_unchanged_writes: true,
+            ..test_options(&archive_path, &output_path)
+        })
+        .await
+        .unwrap();
+        assert_eq!(std::fs::read(&output_path).unwrap(), source);
+    }
+
+    #[tokio::test]
+    async fn clone_source_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = pseudo_random_bytes(64 * 1024);
+        let archive_path = dir.path().join("archive.cba");
+        bitar::api::compress::create_archive(
+            &source[..],
+            File::create(&archive_path).await.unwrap(),
+            &bitar::api::compress::CreateArchiveOptions {
+                chunker_config: chunker::Config::FixedSize(4096),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let output_path = dir.path().join("output.img");
+
+        // Range starting and ending in the middle of chunks.
+        clone_cmd(Options {
+            source_range: Some(10_000..30_000),
+            verify_output: false,
+            ..test_options(&archive_path, &output_path)
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            std::fs::read(&output_path).unwrap(),
+            &source[10_000..30_000]
+        );
+
+        // Range ending past the end of source.
+        let err = clone_cmd(Options {
+            force_create: true,
+            source_range: Some(10_000..70_000),
+            verify_output: false,
+            ..test_options(&archive_path, &output_path)
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Source range 10000:70000 ends beyond the end of source (65536)"
+        );
+    }
+
+    #[tokio::test]
+    async fn clone_sequential_write() {
+        let dir = tempfile::tempdir().unwrap();
+        // Source repeating its chunks, with half of them found in the seed.
+        let blocks = pseudo_random_bytes(8 * 4096);
+        let source: Vec<u8> = (0..64)
+            .flat_map(|index| {
+                let block = index * 5 % 8 * 4096;
+                blocks[block..block + 4096].to_vec()
+            })
+            .collect();
+        let archive_path = dir.path().join("archive.cba");
+        let seed_path = dir.path().join("seed.img");
+        std::fs::write(&seed_path, &blocks[..4 * 4096]).unwrap();
+        bitar::api::compress::create_archive(
+            &source[..],
+            File::create(&archive_path).await.unwrap(),
+            &bitar::api::compress::CreateArchiveOptions {
+                chunker_config: chunker::Config::FixedSize(4096),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let output_path = dir.path().join("output.img");
+        clone_cmd(Options {
+            seed_files: vec![seed_path],
+            max_stream_buffer: 16 * 4096,
+            sequential_write: true,
+            ..test_options(&archive_path, &output_path)
+        })
+        .await
+        .unwrap();
+        assert_eq!(std::fs::read(&output_path).unwrap(), source);
+    }
+
+    #[tokio::test]
+    async fn clone_to_multiple_outputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = pseudo_random_bytes(16 * 4096);
+        let archive_path = dir.path().join("archive.cba");
+        bitar::api::compress::create_archive(
+            &source[..],
+            File::create(&archive_path).await.unwrap(),
+            &bitar::api::compress::CreateArchiveOptions {
+                chunker_config: chunker::Config::FixedSize(4096),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        // One output already holds the second half of the source, the other is new.
+        let (partial_path, new_path) = (dir.path().join("partial.img"), dir.path().join("new.img"));
+        std::fs::write(&partial_path, &source[8 * 4096..]).unwrap();
+        clone_cmd(Options {
+            extra_outputs: vec![new_path.clone()],
+            seed_output: true,
+            max_stream_buffer: 16 * 4096,
+            ..test_options(&archive_path, &partial_path)
+        })
+        .await
+        .unwrap();
+        assert_eq!(std::fs::read(&partial_path).unwrap(), source);
+        assert_eq!(std::fs::read(&new_path).unwrap(), source);
+    }
+
+    #[tokio::test]
+    async fn clone_verifies_checksum_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = pseudo_random_bytes(4 * 4096);
+        let archive_path = dir.path().join("archive.cba");
+        bitar::api::compress::create_archive(
+            &source[..],
+            File::create(&archive_path).await.unwrap(),
+            &bitar::api::compress::CreateArchiveOptions {
+                chunker_config: chunker::Config::FixedSize(4096),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let header_checksum =
+            Archive::try_init(IoReader::new(File::open(&archive_path).await.unwrap()))
+                .await
+                .unwrap()
+                .header_checksum()
+                .clone();
+        let output_path = dir.path().join("output.img");
+        let options = Options {
+            force_create: true,
+            verify_output: false,
+            max_stream_buffer: 16 * 4096,
+            ..test_options(&archive_path, &output_path)
+        };
+        let checksum_path = dir.path().join("archive.cba.b2");
+        std::fs::write(
+            &checksum_path,
+            format!("{}  archive.cba\n", HashSum::from(&[0; 64][..])),
+        )
+        .unwrap();
+        assert!(clone_cmd(options.clone()).await.is_err());
+        // The checksum file is ignored when not verifying the header.
+        clone_cmd(Options {
+            no_verify_header: true,
+            ..options.clone()
+        })
+        .await
+        .unwrap();
+        std::fs::write(
+            &checksum_path,
+            format!("{}  archive.cba\n", header_checksum),
+        )
+        .unwrap();
+        clone_cmd(options).await.unwrap();
+        assert_eq!(std::fs::read(&output_path).unwrap(), source);
+    }
+
+    #[tokio::test]
+    async fn clone_skips_remaining_seeds() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = pseudo_random_bytes(64 * 1024);
+        let archive_path = dir.path().join("archive.cba");
+        let seed_path = dir.path().join("seed.img");
+        std::fs::write(&seed_path, &source).unwrap();
+        bitar::api::compress::create_archive(
+            &source[..],
+            File::create(&archive_path).await.unwrap(),
+            &bitar::api::compress::CreateArchiveOptions {
+                chunker_config: chunker::Config::FixedSize(4096),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        // The second seed doesn't exist, the clone fails if it is opened.
+        let output_path = dir.path().join("output.img");
+        clone_cmd(Options {
+            seed_files: vec![seed_path, dir.path().join("missing.img")],
+            ..test_options(&archive_path, &output_path)
+        })
+        .await
+        .unwrap();
+        assert_eq!(std::fs::read(&output_path).unwrap(), source);
+    }
+
+    #[tokio::test]
+    async fn clone_deadline_exceeded() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = pseudo_random_bytes(64 * 1024);
+        let archive_path = dir.path().join("archive.cba");
+        bitar::api::compress::create_archive(
+            &source[..],
+            File::create(&archive_path).await.unwrap(),
+            &bitar::api::compress::CreateArchiveOptions::default(),
+        )
+        .await
+        .unwrap();
+        let err = clone_cmd(Options {
+            verify_output: false,
+            num_chunk_buffers: 1,
+            total_deadline: Some(Duration::ZERO),
+            ..test_options(&archive_path, &dir.path().join("output.img"))
+        })
+        .await
+        .unwrap_err();
+        assert!(
+            matches!(
+                err.downcast_ref::<CloneError>(),
+                Some(CloneError::DeadlineExceeded { .. })
+            ),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn seed_directory_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let seeds = dir.path().join("seeds");
+        std::fs::create_dir_all(seeds.join("sub")).unwrap();
+        for path in ["b.img", "a.img", "output.img", "sub/c.img"] {
+            std::fs::write(seeds.join(path), path).unwrap();
+        }
+        let mut opts = Options {
+            seed_files: vec![seeds.join("b.img"), seeds.clone()],
+            verify_output: false,
+            num_chunk_buffers: 1,
+            ..test_options(&dir.path().join("archive.cba"), &seeds.join("output.img"))
+        };
+        // Seed files are used once, and never the output.
+        assert_eq!(
+            seed_paths(&opts).unwrap(),
+            vec![seeds.join("b.img"), seeds.join("a.img")]
+        );
+        opts.seed_recursive = true;
+        assert_eq!(
+            seed_paths(&opts).unwrap(),
+            vec![
+                seeds.join("b.img"),
+                seeds.join("a.img"),
+                seeds.join("sub/c.img")
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn clone_with_seed_index_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let chunker_config = chunker::Config::RollSum(chunker::FilterConfig {
+            filter_bits: chunker::FilterBits::from_size(4096),
+            min_chunk_size: 1024,
+            max_chunk_size: 16384,
+            window_size: 64,
+            buzhash_seed: BuzHash::DEFAULT_SEED,
+        });
+        let seed = pseudo_random_bytes(256 * 1024);
+        let mut source = seed.clone();
+        source[100_000..101_000].fill(0xff);
+        let archive_path = dir.path().join("archive.cba");
+        let seed_path = dir.path().join("seed.img");
+        let cache_dir = dir.path().join("cache");
+        std::fs::write(&seed_path, &seed).unwrap();
+        bitar::api::compress::create_archive(
+            &source[..],
+            File::create(&archive_path).await.unwrap(),
+            &bitar::api::compress::CreateArchiveOptions {
+                chunker_config: chunker_config.clone(),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let clone = |output: &str| {
+            clone_cmd(Options {
+                seed_index_cache: Some(cache_dir.clone()),
+                seed_files: vec![seed_path.clone()],
+                ..test_options(&archive_path, &dir.path().join(output))
+            })
+        };
+
+        // First clone caches the seed index.
+        clone("output1").await.unwrap();
+        let cache_path = seed_cache_path(&cache_dir, &seed_path);
+        let key = seed_cache_key(
+            &std::fs::metadata(&seed_path).unwrap(),
+            &chunker_config,
+            HashAlgorithm::Blake2b,
+            HashSum::MAX_LEN,
+        )
+        .unwrap();
+        let cached = read_cached_seed_index(&cache_path, &key, HashSum::MAX_LEN).unwrap();
+        assert!(cached.len() > 10);
+
+        // Second clone reads seed chunks through the cached index.
+        clone("output2").await.unwrap();
+        assert_eq!(std::fs::read(dir.path().join("output2")).unwrap(), source);
+
+        // A seed modified while keeping its size and mtime is still safe to clone from, the
+        // modified chunks are just not used.
+        let mtime = std::fs::metadata(&seed_path).unwrap().modified().unwrap();
+        let mut modified_seed = seed.clone();
+        modified_seed[10_000..200_000].fill(0);
+        std::fs::write(&seed_path, &modified_seed).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&seed_path)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+        assert!(read_cached_seed_index(&cache_path, &key, HashSum::MAX_LEN).is_ok());
+        clone("output3").await.unwrap();
+        assert_eq!(std::fs::read(dir.path().join("output3")).unwrap(), source);
+    }
+
+    #[tokio::test]
+    async fn lenient_clone_zero_fills_invalid_chunk() {
+        let dir = tempfile::tempdir().unwrap();
+        let source: Vec<u8> = (0..4000u32).map(|v| (v / 1000 + v % 7) as u8).collect();
+        let archive_path = dir.path().join("archive.cba");
+        bitar::api::compress::create_archive(
+            &source[..],
+            File::create(&archive_path).await.unwrap(),
+            &bitar::api::compress::CreateArchiveOptions {
+                chunker_config: chunker::Config::FixedSize(1000),
+                compression: None,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let archive = Archive::try_init(IoReader::new(File::open(&archive_path).await.unwrap()))
+            .await
+            .unwrap();
+        let (offset, descriptor) = archive.iter_source_chunks().nth(1).unwrap();
+        let offset = offset as usize;
+        let mut data = std::fs::read(&archive_path).unwrap();
+        data[descriptor.archive_offset as usize] ^= 0xff;
+        std::fs::write(&archive_path, data).unwrap();
+
+        let clone = |output: &str, lenient: bool| {
+            clone_cmd(Options {
+                lenient,
+                ..test_options(&archive_path, &dir.path().join(output))
+            })
+        };
+        let err = clone("strict", false).await.unwrap_err();
+        assert_eq!(crate::exit_code(&err), 1, "{:?}", err);
+
+        let err = clone("lenient", true).await.unwrap_err();
+        assert_eq!(crate::exit_code(&err), 7, "{:?}", err);
+        assert!(err.to_string().starts_with("1 chunks"), "{}", err);
+        let mut expected = source.clone();
+        expected[offset..offset + 1000].fill(0);
+        assert_eq!(std::fs::read(dir.path().join("lenient")).unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn delta_archive_refers_base() {
+        let dir = tempfile::tempdir().unwrap();
+        let (base_path, delta_path, _) = create_delta_archive(dir.path()).await;
+        let base = Archive::try_init(IoReader::new(File::open(&base_path).await.unwrap()))
+            .await
+            .unwrap();
+        let delta = Archive::try_init(IoReader::new(File::open(&delta_path).await.unwrap()))
+            .await
+            .unwrap();
+        assert_eq!(delta.base_header_checksum(), Some(base.header_checksum()));
+        assert!(base.base_header_checksum().is_none());
+        let in_base = delta
+            .chunk_descriptors()
+            .iter()
+            .filter(|cd| cd.in_base)
+            .count();
+        assert!(in_base > 0 && in_base < delta.unique_chunks());
+        assert!(delta.compressed_size() < 64 * 1024);
+    }
+
+    #[tokio::test]
+    async fn clone_delta_with_base_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let (base_path, delta_path, _) = create_delta_archive(dir.path()).await;
+        clone_delta(delta_path, Some(base_path), dir.path().join("output"))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn clone_delta_with_base_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, delta_path, base_source_path) = create_delta_archive(dir.path()).await;
+        clone_delta(
+            delta_path,
+            Some(base_source_path),
+            dir.path().join("output"),
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn clone_delta_without_base() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, delta_path, _) = create_delta_archive(dir.path()).await;
+        let err = clone_delta(delta_path, None, dir.path().join("output"))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("a base seed is required"));
+    }
+
+    #[tokio::test]
+    async fn clone_delta_with_wrong_base() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, delta_path, _) = create_delta_archive(dir.path()).await;
+        // Any other archive is refused as base.
+        let err = clone_delta(
+            delta_path.clone(),
+            Some(delta_path.clone()),
+            dir.path().join("output"),
+        )
+        .await
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Base archive header checksum mismatch"));
+        // And a file not holding the base chunks doesn't complete the clone.
+        let other_path = dir.path().join("other.img");
+        std::fs::write(&other_path, pseudo_random_bytes(64 * 1024)).unwrap();
+        let err = clone_delta(delta_path, Some(other_path), dir.path().join("output2"))
+            .await
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("chunks stored in base were not found"));
+    }
+
+    #[tokio::test]
+    async fn strict_seeds_full_length_hash() {
+        clone_with_strict_seeds(HashSum::MAX_LEN).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn strict_seeds_truncated_hash() {
+        assert!(clone_with_strict_seeds(8).await.is_err());
+    }
+
+    // Clone an archive, having a source checksum not matching its source, without
+    // requesting output verification.
+    async fn clone_with_wrong_source_checksum(hash_length: usize) -> Result<()> {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = dir.path().join("archive.cba");
+        let options = bitar::api::compress::CreateArchiveOptions {
+            chunk_hash_length: hash_length,
+            header_at_end: true,
+            compress_header: false,
+            ..Default::default()
+        };
+        let mut archive = Vec::new();
+        let mut result =
+            bitar::api::compress::create_archive(&[1u8; 10_000][..], &mut archive, &options)
+                .await
+                .unwrap();
+        let footer = &archive[archive.len() - bitar::header::FOOTER_SIZE..];
+        let header_offset = u64::from_le_bytes(footer[0..8].try_into().unwrap()) as usize;
+        archive.truncate(header_offset);
+        result.header.source_checksum = vec![0; 64];
+        archive.extend(
+            bitar::header::build_trailer(
+                &result.header,
+                (header_offset - bitar::header::TRAILER_MAGIC.len()) as u64,
+                None,
             )
+            .unwrap(),
+        );
+        std::fs::write(&archive_path, archive).unwrap();
+        clone_cmd(Options {
+            verify_output: false,
+            num_chunk_buffers: 1,
+            ..test_options(&archive_path, &dir.path().join("output"))
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn short_hash_verifies_output() {
+        clone_with_wrong_source_checksum(MIN_SAFE_HASH_LENGTH)
             .await
+            .unwrap();
+        let err = clone_with_wrong_source_checksum(MIN_SAFE_HASH_LENGTH - 1)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Checksum mismatch"));
+        assert_eq!(crate::exit_code(&err), 5);
+    }
+
+    #[derive(Default)]
+    struct Counts {
+        read: AtomicU64,
+        written: AtomicU64,
+        peak_unwritten: AtomicU64,
+    }
+
+    // Input counting the bytes read, and the most bytes read but not yet written to output.
+    struct CountingInput {
+        data: std::io::Cursor<Vec<u8>>,
+        counts: Arc<Counts>,
+    }
+
+    impl AsyncRead for CountingInput {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            let filled = buf.filled().len();
+            ready!(Pin::new(&mut self.data).poll_read(cx, buf))?;
+            let read = (buf.filled().len() - filled) as u64;
+            let read = self.counts.read.fetch_add(read, Ordering::SeqCst) + read;
+            let unwritten = read - self.counts.written.load(Ordering::SeqCst);
+            self.counts
+                .peak_unwritten
+                .fetch_max(unwritten, Ordering::SeqCst);
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    // Output taking a while to complete every write, counting the bytes written.
+    struct SlowOutput {
+        counts: Arc<Counts>,
+        delay: Option<Pin<Box<tokio::time::Sleep>>>,
+        position: u64,
+    }
+
+    impl AsyncWrite for SlowOutput {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let delay = self
+                .delay
+                .get_or_insert_with(|| Box::pin(tokio::time::sleep(Duration::from_millis(2))));
+            ready!(delay.as_mut().poll(cx));
+            self.delay = None;
+            self.position += buf.len() as u64;
+            self.counts
+                .written
+                .fetch_add(buf.len() as u64, Ordering::SeqCst);
+            Poll::Ready(Ok(buf.len()))
+        }
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncSeek for SlowOutput {
+        fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> std::io::Result<()> {
+            match position {
+                SeekFrom::Start(position) => {
+                    self.position = position;
+                    Ok(())
+                }
+                _ => Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "only seeking from start is supported",
+                )),
+            }
+        }
+        fn poll_complete(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<std::io::Result<u64>> {
+            Poll::Ready(Ok(self.position))
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn clone_from_seed_within_chunk_memory() {
+        const CHUNK_SIZE: usize = 1024 * 1024;
+        const MAX_CHUNK_MEMORY: usize = 12 * CHUNK_SIZE;
+        let source = pseudo_random_bytes(48 * CHUNK_SIZE);
+        let config = chunker::Config::FixedSize(CHUNK_SIZE);
+        let index = chunk_index_from_readable(
+            HashAlgorithm::Xxh3,
+            HashAlgorithm::Xxh3.hash_length(),
+            &config,
+            4,
+            std::io::Cursor::new(source.clone()),
+        )
+        .await
+        .unwrap();
+        let num_chunk_buffers = chunk_buffers::limit_chunk_buffers(
+            64,
+            chunk_buffers::CLONE_STAGES,
+            CHUNK_SIZE,
+            Some(MAX_CHUNK_MEMORY),
+        );
+        assert_eq!(num_chunk_buffers, 3);
+
+        let counts = Arc::new(Counts::default());
+        let mut output = CloneOutput::new(
+            SlowOutput {
+                counts: counts.clone(),
+                delay: None,
+                position: 0,
+            },
+            index,
+        );
+        let input = CountingInput {
+            data: std::io::Cursor::new(source.clone()),
+            counts: counts.clone(),
+        };
+        let written = clone_from_readable(
+            num_chunk_buffers,
+            &config,
+            HashAlgorithm::Xxh3,
+            input,
+            std::slice::from_mut(&mut output),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(written, source.len() as u64);
+        assert!(output.is_empty());
+
+        // The slow output keeps every stage full, but the chunks read and not yet written
+        // stay within the memory limit. Except for what the chunker has read ahead, which
+        // is not split into chunks yet.
+        let peak = counts.peak_unwritten.load(Ordering::SeqCst);
+        assert!(
+            peak <= (MAX_CHUNK_MEMORY + chunker::DEFAULT_REFILL_SIZE) as u64,
+            "{} bytes read but not written",
+            peak
+        );
+    }
+
+    #[tokio::test]
+    async fn spawn_chunker_stays_bounded() {
+        const CHUNK_SIZE: usize = 1024 * 1024;
+        let source = pseudo_random_bytes(32 * CHUNK_SIZE);
+        let counts = Arc::new(Counts::default());
+        let input = CountingInput {
+            data: std::io::Cursor::new(source.clone()),
+            counts: counts.clone(),
+        };
+        let mut chunks = spawn_chunker(&chunker::Config::FixedSize(CHUNK_SIZE), input, 2);
+        let (offset, chunk) = chunks.next().await.unwrap().unwrap();
+        assert_eq!(offset, 0);
+        assert_eq!(chunk.data(), &source[..CHUNK_SIZE]);
+
+        // Let the chunker run until blocked on the full channel. On this single threaded
+        // runtime it doesn't run in between, so reads stop at the same point every time.
+        for _ in 0..16 {
+            tokio::task::yield_now().await;
+        }
+        // The chunk taken, 2 in the channel and 1 waiting to be sent, plus what the chunker
+        // has read ahead.
+        let read = counts.read.load(Ordering::SeqCst);
+        assert!(read >= 3 * CHUNK_SIZE as u64);
+        assert!(read <= (4 * CHUNK_SIZE + chunker::DEFAULT_REFILL_SIZE) as u64);
+
+        // Then the rest of the chunks, in order.
+        let mut expected_offset = CHUNK_SIZE as u64;
+        while let Some(result) = chunks.next().await {
+            let (offset, chunk) = result.unwrap();
+            assert_eq!(offset, expected_offset);
+            assert_eq!(
+                chunk.data(),
+                &source[offset as usize..offset as usize + CHUNK_SIZE]
+            );
+            expected_offset += CHUNK_SIZE as u64;
         }
+        assert_eq!(expected_offset, source.len() as u64);
     }
 }
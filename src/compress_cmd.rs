@@ -2,27 +2,105 @@ use anyhow::{anyhow, Context, Result};
 use blake2::{Blake2b512, Digest};
 use futures_util::{future, StreamExt};
 use log::*;
-use std::io::Write;
-use std::path::PathBuf;
-use std::{collections::BTreeMap, collections::HashMap, io::IsTerminal};
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::IsTerminal,
+};
 use tokio::{
-    fs::{File, OpenOptions},
-    io::{AsyncRead, AsyncWriteExt},
+    fs::File,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
 };
 
-use crate::{human_size, info_cmd};
+use crate::chunk_buffers::{limit_chunk_buffers, COMPRESS_STAGES};
+use crate::{
+    file_metadata, human_size, info_cmd,
+    progress::{self, Progress, ProgressFormat},
+    signature,
+};
 use bitar::{archive_reader::IoReader, chunk_dictionary as dict};
-use bitar::{chunker, Compression};
+use bitar::{chunker, rolling_hash::BuzHash, Archive, Compression, HashAlgorithm, HashSum};
 
 pub const PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
 
-async fn chunk_input<T>(
-    mut input: T,
-    chunker_config: &chunker::Config,
-    compression: Option<Compression>,
-    temp_file_path: &std::path::Path,
+/// Chunks of the base archive a delta archive is created against.
+struct BaseArchive {
+    header_checksum: HashSum,
     hash_length: usize,
-    num_chunk_buffers: usize,
+    chunks: HashSet<HashSum>,
+}
+
+impl BaseArchive {
+    async fn open(path: &Path, opts: &Options) -> Result<Self> {
+        let file = File::open(path)
+            .await
+            .context(format!("Failed to open base archive {}", path.display()))?;
+        let archive = Archive::try_init(IoReader::new(file))
+            .await
+            .context(format!("Failed to read base archive {}", path.display()))?;
+        if opts.hash_algorithm != archive.chunk_hash_algorithm() {
+            return Err(anyhow!(
+                "Chunk hash algorithm ({}) differs from the one of base archive {} ({})",
+                opts.hash_algorithm,
+                path.display(),
+                archive.chunk_hash_algorithm()
+            ));
+        }
+        // Chunks are matched by the base hash, a longer hash in the delta archive could
+        // not be matched against the base when cloning.
+        if opts.hash_length > archive.chunk_hash_length() {
+            return Err(anyhow!(
+                "Hash length ({} bytes) is longer than the one of base archive {} ({} bytes)",
+                opts.hash_length,
+                path.display(),
+                archive.chunk_hash_length()
+            ));
+        }
+        if *archive.chunker_config() != opts.chunker_config {
+            warn!(
+                "Chunker config differs from base archive {}, few chunks are likely to be found in base",
+                path.display()
+            );
+        }
+        Ok(Self {
+            header_checksum: archive.header_checksum().clone(),
+            hash_length: archive.chunk_hash_length(),
+            chunks: archive
+                .chunk_descriptors()
+                .iter()
+                .filter(|cd| !cd.in_base)
+                .map(|cd| cd.checksum.clone())
+                .collect(),
+        })
+    }
+    fn contains(&self, hash: &HashSum) -> bool {
+        let mut hash = hash.clone();
+        hash.truncate(self.hash_length);
+        self.chunks.contains(&hash)
+    }
+}
+
+// Chunks are told apart by their full hash while compressing, fail if two different chunks
+// get the same truncated hash in the archive.
+fn insert_truncated_hash(hashes: &mut HashSet<HashSum>, hash: &HashSum) -> Result<()> {
+    if !hashes.insert(hash.clone()) {
+        return Err(anyhow!(
+            "Different chunks share the truncated chunk hash {} ({} bytes), use a longer hash length",
+            hash,
+            hash.len()
+        ));
+    }
+    Ok(())
+}
+
+async fn chunk_input<T, W>(
+    mut input: T,
+    opts: &Options,
+    chunk_output: &mut W,
+    input_size: Option<u64>,
+    base: Option<&BaseArchive>,
 ) -> Result<(
     Vec<u8>,
     Vec<bitar::chunk_dictionary::ChunkDescriptor>,
@@ -31,39 +109,50 @@ async fn chunk_input<T>(
 )>
 where
     T: AsyncRead + Unpin + Send,
+    W: AsyncWrite + Unpin,
 {
+    let compression = opts.compression;
+    let auto_compression = &opts.auto_compression;
+    let hash_algorithm = opts.hash_algorithm;
+    let num_chunk_buffers = limit_chunk_buffers(
+        opts.num_chunk_buffers,
+        COMPRESS_STAGES,
+        opts.chunker_config.max_chunk_size(),
+        opts.max_chunk_memory,
+    );
     let mut source_hasher = Blake2b512::new();
     let mut unique_chunks = HashMap::new();
+    let mut truncated_hashes = HashSet::new();
     let mut source_size: u64 = 0;
     let mut chunk_order = Vec::new();
     let mut archive_offset: u64 = 0;
     let mut unique_chunk_index: usize = 0;
     let mut archive_chunks = Vec::new();
-
-    let mut temp_file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(temp_file_path)
-        .await
-        .context(format!(
-            "Failed to open temp file {}",
-            temp_file_path.display()
-        ))?;
+    let mut progress = Progress::new(opts.progress, "compress", "Processed", input_size);
     {
-        let chunker = chunker_config.new_chunker(&mut input);
+        let chunker = opts.chunker_config.new_chunker(&mut input);
         let mut chunk_stream = chunker
             .map(|result| {
-                let (offset, chunk) = result.expect("error while chunking");
-                // Build hash of full source
-                source_hasher.update(chunk.data());
-                source_size += chunk.len() as u64;
-                tokio::task::spawn_blocking(move || (offset, chunk.verify()))
+                if let Ok((_offset, chunk)) = &result {
+                    // Build hash of full source
+                    source_hasher.update(chunk.data());
+                    source_size += chunk.len() as u64;
+                    progress.update(source_size);
+                }
+                async move {
+                    let (offset, chunk) = result.context("Failed to read input")?;
+                    tokio::task::spawn_blocking(move || (offset, chunk.verify_with(hash_algorithm)))
+                        .await
+                        .context("Error hashing chunk")
+                }
             })
             .buffered(num_chunk_buffers)
             .filter_map(|result| {
                 // Filter unique chunks to be compressed
-                let (offset, verified) = result.expect("error while hashing chunk");
+                let (offset, verified) = match result {
+                    Ok(result) => result,
+                    Err(err) => return future::ready(Some(Err(err))),
+                };
                 let (unique, chunk_index) = if unique_chunks.contains_key(verified.hash()) {
                     (false, *unique_chunks.get(verified.hash()).unwrap())
                 } else {
@@ -74,28 +163,74 @@ where
                 };
                 // Store a pointer (as index) to unique chunk index for each chunk
                 chunk_order.push(chunk_index);
+                let in_base = base.is_some_and(|base| base.contains(verified.hash()));
                 future::ready(if unique {
-                    Some((chunk_index, offset, verified))
+                    Some(Ok((chunk_index, offset, verified, in_base)))
                 } else {
                     None
                 })
             })
-            .map(|(chunk_index, offset, verified)| {
-                tokio::task::spawn_blocking(move || {
-                    // Compress each chunk
-                    let compressed = verified
-                        .chunk()
-                        .clone()
-                        .compress(compression)
-                        .expect("compress chunk");
-                    (chunk_index, offset, verified, compressed)
-                })
+            .map(|result| {
+                let auto_compression = auto_compression.clone();
+                async move {
+                    let (chunk_index, offset, verified, in_base) = result?;
+                    tokio::task::spawn_blocking(move || {
+                        // Chunks found in the base archive are not stored at all
+                        if in_base {
+                            return (chunk_index, offset, verified, None);
+                        }
+                        // Compress each chunk, with the best of the auto compressions if given
+                        let (compression, compressed) = if auto_compression.is_empty() {
+                            let compressed = verified
+                                .chunk()
+                                .clone()
+                                .compress(compression)
+                                .expect("compress chunk");
+                            (compression, compressed)
+                        } else {
+                            verified
+                                .chunk()
+                                .clone()
+                                .compress_smallest(&auto_compression)
+                                .expect("compress chunk")
+                        };
+                        (
+                            chunk_index,
+                            offset,
+                            verified,
+                            Some((compression, compressed)),
+                        )
+                    })
+                    .await
+                    .context("Error compressing")
+                }
             })
             .buffered(num_chunk_buffers);
 
         while let Some(result) = chunk_stream.next().await {
-            let (index, offset, verified, compressed) = result.context("Error compressing")?;
+            let (index, offset, verified, compressed) = result?;
             let chunk_len = verified.len();
+            let Some((chunk_compression, compressed)) = compressed else {
+                debug!(
+                    "Chunk {}, '{}', offset: {}, size: {}, found in base archive",
+                    index,
+                    verified.hash(),
+                    offset,
+                    human_size!(chunk_len),
+                );
+                let mut hash = verified.hash().clone();
+                hash.truncate(opts.hash_length);
+                insert_truncated_hash(&mut truncated_hashes, &hash)?;
+                archive_chunks.push(dict::ChunkDescriptor {
+                    checksum: hash.to_vec(),
+                    source_size: chunk_len as u32,
+                    archive_offset,
+                    archive_size: 0,
+                    compression: None,
+                    in_base: true,
+                });
+                continue;
+            };
             let use_uncompressed = compressed.len() >= chunk_len;
             debug!(
                 "Chunk {}, '{}', offset: {}, size: {}, {}",
@@ -103,10 +238,13 @@ where
                 verified.hash(),
                 offset,
                 human_size!(chunk_len),
-                if use_uncompressed {
-                    "left uncompressed".to_owned()
-                } else {
-                    format!("compressed to: {}", human_size!(compressed.len()))
+                match chunk_compression {
+                    Some(chunk_compression) if !use_uncompressed => format!(
+                        "compressed to: {} using {}",
+                        human_size!(compressed.len()),
+                        chunk_compression
+                    ),
+                    _ => "left uncompressed".to_owned(),
                 },
             );
             let (mut hash, chunk) = verified.into_parts();
@@ -115,7 +253,8 @@ where
             } else {
                 compressed.data()
             };
-            hash.truncate(hash_length);
+            hash.truncate(opts.hash_length);
+            insert_truncated_hash(&mut truncated_hashes, &hash)?;
 
             // Store a descriptor which refers to the compressed data
             archive_chunks.push(dict::ChunkDescriptor {
@@ -123,16 +262,24 @@ where
                 source_size: chunk_len as u32,
                 archive_offset,
                 archive_size: use_data.len() as u32,
+                compression: (!use_uncompressed && chunk_compression != compression)
+                    .then(|| chunk_compression.into()),
+                in_base: false,
             });
             archive_offset += use_data.len() as u64;
 
-            // Write the compressed chunk to temp file
-            temp_file
+            // Write the compressed chunk to temp file (or output)
+            chunk_output
                 .write_all(use_data)
                 .await
-                .context("Failed to write to temp file")?;
+                .context("Failed to write chunk data")?;
         }
     }
+    chunk_output
+        .flush()
+        .await
+        .context("Failed to write chunk data")?;
+    progress.finish();
     Ok((
         source_hasher.finalize().to_vec(),
         archive_chunks,
@@ -141,24 +288,281 @@ where
     ))
 }
 
+/// Chunker parameters to store in the archive dictionary.
+pub fn chunker_params(
+    config: &chunker::Config,
+    hash_algorithm: HashAlgorithm,
+    hash_length: usize,
+) -> dict::ChunkerParameters {
+    let chunk_hash_algorithm =
+        dict::chunker_parameters::ChunkHashAlgorithm::from(hash_algorithm) as i32;
+    match config {
+        chunker::Config::BuzHash(hash_config) => dict::ChunkerParameters {
+            chunk_filter_bits: hash_config.filter_bits.bits(),
+            min_chunk_size: hash_config.min_chunk_size as u32,
+            max_chunk_size: hash_config.max_chunk_size as u32,
+            rolling_hash_window_size: hash_config.window_size as u32,
+            chunk_hash_length: hash_length as u32,
+            chunking_algorithm: dict::chunker_parameters::ChunkingAlgorithm::Buzhash as i32,
+            buzhash_seed: (hash_config.buzhash_seed != BuzHash::DEFAULT_SEED)
+                .then_some(hash_config.buzhash_seed),
+            chunk_hash_algorithm,
+        },
+        chunker::Config::RollSum(hash_config) => dict::ChunkerParameters {
+            chunk_filter_bits: hash_config.filter_bits.bits(),
+            min_chunk_size: hash_config.min_chunk_size as u32,
+            max_chunk_size: hash_config.max_chunk_size as u32,
+            rolling_hash_window_size: hash_config.window_size as u32,
+            chunk_hash_length: hash_length as u32,
+            chunking_algorithm: dict::chunker_parameters::ChunkingAlgorithm::Rollsum as i32,
+            buzhash_seed: None,
+            chunk_hash_algorithm,
+        },
+        chunker::Config::FixedSize(chunk_size) => dict::ChunkerParameters {
+            min_chunk_size: 0,
+            chunk_filter_bits: 0,
+            rolling_hash_window_size: 0,
+            max_chunk_size: *chunk_size as u32,
+            chunk_hash_length: hash_length as u32,
+            chunking_algorithm: dict::chunker_parameters::ChunkingAlgorithm::FixedSize as i32,
+            buzhash_seed: None,
+            chunk_hash_algorithm,
+        },
+        chunker::Config::Explicit(_) => dict::ChunkerParameters {
+            min_chunk_size: 0,
+            chunk_filter_bits: 0,
+            rolling_hash_window_size: 0,
+            max_chunk_size: 0,
+            chunk_hash_length: hash_length as u32,
+            chunking_algorithm: dict::chunker_parameters::ChunkingAlgorithm::Explicit as i32,
+            buzhash_seed: None,
+            chunk_hash_algorithm,
+        },
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Options {
     pub force_create: bool,
-    /// File to compress, or stdin if input is None.
-    pub input: Option<PathBuf>,
+    /// Files to compress as if concatenated, `-` for stdin. Stdin is used if empty.
+    pub inputs: Vec<PathBuf>,
     pub output: PathBuf,
-    pub temp_file: PathBuf,
+    /// Directory of the temp file holding chunk data until the header is written, the
+    /// output directory if None.
+    pub temp_dir: Option<PathBuf>,
     pub hash_length: usize,
+    /// Algorithm to hash chunks with, the source checksum is always blake2.
+    pub hash_algorithm: HashAlgorithm,
     pub chunker_config: chunker::Config,
     pub compression: Option<Compression>,
+    /// Compressions to try for each chunk, keeping the smallest. Empty to always use
+    /// `compression`.
+    pub auto_compression: Vec<Compression>,
+    /// Number of chunks buffered ahead while streaming.
     pub num_chunk_buffers: usize,
+    /// Lower num_chunk_buffers to keep the buffered chunks within this many bytes.
+    pub max_chunk_memory: Option<usize>,
+    /// Size of the blocking thread pool hashing and compressing chunks.
+    pub num_threads: usize,
     pub metadata_files: Vec<(String, PathBuf)>,
     pub metadata_strings: Vec<(String, String)>,
+    /// Store the mode and ownership of the input file as archive metadata.
+    pub store_metadata: bool,
+    /// PKCS#8 PEM file with ed25519 key to sign the archive header with.
+    pub sign_key: Option<PathBuf>,
+    /// Write the header at the end of the archive, skipping the temp file.
+    pub header_at_end: bool,
+    /// Compress the chunk dictionary of the header.
+    pub compress_header: bool,
+    /// Base archive to create a delta archive against, storing only chunks not in the base.
+    pub base: Option<PathBuf>,
+    /// Replace the level of `compression` with the highest level estimated to compress the
+    /// input within this time.
+    pub compress_time_budget: Option<Duration>,
+    /// Record a fixed version string in the header, see
+    /// `bitar::api::compress::CreateArchiveOptions::reproducible`.
+    pub reproducible: bool,
+    /// How to report the progress of compressing.
+    pub progress: ProgressFormat,
+}
+
+// Open the inputs to compress, chained into a single reader as if concatenated.
+//
+// Returns the reader and the total size of the inputs, if known up front.
+async fn open_inputs(
+    inputs: &[PathBuf],
+) -> Result<(Box<dyn AsyncRead + Unpin + Send>, Option<u64>)> {
+    if inputs.is_empty() {
+        if std::io::stdin().is_terminal() {
+            return Err(anyhow!("Missing input"));
+        }
+        return Ok((Box::new(tokio::io::stdin()), None));
+    }
+    let mut reader: Box<dyn AsyncRead + Unpin + Send> = Box::new(tokio::io::empty());
+    let mut total_size = Some(0);
+    for path in inputs {
+        if path == Path::new("-") {
+            reader = Box::new(reader.chain(tokio::io::stdin()));
+            total_size = None;
+            continue;
+        }
+        let input = File::open(path)
+            .await
+            .context(format!("Failed to open input file {}", path.display()))?;
+        // Size is only known up front for regular files.
+        let input_size = input
+            .metadata()
+            .await
+            .ok()
+            .filter(|metadata| metadata.is_file())
+            .map(|metadata| metadata.len());
+        total_size = total_size.zip(input_size).map(|(total, size)| total + size);
+        reader = Box::new(reader.chain(input));
+    }
+    Ok((reader, total_size))
+}
+
+// Create the temp file for chunk data. The file is unnamed, or removed when closed, so it's
+// cleaned up whether compress succeeds or fails.
+fn create_temp_file(opts: &Options) -> Result<std::fs::File> {
+    let dir = match (&opts.temp_dir, opts.output.parent()) {
+        (Some(dir), _) => dir.as_path(),
+        (None, Some(parent)) if parent != Path::new("") => parent,
+        (None, _) => Path::new("."),
+    };
+    tempfile::tempfile_in(dir).context(format!("Failed to create temp file in {}", dir.display()))
+}
+
+const LEVEL_SAMPLE_COUNT: u64 = 8;
+const LEVEL_SAMPLE_SIZE: u64 = 256 * 1024;
+
+// Read samples spread evenly over the inputs, as if concatenated.
+fn read_level_samples(inputs: &[PathBuf], input_size: u64) -> Result<Vec<bitar::Chunk>> {
+    let sample_size = LEVEL_SAMPLE_SIZE.min(input_size);
+    let sample_count = input_size
+        .checked_div(sample_size)
+        .map_or(0, |count| count.min(LEVEL_SAMPLE_COUNT));
+    let mut samples = Vec::new();
+    for i in 0..sample_count {
+        let mut offset = (input_size - sample_size) / sample_count.max(2).saturating_sub(1) * i;
+        let mut sample = Vec::with_capacity(sample_size as usize);
+        for path in inputs {
+            let mut input = std::fs::File::open(path)
+                .context(format!("Failed to open input file {}", path.display()))?;
+            let size = input.metadata()?.len();
+            if offset >= size {
+                offset -= size;
+                continue;
+            }
+            input.seek(std::io::SeekFrom::Start(offset))?;
+            input
+                .take(sample_size - sample.len() as u64)
+                .read_to_end(&mut sample)
+                .context(format!("Failed to read input file {}", path.display()))?;
+            offset = 0;
+            if sample.len() as u64 == sample_size {
+                break;
+            }
+        }
+        samples.push(bitar::Chunk::from(sample));
+    }
+    Ok(samples)
+}
+
+// Pick the highest level of the compression estimated to compress the input within the time
+// budget.
+//
+// The samples are compressed at each level, from the lowest and up, and the time it took is
+// scaled by the input size over the sampled size and divided by the number of threads
+// compressing in parallel. Higher levels are assumed to be slower, so sampling stops at the
+// first level over budget. The lowest level is used if no level fits.
+fn select_compression_level(
+    samples: &[bitar::Chunk],
+    input_size: u64,
+    compression: Compression,
+    time_budget: Duration,
+    num_threads: usize,
+) -> Result<Compression> {
+    let sampled_size: u64 = samples.iter().map(|sample| sample.len() as u64).sum();
+    let mut selected = compression.with_level(1)?;
+    for level in 1..=compression.algorithm().max_level() {
+        let candidate = compression.with_level(level)?;
+        let start = Instant::now();
+        for sample in samples {
+            sample.clone().compress(Some(candidate))?;
+        }
+        let estimate = if sampled_size == 0 {
+            Duration::ZERO
+        } else {
+            start
+                .elapsed()
+                .mul_f64(input_size as f64 / sampled_size as f64)
+                / num_threads.max(1) as u32
+        };
+        debug!(
+            "Estimated time to compress input using {}: {:?}",
+            candidate, estimate
+        );
+        if estimate > time_budget {
+            if level == 1 {
+                warn!(
+                    "No compression level is estimated to compress the input within {:?}",
+                    time_budget
+                );
+            }
+            break;
+        }
+        selected = candidate;
+    }
+    Ok(selected)
+}
+
+async fn auto_compression_level(
+    opts: &Options,
+    compression: Compression,
+    time_budget: Duration,
+) -> Result<Compression> {
+    let (_, input_size) = open_inputs(&opts.inputs).await?;
+    let input_size = input_size
+        .ok_or_else(|| anyhow!("Auto compression level requires input files of known size"))?;
+    let inputs = opts.inputs.clone();
+    let num_threads = opts.num_threads;
+    let compression = tokio::task::spawn_blocking(move || {
+        let samples = read_level_samples(&inputs, input_size)?;
+        select_compression_level(&samples, input_size, compression, time_budget, num_threads)
+    })
+    .await??;
+    info!(
+        "Using {} to compress {} within {:?}",
+        compression,
+        human_size!(input_size),
+        time_budget
+    );
+    Ok(compression)
 }
 
 pub async fn compress_cmd(opts: Options) -> Result<()> {
-    let chunker_config = opts.chunker_config.clone();
-    let compression = opts.compression;
+    if opts.store_metadata && (opts.inputs.len() != 1 || opts.inputs[0] == Path::new("-")) {
+        return Err(anyhow!(
+            "Storing file metadata requires a single input file"
+        ));
+    }
+    let opts = match (opts.compress_time_budget, opts.compression) {
+        (Some(time_budget), Some(compression)) => Options {
+            compression: Some(auto_compression_level(&opts, compression, time_budget).await?),
+            ..opts
+        },
+        _ => opts,
+    };
+    let signing_key = opts
+        .sign_key
+        .as_deref()
+        .map(signature::read_signing_key)
+        .transpose()?;
+    let base = match &opts.base {
+        Some(path) => Some(BaseArchive::open(path, &opts).await?),
+        None => None,
+    };
     let mut output_file = std::fs::OpenOptions::new()
         .write(true)
         .read(true)
@@ -171,64 +575,41 @@ pub async fn compress_cmd(opts: Options) -> Result<()> {
             opts.output.display()
         ))?;
 
+    // With the header at the end chunk data is written straight to the output, otherwise to
+    // a temp file which is appended to the output after the header.
+    let mut temp_file = if opts.header_at_end {
+        output_file
+            .write_all(bitar::header::TRAILER_MAGIC)
+            .context(format!(
+                "Failed to write to output file {}",
+                opts.output.display()
+            ))?;
+        None
+    } else {
+        Some(create_temp_file(&opts)?)
+    };
+    let mut chunk_output = File::from_std(
+        temp_file
+            .as_ref()
+            .unwrap_or(&output_file)
+            .try_clone()
+            .context("Failed to clone file handle")?,
+    );
+
+    let (input, input_size) = open_inputs(&opts.inputs).await?;
     let (source_hash, archive_chunks, source_size, chunk_order) =
-        if let Some(input_path) = opts.input {
-            chunk_input(
-                File::open(&input_path).await.context(format!(
-                    "Failed to open input file {}",
-                    input_path.display()
-                ))?,
-                &chunker_config,
-                compression,
-                &opts.temp_file,
-                opts.hash_length,
-                opts.num_chunk_buffers,
-            )
-            .await?
-        } else if !std::io::stdin().is_terminal() {
-            // Read source from stdin
-            chunk_input(
-                tokio::io::stdin(),
-                &chunker_config,
-                compression,
-                &opts.temp_file,
-                opts.hash_length,
-                opts.num_chunk_buffers,
-            )
-            .await?
-        } else {
-            return Err(anyhow!("Missing input"));
-        };
+        chunk_input(input, &opts, &mut chunk_output, input_size, base.as_ref()).await?;
+    drop(chunk_output);
 
-    let chunker_params = match opts.chunker_config {
-        chunker::Config::BuzHash(hash_config) => dict::ChunkerParameters {
-            chunk_filter_bits: hash_config.filter_bits.bits(),
-            min_chunk_size: hash_config.min_chunk_size as u32,
-            max_chunk_size: hash_config.max_chunk_size as u32,
-            rolling_hash_window_size: hash_config.window_size as u32,
-            chunk_hash_length: opts.hash_length as u32,
-            chunking_algorithm: dict::chunker_parameters::ChunkingAlgorithm::Buzhash as i32,
-        },
-        chunker::Config::RollSum(hash_config) => dict::ChunkerParameters {
-            chunk_filter_bits: hash_config.filter_bits.bits(),
-            min_chunk_size: hash_config.min_chunk_size as u32,
-            max_chunk_size: hash_config.max_chunk_size as u32,
-            rolling_hash_window_size: hash_config.window_size as u32,
-            chunk_hash_length: opts.hash_length as u32,
-            chunking_algorithm: dict::chunker_parameters::ChunkingAlgorithm::Rollsum as i32,
-        },
-        chunker::Config::FixedSize(chunk_size) => dict::ChunkerParameters {
-            min_chunk_size: 0,
-            chunk_filter_bits: 0,
-            rolling_hash_window_size: 0,
-            max_chunk_size: chunk_size as u32,
-            chunk_hash_length: opts.hash_length as u32,
-            chunking_algorithm: dict::chunker_parameters::ChunkingAlgorithm::FixedSize as i32,
-        },
-    };
+    let chunker_params =
+        chunker_params(&opts.chunker_config, opts.hash_algorithm, opts.hash_length);
 
     // Construct custom metadata hashmap
-    let mut metadata = BTreeMap::new();
+    let mut metadata = if opts.store_metadata {
+        file_metadata::read(&opts.inputs[0])?
+    } else {
+        BTreeMap::new()
+    };
     for (key, value) in opts.metadata_strings {
         metadata.insert(key, value.into());
     }
@@ -241,39 +622,408 @@ pub async fn compress_cmd(opts: Options) -> Result<()> {
     // Build the final archive
     let file_header = dict::ChunkDictionary {
         rebuild_order: chunk_order.iter().map(|&index| index as u32).collect(),
-        application_version: PKG_VERSION.to_string(),
+        application_version: if opts.reproducible {
+            bitar::api::compress::REPRODUCIBLE_VERSION
+        } else {
+            PKG_VERSION
+        }
+        .to_string(),
         chunk_descriptors: archive_chunks,
         source_checksum: source_hash,
         chunk_compression: Some(opts.compression.into()),
         source_total_size: source_size,
         chunker_params: Some(chunker_params),
         metadata,
+        base_header_checksum: base
+            .map(|base| base.header_checksum.to_vec())
+            .unwrap_or_default(),
     };
-    let header_buf = bitar::header::build(&file_header, None)?;
-    output_file.write_all(&header_buf).context(format!(
-        "Failed to write header to output file {}",
-        opts.output.display()
-    ))?;
-    {
-        let mut temp_file = std::fs::File::open(&opts.temp_file).context(format!(
-            "Failed to open temp file {}",
-            opts.temp_file.display()
+    if opts.header_at_end {
+        let chunk_data_size = file_header
+            .chunk_descriptors
+            .iter()
+            .map(|descriptor| descriptor.archive_size as u64)
+            .sum();
+        let build_trailer = if opts.compress_header {
+            bitar::header::build_compressed_trailer
+        } else {
+            bitar::header::build_trailer
+        };
+        let trailer = build_trailer(&file_header, chunk_data_size, signing_key.as_ref())?;
+        output_file.write_all(&trailer).context(format!(
+            "Failed to write header to output file {}",
+            opts.output.display()
         ))?;
-        std::io::copy(&mut temp_file, &mut output_file).context(format!(
-            "Failed to copy from temp file {} to output file {}",
-            opts.temp_file.display(),
+    } else {
+        let build_header = if opts.compress_header {
+            bitar::header::build_compressed
+        } else {
+            bitar::header::build
+        };
+        let header_buf = build_header(&file_header, None, signing_key.as_ref())?;
+        output_file.write_all(&header_buf).context(format!(
+            "Failed to write header to output file {}",
             opts.output.display()
         ))?;
+        let mut temp_file = temp_file.take().unwrap();
+        temp_file
+            .rewind()
+            .and_then(|()| std::io::copy(&mut temp_file, &mut output_file))
+            .context(format!(
+                "Failed to copy from temp file to output file {}",
+                opts.output.display()
+            ))?;
     }
-    std::fs::remove_file(&opts.temp_file).context(format!(
-        "Failed to remove temporary file {}",
-        opts.temp_file.display()
-    ))?;
+    let archive_size = output_file
+        .metadata()
+        .context(format!("Failed to stat {}", opts.output.display()))?
+        .len();
     drop(output_file);
     {
         // Print archive info
         let reader = IoReader::new(File::open(opts.output).await?);
         info_cmd::print_archive_reader(reader).await?;
     }
+    progress::report_done(
+        opts.progress,
+        &[("source_size", source_size), ("archive_size", archive_size)],
+    );
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::ReadBuf;
+
+    // Reader returning some data and then failing.
+    struct FailingReader {
+        remaining: usize,
+    }
+
+    impl AsyncRead for FailingReader {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            if self.remaining == 0 {
+                return Poll::Ready(Err(std::io::ErrorKind::BrokenPipe.into()));
+            }
+            let n = self.remaining.min(buf.remaining());
+            buf.put_slice(&vec![0xaa; n]);
+            self.remaining -= n;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn chunk_input_read_error() {
+        let mut output = Vec::new();
+        let opts = Options {
+            force_create: false,
+            inputs: Vec::new(),
+            output: "out.cba".into(),
+            temp_dir: None,
+            hash_length: 64,
+            hash_algorithm: HashAlgorithm::Blake2b,
+            chunker_config: chunker::Config::RollSum(chunker::FilterConfig::default()),
+            compression: None,
+            auto_compression: Vec::new(),
+            num_chunk_buffers: 2,
+            num_threads: 1,
+            metadata_files: Vec::new(),
+            metadata_strings: Vec::new(),
+            store_metadata: false,
+            sign_key: None,
+            header_at_end: false,
+            compress_header: false,
+            base: None,
+            compress_time_budget: None,
+            reproducible: false,
+            progress: ProgressFormat::Human,
+            max_chunk_memory: None,
+        };
+        let input = FailingReader {
+            remaining: 1024 * 1024,
+        };
+        let err = chunk_input(input, &opts, &mut output, None, None)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Failed to read input");
+        assert_eq!(
+            err.root_cause()
+                .downcast_ref::<std::io::Error>()
+                .unwrap()
+                .kind(),
+            std::io::ErrorKind::BrokenPipe
+        );
+    }
+
+    #[tokio::test]
+    async fn chunk_input_truncated_hash_collision() {
+        let mut output = Vec::new();
+        let opts = Options {
+            force_create: false,
+            inputs: Vec::new(),
+            output: "out.cba".into(),
+            temp_dir: None,
+            hash_length: 1,
+            hash_algorithm: HashAlgorithm::Blake2b,
+            chunker_config: chunker::Config::FixedSize(64),
+            compression: None,
+            auto_compression: Vec::new(),
+            num_chunk_buffers: 2,
+            num_threads: 1,
+            metadata_files: Vec::new(),
+            metadata_strings: Vec::new(),
+            store_metadata: false,
+            sign_key: None,
+            header_at_end: false,
+            compress_header: false,
+            base: None,
+            compress_time_budget: None,
+            reproducible: false,
+            progress: ProgressFormat::Human,
+            max_chunk_memory: None,
+        };
+        // More different chunks than there are 1 byte hashes.
+        let input: Vec<u8> = (0..300u32)
+            .flat_map(|i| i.to_le_bytes().repeat(16))
+            .collect();
+        let err = chunk_input(&input[..], &opts, &mut output, None, None)
+            .await
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Different chunks share the truncated chunk hash"));
+    }
+
+    #[tokio::test]
+    async fn compress_concatenated_inputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let first: Vec<u8> = (0..1500u32).map(|v| (v % 251) as u8).collect();
+        let second: Vec<u8> = (0..2500u32).map(|v| (v % 241) as u8).collect();
+        std::fs::write(dir.path().join("first"), &first).unwrap();
+        std::fs::write(dir.path().join("second"), &second).unwrap();
+        let output = dir.path().join("out.cba");
+        compress_cmd(Options {
+            force_create: false,
+            inputs: vec![dir.path().join("first"), dir.path().join("second")],
+            output: output.clone(),
+            temp_dir: None,
+            hash_length: 64,
+            hash_algorithm: HashAlgorithm::Blake2b,
+            chunker_config: chunker::Config::FixedSize(1000),
+            compression: None,
+            auto_compression: Vec::new(),
+            num_chunk_buffers: 2,
+            num_threads: 1,
+            metadata_files: Vec::new(),
+            metadata_strings: Vec::new(),
+            store_metadata: false,
+            sign_key: None,
+            header_at_end: false,
+            compress_header: false,
+            base: None,
+            compress_time_budget: None,
+            reproducible: false,
+            progress: ProgressFormat::Human,
+            max_chunk_memory: None,
+        })
+        .await
+        .unwrap();
+
+        let concatenated = [first, second].concat();
+        let archive = Archive::try_init(IoReader::new(File::open(&output).await.unwrap()))
+            .await
+            .unwrap();
+        assert_eq!(archive.total_source_size(), concatenated.len() as u64);
+        assert_eq!(
+            archive.source_checksum(),
+            &HashSum::from(&Blake2b512::digest(&concatenated)[..])
+        );
+        // Chunk boundaries are the ones of the concatenated inputs, the second chunk spans
+        // the join.
+        let chunks: Vec<(u64, HashSum)> = archive
+            .iter_source_chunks()
+            .map(|(offset, descriptor)| (offset, descriptor.checksum.clone()))
+            .collect();
+        let expected: Vec<(u64, HashSum)> = concatenated
+            .chunks(1000)
+            .enumerate()
+            .map(|(index, data)| {
+                (
+                    (index * 1000) as u64,
+                    bitar::Chunk::from(data.to_vec()).verify().hash().clone(),
+                )
+            })
+            .collect();
+        assert_eq!(chunks, expected);
+    }
+
+    #[tokio::test]
+    async fn compress_reproducible() {
+        let dir = tempfile::tempdir().unwrap();
+        let input: Vec<u8> = (0..200_000u32)
+            .map(|v| (v % 7000 * 7 % 251) as u8)
+            .collect();
+        std::fs::write(dir.path().join("input"), &input).unwrap();
+        let options = |output: &str, header_at_end: bool| Options {
+            force_create: false,
+            inputs: vec![dir.path().join("input")],
+            output: dir.path().join(output),
+            temp_dir: None,
+            hash_length: 64,
+            hash_algorithm: HashAlgorithm::Blake2b,
+            chunker_config: chunker::Config::RollSum(chunker::FilterConfig::default()),
+            compression: Some(Compression::brotli(6).unwrap()),
+            auto_compression: Vec::new(),
+            num_chunk_buffers: 4,
+            num_threads: 2,
+            metadata_files: Vec::new(),
+            metadata_strings: vec![
+                ("b".to_string(), "2".to_string()),
+                ("a".to_string(), "1".to_string()),
+            ],
+            store_metadata: false,
+            sign_key: None,
+            header_at_end,
+            compress_header: false,
+            base: None,
+            compress_time_budget: None,
+            reproducible: true,
+            progress: ProgressFormat::Human,
+            max_chunk_memory: None,
+        };
+        for header_at_end in [false, true] {
+            compress_cmd(options("1.cba", header_at_end)).await.unwrap();
+            compress_cmd(options("2.cba", header_at_end)).await.unwrap();
+            let archive = std::fs::read(dir.path().join("1.cba")).unwrap();
+            assert_eq!(archive, std::fs::read(dir.path().join("2.cba")).unwrap());
+            let archive = Archive::try_init(IoReader::new(std::io::Cursor::new(archive)))
+                .await
+                .unwrap();
+            assert_eq!(
+                archive.built_with_version(),
+                bitar::api::compress::REPRODUCIBLE_VERSION
+            );
+            std::fs::remove_file(dir.path().join("1.cba")).unwrap();
+            std::fs::remove_file(dir.path().join("2.cba")).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn compress_temp_dir_cleaned_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let temp_dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("input"), vec![7u8; 10_000]).unwrap();
+        let options = |input: &str, output: &str| Options {
+            force_create: false,
+            inputs: vec![dir.path().join(input)],
+            output: dir.path().join(output),
+            temp_dir: Some(temp_dir.path().to_path_buf()),
+            hash_length: 64,
+            hash_algorithm: HashAlgorithm::Blake2b,
+            chunker_config: chunker::Config::FixedSize(1000),
+            compression: None,
+            auto_compression: Vec::new(),
+            num_chunk_buffers: 2,
+            num_threads: 1,
+            metadata_files: Vec::new(),
+            metadata_strings: Vec::new(),
+            store_metadata: false,
+            sign_key: None,
+            header_at_end: false,
+            compress_header: false,
+            base: None,
+            compress_time_budget: None,
+            reproducible: false,
+            progress: ProgressFormat::Human,
+            max_chunk_memory: None,
+        };
+        let temp_dir_is_empty = || std::fs::read_dir(temp_dir.path()).unwrap().next().is_none();
+
+        compress_cmd(options("input", "ok.cba")).await.unwrap();
+        assert!(temp_dir_is_empty());
+        let archive = Archive::try_init(IoReader::new(
+            File::open(dir.path().join("ok.cba")).await.unwrap(),
+        ))
+        .await
+        .unwrap();
+        assert_eq!(archive.total_source_size(), 10_000);
+
+        // Reading a directory fails after the temp file is created.
+        std::fs::create_dir(dir.path().join("input_dir")).unwrap();
+        compress_cmd(options("input_dir", "failed.cba"))
+            .await
+            .unwrap_err();
+        assert!(temp_dir_is_empty());
+        // Nor is anything left next to the output.
+        let mut names: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["failed.cba", "input", "input_dir", "ok.cba"]);
+
+        // A missing temp dir is reported.
+        let mut opts = options("input", "missing.cba");
+        opts.temp_dir = Some(dir.path().join("no_such_dir"));
+        let err = compress_cmd(opts).await.unwrap_err();
+        assert!(err.to_string().starts_with("Failed to create temp file"));
+    }
+
+    #[test]
+    fn read_level_samples_spread_over_inputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let inputs = vec![dir.path().join("a"), dir.path().join("b")];
+        let size = LEVEL_SAMPLE_SIZE as usize * 4;
+        std::fs::write(&inputs[0], vec![1u8; size + 10]).unwrap();
+        std::fs::write(&inputs[1], vec![2u8; size - 10]).unwrap();
+        let samples = read_level_samples(&inputs, size as u64 * 2).unwrap();
+        assert_eq!(samples.len(), LEVEL_SAMPLE_COUNT as usize);
+        assert!(samples
+            .iter()
+            .all(|sample| sample.len() == LEVEL_SAMPLE_SIZE as usize));
+        assert!(samples[0].data().iter().all(|&b| b == 1));
+        assert!(samples[7].data().iter().all(|&b| b == 2));
+        // A sample spanning both inputs.
+        let spanning = samples
+            .iter()
+            .find(|sample| sample.data()[0] != sample.data()[sample.len() - 1]);
+        assert!(spanning.is_some());
+
+        let samples = read_level_samples(&inputs[1..], 1000).unwrap();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].len(), 1000);
+    }
+
+    #[test]
+    fn select_compression_level_within_budget() {
+        let samples = vec![bitar::Chunk::from(
+            (0..16 * 1024u32)
+                .map(|i| (i % 251) as u8)
+                .collect::<Vec<u8>>(),
+        )];
+        let compression = Compression::brotli(6).unwrap();
+        assert_eq!(
+            select_compression_level(
+                &samples,
+                1024 * 1024,
+                compression,
+                Duration::from_secs(3600),
+                1
+            )
+            .unwrap(),
+            Compression::brotli(11).unwrap()
+        );
+        assert_eq!(
+            select_compression_level(&samples, 1024 * 1024, compression, Duration::ZERO, 1)
+                .unwrap(),
+            Compression::brotli(1).unwrap()
+        );
+    }
+}
@@ -0,0 +1,143 @@
+use anyhow::{Context, Result};
+use log::*;
+use tokio::fs::File;
+
+use crate::clone_cmd::InputArchive;
+use crate::{human_size, signature};
+use bitar::analyze::SharedStats;
+use bitar::archive_reader::{ArchiveReader, IoReader};
+use bitar::Archive;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// Local file or URL to read archive A from.
+    pub archive_a: InputArchive,
+    /// Local file or URL to read archive B from.
+    pub archive_b: InputArchive,
+}
+
+async fn init_archive<R>(input: &InputArchive, reader: R) -> Result<Archive<R>>
+where
+    R: ArchiveReader,
+    R::Error: std::error::Error + Send + Sync + 'static,
+{
+    signature::init_archive(reader, None, true)
+        .await
+        .context(format!("Failed to read archive at {}", input.source()))
+}
+
+// Compare archive A with archive B, opening B using the reader for its input.
+async fn compare_with_b<A>(opts: &Options, a: &Archive<A>) -> Result<SharedStats> {
+    let input = &opts.archive_b;
+    Ok(match input {
+        InputArchive::Local(path) => {
+            let reader = IoReader::new(
+                File::open(path)
+                    .await
+                    .context(format!("Failed to open {}", path.display()))?,
+            );
+            a.shared_chunks(&init_archive(input, reader).await?)
+        }
+        InputArchive::Remote(remote) => {
+            a.shared_chunks(&init_archive(input, remote.reader()?).await?)
+        }
+        #[cfg(feature = "object-store")]
+        InputArchive::ObjectStore(url) => {
+            let reader = crate::clone_cmd::object_store_reader(url)?;
+            a.shared_chunks(&init_archive(input, reader).await?)
+        }
+    })
+}
+
+// Compare the chunks of archive A and B, reading only the archive headers.
+async fn shared_stats(opts: &Options) -> Result<SharedStats> {
+    let input = &opts.archive_a;
+    match input {
+        InputArchive::Local(path) => {
+            let reader = IoReader::new(
+                File::open(path)
+                    .await
+                    .context(format!("Failed to open {}", path.display()))?,
+            );
+            compare_with_b(opts, &init_archive(input, reader).await?).await
+        }
+        InputArchive::Remote(remote) => {
+            compare_with_b(opts, &init_archive(input, remote.reader()?).await?).await
+        }
+        #[cfg(feature = "object-store")]
+        InputArchive::ObjectStore(url) => {
+            let reader = crate::clone_cmd::object_store_reader(url)?;
+            compare_with_b(opts, &init_archive(input, reader).await?).await
+        }
+    }
+}
+
+fn percent_of(part: u64, total: u64) -> f64 {
+    if total > 0 {
+        part as f64 / total as f64 * 100.0
+    } else {
+        100.0
+    }
+}
+
+pub async fn diff_archives_cmd(opts: Options) -> Result<()> {
+    let stats = shared_stats(&opts).await?;
+    let (a, b) = (opts.archive_a.source(), opts.archive_b.source());
+    let size_a = stats.shared_bytes + stats.unique_bytes;
+    let size_b = stats.shared_bytes + stats.other_unique_bytes;
+    info!(
+        "Chunks shared: {} (size: {}, {:.1}% of {}, {:.1}% of {})",
+        stats.shared_chunks,
+        human_size!(stats.shared_bytes),
+        percent_of(stats.shared_bytes, size_a),
+        a,
+        percent_of(stats.shared_bytes, size_b),
+        b
+    );
+    info!("Only in {}: {}", a, human_size!(stats.unique_bytes));
+    info!("Only in {}: {}", b, human_size!(stats.other_unique_bytes));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitar::chunker;
+
+    async fn create_archive(path: &std::path::Path, blocks: &[u8]) {
+        let source: Vec<u8> = blocks.iter().flat_map(|&block| [block; 1000]).collect();
+        let options = bitar::api::compress::CreateArchiveOptions {
+            chunker_config: chunker::Config::FixedSize(1000),
+            ..Default::default()
+        };
+        bitar::api::compress::create_archive(
+            &source[..],
+            File::create(path).await.unwrap(),
+            &options,
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn shared_stats_of_local_archives() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path_a, path_b) = (dir.path().join("a.cba"), dir.path().join("b.cba"));
+        create_archive(&path_a, &[0, 1, 2, 3]).await;
+        create_archive(&path_b, &[3, 2, 4]).await;
+        let opts = Options {
+            archive_a: InputArchive::Local(path_a),
+            archive_b: InputArchive::Local(path_b),
+        };
+        assert_eq!(
+            shared_stats(&opts).await.unwrap(),
+            SharedStats {
+                shared_chunks: 2,
+                shared_bytes: 2000,
+                unique_bytes: 2000,
+                other_unique_bytes: 1000,
+            }
+        );
+        diff_archives_cmd(opts).await.unwrap();
+    }
+}
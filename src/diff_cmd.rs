@@ -1,12 +1,16 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use blake2::{Blake2b512, Digest};
 use futures_util::StreamExt;
 use log::*;
 use std::collections::{HashMap, HashSet};
+use std::io::Write;
 use std::path::{Path, PathBuf};
 use tokio::fs::File;
+use tokio::io::AsyncReadExt;
 
+use crate::patch;
 use crate::{human_size, info_cmd};
-use bitar::{chunker, Compression, HashSum};
+use bitar::{chunker, ChunkIndex, Compression, HashSum};
 
 #[derive(Clone, Debug)]
 struct ChunkDescriptor {
@@ -111,7 +115,8 @@ fn print_info(path: &Path, result: &ChunkerResult, diff: &[HashSum]) {
         .iter()
         .map(|d| d.1.source_size as u64)
         .sum::<u64>()
-        / result.descriptors.len() as u64;
+        .checked_div(result.descriptors.len() as u64)
+        .unwrap_or(0);
     info!("{}:", path.display());
     info!(
         "  Chunks: {} (unique {})",
@@ -150,6 +155,167 @@ fn selection_string(
     )
 }
 
+fn chunk_index(result: &ChunkerResult) -> ChunkIndex {
+    let mut index = ChunkIndex::new_empty(HashSum::MAX_LEN);
+    for (hash, descriptor) in &result.descriptors {
+        index.add_chunk(
+            hash.clone(),
+            descriptor.source_size,
+            &descriptor.occurrences,
+        );
+    }
+    index
+}
+
+// Print what it would cost to get B when already having A.
+fn print_transfer_cost(path_a: &Path, a: &ChunkerResult, path_b: &Path, b: &ChunkerResult) {
+    let index_a = chunk_index(a);
+    let mut index_b = chunk_index(b);
+    let (in_place_count, in_place_size) = index_a.strip_chunks_already_in_place(&mut index_b);
+    let to_fetch = index_b.difference(&index_a);
+    let mut fetch_size = 0u64;
+    let mut fetch_compressed_size = 0u64;
+    let mut not_covered_size = 0u64;
+    for (hash, location) in to_fetch.iter_chunks() {
+        let descriptor = b.descriptors.get(hash).unwrap();
+        fetch_size += location.size() as u64;
+        fetch_compressed_size +=
+            descriptor.compressed_size.unwrap_or(descriptor.source_size) as u64;
+        not_covered_size += (location.size() * location.offsets().len()) as u64;
+    }
+    let covered_percent = if b.total_size > 0 {
+        (b.total_size - not_covered_size) as f64 / b.total_size as f64 * 100.0
+    } else {
+        100.0
+    };
+    info!(
+        "Transfer cost of {} when having {}:",
+        path_b.display(),
+        path_a.display()
+    );
+    info!(
+        "  Chunks already in place: {} (size: {})",
+        in_place_count,
+        human_size!(in_place_size)
+    );
+    info!(
+        "  Chunks to fetch: {} (size: {}, compressed size: {})",
+        to_fetch.len(),
+        human_size!(fetch_size),
+        human_size!(fetch_compressed_size)
+    );
+    info!("  Covered by {}: {:.1}%", path_a.display(), covered_percent);
+}
+
+async fn file_checksum(path: &Path) -> Result<HashSum> {
+    let mut file = File::open(path)
+        .await
+        .context(format!("Failed to open {}", path.display()))?;
+    let mut hasher = Blake2b512::new();
+    let mut buffer: Vec<u8> = vec![0; 4 * 1024 * 1024];
+    loop {
+        let rc = file.read(&mut buffer).await?;
+        if rc == 0 {
+            break;
+        }
+        hasher.update(&buffer[0..rc]);
+    }
+    Ok(HashSum::from(&hasher.finalize()[..]))
+}
+
+// Write a patch which applied to A gives B. Chunks of B also in A are copied from A while the
+// data of chunks only in B is stored in the patch.
+async fn write_patch(
+    opts: &Options,
+    a: &ChunkerResult,
+    b: &ChunkerResult,
+    patch_path: &Path,
+) -> Result<()> {
+    let index_a = chunk_index(a);
+    let index_b = chunk_index(b);
+    let mut to_store = index_b.difference(&index_a);
+    let header = patch::Header {
+        base_size: a.total_size,
+        base_checksum: file_checksum(&opts.input_a).await?,
+        target_size: b.total_size,
+        target_checksum: file_checksum(&opts.input_b).await?,
+    };
+    let mut output = std::io::BufWriter::new(
+        std::fs::File::create(patch_path)
+            .context(format!("Failed to create {}", patch_path.display()))?,
+    );
+    patch::write_header(&mut output, &header)?;
+
+    // Chunks of B copied from where first found in A, ordered by where read from A.
+    let mut copies: Vec<patch::Op> = index_b
+        .iter_chunks()
+        .filter_map(|(hash, location)| {
+            Some(patch::Op::Copy {
+                base_offset: index_a.offsets(hash)?.next()?,
+                size: location.size() as u64,
+                target_offsets: location.offsets().to_vec(),
+            })
+        })
+        .collect();
+    copies.sort_by_key(|op| match op {
+        patch::Op::Copy { base_offset, .. } => *base_offset,
+        patch::Op::Data { .. } => 0,
+    });
+    for op in &copies {
+        patch::write_op(&mut output, op)?;
+    }
+
+    // Chunks only in B are stored once, in order of B, with all their offsets in B.
+    let mut stored_chunks = 0;
+    let mut stored_size = 0u64;
+    let mut file = File::open(&opts.input_b)
+        .await
+        .context(format!("Failed to open {}", opts.input_b.display()))?;
+    let mut chunk_stream = opts
+        .chunker_config
+        .new_chunker(&mut file)
+        .map(|result| {
+            tokio::task::spawn_blocking(move || result.map(|(_offset, chunk)| chunk.verify()))
+        })
+        .buffered(opts.num_chunk_buffers);
+    while let Some(result) = chunk_stream.next().await {
+        let verified = result.context("Failed to hash chunk")??;
+        let location = match to_store.remove(verified.hash()) {
+            Some(location) => location,
+            None => continue,
+        };
+        let compression = opts.compression;
+        let source_size = verified.len();
+        let compressed =
+            tokio::task::spawn_blocking(move || verified.chunk().clone().compress(compression))
+                .await?
+                .context("Failed to compress chunk")?;
+        let (compression, data) = compressed.into_inner();
+        stored_chunks += 1;
+        stored_size += data.len() as u64;
+        patch::write_op(
+            &mut output,
+            &patch::Op::Data {
+                compression,
+                source_size,
+                data: data.to_vec(),
+                target_offsets: location.offsets().to_vec(),
+            },
+        )?;
+    }
+    patch::write_end(&mut output)?;
+    output.flush()?;
+    info!(
+        "Wrote patch {} with {} chunks copied from {} and {} chunks stored (size: {})",
+        patch_path.display(),
+        copies.len(),
+        opts.input_a.display(),
+        stored_chunks,
+        human_size!(stored_size)
+    );
+    Ok(())
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Options {
     pub input_a: PathBuf,
@@ -157,6 +323,9 @@ pub struct Options {
     pub chunker_config: chunker::Config,
     pub compression: Option<Compression>,
     pub num_chunk_buffers: usize,
+    pub transfer_cost: bool,
+    /// Write a patch to this file which applied to A gives B.
+    pub patch: Option<PathBuf>,
 }
 
 pub async fn diff_cmd(opts: Options) -> Result<()> {
@@ -215,5 +384,14 @@ pub async fn diff_cmd(opts: Options) -> Result<()> {
     print_info(&opts.input_b, &b, &diff_ba);
     println!();
 
+    if opts.transfer_cost {
+        print_transfer_cost(&opts.input_a, &a, &opts.input_b, &b);
+        println!();
+    }
+
+    if let Some(patch_path) = &opts.patch {
+        write_patch(&opts, &a, &b, patch_path).await?;
+    }
+
     Ok(())
 }
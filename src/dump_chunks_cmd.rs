@@ -0,0 +1,126 @@
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::path::PathBuf;
+use tokio::fs::File;
+
+use crate::clone_cmd::InputArchive;
+use crate::signature;
+use bitar::archive_reader::{ArchiveReader, IoReader};
+use bitar::Archive;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// Comma separated values with a header row.
+    Csv,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// Local file or URL to read archive from.
+    pub input_archive: InputArchive,
+    /// PEM file with ed25519 public key to verify the archive header signature with.
+    pub verify_signature: Option<PathBuf>,
+    pub format: DumpFormat,
+}
+
+// Write one row per chunk in source order, each row written as it's formatted.
+fn write_csv<R, W>(archive: &Archive<R>, mut output: W) -> std::io::Result<()>
+where
+    W: Write,
+{
+    writeln!(
+        output,
+        "source_offset,source_size,archive_offset,archive_size,compression_ratio,hash,repeat"
+    )?;
+    // Chunks already seen in source, by index of chunk descriptor.
+    let mut seen = vec![false; archive.chunk_descriptors().len()];
+    for (index, (source_offset, descriptor)) in archive
+        .source_order()
+        .iter()
+        .zip(archive.iter_source_chunks())
+    {
+        let ratio = if descriptor.archive_size == 0 {
+            0.0
+        } else {
+            descriptor.source_size as f64 / descriptor.archive_size as f64
+        };
+        writeln!(
+            output,
+            "{},{},{},{},{:.3},{},{}",
+            source_offset,
+            descriptor.source_size,
+            descriptor.archive_offset,
+            descriptor.archive_size,
+            ratio,
+            descriptor.checksum,
+            seen[*index]
+        )?;
+        seen[*index] = true;
+    }
+    output.flush()
+}
+
+async fn dump_archive_chunks<R>(opts: &Options, reader: R) -> Result<()>
+where
+    R: ArchiveReader,
+    R::Error: std::error::Error + Send + Sync + 'static,
+{
+    let archive = signature::init_archive(reader, opts.verify_signature.as_deref(), true).await?;
+    let stdout = std::io::stdout();
+    match opts.format {
+        DumpFormat::Csv => write_csv(&archive, std::io::BufWriter::new(stdout.lock())),
+    }
+    .context("Failed to write chunks to stdout")
+}
+
+pub async fn dump_chunks_cmd(opts: Options) -> Result<()> {
+    match opts.input_archive.clone() {
+        InputArchive::Local(path) => {
+            dump_archive_chunks(&opts, IoReader::new(File::open(path).await?)).await
+        }
+        InputArchive::Remote(input) => dump_archive_chunks(&opts, input.reader()?).await,
+        #[cfg(feature = "object-store")]
+        InputArchive::ObjectStore(url) => {
+            dump_archive_chunks(&opts, crate::clone_cmd::object_store_reader(&url)?).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitar::chunker;
+
+    #[tokio::test]
+    async fn csv_marks_repeated_chunks() {
+        // Chunks A, B, A of 1000 bytes each.
+        let source: Vec<u8> = [[1u8; 1000], [2u8; 1000], [1u8; 1000]].concat();
+        let mut archive_data = Vec::new();
+        bitar::api::compress::create_archive(
+            &source[..],
+            &mut archive_data,
+            &bitar::api::compress::CreateArchiveOptions {
+                chunker_config: chunker::Config::FixedSize(1000),
+                compression: None,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let archive = Archive::try_init(IoReader::new(std::io::Cursor::new(archive_data)))
+            .await
+            .unwrap();
+        let mut csv = Vec::new();
+        write_csv(&archive, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let rows: Vec<Vec<&str>> = csv.lines().map(|row| row.split(',').collect()).collect();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[0][0], "source_offset");
+        let source_offsets: Vec<&str> = rows[1..].iter().map(|row| row[0]).collect();
+        assert_eq!(source_offsets, ["0", "1000", "2000"]);
+        let repeats: Vec<&str> = rows[1..].iter().map(|row| row[6]).collect();
+        assert_eq!(repeats, ["false", "false", "true"]);
+        assert_eq!(rows[1][5], rows[3][5]);
+        assert_eq!(rows[1][4], "1.000");
+    }
+}
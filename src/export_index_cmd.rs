@@ -0,0 +1,67 @@
+use anyhow::{Context, Result};
+use log::*;
+use std::io::Write;
+use std::path::PathBuf;
+use tokio::fs::File;
+
+use crate::clone_cmd::InputArchive;
+use crate::{human_size, signature};
+use bitar::api::export_index::{self, IndexFormat};
+use bitar::archive_reader::{ArchiveReader, IoReader};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// Local file or URL to read archive from.
+    pub input_archive: InputArchive,
+    /// PEM file with ed25519 public key to verify the archive header signature with.
+    pub verify_signature: Option<PathBuf>,
+    pub format: IndexFormat,
+    pub output: PathBuf,
+    pub force_create: bool,
+}
+
+async fn export_archive_index<R>(opts: &Options, reader: R) -> Result<()>
+where
+    R: ArchiveReader,
+    R::Error: std::error::Error + Send + Sync + 'static,
+{
+    let archive = signature::init_archive(reader, opts.verify_signature.as_deref(), true).await?;
+    let index = export_index::build(&archive, opts.format).context(format!(
+        "Failed to export index of {}",
+        opts.input_archive.source()
+    ))?;
+    let mut output_file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(opts.force_create)
+        .truncate(opts.force_create)
+        .create_new(!opts.force_create)
+        .open(&opts.output)
+        .context(format!(
+            "Failed to open output file {}",
+            opts.output.display()
+        ))?;
+    output_file.write_all(&index).context(format!(
+        "Failed to write index to {}",
+        opts.output.display()
+    ))?;
+    info!(
+        "Exported index of {} chunks ({}) to {}.",
+        archive.total_chunks(),
+        human_size!(index.len()),
+        opts.output.display()
+    );
+    Ok(())
+}
+
+pub async fn export_index_cmd(opts: Options) -> Result<()> {
+    match opts.input_archive.clone() {
+        InputArchive::Local(path) => {
+            export_archive_index(&opts, IoReader::new(File::open(path).await?)).await
+        }
+        InputArchive::Remote(input) => export_archive_index(&opts, input.reader()?).await,
+        #[cfg(feature = "object-store")]
+        InputArchive::ObjectStore(url) => {
+            export_archive_index(&opts, crate::clone_cmd::object_store_reader(&url)?).await
+        }
+    }
+}
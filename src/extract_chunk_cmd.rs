@@ -0,0 +1,220 @@
+use anyhow::{anyhow, bail, Context, Result};
+use futures_util::StreamExt;
+use log::*;
+use std::io::Write;
+use std::path::PathBuf;
+use tokio::fs::File;
+
+use crate::clone_cmd::InputArchive;
+use crate::{human_size, signature};
+use bitar::archive_reader::{ArchiveReader, IoReader};
+use bitar::{ChunkIndex, HashSum};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// Local file or URL to read archive from.
+    pub input_archive: InputArchive,
+    /// PEM file with ed25519 public key to verify the archive header signature with.
+    pub verify_signature: Option<PathBuf>,
+    /// Hash of the chunk to extract, may be truncated.
+    pub hash: HashSum,
+    pub output: PathBuf,
+    pub force_create: bool,
+}
+
+// Hashes match if one is a prefix of the other, since both the given hash and the archive
+// chunk hashes may be truncated.
+fn hash_matches(checksum: &HashSum, hash: &HashSum) -> bool {
+    let len = checksum.len().min(hash.len());
+    checksum.slice()[..len] == hash.slice()[..len]
+}
+
+async fn extract_archive_chunk<R>(opts: &Options, reader: R) -> Result<()>
+where
+    R: ArchiveReader,
+    R::Error: std::error::Error + Send + Sync + 'static,
+{
+    let mut archive =
+        signature::init_archive(reader, opts.verify_signature.as_deref(), true).await?;
+    let mut matches = archive
+        .chunk_descriptors()
+        .iter()
+        .filter(|descriptor| hash_matches(&descriptor.checksum, &opts.hash));
+    let descriptor = match (matches.next(), matches.next()) {
+        (Some(descriptor), None) => descriptor.clone(),
+        (None, _) => bail!(
+            "Chunk {} not found in {}",
+            opts.hash,
+            opts.input_archive.source()
+        ),
+        (Some(_), Some(_)) => bail!(
+            "Hash {} matches {} chunks of {}, use a longer hash",
+            opts.hash,
+            2 + matches.count(),
+            opts.input_archive.source()
+        ),
+    };
+    if descriptor.in_base {
+        bail!(
+            "Chunk {} is stored in the base archive of {}",
+            descriptor.checksum,
+            opts.input_archive.source()
+        );
+    }
+    info!(
+        "Extracting chunk {} ({} at offset {}, {} in archive)...",
+        descriptor.checksum,
+        human_size!(descriptor.source_size),
+        descriptor.archive_offset,
+        human_size!(descriptor.archive_size)
+    );
+
+    let mut chunk_index = ChunkIndex::new_empty(archive.chunk_hash_length());
+    chunk_index.add_chunk(
+        descriptor.checksum.clone(),
+        descriptor.source_size as usize,
+        &[0],
+    );
+    let compressed = archive
+        .chunk_stream(&chunk_index)
+        .next()
+        .await
+        .ok_or_else(|| anyhow!("Chunk {} was not read", descriptor.checksum))?
+        .context(format!(
+            "Failed to read chunk at offset {}",
+            descriptor.archive_offset
+        ))?;
+    let verified = compressed
+        .decompress()
+        .context(format!(
+            "Failed to decompress chunk at offset {}",
+            descriptor.archive_offset
+        ))?
+        .verify()
+        .context(format!(
+            "Chunk at offset {} failed verification",
+            descriptor.archive_offset
+        ))?;
+
+    let mut output_file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(opts.force_create)
+        .truncate(opts.force_create)
+        .create_new(!opts.force_create)
+        .open(&opts.output)
+        .context(format!(
+            "Failed to open output file {}",
+            opts.output.display()
+        ))?;
+    output_file.write_all(verified.data()).context(format!(
+        "Failed to write chunk to {}",
+        opts.output.display()
+    ))?;
+    info!("Chunk verified and written to {}.", opts.output.display());
+    Ok(())
+}
+
+pub async fn extract_chunk_cmd(opts: Options) -> Result<()> {
+    match opts.input_archive.clone() {
+        InputArchive::Local(path) => {
+            extract_archive_chunk(&opts, IoReader::new(File::open(path).await?)).await
+        }
+        InputArchive::Remote(input) => extract_archive_chunk(&opts, input.reader()?).await,
+        #[cfg(feature = "object-store")]
+        InputArchive::ObjectStore(url) => {
+            extract_archive_chunk(&opts, crate::clone_cmd::object_store_reader(&url)?).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitar::{chunker, Archive};
+
+    // Create an archive of a source made of four different 1000 byte chunks.
+    async fn create_archive(path: &std::path::Path) -> Vec<u8> {
+        let source: Vec<u8> = (0..4000u32).map(|v| (v / 1000 + v % 7) as u8).collect();
+        let options = bitar::api::compress::CreateArchiveOptions {
+            chunker_config: chunker::Config::FixedSize(1000),
+            compression: None,
+            ..Default::default()
+        };
+        bitar::api::compress::create_archive(
+            &source[..],
+            File::create(path).await.unwrap(),
+            &options,
+        )
+        .await
+        .unwrap();
+        source
+    }
+
+    fn options(dir: &tempfile::TempDir, hash: HashSum) -> Options {
+        Options {
+            input_archive: InputArchive::Local(dir.path().join("archive.cba")),
+            verify_signature: None,
+            hash,
+            output: dir.path().join("chunk"),
+            force_create: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn extract_by_truncated_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = create_archive(&dir.path().join("archive.cba")).await;
+        let archive = Archive::try_init(IoReader::new(
+            File::open(dir.path().join("archive.cba")).await.unwrap(),
+        ))
+        .await
+        .unwrap();
+        let (offset, descriptor) = archive.iter_source_chunks().nth(2).unwrap();
+        let hash = HashSum::from(&descriptor.checksum.slice()[..4]);
+        let offset = offset as usize;
+
+        extract_chunk_cmd(options(&dir, hash)).await.unwrap();
+        assert_eq!(
+            std::fs::read(dir.path().join("chunk")).unwrap(),
+            &source[offset..offset + 1000]
+        );
+    }
+
+    #[tokio::test]
+    async fn extract_missing_chunk() {
+        let dir = tempfile::tempdir().unwrap();
+        create_archive(&dir.path().join("archive.cba")).await;
+        let archive = Archive::try_init(IoReader::new(
+            File::open(dir.path().join("archive.cba")).await.unwrap(),
+        ))
+        .await
+        .unwrap();
+        // Flip the first bit of an existing hash.
+        let mut hash = archive.chunk_descriptors()[0].checksum.to_vec();
+        hash[0] ^= 0x80;
+        let err = extract_chunk_cmd(options(&dir, HashSum::from(hash)))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("not found"), "{}", err);
+        assert!(!dir.path().join("chunk").exists());
+    }
+
+    #[tokio::test]
+    async fn extract_corrupt_chunk() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("archive.cba");
+        create_archive(&path).await;
+        let archive = Archive::try_init(IoReader::new(File::open(&path).await.unwrap()))
+            .await
+            .unwrap();
+        let descriptor = archive.chunk_descriptors()[1].clone();
+        let mut data = std::fs::read(&path).unwrap();
+        data[descriptor.archive_offset as usize] ^= 0xff;
+        std::fs::write(&path, data).unwrap();
+
+        let err = extract_chunk_cmd(options(&dir, descriptor.checksum))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("failed verification"), "{}", err);
+    }
+}
@@ -0,0 +1,109 @@
+use anyhow::{anyhow, Context, Result};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Archive metadata key of the file permission bits, stored in octal.
+pub const MODE_KEY: &str = "unix.mode";
+/// Archive metadata key of the file owner user id.
+pub const UID_KEY: &str = "unix.uid";
+/// Archive metadata key of the file owner group id.
+pub const GID_KEY: &str = "unix.gid";
+
+/// Read the mode and ownership of a file as archive metadata.
+#[cfg(target_os = "linux")]
+pub fn read(path: &Path) -> Result<BTreeMap<String, Vec<u8>>> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = std::fs::metadata(path)
+        .context(format!("Failed to read metadata of {}", path.display()))?;
+    Ok(BTreeMap::from([
+        (
+            MODE_KEY.to_string(),
+            format!("{:o}", metadata.mode() & 0o7777).into_bytes(),
+        ),
+        (UID_KEY.to_string(), metadata.uid().to_string().into_bytes()),
+        (GID_KEY.to_string(), metadata.gid().to_string().into_bytes()),
+    ]))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn read(_path: &Path) -> Result<BTreeMap<String, Vec<u8>>> {
+    Err(anyhow!("Storing file metadata is only supported on Linux"))
+}
+
+#[cfg(target_os = "linux")]
+fn parse_value(metadata: &BTreeMap<String, Vec<u8>>, key: &str, radix: u32) -> Result<Option<u32>> {
+    metadata
+        .get(key)
+        .map(|value| {
+            std::str::from_utf8(value)
+                .ok()
+                .and_then(|value| u32::from_str_radix(value, radix).ok())
+                .ok_or_else(|| anyhow!("Invalid {} in archive metadata", key))
+        })
+        .transpose()
+}
+
+/// Restore the mode and ownership stored in archive metadata to a file.
+///
+/// Returns false if the archive holds none of the keys. Ownership is changed before the
+/// mode since changing owner may clear the setuid and setgid bits.
+#[cfg(target_os = "linux")]
+pub fn restore(path: &Path, metadata: &BTreeMap<String, Vec<u8>>) -> Result<bool> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = parse_value(metadata, MODE_KEY, 8)?;
+    let uid = parse_value(metadata, UID_KEY, 10)?;
+    let gid = parse_value(metadata, GID_KEY, 10)?;
+    if uid.is_some() || gid.is_some() {
+        std::os::unix::fs::chown(path, uid, gid)
+            .context(format!("Failed to change owner of {}", path.display()))?;
+    }
+    if let Some(mode) = mode {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+            .context(format!("Failed to change mode of {}", path.display()))?;
+    }
+    Ok(mode.is_some() || uid.is_some() || gid.is_some())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn restore(_path: &Path, _metadata: &BTreeMap<String, Vec<u8>>) -> Result<bool> {
+    Err(anyhow!(
+        "Restoring file metadata is only supported on Linux"
+    ))
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn read_and_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let (source, target) = (dir.path().join("source"), dir.path().join("target"));
+        std::fs::write(&source, b"source").unwrap();
+        std::fs::write(&target, b"target").unwrap();
+        std::fs::set_permissions(&source, std::fs::Permissions::from_mode(0o640)).unwrap();
+
+        let metadata = read(&source).unwrap();
+        assert_eq!(metadata[MODE_KEY], b"640");
+        assert!(restore(&target, &metadata).unwrap());
+        let mode = std::fs::metadata(&target).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o640);
+    }
+
+    #[test]
+    fn restore_without_keys() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        assert!(!restore(file.path(), &BTreeMap::new()).unwrap());
+    }
+
+    #[test]
+    fn restore_invalid_mode() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let metadata = BTreeMap::from([(MODE_KEY.to_string(), b"rw-r--r--".to_vec())]);
+        assert_eq!(
+            restore(file.path(), &metadata).unwrap_err().to_string(),
+            "Invalid unix.mode in archive metadata"
+        );
+    }
+}
@@ -1,13 +1,15 @@
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use log::*;
 use std::io::Write;
+use std::path::PathBuf;
 use tokio::fs::File;
 
-use crate::clone_cmd::InputArchive;
-use crate::human_size;
+use crate::clone_cmd::{CloneError, InputArchive};
+use crate::string_utils::hex_str_to_vec;
+use crate::{human_size, signature};
 use bitar::{
-    archive_reader::{ArchiveReader, HttpReader, IoReader},
-    chunker, Archive,
+    archive_reader::{ArchiveReader, IoReader},
+    chunker, Archive, HashSum,
 };
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -16,6 +18,108 @@ pub struct Options {
     pub metadata_key: Option<String>,
     /// Local file or URL to read archive from.
     pub input_archive: InputArchive,
+    /// PEM file with ed25519 public key to verify the archive header signature with.
+    pub verify_signature: Option<PathBuf>,
+    /// Skip verifying the header against its internal checksum, see `--no-verify-header`.
+    pub no_verify_header: bool,
+}
+
+/// Path of the header checksum file of a local archive, `<archive>.b2`.
+fn checksum_file_path(input: &InputArchive) -> Option<PathBuf> {
+    match input {
+        InputArchive::Local(path) => {
+            let mut file_name = path.file_name()?.to_os_string();
+            file_name.push(".b2");
+            Some(path.with_file_name(file_name))
+        }
+        _ => None,
+    }
+}
+
+/// Read the header checksum from the checksum file of a local archive, if there is one.
+///
+/// The file holds the hex header checksum printed by `bita info`, optionally followed by
+/// whitespace and anything else (eg the archive name).
+pub fn read_checksum_file(input: &InputArchive) -> Result<Option<(PathBuf, HashSum)>> {
+    let Some(path) = checksum_file_path(input) else {
+        return Ok(None);
+    };
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => {
+            return Err(err).context(format!("Failed to read checksum file {}", path.display()))
+        }
+    };
+    let checksum = content
+        .split_whitespace()
+        .next()
+        .filter(|hex| hex.len() == HashSum::MAX_LEN * 2)
+        .and_then(|hex| hex_str_to_vec(hex).ok())
+        .map(HashSum::from)
+        .ok_or_else(|| anyhow!("Invalid checksum file {}", path.display()))?;
+    Ok(Some((path, checksum)))
+}
+
+/// Write the header checksum file of a local archive, as read by `read_checksum_file`.
+///
+/// The file holds the hex header checksum and the archive file name, separated by two
+/// spaces like the output of `b2sum`. Returns the path of the written file.
+pub fn write_checksum_file(
+    input: &InputArchive,
+    checksum: &HashSum,
+    force_create: bool,
+) -> Result<PathBuf> {
+    let (Some(path), InputArchive::Local(archive_path)) = (checksum_file_path(input), input) else {
+        bail!("Checksum file can only be written next to a local archive");
+    };
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(force_create)
+        .truncate(force_create)
+        .create_new(!force_create)
+        .open(&path)
+        .context(format!("Failed to create checksum file {}", path.display()))?;
+    writeln!(
+        file,
+        "{}  {}",
+        checksum,
+        archive_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+    )
+    .context(format!("Failed to write checksum file {}", path.display()))?;
+    Ok(path)
+}
+
+/// Compare the archive header checksum with the one of the archive checksum file, if any.
+pub fn verify_checksum_file<R>(
+    archive: &Archive<R>,
+    checksum_file: Option<&(PathBuf, HashSum)>,
+    no_verify_header: bool,
+) -> Result<()> {
+    let Some((path, checksum)) = checksum_file else {
+        return Ok(());
+    };
+    if no_verify_header {
+        warn!(
+            "Header not verified, ignoring checksum file {}",
+            path.display()
+        );
+    } else if checksum != archive.header_checksum() {
+        return Err(
+            anyhow::Error::from(CloneError::HeaderChecksumMismatch).context(format!(
+                "Header checksum {} doesn't match {} of {}",
+                archive.header_checksum(),
+                checksum,
+                path.display()
+            )),
+        );
+    } else {
+        info!("Header checksum verified OK against {}", path.display());
+    }
+    Ok(())
 }
 
 pub async fn print_archive_reader<R>(reader: R) -> Result<()>
@@ -34,7 +138,11 @@ fn print_rolling_hash_config(hc: &chunker::FilterConfig) {
         human_size!(hc.window_size)
     );
     info!("  Chunk minimum size: {}", human_size!(hc.min_chunk_size));
-    info!("  Chunk maximum size: {}", human_size!(hc.max_chunk_size));
+    if hc.max_chunk_size == 0 {
+        info!("  Chunk maximum size: Unbounded");
+    } else {
+        info!("  Chunk maximum size: {}", human_size!(hc.max_chunk_size));
+    }
     info!(
         "  Chunk average target size: {} (mask: {:#b})",
         human_size!(hc.filter_bits.chunk_target_average()),
@@ -49,14 +157,23 @@ pub fn print_chunker_config(config: &chunker::Config) {
             chunker::Config::BuzHash(_) => "BuzHash",
             chunker::Config::RollSum(_) => "RollSum",
             chunker::Config::FixedSize(_) => "Fixed Size",
+            chunker::Config::Explicit(_) => "Explicit",
         }
     );
     match config {
-        chunker::Config::BuzHash(hc) => print_rolling_hash_config(hc),
+        chunker::Config::BuzHash(hc) => {
+            info!("  BuzHash seed: {:#x}", hc.buzhash_seed);
+            print_rolling_hash_config(hc)
+        }
         chunker::Config::RollSum(hc) => print_rolling_hash_config(hc),
         chunker::Config::FixedSize(chunk_size) => {
-            info!("  Fixed chunk size: {}", human_size!(*chunk_size));
+            if *chunk_size == 0 {
+                info!("  Fixed chunk size: Unbounded");
+            } else {
+                info!("  Fixed chunk size: {}", human_size!(*chunk_size));
+            }
         }
+        chunker::Config::Explicit(_) => {}
     }
 }
 
@@ -80,6 +197,18 @@ pub fn print_archive<R>(archive: &Archive<R>) {
     }
 
     info!("  Header checksum: {}", archive.header_checksum());
+    if let Some(base_checksum) = archive.base_header_checksum() {
+        info!(
+            "  Delta against base archive: {} ({} chunks stored in base)",
+            base_checksum,
+            archive
+                .chunk_descriptors()
+                .iter()
+                .filter(|cd| cd.in_base)
+                .count()
+        );
+    }
+    info!("  Chunk hash algorithm: {}", archive.chunk_hash_algorithm());
     info!("  Chunk hash length: {} bytes", archive.chunk_hash_length());
     info!(
         "  Chunk compression: {}",
@@ -88,6 +217,27 @@ pub fn print_archive<R>(archive: &Archive<R>) {
             Some(c) => format!("{}", c),
         }
     );
+    // Archives created with auto compression mix compressions between chunks.
+    if archive
+        .chunk_descriptors()
+        .iter()
+        .any(|cd| cd.compression.is_some() && cd.compression != archive.chunk_compression())
+    {
+        let mut chunks_per_compression = std::collections::BTreeMap::new();
+        for cd in archive.chunk_descriptors() {
+            let name = match cd.compression {
+                None => "None".to_string(),
+                Some(c) => format!("{}", c),
+            };
+            *chunks_per_compression.entry(name).or_insert(0) += 1;
+        }
+        let display = chunks_per_compression
+            .iter()
+            .map(|(name, count)| format!("{}: {}", name, count))
+            .collect::<Vec<String>>()
+            .join(", ");
+        info!("  Chunks per compression: {}", display);
+    }
 
     print_chunker_config(archive.chunker_config());
 
@@ -100,55 +250,70 @@ pub fn print_archive<R>(archive: &Archive<R>) {
     );
     info!(
         "  Average chunk size: {}",
-        human_size!(
-            archive
-                .chunk_descriptors()
-                .iter()
-                .map(|cdesc| u64::from(cdesc.source_size))
-                .sum::<u64>()
-                / archive.chunk_descriptors().len() as u64
-        )
+        human_size!(archive
+            .chunk_descriptors()
+            .iter()
+            .map(|cdesc| u64::from(cdesc.source_size))
+            .sum::<u64>()
+            // No chunks in an empty source.
+            .checked_div(archive.chunk_descriptors().len() as u64)
+            .unwrap_or(0))
     );
     info!(
         "  Source size: {}",
         human_size!(archive.total_source_size())
     );
+
+    debug!("Chunks (archive order):");
+    for cd in archive.chunk_descriptors() {
+        debug!(
+            "  {} at offset {}, size {} ({} in source){}",
+            cd.checksum,
+            cd.archive_offset,
+            cd.archive_size,
+            cd.source_size,
+            if cd.in_base { ", in base" } else { "" }
+        );
+    }
 }
 
-async fn info_impl<R>(reader: R, metadata_key: Option<String>) -> Result<()>
+async fn info_impl<R>(reader: R, options: &Options) -> Result<()>
 where
     R: ArchiveReader,
     R::Error: std::error::Error + Send + Sync + 'static,
 {
-    if let Some(key) = metadata_key {
-        let archive = Archive::try_init(reader).await?;
+    let archive = signature::init_archive(
+        reader,
+        options.verify_signature.as_deref(),
+        !options.no_verify_header,
+    )
+    .await?;
+    let checksum_file = read_checksum_file(&options.input_archive)?;
+    if options.no_verify_header {
+        warn!("Header not verified against its checksum, the archive may be corrupt");
+    }
+    verify_checksum_file(&archive, checksum_file.as_ref(), options.no_verify_header)?;
+    if let Some(key) = &options.metadata_key {
         if let Some(value) = archive.metadata_value(key.as_str()) {
             std::io::stdout().write_all(value)?;
         } else {
             bail!("Metadata key not found: {}", key);
         }
-        Ok(())
     } else {
-        print_archive_reader(reader).await
+        print_archive(&archive);
     }
+    Ok(())
 }
 
 pub async fn info_cmd(options: Options) -> Result<()> {
-    match options.input_archive {
+    match &options.input_archive {
         InputArchive::Local(path) => {
-            info_impl(IoReader::new(File::open(path).await?), options.metadata_key).await
+            info_impl(IoReader::new(File::open(path).await?), &options).await
         }
-        InputArchive::Remote(input) => {
-            let mut request = reqwest::Client::new()
-                .get(input.url.clone())
-                .headers(input.headers.clone());
-            if let Some(timeout) = input.receive_timeout {
-                request = request.timeout(timeout);
-            }
-            let reader = HttpReader::from_request(request)
-                .retries(input.retries)
-                .retry_delay(input.retry_delay);
-            info_impl(reader, options.metadata_key).await
+        InputArchive::Remote(input) => info_impl(input.reader()?, &options).await,
+        #[cfg(feature = "object-store")]
+        InputArchive::ObjectStore(url) => {
+            info_impl(crate::clone_cmd::object_store_reader(url)?, &options).await
         }
     }
 }
@@ -1,9 +1,22 @@
+mod checksum_cmd;
+mod chunk_buffers;
 mod cli;
 mod clone_cmd;
 mod compress_cmd;
+mod diff_archives_cmd;
 mod diff_cmd;
+mod dump_chunks_cmd;
+mod export_index_cmd;
+mod extract_chunk_cmd;
+mod file_metadata;
 mod info_cmd;
+mod patch;
+mod patch_cmd;
+mod progress;
+mod repack_cmd;
+mod signature;
 mod string_utils;
+mod verify_cmd;
 
 use anyhow::{Context, Result};
 use cli::parse_opts;
@@ -16,20 +29,78 @@ pub const PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 fn main() -> Result<()> {
     let (command_opts, log_opts) = parse_opts(std::env::args_os()).unwrap_or_else(|e| e.exit());
-    init_log(log_opts)?;
-    tokio::runtime::Runtime::new()?.block_on(async {
+    // Keep stdout clean when it's used for output.
+    let log_to_stderr = match &command_opts {
+        CommandOpts::Clone(opts) => opts.output_is_stdout(),
+        CommandOpts::DumpChunks(_) => true,
+        _ => false,
+    };
+    init_log(log_opts, log_to_stderr)?;
+    let result = build_runtime(&command_opts)?.block_on(async {
         match command_opts {
             CommandOpts::Compress(opts) => compress_cmd::compress_cmd(opts).await,
             CommandOpts::Clone(opts) => clone_cmd::clone_cmd(opts).await,
             CommandOpts::Info(opts) => info_cmd::info_cmd(opts).await,
             CommandOpts::Diff(opts) => diff_cmd::diff_cmd(opts).await,
+            CommandOpts::DiffArchives(opts) => diff_archives_cmd::diff_archives_cmd(opts).await,
+            CommandOpts::Verify(opts) => verify_cmd::verify_cmd(opts).await,
+            CommandOpts::Repack(opts) => repack_cmd::repack_cmd(opts).await,
+            CommandOpts::ExportIndex(opts) => export_index_cmd::export_index_cmd(opts).await,
+            CommandOpts::ExtractChunk(opts) => extract_chunk_cmd::extract_chunk_cmd(opts).await,
+            CommandOpts::DumpChunks(opts) => dump_chunks_cmd::dump_chunks_cmd(opts).await,
+            CommandOpts::Patch(opts) => patch_cmd::patch_cmd(opts).await,
+            CommandOpts::Checksum(opts) => checksum_cmd::checksum_cmd(opts).await,
         }
-    })
+    });
+    if let Err(err) = result {
+        // Same output as when returning the error from main.
+        eprintln!("Error: {:?}", err);
+        std::process::exit(exit_code(&err));
+    }
+    Ok(())
+}
+
+// Exit status of the process when failing with err:
+//   1: any other failure
+//   2: invalid command line arguments (set by clap)
+//   3: header checksum mismatch
+//   4: output device smaller than the archive source
+//   5: output checksum differs from the archive source checksum
+//   6: I/O error
+//   7: chunks zero filled by a lenient clone
+//   8: clone not finished within the total deadline
+fn exit_code(err: &anyhow::Error) -> i32 {
+    use clone_cmd::CloneError;
+    match err.chain().find_map(|err| err.downcast_ref::<CloneError>()) {
+        Some(CloneError::HeaderChecksumMismatch) => 3,
+        Some(CloneError::OutputSizeMismatch { .. }) => 4,
+        Some(CloneError::SourceChecksumMismatch { .. }) => 5,
+        Some(CloneError::InvalidChunksZeroFilled { .. }) => 7,
+        Some(CloneError::DeadlineExceeded { .. }) => 8,
+        None if err.chain().any(|err| err.is::<std::io::Error>()) => 6,
+        None => 1,
+    }
+}
+
+// Build the runtime, with the blocking pool sized for the commands supporting --threads.
+//
+// Chunks are hashed and (de)compressed on the blocking pool, which is also used for file
+// I/O, so the pool size limits the CPU used while --buffered-chunks only limits how many
+// chunks are kept in memory.
+fn build_runtime(command_opts: &CommandOpts) -> Result<tokio::runtime::Runtime> {
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder.enable_all();
+    match command_opts {
+        CommandOpts::Compress(opts) => builder.max_blocking_threads(opts.num_threads),
+        CommandOpts::Clone(opts) => builder.max_blocking_threads(opts.num_threads),
+        _ => &mut builder,
+    };
+    builder.build().context("Failed to build runtime")
 }
 
-fn init_log(log_opts: LogOpts) -> Result<()> {
+fn init_log(log_opts: LogOpts, log_to_stderr: bool) -> Result<()> {
     let local_level = log_opts.filter;
-    fern::Dispatch::new()
+    let dispatch = fern::Dispatch::new()
         .format(move |out, message, record| {
             if local_level > LevelFilter::Info {
                 // Add some extra info to each message in debug
@@ -44,9 +115,13 @@ fn init_log(log_opts: LogOpts) -> Result<()> {
                 out.finish(format_args!("{}", message))
             }
         })
-        .level(log_opts.filter)
-        .chain(std::io::stdout())
-        .apply()
-        .context("Unable to initialize log")?;
+        .level(log_opts.filter);
+    if log_to_stderr {
+        dispatch.chain(std::io::stderr())
+    } else {
+        dispatch.chain(std::io::stdout())
+    }
+    .apply()
+    .context("Unable to initialize log")?;
     Ok(())
 }
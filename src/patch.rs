@@ -0,0 +1,274 @@
+// Binary patch format written by diff and applied by patch.
+//
+// All integers are little endian. A patch starts with the magic and format version, followed
+// by the size and blake2b checksum of the base file (A) and of the target file (B):
+//
+//   magic "BITAPTCH", version (u32)
+//   base size (u64), base checksum (64 bytes)
+//   target size (u64), target checksum (64 bytes)
+//
+// Then follows a sequence of operations, each starting with its type byte:
+//
+//   0: end of patch
+//   1: copy from base: base offset (u64), size (u64), target offsets
+//   2: chunk data: compression (u8), source size (u32), data size (u32), data, target offsets
+//
+// Where target offsets is the number of offsets (u32) followed by the offsets (u64) in the
+// target to write the bytes to.
+use anyhow::{anyhow, Context, Result};
+use bitar::{CompressionAlgorithm, HashSum};
+use std::io::{Read, Write};
+
+const MAGIC: &[u8; 8] = b"BITAPTCH";
+const VERSION: u32 = 1;
+const CHECKSUM_SIZE: usize = 64;
+
+const OP_END: u8 = 0;
+const OP_COPY: u8 = 1;
+const OP_DATA: u8 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub base_size: u64,
+    pub base_checksum: HashSum,
+    pub target_size: u64,
+    pub target_checksum: HashSum,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    /// Copy size bytes at offset in base to the target offsets.
+    Copy {
+        base_offset: u64,
+        size: u64,
+        target_offsets: Vec<u64>,
+    },
+    /// Write chunk data, possibly compressed, to the target offsets.
+    Data {
+        compression: Option<CompressionAlgorithm>,
+        source_size: usize,
+        data: Vec<u8>,
+        target_offsets: Vec<u64>,
+    },
+}
+
+fn compression_to_u8(compression: Option<CompressionAlgorithm>) -> u8 {
+    match compression {
+        None => 0,
+        Some(CompressionAlgorithm::Brotli) => 1,
+        #[cfg(feature = "lzma-compression")]
+        Some(CompressionAlgorithm::Lzma) => 2,
+        #[cfg(feature = "zstd-compression")]
+        Some(CompressionAlgorithm::Zstd) => 3,
+        #[cfg(feature = "lz4-compression")]
+        Some(CompressionAlgorithm::Lz4) => 4,
+    }
+}
+
+fn compression_from_u8(value: u8) -> Result<Option<CompressionAlgorithm>> {
+    match value {
+        0 => Ok(None),
+        1 => Ok(Some(CompressionAlgorithm::Brotli)),
+        #[cfg(feature = "lzma-compression")]
+        2 => Ok(Some(CompressionAlgorithm::Lzma)),
+        #[cfg(not(feature = "lzma-compression"))]
+        2 => Err(anyhow!(
+            "LZMA compressed patch but LZMA support is not enabled"
+        )),
+        #[cfg(feature = "zstd-compression")]
+        3 => Ok(Some(CompressionAlgorithm::Zstd)),
+        #[cfg(not(feature = "zstd-compression"))]
+        3 => Err(anyhow!(
+            "zstd compressed patch but zstd support is not enabled"
+        )),
+        #[cfg(feature = "lz4-compression")]
+        4 => Ok(Some(CompressionAlgorithm::Lz4)),
+        #[cfg(not(feature = "lz4-compression"))]
+        4 => Err(anyhow!(
+            "LZ4 compressed patch but LZ4 support is not enabled"
+        )),
+        value => Err(anyhow!("Unknown compression {} in patch", value)),
+    }
+}
+
+fn read_u8<R: Read>(input: &mut R) -> std::io::Result<u8> {
+    let mut buf = [0u8; 1];
+    input.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u32<R: Read>(input: &mut R) -> std::io::Result<u32> {
+    let mut buf = [0u8; 4];
+    input.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(input: &mut R) -> std::io::Result<u64> {
+    let mut buf = [0u8; 8];
+    input.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_checksum<R: Read>(input: &mut R) -> std::io::Result<HashSum> {
+    let mut buf = [0u8; CHECKSUM_SIZE];
+    input.read_exact(&mut buf)?;
+    Ok(HashSum::from(&buf[..]))
+}
+
+fn write_checksum<W: Write>(output: &mut W, checksum: &HashSum) -> Result<()> {
+    if checksum.len() != CHECKSUM_SIZE {
+        return Err(anyhow!("Invalid patch checksum length {}", checksum.len()));
+    }
+    output.write_all(checksum.slice())?;
+    Ok(())
+}
+
+fn read_target_offsets<R: Read>(input: &mut R) -> std::io::Result<Vec<u64>> {
+    let count = read_u32(input)?;
+    (0..count).map(|_| read_u64(input)).collect()
+}
+
+fn write_target_offsets<W: Write>(output: &mut W, offsets: &[u64]) -> Result<()> {
+    let count = u32::try_from(offsets.len()).context("Too many target offsets in patch")?;
+    output.write_all(&count.to_le_bytes())?;
+    for offset in offsets {
+        output.write_all(&offset.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+pub fn write_header<W: Write>(output: &mut W, header: &Header) -> Result<()> {
+    output.write_all(MAGIC)?;
+    output.write_all(&VERSION.to_le_bytes())?;
+    output.write_all(&header.base_size.to_le_bytes())?;
+    write_checksum(output, &header.base_checksum)?;
+    output.write_all(&header.target_size.to_le_bytes())?;
+    write_checksum(output, &header.target_checksum)?;
+    Ok(())
+}
+
+pub fn read_header<R: Read>(input: &mut R) -> Result<Header> {
+    let mut magic = [0u8; 8];
+    input
+        .read_exact(&mut magic)
+        .context("Failed to read patch header")?;
+    if &magic != MAGIC {
+        return Err(anyhow!("Not a patch file"));
+    }
+    let version = read_u32(input)?;
+    if version != VERSION {
+        return Err(anyhow!("Unsupported patch version {}", version));
+    }
+    Ok(Header {
+        base_size: read_u64(input)?,
+        base_checksum: read_checksum(input)?,
+        target_size: read_u64(input)?,
+        target_checksum: read_checksum(input)?,
+    })
+}
+
+pub fn write_op<W: Write>(output: &mut W, op: &Op) -> Result<()> {
+    match op {
+        Op::Copy {
+            base_offset,
+            size,
+            target_offsets,
+        } => {
+            output.write_all(&[OP_COPY])?;
+            output.write_all(&base_offset.to_le_bytes())?;
+            output.write_all(&size.to_le_bytes())?;
+            write_target_offsets(output, target_offsets)?;
+        }
+        Op::Data {
+            compression,
+            source_size,
+            data,
+            target_offsets,
+        } => {
+            let source_size = u32::try_from(*source_size).context("Chunk too large for patch")?;
+            let data_size = u32::try_from(data.len()).context("Chunk too large for patch")?;
+            output.write_all(&[OP_DATA, compression_to_u8(*compression)])?;
+            output.write_all(&source_size.to_le_bytes())?;
+            output.write_all(&data_size.to_le_bytes())?;
+            output.write_all(data)?;
+            write_target_offsets(output, target_offsets)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn write_end<W: Write>(output: &mut W) -> Result<()> {
+    output.write_all(&[OP_END])?;
+    Ok(())
+}
+
+// Read the next operation of patch, None at the end of patch.
+pub fn read_op<R: Read>(input: &mut R) -> Result<Option<Op>> {
+    match read_u8(input)? {
+        OP_END => Ok(None),
+        OP_COPY => Ok(Some(Op::Copy {
+            base_offset: read_u64(input)?,
+            size: read_u64(input)?,
+            target_offsets: read_target_offsets(input)?,
+        })),
+        OP_DATA => {
+            let compression = compression_from_u8(read_u8(input)?)?;
+            let source_size = read_u32(input)? as usize;
+            let mut data = vec![0; read_u32(input)? as usize];
+            input.read_exact(&mut data)?;
+            Ok(Some(Op::Data {
+                compression,
+                source_size,
+                data,
+                target_offsets: read_target_offsets(input)?,
+            }))
+        }
+        op => Err(anyhow!("Unknown operation {} in patch", op)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_and_read_patch() {
+        let header = Header {
+            base_size: 100,
+            base_checksum: HashSum::from(&[1u8; 64][..]),
+            target_size: 200,
+            target_checksum: HashSum::from(&[2u8; 64][..]),
+        };
+        let ops = vec![
+            Op::Copy {
+                base_offset: 10,
+                size: 20,
+                target_offsets: vec![0, 100],
+            },
+            Op::Data {
+                compression: Some(CompressionAlgorithm::Brotli),
+                source_size: 30,
+                data: vec![3; 5],
+                target_offsets: vec![20],
+            },
+        ];
+        let mut patch = Vec::new();
+        write_header(&mut patch, &header).unwrap();
+        for op in &ops {
+            write_op(&mut patch, op).unwrap();
+        }
+        write_end(&mut patch).unwrap();
+
+        let mut input = &patch[..];
+        assert_eq!(read_header(&mut input).unwrap(), header);
+        assert_eq!(read_op(&mut input).unwrap(), Some(ops[0].clone()));
+        assert_eq!(read_op(&mut input).unwrap(), Some(ops[1].clone()));
+        assert_eq!(read_op(&mut input).unwrap(), None);
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn read_patch_bad_magic() {
+        assert!(read_header(&mut &[0u8; 160][..]).is_err());
+    }
+}
@@ -0,0 +1,255 @@
+use anyhow::{anyhow, Context, Result};
+use blake2::{Blake2b512, Digest};
+use log::*;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::human_size;
+use crate::patch::{self, Op};
+use bitar::{CompressedChunk, HashSum};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// File to apply the patch to.
+    pub base: PathBuf,
+    pub patch: PathBuf,
+    pub output: PathBuf,
+    pub force_create: bool,
+}
+
+fn file_checksum(file: &mut File) -> Result<HashSum> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut hasher = Blake2b512::new();
+    let mut buffer: Vec<u8> = vec![0; 4 * 1024 * 1024];
+    loop {
+        let rc = file.read(&mut buffer)?;
+        if rc == 0 {
+            break;
+        }
+        hasher.update(&buffer[0..rc]);
+    }
+    Ok(HashSum::from(&hasher.finalize()[..]))
+}
+
+fn write_at(output: &mut File, target_size: u64, offsets: &[u64], data: &[u8]) -> Result<()> {
+    for &offset in offsets {
+        if offset + data.len() as u64 > target_size {
+            return Err(anyhow!("Patch writes beyond the end of output"));
+        }
+        output.seek(SeekFrom::Start(offset))?;
+        output.write_all(data)?;
+    }
+    Ok(())
+}
+
+fn apply_patch(opts: &Options) -> Result<()> {
+    let mut patch_input = BufReader::new(
+        File::open(&opts.patch).context(format!("Failed to open {}", opts.patch.display()))?,
+    );
+    let header = patch::read_header(&mut patch_input)
+        .context(format!("Failed to read patch {}", opts.patch.display()))?;
+
+    let mut base =
+        File::open(&opts.base).context(format!("Failed to open {}", opts.base.display()))?;
+    let base_size = base.metadata()?.len();
+    if base_size != header.base_size || file_checksum(&mut base)? != header.base_checksum {
+        return Err(anyhow!(
+            "{} is not the file the patch was created from",
+            opts.base.display()
+        ));
+    }
+    if same_file(&opts.base, &opts.output) {
+        return Err(anyhow!("Output can't be the file patched"));
+    }
+
+    let mut output = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(opts.force_create)
+        .truncate(opts.force_create)
+        .create_new(!opts.force_create)
+        .open(&opts.output)
+        .context(format!("Failed to open {}", opts.output.display()))?;
+    output.set_len(header.target_size)?;
+
+    let mut copied_size = 0u64;
+    let mut stored_size = 0u64;
+    let mut buffer = Vec::new();
+    while let Some(op) = patch::read_op(&mut patch_input)
+        .context(format!("Failed to read patch {}", opts.patch.display()))?
+    {
+        match op {
+            Op::Copy {
+                base_offset,
+                size,
+                target_offsets,
+            } => {
+                if base_offset + size > base_size {
+                    return Err(anyhow!(
+                        "Patch reads beyond the end of {}",
+                        opts.base.display()
+                    ));
+                }
+                buffer.resize(size as usize, 0);
+                base.seek(SeekFrom::Start(base_offset))?;
+                base.read_exact(&mut buffer)?;
+                write_at(&mut output, header.target_size, &target_offsets, &buffer)?;
+                copied_size += size * target_offsets.len() as u64;
+            }
+            Op::Data {
+                compression,
+                source_size,
+                data,
+                target_offsets,
+            } => {
+                let chunk = CompressedChunk::new(compression, data.into(), source_size)
+                    .decompress()
+                    .context("Failed to decompress chunk in patch")?;
+                if chunk.len() != source_size {
+                    return Err(anyhow!("Chunk in patch of unexpected size"));
+                }
+                write_at(
+                    &mut output,
+                    header.target_size,
+                    &target_offsets,
+                    chunk.data(),
+                )?;
+                stored_size += (chunk.len() * target_offsets.len()) as u64;
+            }
+        }
+    }
+    output.flush()?;
+
+    if file_checksum(&mut output)? != header.target_checksum {
+        return Err(anyhow!(
+            "Checksum mismatch ({} is incomplete or corrupt)",
+            opts.output.display()
+        ));
+    }
+    info!(
+        "Patched {} into {} ({} copied, {} from patch)",
+        opts.base.display(),
+        opts.output.display(),
+        human_size!(copied_size),
+        human_size!(stored_size)
+    );
+    Ok(())
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+pub async fn patch_cmd(opts: Options) -> Result<()> {
+    tokio::task::spawn_blocking(move || apply_patch(&opts)).await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff_cmd;
+    use bitar::{chunker, Compression};
+
+    fn pseudo_random_bytes(size: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..size)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn diff_patch_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let base_path = dir.path().join("a");
+        let target_path = dir.path().join("b");
+        let patch_path = dir.path().join("a-b.patch");
+        let output_path = dir.path().join("output");
+        // B moves, repeats and replaces chunks of A.
+        let base = pseudo_random_bytes(64 * 1024, 1);
+        let target = [
+            &base[32 * 1024..48 * 1024],
+            &pseudo_random_bytes(10 * 1024, 2)[..],
+            &base[..16 * 1024],
+            &base[..16 * 1024],
+            &pseudo_random_bytes(10 * 1024, 2)[..],
+            &[7u8; 100][..],
+        ]
+        .concat();
+        std::fs::write(&base_path, &base).unwrap();
+        std::fs::write(&target_path, &target).unwrap();
+
+        diff_cmd::diff_cmd(diff_cmd::Options {
+            input_a: base_path.clone(),
+            input_b: target_path.clone(),
+            chunker_config: chunker::Config::FixedSize(1024),
+            compression: Some(Compression::brotli(6).unwrap()),
+            num_chunk_buffers: 2,
+            transfer_cost: false,
+            patch: Some(patch_path.clone()),
+        })
+        .await
+        .unwrap();
+        // Only the chunks not in A are stored.
+        assert!(std::fs::metadata(&patch_path).unwrap().len() < 16 * 1024);
+
+        patch_cmd(Options {
+            base: base_path.clone(),
+            patch: patch_path.clone(),
+            output: output_path.clone(),
+            force_create: false,
+        })
+        .await
+        .unwrap();
+        assert_eq!(std::fs::read(&output_path).unwrap(), target);
+
+        // Output exists and is not overwritten unless forced.
+        assert!(patch_cmd(Options {
+            base: base_path.clone(),
+            patch: patch_path.clone(),
+            output: output_path.clone(),
+            force_create: false,
+        })
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn patch_wrong_base() {
+        let dir = tempfile::tempdir().unwrap();
+        let base_path = dir.path().join("a");
+        let target_path = dir.path().join("b");
+        let patch_path = dir.path().join("a-b.patch");
+        std::fs::write(&base_path, pseudo_random_bytes(8000, 1)).unwrap();
+        std::fs::write(&target_path, pseudo_random_bytes(8000, 2)).unwrap();
+        diff_cmd::diff_cmd(diff_cmd::Options {
+            input_a: base_path.clone(),
+            input_b: target_path.clone(),
+            chunker_config: chunker::Config::FixedSize(1024),
+            compression: None,
+            num_chunk_buffers: 2,
+            transfer_cost: false,
+            patch: Some(patch_path.clone()),
+        })
+        .await
+        .unwrap();
+
+        let result = patch_cmd(Options {
+            base: target_path.clone(),
+            patch: patch_path,
+            output: dir.path().join("output"),
+            force_create: false,
+        })
+        .await;
+        assert!(result.is_err());
+        assert!(!dir.path().join("output").exists());
+    }
+}
@@ -0,0 +1,235 @@
+use log::*;
+use std::time::{Duration, Instant};
+
+/// Minimum time between two progress reports.
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How progress is reported.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProgressFormat {
+    /// Log messages for a human to read.
+    #[default]
+    Human,
+    /// Newline delimited JSON events written to stderr, for a program to parse.
+    Json,
+}
+
+/// Periodic report of bytes processed, with ETA when the total size is known.
+pub struct Progress {
+    format: ProgressFormat,
+    phase: &'static str,
+    action: &'static str,
+    total: Option<u64>,
+    processed: u64,
+    start: Instant,
+    last_report: Instant,
+}
+
+impl Progress {
+    /// Create a report of phase, logged as action in human readable messages.
+    pub fn new(
+        format: ProgressFormat,
+        phase: &'static str,
+        action: &'static str,
+        total: Option<u64>,
+    ) -> Self {
+        let now = Instant::now();
+        Self {
+            format,
+            phase,
+            action,
+            total,
+            processed: 0,
+            start: now,
+            last_report: now,
+        }
+    }
+
+    /// Set the total once it's known.
+    pub fn set_total(&mut self, total: u64) {
+        self.total = Some(total);
+    }
+
+    /// Update the number of bytes processed so far, reporting it if it's time to.
+    pub fn update(&mut self, processed: u64) {
+        self.processed = processed;
+        let now = Instant::now();
+        if now.duration_since(self.last_report) < REPORT_INTERVAL {
+            return;
+        }
+        self.last_report = now;
+        self.report(now);
+    }
+
+    /// Report the bytes processed when the phase is done.
+    ///
+    /// Only reported as a JSON event, a human already gets a summary logged at the end of
+    /// every phase.
+    pub fn finish(&self) {
+        if self.format == ProgressFormat::Json {
+            self.report(Instant::now());
+        }
+    }
+
+    fn report(&self, now: Instant) {
+        let elapsed = now.duration_since(self.start);
+        match self.format {
+            ProgressFormat::Human => info!(
+                "{}",
+                progress_message(self.action, self.processed, self.total, elapsed)
+            ),
+            ProgressFormat::Json => eprintln!(
+                "{}",
+                progress_event(self.phase, self.processed, self.total, elapsed)
+            ),
+        }
+    }
+}
+
+/// Report the final summary of a command, as the last JSON event.
+///
+/// Nothing is reported in the human format, where the summary is logged as is.
+pub fn report_done(format: ProgressFormat, summary: &[(&str, u64)]) {
+    if format == ProgressFormat::Json {
+        eprintln!("{}", done_event(summary));
+    }
+}
+
+fn rate(processed: u64, elapsed: Duration) -> f64 {
+    processed as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+}
+
+// Field names and phases are plain identifiers, none of them needs escaping.
+fn progress_event(phase: &str, processed: u64, total: Option<u64>, elapsed: Duration) -> String {
+    format!(
+        r#"{{"event":"progress","phase":"{}","bytes":{},"total":{},"rate":{}}}"#,
+        phase,
+        processed,
+        total.map_or_else(|| "null".to_string(), |total| total.to_string()),
+        rate(processed, elapsed) as u64
+    )
+}
+
+fn done_event(summary: &[(&str, u64)]) -> String {
+    let mut event = r#"{"event":"done""#.to_string();
+    for (name, value) in summary {
+        event.push_str(&format!(r#","{}":{}"#, name, value));
+    }
+    event.push('}');
+    event
+}
+
+fn progress_message(action: &str, processed: u64, total: Option<u64>, elapsed: Duration) -> String {
+    let rate = rate(processed, elapsed);
+    match total {
+        Some(total) if total > 0 => {
+            // Unknown until something has been processed.
+            let eta = Duration::try_from_secs_f64(total.saturating_sub(processed) as f64 / rate)
+                .map_or_else(|_| "unknown".to_string(), short_duration);
+            format!(
+                "{} {} of {} ({}%), {}/s, ETA {}",
+                action,
+                short_size(processed),
+                short_size(total),
+                processed.min(total) * 100 / total,
+                short_size(rate as u64),
+                eta
+            )
+        }
+        _ => format!(
+            "{} {}, {}/s",
+            action,
+            short_size(processed),
+            short_size(rate as u64)
+        ),
+    }
+}
+
+fn short_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if size < 1024 {
+        return format!("{} B", size);
+    }
+    let mut value = size as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+fn short_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}h {}m", secs / 3600, secs % 3600 / 60)
+    } else if secs >= 60 {
+        format!("{}m {}s", secs / 60, secs % 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_with_known_size() {
+        assert_eq!(
+            progress_message(
+                "Processed",
+                256 * 1024 * 1024,
+                Some(1024 * 1024 * 1024),
+                Duration::from_secs(2)
+            ),
+            "Processed 256.0 MiB of 1.0 GiB (25%), 128.0 MiB/s, ETA 6s"
+        );
+        assert_eq!(
+            progress_message(
+                "Processed",
+                1024 * 1024,
+                Some(20 * 1024 * 1024 * 1024),
+                Duration::from_secs(1)
+            ),
+            "Processed 1.0 MiB of 20.0 GiB (0%), 1.0 MiB/s, ETA 5h 41m"
+        );
+    }
+
+    #[test]
+    fn message_before_any_progress() {
+        assert_eq!(
+            progress_message("Processed", 0, Some(1024), Duration::from_secs(1)),
+            "Processed 0 B of 1.0 KiB (0%), 0 B/s, ETA unknown"
+        );
+    }
+
+    #[test]
+    fn json_events() {
+        assert_eq!(
+            progress_event("fetch", 3 * 1024, Some(8 * 1024), Duration::from_secs(2)),
+            r#"{"event":"progress","phase":"fetch","bytes":3072,"total":8192,"rate":1536}"#
+        );
+        assert_eq!(
+            progress_event("seed-scan", 0, None, Duration::ZERO),
+            r#"{"event":"progress","phase":"seed-scan","bytes":0,"total":null,"rate":0}"#
+        );
+        assert_eq!(
+            done_event(&[("bytes_from_archive", 10), ("bytes_from_seeds", 20)]),
+            r#"{"event":"done","bytes_from_archive":10,"bytes_from_seeds":20}"#
+        );
+        assert_eq!(done_event(&[]), r#"{"event":"done"}"#);
+    }
+
+    #[test]
+    fn message_with_unknown_size() {
+        assert_eq!(
+            progress_message("Processed", 1000, None, Duration::from_secs(4)),
+            "Processed 1000 B, 250 B/s"
+        );
+        assert_eq!(
+            progress_message("Processed", 3 * 1024, Some(0), Duration::from_millis(500)),
+            "Processed 3.0 KiB, 6.0 KiB/s"
+        );
+    }
+}
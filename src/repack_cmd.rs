@@ -0,0 +1,326 @@
+use anyhow::{anyhow, Context, Result};
+use futures_util::StreamExt;
+use log::*;
+use std::io::Write;
+use std::path::PathBuf;
+use tokio::{
+    fs::{File, OpenOptions},
+    io::AsyncWriteExt,
+    task::spawn_blocking,
+};
+
+use crate::clone_cmd::InputArchive;
+use crate::{compress_cmd, human_size, info_cmd, signature};
+use bitar::archive_reader::{ArchiveReader, IoReader};
+use bitar::{chunk_dictionary as dict, Compression};
+
+pub const PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// Local file or URL to read archive from.
+    pub input_archive: InputArchive,
+    /// PEM file with ed25519 public key to verify the archive header signature with.
+    pub verify_signature: Option<PathBuf>,
+    pub output: PathBuf,
+    pub temp_file: PathBuf,
+    pub force_create: bool,
+    /// Compression to use for chunks in the new archive.
+    pub compression: Option<Compression>,
+    /// Compressions to try for each chunk, keeping the smallest. Empty to always use
+    /// `compression`.
+    pub auto_compression: Vec<Compression>,
+    /// PKCS#8 PEM file with ed25519 key to sign the new archive header with.
+    pub sign_key: Option<PathBuf>,
+    pub num_chunk_buffers: usize,
+}
+
+// Recompress all chunks of the archive into the temp file.
+// Returns the descriptors of the recompressed chunks, in archive order.
+async fn recompress_chunks<R>(
+    opts: &Options,
+    archive: &mut bitar::Archive<R>,
+) -> Result<Vec<dict::ChunkDescriptor>>
+where
+    R: ArchiveReader,
+    R::Error: std::error::Error + Send + Sync + 'static,
+{
+    let compression = opts.compression;
+    let auto_compression = &opts.auto_compression;
+    let mut temp_file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&opts.temp_file)
+        .await
+        .context(format!(
+            "Failed to open temp file {}",
+            opts.temp_file.display()
+        ))?;
+
+    // Chunks are streamed in the same order as the archive chunk descriptors.
+    let checksums: Vec<Vec<u8>> = archive
+        .chunk_descriptors()
+        .iter()
+        .map(|descriptor| descriptor.checksum.to_vec())
+        .collect();
+    let source_index = archive.build_source_index();
+    let mut chunk_stream = archive
+        .chunk_stream(&source_index)
+        .enumerate()
+        .map(|(index, result)| {
+            let auto_compression = auto_compression.clone();
+            async move {
+                let compressed = result.map_err(|err| {
+                    anyhow!(err).context(format!("Failed to read chunk {}", index))
+                })?;
+                spawn_blocking(move || {
+                    let verified = compressed
+                        .decompress()
+                        .map_err(|err| {
+                            anyhow!(err).context(format!("Failed to decompress chunk {}", index))
+                        })?
+                        .verify()
+                        .map_err(|err| anyhow!(err).context(format!("Invalid chunk {}", index)))?;
+                    let (chunk_compression, recompressed) = if auto_compression.is_empty() {
+                        let recompressed = verified
+                            .chunk()
+                            .clone()
+                            .compress(compression)
+                            .context("Failed to compress chunk")?;
+                        (compression, recompressed)
+                    } else {
+                        verified
+                            .chunk()
+                            .clone()
+                            .compress_smallest(&auto_compression)
+                            .context("Failed to compress chunk")?
+                    };
+                    Ok::<_, anyhow::Error>((index, verified, chunk_compression, recompressed))
+                })
+                .await?
+            }
+        })
+        .buffered(opts.num_chunk_buffers);
+
+    let mut descriptors = Vec::with_capacity(checksums.len());
+    let mut archive_offset: u64 = 0;
+    while let Some(result) = chunk_stream.next().await {
+        let (index, verified, chunk_compression, recompressed) = result?;
+        let chunk_len = verified.len();
+        // Keep chunk uncompressed if compression makes it bigger.
+        let use_uncompressed = recompressed.len() >= chunk_len;
+        let use_data = if use_uncompressed {
+            verified.data()
+        } else {
+            recompressed.data()
+        };
+        debug!(
+            "Chunk {}, '{}', size: {}, stored as: {}",
+            index,
+            verified.hash(),
+            human_size!(chunk_len),
+            human_size!(use_data.len())
+        );
+        descriptors.push(dict::ChunkDescriptor {
+            checksum: checksums[index].clone(),
+            source_size: chunk_len as u32,
+            archive_offset,
+            archive_size: use_data.len() as u32,
+            compression: (!use_uncompressed && chunk_compression != compression)
+                .then(|| chunk_compression.into()),
+            in_base: false,
+        });
+        archive_offset += use_data.len() as u64;
+        temp_file
+            .write_all(use_data)
+            .await
+            .context("Failed to write to temp file")?;
+    }
+    temp_file
+        .flush()
+        .await
+        .context("Failed to write to temp file")?;
+    Ok(descriptors)
+}
+
+async fn repack_archive<R>(opts: &Options, reader: R) -> Result<()>
+where
+    R: ArchiveReader,
+    R::Error: std::error::Error + Send + Sync + 'static,
+{
+    let signing_key = opts
+        .sign_key
+        .as_deref()
+        .map(signature::read_signing_key)
+        .transpose()?;
+    let mut archive =
+        signature::init_archive(reader, opts.verify_signature.as_deref(), true).await?;
+    if archive.base_header_checksum().is_some() {
+        // Chunks stored in the base can't be recompressed.
+        return Err(anyhow!("Repacking a delta archive is not supported"));
+    }
+    let mut output_file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(opts.force_create)
+        .truncate(opts.force_create)
+        .create_new(!opts.force_create)
+        .open(&opts.output)
+        .context(format!(
+            "Failed to open output file {}",
+            opts.output.display()
+        ))?;
+    info!(
+        "Repacking {} chunks of {}...",
+        archive.unique_chunks(),
+        opts.input_archive.source()
+    );
+    let chunk_descriptors = recompress_chunks(opts, &mut archive).await?;
+
+    // Chunks keep their boundaries and order, only the chunk data changes.
+    let file_header = dict::ChunkDictionary {
+        rebuild_order: archive
+            .source_order()
+            .iter()
+            .map(|&index| index as u32)
+            .collect(),
+        application_version: PKG_VERSION.to_string(),
+        chunk_descriptors,
+        source_checksum: archive.source_checksum().to_vec(),
+        chunk_compression: Some(opts.compression.into()),
+        source_total_size: archive.total_source_size(),
+        chunker_params: Some(compress_cmd::chunker_params(
+            archive.chunker_config(),
+            archive.chunk_hash_algorithm(),
+            archive.chunk_hash_length(),
+        )),
+        metadata: archive.metadata().clone(),
+        base_header_checksum: Vec::new(),
+    };
+    let header_buf = bitar::header::build(&file_header, None, signing_key.as_ref())?;
+    output_file.write_all(&header_buf).context(format!(
+        "Failed to write header to output file {}",
+        opts.output.display()
+    ))?;
+    {
+        let mut temp_file = std::fs::File::open(&opts.temp_file).context(format!(
+            "Failed to open temp file {}",
+            opts.temp_file.display()
+        ))?;
+        std::io::copy(&mut temp_file, &mut output_file).context(format!(
+            "Failed to copy from temp file {} to output file {}",
+            opts.temp_file.display(),
+            opts.output.display()
+        ))?;
+    }
+    std::fs::remove_file(&opts.temp_file).context(format!(
+        "Failed to remove temporary file {}",
+        opts.temp_file.display()
+    ))?;
+    Ok(())
+}
+
+pub async fn repack_cmd(opts: Options) -> Result<()> {
+    match opts.input_archive.clone() {
+        InputArchive::Local(path) => {
+            repack_archive(&opts, IoReader::new(File::open(path).await?)).await?
+        }
+        InputArchive::Remote(input) => repack_archive(&opts, input.reader()?).await?,
+        #[cfg(feature = "object-store")]
+        InputArchive::ObjectStore(url) => {
+            repack_archive(&opts, crate::clone_cmd::object_store_reader(&url)?).await?
+        }
+    }
+    // Print archive info
+    let reader = IoReader::new(File::open(&opts.output).await?);
+    info_cmd::print_archive_reader(reader).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitar::{chunker, Archive};
+    use std::collections::{BTreeMap, HashMap};
+
+    #[tokio::test]
+    async fn repack_keeps_chunks_and_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let input_path = dir.path().join("input.cba");
+        let output_path = dir.path().join("output.cba");
+        // Source with both unique and repeated chunks.
+        let mut source: Vec<u8> = (0..200_000u32).map(|v| (v * 7 % 251) as u8).collect();
+        source.extend_from_within(0..50_000);
+        let options = bitar::api::compress::CreateArchiveOptions {
+            chunker_config: chunker::Config::BuzHash(chunker::FilterConfig {
+                filter_bits: chunker::FilterBits::from_size(4096),
+                min_chunk_size: 1024,
+                max_chunk_size: 16384,
+                window_size: 16,
+                ..Default::default()
+            }),
+            chunk_hash_length: 32,
+            compression: Some(Compression::brotli(6).unwrap()),
+            metadata: BTreeMap::from([("key".to_string(), b"value".to_vec())]),
+            ..Default::default()
+        };
+        bitar::api::compress::create_archive(
+            &source[..],
+            File::create(&input_path).await.unwrap(),
+            &options,
+        )
+        .await
+        .unwrap();
+
+        repack_cmd(Options {
+            input_archive: InputArchive::Local(input_path.clone()),
+            verify_signature: None,
+            output: output_path.clone(),
+            temp_file: dir.path().join("output.tmp"),
+            force_create: false,
+            compression: None,
+            auto_compression: Vec::new(),
+            sign_key: None,
+            num_chunk_buffers: 2,
+        })
+        .await
+        .unwrap();
+
+        let input = Archive::try_init(IoReader::new(File::open(&input_path).await.unwrap()))
+            .await
+            .unwrap();
+        let mut output = Archive::try_init(IoReader::new(File::open(&output_path).await.unwrap()))
+            .await
+            .unwrap();
+        assert_eq!(output.chunk_compression(), None);
+        assert_eq!(output.source_order(), input.source_order());
+        assert_eq!(output.source_checksum(), input.source_checksum());
+        assert_eq!(output.total_source_size(), input.total_source_size());
+        assert_eq!(output.chunker_config(), input.chunker_config());
+        assert_eq!(output.chunk_hash_length(), input.chunk_hash_length());
+        assert_eq!(output.metadata(), input.metadata());
+        let checksums = |archive: &Archive<IoReader<File>>| {
+            archive
+                .chunk_descriptors()
+                .iter()
+                .map(|descriptor| descriptor.checksum.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(checksums(&output), checksums(&input));
+
+        // Rebuild the source from the repacked archive.
+        let source_index = output.build_source_index();
+        let mut chunks = HashMap::new();
+        let mut chunk_stream = output.chunk_stream(&source_index);
+        while let Some(result) = chunk_stream.next().await {
+            let verified = result.unwrap().decompress().unwrap().verify().unwrap();
+            chunks.insert(verified.hash().clone(), verified.data().to_vec());
+        }
+        drop(chunk_stream);
+        let mut rebuilt = Vec::new();
+        for (_offset, descriptor) in output.iter_source_chunks() {
+            rebuilt.extend_from_slice(&chunks[&descriptor.checksum]);
+        }
+        assert_eq!(rebuilt, source);
+    }
+}
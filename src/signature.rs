@@ -0,0 +1,51 @@
+use anyhow::{Context, Result};
+use ed25519_dalek::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use std::path::Path;
+
+use bitar::{
+    archive_reader::ArchiveReader,
+    header::{SigningKey, VerifyingKey},
+    Archive,
+};
+
+/// Read an ed25519 private key from a PKCS#8 PEM file.
+pub fn read_signing_key(path: &Path) -> Result<SigningKey> {
+    let pem = std::fs::read_to_string(path)
+        .context(format!("Failed to read signing key {}", path.display()))?;
+    SigningKey::from_pkcs8_pem(&pem)
+        .map_err(|err| anyhow::anyhow!("{}", err))
+        .context(format!("Invalid signing key {}", path.display()))
+}
+
+/// Read an ed25519 public key from a PEM file.
+pub fn read_verifying_key(path: &Path) -> Result<VerifyingKey> {
+    let pem = std::fs::read_to_string(path)
+        .context(format!("Failed to read public key {}", path.display()))?;
+    VerifyingKey::from_public_key_pem(&pem)
+        .map_err(|err| anyhow::anyhow!("{}", err))
+        .context(format!("Invalid public key {}", path.display()))
+}
+
+/// Initialize archive and verify its header signature if a public key is given.
+///
+/// The header is verified against its internal checksum unless `verify_header_checksum` is
+/// false, which is only meant for best-effort recovery of a corrupt archive.
+pub async fn init_archive<R>(
+    reader: R,
+    public_key: Option<&Path>,
+    verify_header_checksum: bool,
+) -> Result<Archive<R>>
+where
+    R: ArchiveReader,
+    R::Error: std::error::Error + Send + Sync + 'static,
+{
+    let mut builder = Archive::builder(reader).verify_header_checksum(verify_header_checksum);
+    if let Some(path) = public_key {
+        builder = builder.verify_signature(&read_verifying_key(path)?);
+    }
+    let archive = builder.try_init().await?;
+    if public_key.is_some() {
+        log::info!("Header signature verified OK");
+    }
+    Ok(archive)
+}
@@ -0,0 +1,148 @@
+use anyhow::{anyhow, Result};
+use futures_util::StreamExt;
+use log::*;
+use std::path::PathBuf;
+use tokio::fs::File;
+use tokio::task::spawn_blocking;
+
+use crate::clone_cmd::InputArchive;
+use crate::{human_size, signature};
+use bitar::archive_reader::{ArchiveReader, IoReader};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// Local file or URL to read archive from.
+    pub input_archive: InputArchive,
+    /// PEM file with ed25519 public key to verify the archive header signature with.
+    pub verify_signature: Option<PathBuf>,
+    pub num_chunk_buffers: usize,
+}
+
+async fn verify_archive<R>(opts: &Options, reader: R) -> Result<()>
+where
+    R: ArchiveReader,
+    R::Error: std::error::Error + Send + Sync + 'static,
+{
+    let mut archive =
+        signature::init_archive(reader, opts.verify_signature.as_deref(), true).await?;
+    info!("Header checksum verified OK");
+
+    // Chunks are streamed in the same order as the archive chunk descriptors, each unique
+    // chunk read once however many times it's used in the source. Chunks stored in the base
+    // of a delta archive are not part of the stream.
+    let archive_offsets: Vec<u64> = archive
+        .chunk_descriptors()
+        .iter()
+        .filter(|descriptor| !descriptor.in_base)
+        .map(|descriptor| descriptor.archive_offset)
+        .collect();
+    let source_index = archive.build_source_index();
+    info!(
+        "Verifying {} chunks of {}...",
+        archive_offsets.len(),
+        opts.input_archive.source()
+    );
+    let mut chunk_stream = archive
+        .chunk_stream(&source_index)
+        .enumerate()
+        .map(|(index, result)| {
+            let offset = archive_offsets[index];
+            async move {
+                let compressed = result.map_err(|err| {
+                    anyhow!(err).context(format!("Failed to read chunk at offset {}", offset))
+                })?;
+                spawn_blocking(move || -> Result<u64> {
+                    let verified = compressed
+                        .decompress()
+                        .map_err(|err| {
+                            anyhow!(err)
+                                .context(format!("Failed to decompress chunk at offset {}", offset))
+                        })?
+                        .verify()
+                        .map_err(|err| {
+                            anyhow!(err).context(format!("Invalid chunk at offset {}", offset))
+                        })?;
+                    Ok(verified.len() as u64)
+                })
+                .await?
+            }
+        })
+        .buffered(opts.num_chunk_buffers);
+
+    let mut verified_chunks = 0usize;
+    let mut verified_size = 0u64;
+    while let Some(result) = chunk_stream.next().await {
+        verified_size += result?;
+        verified_chunks += 1;
+    }
+    info!(
+        "Successfully verified {} chunks ({}).",
+        verified_chunks,
+        human_size!(verified_size)
+    );
+    Ok(())
+}
+
+pub async fn verify_cmd(opts: Options) -> Result<()> {
+    match opts.input_archive.clone() {
+        InputArchive::Local(path) => {
+            verify_archive(&opts, IoReader::new(File::open(path).await?)).await
+        }
+        InputArchive::Remote(input) => verify_archive(&opts, input.reader()?).await,
+        #[cfg(feature = "object-store")]
+        InputArchive::ObjectStore(url) => {
+            verify_archive(&opts, crate::clone_cmd::object_store_reader(&url)?).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitar::archive_reader::{TracedRead, TracingReader};
+    use bitar::chunker;
+
+    #[tokio::test]
+    async fn verify_reads_unique_chunks_once() {
+        // Four different chunks, repeated over and over.
+        let source: Vec<u8> = (0..256u32)
+            .flat_map(|block| [(block % 4) as u8; 1024])
+            .collect();
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = dir.path().join("archive.cba");
+        bitar::api::compress::create_archive(
+            &source[..],
+            File::create(&archive_path).await.unwrap(),
+            &bitar::api::compress::CreateArchiveOptions {
+                chunker_config: chunker::Config::FixedSize(1024),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let opts = Options {
+            input_archive: InputArchive::Local(archive_path.clone()),
+            verify_signature: None,
+            num_chunk_buffers: 2,
+        };
+        let reader = TracingReader::new(IoReader::new(File::open(&archive_path).await.unwrap()));
+        let trace = reader.trace();
+        verify_archive(&opts, reader).await.unwrap();
+
+        let chunk_reads: Vec<_> = trace
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|read| match read {
+                TracedRead::ReadChunks(chunks) => Some(chunks.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(chunk_reads.len(), 1);
+        let mut offsets: Vec<u64> = chunk_reads[0].iter().map(|chunk| chunk.offset).collect();
+        assert_eq!(offsets.len(), 4);
+        offsets.sort_unstable();
+        offsets.dedup();
+        assert_eq!(offsets.len(), 4);
+    }
+}